@@ -1,187 +1,854 @@
-use std::cell::RefCell;
-use std::error::Error;
-use std::rc::Rc;
+mod bitmap;
+mod geo;
+mod hash;
+mod hyperloglog;
+mod keyspace;
+mod list;
+mod set;
+mod stream;
+mod string;
+mod zset;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use crate::{FromResp, ToResp};
+use crate::Protocol;
+use crate::RespErr;
 use crate::RespType;
 use crate::Storage;
+use crate::WrongType;
 
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
 
+/// Per-connection state that commands such as `HELLO` can read and change.
+pub struct ClientState {
+    pub id: u64,
+    pub name: Option<Vec<u8>>,
+    pub protocol: Protocol,
+    /// Set when the last command blocked instead of replying.
+    pub blocked: Option<BlockState>,
+}
+
+/// What a blocking command that couldn't be served right away waits for. The connection
+/// is parked until one of `keys` is written to or `deadline` passes, and the command is
+/// then run again.
+#[derive(Debug, Clone)]
+pub struct BlockState {
+    pub keys: Vec<Vec<u8>>,
+    /// `None` blocks until a key is ready, however long that takes.
+    pub deadline: Option<Instant>,
+    /// The command to run again once a key is ready.
+    pub command: RespType,
+    /// Sent to the client if the deadline passes first.
+    pub timeout_reply: RespType,
+}
+
+impl Default for ClientState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientState {
+    pub fn new() -> Self {
+        Self {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            name: None,
+            protocol: Protocol::Resp2,
+            blocked: None,
+        }
+    }
+}
+
+/// Everything a command may touch while it runs.
+pub struct CommandContext<'a> {
+    pub storage: &'a Arc<Mutex<Storage>>,
+    pub client: &'a mut ClientState,
+}
+
+impl CommandContext<'_> {
+    /// Blocks the client on `keys` until `deadline`. The command should then return the
+    /// reply for when it times out; callers that can't park the client send it right away.
+    pub fn block(&mut self, keys: Vec<Vec<u8>>, deadline: Option<Instant>) {
+        self.client.blocked = Some(BlockState {
+            keys,
+            deadline,
+            command: RespType::Null,
+            timeout_reply: RespType::Null,
+        });
+    }
+
+    /// Like `block`, but runs `command` once a key is ready instead of the command as it
+    /// was sent, for commands resolving some arguments when they first run, like the `$`
+    /// of `XREAD`.
+    pub fn block_rerunning(&mut self, keys: Vec<Vec<u8>>, deadline: Option<Instant>, command: RespType) {
+        self.block(keys, deadline);
+        if let Some(state) = self.client.blocked.as_mut() {
+            state.command = command;
+        }
+    }
+}
+
+/// Executes commands for a single client connection and holds its per-connection state.
 pub struct CommandHandler {
-    storage: Arc<Mutex<Storage>>
+    storage: Arc<Mutex<Storage>>,
+    client: ClientState,
 }
 
 
 impl CommandHandler {
 
     pub fn new(storage: Arc<Mutex<Storage>>) -> Self {
-        Self { storage }
+        Self {
+            storage,
+            client: ClientState::new(),
+        }
+    }
+
+    /// Protocol replies to this connection must be serialized with.
+    pub fn protocol(&self) -> Protocol {
+        self.client.protocol
+    }
+
+    pub fn client_name(&self) -> Option<&[u8]> {
+        self.client.name.as_deref()
+    }
+
+    pub fn client_id(&self) -> u64 {
+        self.client.id
+    }
+
+    /// Takes what the last command blocked on, if it did. The connection is then expected
+    /// to wait for it and run `BlockState::command` again.
+    pub fn take_blocked(&mut self) -> Option<BlockState> {
+        self.client.blocked.take()
     }
 
     pub fn handle_cmd(&mut self, d: RespType) -> Result<RespType, CommandErr> {
-        let RespType::Array(parts) = d else { 
+        self.client.blocked = None;
+        let RespType::Array(parts) = d else {
             return Err(CommandErr::InvalidArgs("No command provided".to_string()));
         };
 
-         let RespType::BString(cmd_name) = &parts[0] else {
+        let Some(cmd_name) = parts.first().and_then(RespType::as_bytes) else {
             return Err(CommandErr::InvalidArgs("Invalid command".to_string()));
-         };
-
-         if cmd_name == "PING" {
-            return Ok(RespType::String("PONG".to_string()))
-         }
+        };
 
-         let cmd = Self::match_cmd(cmd_name).ok_or_else(|| CommandErr::UnknownCommand(cmd_name.to_string()))?;
-         
+        let cmd_name = String::from_utf8_lossy(cmd_name);
 
-         let (valid_args_num, expected) = cmd.validate_args(&parts[1..]);
+        let Some(entry) = CommandRegistry::global().lookup(&cmd_name) else {
+            let args = parts[1..]
+                .iter()
+                .map(|arg| String::from_utf8_lossy(arg.as_bytes().unwrap_or_default()).into_owned())
+                .collect();
+            return Err(CommandErr::UnknownCommand(cmd_name.to_string(), args));
+        };
 
-        if valid_args_num {
-            cmd.execute(&parts[1..], Arc::clone(&self.storage)) // Execute the command
-        } else {
-            Err(CommandErr::InvalidArgs(format!("Expected {}. Got {}", expected, &parts[1..].len())))
+        if !entry.spec.accepts_argc(parts.len()) {
+            return Err(CommandErr::WrongArity(entry.spec.name.to_string()));
         }
 
-         
+        let mut ctx = CommandContext {
+            storage: &self.storage,
+            client: &mut self.client,
+        };
+        let res = entry.cmd.execute(&parts[1..], &mut ctx);
+
+        match (&res, self.client.blocked.as_mut()) {
+            (Ok(reply), Some(state)) => {
+                state.timeout_reply = reply.clone();
+                if state.command == RespType::Null {
+                    state.command = RespType::Array(parts);
+                }
+            }
+            (Err(_), _) => self.client.blocked = None,
+            _ => {}
+        }
+        res
     }
+}
 
+trait Command: Send + Sync {
+    fn spec(&self) -> CommandSpec;
 
+    /// Runs the command with its arguments, not including the command name itself.
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr>;
 
-    fn match_cmd(cmd_name: &str) -> Option<Box<dyn Command>> {
-        match cmd_name {
-            "SET" => Some(Box::new(Set::new())),
-            "GET" => Some(Box::new(Get::new())),
-            "DEL" => Some(Box::new(Del::new())),
-            "COMMAND" => Some(Box::new(CommandCmd::new())),
-            _ => None
-        }
+    /// Positions of the key arguments in `argv`, which does include the command name.
+    ///
+    /// Commands whose keys can't be found from `first_key`/`last_key`/`step` override this.
+    fn key_positions(&self, spec: &CommandSpec, argv: &[RespType]) -> Vec<usize> {
+        spec.key_positions(argv.len())
     }
 }
 
-trait Command<'a> {
-    
-    fn execute(&self, parts: &'a [RespType], storage: Arc<Mutex<Storage>>) -> Result<RespType, CommandErr>;
+/// Key positions of commands that take a `numkeys` argument at position `at` of `argv`,
+/// followed by that many keys.
+fn numkeys_positions(argv: &[RespType], at: usize) -> Vec<usize> {
+    let numkeys = argv.get(at).and_then(|arg| i64::from_resp(arg).ok()).unwrap_or(0).max(0) as usize;
+    (at + 1..(at + 1 + numkeys).min(argv.len())).collect()
+}
 
-    fn validate_args(&self, parts: &'a [RespType]) -> (bool, u8);
+/// Parses the timeout of a blocking command, given in seconds, into a deadline.
+/// Zero means no deadline.
+fn parse_timeout(arg: &RespType) -> Result<Option<Instant>, CommandErr> {
+    let timeout = f64::from_resp(arg)
+        .ok()
+        .filter(|t| t.is_finite())
+        .ok_or_else(|| CommandErr::InvalidArgs("timeout is not a float or out of range".to_string()))?;
+    if timeout < 0.0 {
+        return Err(CommandErr::InvalidArgs("timeout is negative".to_string()));
+    }
+    if timeout == 0.0 {
+        return Ok(None);
+    }
+    Ok(Instant::now().checked_add(Duration::from_secs_f64(timeout)))
 }
 
+/// The arguments of `HSCAN`-style commands after the key.
+struct ScanArgs {
+    cursor: u64,
+    pattern: Option<Vec<u8>>,
+    count: usize,
+    /// `NOVALUES`, which only `HSCAN` takes.
+    novalues: bool,
+}
 
-struct CommandCmd;
+impl ScanArgs {
+    fn parse(parts: &[RespType], allow_novalues: bool) -> Result<Self, CommandErr> {
+        let cursor = String::from_resp(&parts[0])?
+            .parse()
+            .map_err(|_| CommandErr::InvalidArgs("invalid cursor".to_string()))?;
+        let mut args = Self { cursor, pattern: None, count: 10, novalues: false };
+
+        let mut opts = parts[1..].iter();
+        while let Some(opt) = opts.next() {
+            match String::from_resp(opt)?.to_ascii_uppercase().as_str() {
+                "MATCH" => {
+                    let pattern = Vec::<u8>::from_resp(opts.next().ok_or(CommandErr::SyntaxError)?)?;
+                    // a lone `*` matches everything, so don't bother matching it
+                    args.pattern = (pattern != b"*").then_some(pattern);
+                }
+                "COUNT" => match i64::from_resp(opts.next().ok_or(CommandErr::SyntaxError)?)? {
+                    n if n >= 1 => args.count = n as usize,
+                    _ => return Err(CommandErr::SyntaxError),
+                },
+                "NOVALUES" if allow_novalues => args.novalues = true,
+                _ => return Err(CommandErr::SyntaxError),
+            }
+        }
+        Ok(args)
+    }
+
+    fn matches(&self, s: &[u8]) -> bool {
+        self.pattern.as_ref().is_none_or(|p| glob_match(p, s))
+    }
 
-impl CommandCmd {
-    fn new() -> Self {Self{}}
+    /// The reply of a scan: the next cursor, then what was found.
+    fn reply(next: u64, found: Vec<RespType>) -> RespType {
+        RespType::Array(vec![next.to_string().to_resp(), RespType::Array(found)])
+    }
 }
 
-impl<'a> Command<'a> for CommandCmd {
-    fn execute(&self, parts: &'a [RespType], storage: Arc<Mutex<Storage>>) -> Result<RespType, CommandErr> {
-        Ok(RespType::String("OK".to_string()))
+/// Matches `s` against a glob-style pattern, supporting `*`, `?`, `[...]` classes with
+/// ranges and `^` negation, and `\` escapes, like redis' `stringmatchlen`.
+fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // where the last `*` was seen and how much of `s` it consumes so far, to backtrack to
+    let mut star: Option<(usize, usize)> = None;
+
+    while i < s.len() {
+        if p < pattern.len() {
+            if pattern[p] == b'*' {
+                star = Some((p, i));
+                p += 1;
+                continue;
+            }
+            if let Some(len) = glob_match_one(&pattern[p..], s[i]) {
+                p += len;
+                i += 1;
+                continue;
+            }
+        }
+        match star {
+            Some((star_p, star_i)) => {
+                star = Some((star_p, star_i + 1));
+                p = star_p + 1;
+                i = star_i + 1;
+            }
+            None => return false,
+        }
     }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
 
-    fn validate_args(&self, parts: &'a [RespType]) -> (bool, u8) {
-        (true, 0)
+/// Matches a single byte against the start of a pattern that isn't `*`, returning how
+/// much of the pattern was used if it matched.
+fn glob_match_one(pattern: &[u8], c: u8) -> Option<usize> {
+    match pattern {
+        [b'?', ..] => Some(1),
+        [b'\\', escaped, ..] => (*escaped == c).then_some(2),
+        [b'[', class @ ..] => {
+            let (negate, start) = if class.first() == Some(&b'^') { (true, 1) } else { (false, 0) };
+            let mut matched = false;
+            let mut j = start;
+            while j < class.len() && class[j] != b']' {
+                if class[j] == b'\\' && j + 1 < class.len() {
+                    matched |= class[j + 1] == c;
+                    j += 2;
+                } else if j + 2 < class.len() && class[j + 1] == b'-' && class[j + 2] != b']' {
+                    let (lo, hi) = (class[j].min(class[j + 2]), class[j].max(class[j + 2]));
+                    matched |= (lo..=hi).contains(&c);
+                    j += 3;
+                } else {
+                    matched |= class[j] == c;
+                    j += 1;
+                }
+            }
+            // an unterminated class runs to the end of the pattern
+            let len = 1 + (j + 1).min(class.len());
+            (matched != negate).then_some(len)
+        }
+        [literal, ..] => (*literal == c).then_some(1),
+        [] => None,
     }
 }
 
-struct Set;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandFlag {
+    Write,
+    Readonly,
+    DenyOom,
+    Admin,
+    Pubsub,
+    Noscript,
+    Blocking,
+    Loading,
+    Stale,
+    Fast,
+    NoAuth,
+    MovableKeys,
+}
+
+impl CommandFlag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandFlag::Write => "write",
+            CommandFlag::Readonly => "readonly",
+            CommandFlag::DenyOom => "denyoom",
+            CommandFlag::Admin => "admin",
+            CommandFlag::Pubsub => "pubsub",
+            CommandFlag::Noscript => "noscript",
+            CommandFlag::Blocking => "blocking",
+            CommandFlag::Loading => "loading",
+            CommandFlag::Stale => "stale",
+            CommandFlag::Fast => "fast",
+            CommandFlag::NoAuth => "no_auth",
+            CommandFlag::MovableKeys => "movablekeys",
+        }
+    }
+}
 
-impl Set {
-    fn new() -> Self {Self {}}
+/// A command's metadata, as reported by `COMMAND INFO` and `COMMAND DOCS`.
+#[derive(Debug, Clone)]
+pub struct CommandSpec {
+    /// Lowercase name, or `parent|sub` for subcommands.
+    pub name: &'static str,
+    /// Number of arguments including the command name; negative means "at least".
+    pub arity: i32,
+    pub flags: &'static [CommandFlag],
+    pub first_key: i32,
+    /// Negative values count from the end of the arguments, -1 being the last one.
+    pub last_key: i32,
+    pub step: i32,
+    /// Categories beyond the ones implied by the flags, e.g. `@string`.
+    pub acl_categories: &'static [&'static str],
+    pub summary: &'static str,
+    pub since: &'static str,
+    pub group: &'static str,
+    pub complexity: &'static str,
+    pub subcommands: Vec<CommandSpec>,
 }
 
-impl<'a> Command<'a> for Set {
-    
+impl CommandSpec {
+    fn new(name: &'static str, arity: i32) -> Self {
+        Self {
+            name,
+            arity,
+            flags: &[],
+            first_key: 0,
+            last_key: 0,
+            step: 0,
+            acl_categories: &[],
+            summary: "",
+            since: "",
+            group: "",
+            complexity: "",
+            subcommands: Vec::new(),
+        }
+    }
+
+    fn flags(mut self, flags: &'static [CommandFlag]) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    fn keys(mut self, first_key: i32, last_key: i32, step: i32) -> Self {
+        self.first_key = first_key;
+        self.last_key = last_key;
+        self.step = step;
+        self
+    }
+
+    fn acl(mut self, acl_categories: &'static [&'static str]) -> Self {
+        self.acl_categories = acl_categories;
+        self
+    }
+
+    fn docs(mut self, group: &'static str, since: &'static str, complexity: &'static str, summary: &'static str) -> Self {
+        self.group = group;
+        self.since = since;
+        self.complexity = complexity;
+        self.summary = summary;
+        self
+    }
+
+    fn subcommand(mut self, spec: CommandSpec) -> Self {
+        self.subcommands.push(spec);
+        self
+    }
+
+    pub fn has_flag(&self, flag: CommandFlag) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /// Whether `argc` arguments, counting the command name, satisfy the arity.
+    pub fn accepts_argc(&self, argc: usize) -> bool {
+        let arity = self.arity.unsigned_abs() as usize;
+        if self.arity < 0 { argc >= arity } else { argc == arity }
+    }
+
+    /// ACL categories, including those derived from the flags like redis does.
+    pub fn all_acl_categories(&self) -> Vec<&'static str> {
+        let mut categories = Vec::new();
+        if self.has_flag(CommandFlag::Write) {
+            categories.push("@write");
+        }
+        if self.has_flag(CommandFlag::Readonly) {
+            categories.push("@read");
+        }
+        if self.has_flag(CommandFlag::Admin) {
+            categories.extend(["@admin", "@dangerous"]);
+        }
+        if self.has_flag(CommandFlag::Pubsub) {
+            categories.push("@pubsub");
+        }
+        if self.has_flag(CommandFlag::Blocking) {
+            categories.push("@blocking");
+        }
+        if self.has_flag(CommandFlag::Fast) {
+            categories.push("@fast");
+        } else {
+            categories.push("@slow");
+        }
+        categories.extend(self.acl_categories);
+        categories
+    }
 
-    fn execute(&self, parts: &'a [RespType], storage: Arc<Mutex<Storage>>) -> Result<RespType, CommandErr> {
-        let RespType::BString(k) = &parts[0] else {
-            return Err(CommandErr::InvalidArgs("wrong key format".to_string()));
+    fn key_positions(&self, argc: usize) -> Vec<usize> {
+        if self.first_key <= 0 || self.step <= 0 {
+            return Vec::new();
+        }
+        let last = if self.last_key < 0 {
+            argc as i32 + self.last_key
+        } else {
+            self.last_key.min(argc as i32 - 1)
         };
+        (self.first_key..=last)
+            .step_by(self.step as usize)
+            .map(|i| i as usize)
+            .collect()
+    }
 
-        let RespType::BString(v) = &parts[1] else {
-            return Err(CommandErr::InvalidArgs("wrong value format".to_string()));
+    /// The reply to `COMMAND INFO` for this command.
+    fn info(&self) -> RespType {
+        let status = |s: &str| RespType::String(s.to_string());
+        let key_specs = if self.first_key > 0 {
+            let last_key = if self.last_key < 0 { self.last_key } else { self.last_key - self.first_key };
+            vec![RespType::Map(vec![
+                (
+                    "begin_search".to_resp(),
+                    RespType::Map(vec![
+                        ("type".to_resp(), "index".to_resp()),
+                        ("spec".to_resp(), RespType::Map(vec![("index".to_resp(), self.first_key.to_resp())])),
+                    ]),
+                ),
+                (
+                    "find_keys".to_resp(),
+                    RespType::Map(vec![
+                        ("type".to_resp(), "range".to_resp()),
+                        (
+                            "spec".to_resp(),
+                            RespType::Map(vec![
+                                ("lastkey".to_resp(), last_key.to_resp()),
+                                ("keystep".to_resp(), self.step.to_resp()),
+                                ("limit".to_resp(), 0.to_resp()),
+                            ]),
+                        ),
+                    ]),
+                ),
+            ])]
+        } else {
+            Vec::new()
         };
 
-        storage.lock().unwrap().set(k.as_str(), v.as_str());
-        Ok(RespType::String("OK".to_string()))
+        RespType::Array(vec![
+            self.name.to_resp(),
+            self.arity.to_resp(),
+            RespType::Set(self.flags.iter().map(|f| status(f.as_str())).collect()),
+            self.first_key.to_resp(),
+            self.last_key.to_resp(),
+            self.step.to_resp(),
+            RespType::Set(self.all_acl_categories().into_iter().map(status).collect()),
+            RespType::Array(vec![]),
+            RespType::Array(key_specs),
+            RespType::Array(self.subcommands.iter().map(CommandSpec::info).collect()),
+        ])
     }
 
-    fn validate_args(&self ,parts: &'a [RespType]) -> (bool, u8) {
-        (parts.len() == 2, 2)
+    /// The reply to `COMMAND DOCS` for this command.
+    fn docs_reply(&self) -> RespType {
+        let mut docs = vec![
+            ("summary".to_resp(), self.summary.to_resp()),
+            ("since".to_resp(), self.since.to_resp()),
+            ("group".to_resp(), self.group.to_resp()),
+            ("complexity".to_resp(), self.complexity.to_resp()),
+        ];
+        if !self.subcommands.is_empty() {
+            docs.push((
+                "subcommands".to_resp(),
+                RespType::Map(
+                    self.subcommands
+                        .iter()
+                        .map(|sub| (sub.name.to_resp(), sub.docs_reply()))
+                        .collect(),
+                ),
+            ));
+        }
+        RespType::Map(docs)
     }
 }
 
-struct Get;
+struct RegisteredCommand {
+    spec: CommandSpec,
+    cmd: Box<dyn Command>,
+}
 
-impl Get {
-    fn new() -> Self {Self {}}
+/// Every command the server knows, along with its metadata.
+pub struct CommandRegistry {
+    commands: HashMap<String, RegisteredCommand>,
 }
 
-impl<'a> Command<'a> for Get {
-    
+impl CommandRegistry {
+    fn new() -> Self {
+        let mut commands: Vec<Box<dyn Command>> = vec![
+            Box::new(Ping),
+            Box::new(Hello),
+            Box::new(CommandCmd),
+        ];
+        commands.extend(keyspace::commands());
+        commands.extend(string::commands());
+        commands.extend(bitmap::commands());
+        commands.extend(list::commands());
+        commands.extend(hash::commands());
+        commands.extend(set::commands());
+        commands.extend(zset::commands());
+        commands.extend(stream::commands());
+        commands.extend(hyperloglog::commands());
+        commands.extend(geo::commands());
+
+        let mut registry = Self { commands: HashMap::new() };
+        for cmd in commands {
+            let spec = cmd.spec();
+            registry
+                .commands
+                .insert(spec.name.to_string(), RegisteredCommand { spec, cmd });
+        }
+        registry
+    }
 
-    fn execute(&self, parts: &'a [RespType], storage: Arc<Mutex<Storage>>) -> Result<RespType, CommandErr> {
-        let RespType::BString(k) = &parts[0] else {
-            return Err(CommandErr::InvalidArgs("wrong key format".to_string()));
-        };
+    pub fn global() -> &'static CommandRegistry {
+        static REGISTRY: OnceLock<CommandRegistry> = OnceLock::new();
+        REGISTRY.get_or_init(CommandRegistry::new)
+    }
 
-        let value = storage.lock().unwrap().get(k).cloned();
+    /// Finds a command by name, ignoring case like redis does.
+    fn lookup(&self, name: &str) -> Option<&RegisteredCommand> {
+        self.commands.get(&name.to_ascii_lowercase())
+    }
 
-        match value {
-            Some(v) => {
-                Ok(RespType::BString(v))
-            },
-            None => {
-                Ok(RespType::Null)
-            }
-        }
+    pub fn spec(&self, name: &str) -> Option<&CommandSpec> {
+        self.lookup(name).map(|entry| &entry.spec)
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
     }
 
-    fn validate_args(&self ,parts: &'a [RespType]) -> (bool, u8) {
-        (parts.len() == 1, 1)
+    pub fn specs(&self) -> impl Iterator<Item = &CommandSpec> {
+        self.commands.values().map(|entry| &entry.spec)
     }
 }
 
 
-struct Del;
+struct Ping;
+
+impl Command for Ping {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("ping", -1)
+            .flags(&[CommandFlag::Fast])
+            .acl(&["@connection"])
+            .docs("connection", "1.0.0", "O(1)", "Returns the server's liveliness response.")
+    }
 
-impl Del {
-    fn new() -> Self {Self {}}
+    fn execute(&self, parts: &[RespType], _ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        match parts {
+            [] => Ok(RespType::String("PONG".to_string())),
+            [message] => Ok(message.clone()),
+            _ => Err(CommandErr::WrongArity("ping".to_string())),
+        }
+    }
 }
 
-impl<'a> Command<'a> for Del {
-    
 
-    fn execute(&self, parts: &'a [RespType], storage: Arc<Mutex<Storage>>) -> Result<RespType, CommandErr> {
-        
-        let mut c = 0;
-        for key in parts {
-            if let RespType::BString(k) = key  {
-                let deleted  = storage.lock().unwrap().del(k);
-                if deleted {c += 1}
+struct Hello;
+
+impl Command for Hello {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hello", -1)
+            .flags(&[CommandFlag::Noscript, CommandFlag::Loading, CommandFlag::Stale, CommandFlag::Fast, CommandFlag::NoAuth])
+            .acl(&["@connection"])
+            .docs("connection", "6.0.0", "O(1)", "Handshakes with the Redis server.")
+    }
+
+    /// `HELLO [protover [AUTH username password] [SETNAME clientname]]`
+    ///
+    /// Switches the connection's protocol and replies with the server's properties.
+    fn execute(&self, args: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let mut protocol = ctx.client.protocol;
+        let mut client_name = None;
+
+        if let Some(version) = args.first() {
+            protocol = match i64::from_resp(version) {
+                Ok(2) => Protocol::Resp2,
+                Ok(3) => Protocol::Resp3,
+                Ok(_) => return Err(CommandErr::NoProto),
+                Err(_) => return Err(CommandErr::InvalidArgs("Protocol version is not an integer or out of range".to_string())),
+            };
+        }
+
+        let mut i = 1;
+        while i < args.len() {
+            let opt = Vec::<u8>::from_resp(&args[i])?;
+            if opt.eq_ignore_ascii_case(b"AUTH") && i + 2 < args.len() {
+                // there's no auth yet, every client is the passwordless default user
+                i += 3;
+            } else if opt.eq_ignore_ascii_case(b"SETNAME") && i + 1 < args.len() {
+                client_name = Some(Vec::<u8>::from_resp(&args[i + 1])?);
+                i += 2;
+            } else {
+                return Err(CommandErr::InvalidArgs(format!("Syntax error in HELLO option '{}'", String::from_utf8_lossy(&opt))));
             }
         }
 
-        Ok(RespType::Int(c))
-    }
+        ctx.client.protocol = protocol;
+        if client_name.is_some() {
+            ctx.client.name = client_name;
+        }
 
-    fn validate_args(&self ,parts: &'a [RespType]) -> (bool, u8) {
-        (parts.len() >= 1, 1)
+        let protover = match protocol {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        };
+        Ok(RespType::Map(vec![
+            (RespType::BString("server".into()), RespType::BString("rkey".into())),
+            (RespType::BString("version".into()), RespType::BString(env!("CARGO_PKG_VERSION").into())),
+            (RespType::BString("proto".into()), RespType::Int(protover)),
+            (RespType::BString("id".into()), RespType::Int(ctx.client.id as isize)),
+            (RespType::BString("mode".into()), RespType::BString("standalone".into())),
+            (RespType::BString("role".into()), RespType::BString("master".into())),
+            (RespType::BString("modules".into()), RespType::Array(vec![])),
+        ]))
     }
 }
 
 
+/// `COMMAND [COUNT | INFO name... | DOCS name... | GETKEYS cmd args... | LIST]`, all served
+/// from the registry.
+struct CommandCmd;
 
-#[derive(Debug)]
+impl Command for CommandCmd {
+    fn spec(&self) -> CommandSpec {
+        let sub = |name, arity, complexity, summary| {
+            CommandSpec::new(name, arity)
+                .flags(&[CommandFlag::Loading, CommandFlag::Stale])
+                .acl(&["@connection"])
+                .docs("server", "7.0.0", complexity, summary)
+        };
+        CommandSpec::new("command", -1)
+            .flags(&[CommandFlag::Loading, CommandFlag::Stale])
+            .acl(&["@connection"])
+            .docs("server", "2.8.13", "O(N) where N is the total number of Redis commands", "Returns detailed information about all commands.")
+            .subcommand(sub("command|count", 2, "O(1)", "Returns a count of commands."))
+            .subcommand(sub("command|info", -2, "O(N) where N is the number of commands to look up", "Returns information about one, multiple or all commands."))
+            .subcommand(sub("command|docs", -2, "O(N) where N is the number of commands to look up", "Returns documentary information about one, multiple or all commands."))
+            .subcommand(sub("command|getkeys", -3, "O(N) where N is the number of arguments to the command", "Extracts the key names from an arbitrary command."))
+            .subcommand(sub("command|list", -2, "O(N) where N is the total number of Redis commands", "Returns a list of command names."))
+    }
+
+    fn execute(&self, parts: &[RespType], _ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let registry = CommandRegistry::global();
+        let mut specs: Vec<&CommandSpec> = registry.specs().collect();
+        specs.sort_by_key(|spec| spec.name);
+
+        let Some(sub) = parts.first() else {
+            return Ok(RespType::Array(specs.iter().map(|spec| spec.info()).collect()));
+        };
+        let sub = String::from_resp(sub)?.to_uppercase();
+        let names = parts[1..]
+            .iter()
+            .map(String::from_resp)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match sub.as_str() {
+            "COUNT" if names.is_empty() => Ok(registry.len().to_resp()),
+            "LIST" if names.is_empty() => Ok(RespType::Array(specs.iter().map(|spec| spec.name.to_resp()).collect())),
+            "INFO" if names.is_empty() => Ok(RespType::Array(specs.iter().map(|spec| spec.info()).collect())),
+            "INFO" => Ok(RespType::Array(
+                names
+                    .iter()
+                    .map(|name| match registry.spec(name) {
+                        Some(spec) => spec.info(),
+                        None => RespType::Null,
+                    })
+                    .collect(),
+            )),
+            "DOCS" => {
+                // unknown names are left out of the reply
+                let wanted: Vec<&CommandSpec> = if names.is_empty() {
+                    specs
+                } else {
+                    names.iter().filter_map(|name| registry.spec(name)).collect()
+                };
+                Ok(RespType::Map(
+                    wanted.iter().map(|spec| (spec.name.to_resp(), spec.docs_reply())).collect(),
+                ))
+            }
+            "GETKEYS" if !names.is_empty() => {
+                let argv = &parts[1..];
+                let entry = registry
+                    .lookup(&names[0])
+                    .ok_or_else(|| CommandErr::InvalidArgs("Invalid command specified".to_string()))?;
+                if !entry.spec.accepts_argc(argv.len()) {
+                    return Err(CommandErr::InvalidArgs("Invalid number of arguments specified for command".to_string()));
+                }
+                let positions = entry.cmd.key_positions(&entry.spec, argv);
+                if positions.is_empty() {
+                    return Err(CommandErr::InvalidArgs("The command has no key arguments".to_string()));
+                }
+                Ok(RespType::Array(positions.into_iter().map(|i| argv[i].clone()).collect()))
+            }
+            _ => Err(CommandErr::InvalidArgs(format!("unknown subcommand '{}'. Try COMMAND HELP.", echoed(&sub)))),
+        }
+    }
+}
+
+/// Errors a command can fail with. Their `Display` is the error line sent to the client,
+/// starting with the same error code redis uses, as clients branch on it.
+#[derive(Debug, PartialEq)]
 pub enum CommandErr {
+    /// Replied as a generic `ERR` with the message.
     InvalidArgs(String),
-    UnknownCommand(String),
+    /// The unknown command name and its arguments.
+    UnknownCommand(String, Vec<String>),
+    WrongArity(String),
+    SyntaxError,
+    WrongType,
+    NoAuth,
+    NoPerm(String),
+    Oom,
+    ReadOnly,
+    Busy,
+    NoProto,
+    /// A consumer group of that name exists already.
+    BusyGroup,
+    /// The consumer group or its stream doesn't exist, with the message saying which.
+    NoGroup(String),
+    /// The key holds a string that isn't a HyperLogLog.
+    NotHll,
+    /// A HyperLogLog whose encoding doesn't add up.
+    CorruptHll,
+}
+
+impl From<WrongType> for CommandErr {
+    fn from(_: WrongType) -> Self {
+        CommandErr::WrongType
+    }
+}
+
+impl From<RespErr> for CommandErr {
+    fn from(e: RespErr) -> Self {
+        CommandErr::InvalidArgs(e.to_string())
+    }
 }
 
 impl std::fmt::Display for CommandErr {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            CommandErr::InvalidArgs(msg) => write!(f, "Invalid arguments: {}", msg),
-            CommandErr::UnknownCommand(cmd) => write!(f, "Unknown command: {}", cmd),
+            CommandErr::InvalidArgs(msg) => write!(f, "ERR {}", msg),
+            CommandErr::UnknownCommand(cmd, args) => {
+                write!(f, "ERR unknown command '{}', with args beginning with: ", echoed(cmd))?;
+                for arg in args.iter().take(8) {
+                    write!(f, "'{}' ", echoed(arg))?;
+                }
+                Ok(())
+            }
+            CommandErr::WrongArity(cmd) => write!(f, "ERR wrong number of arguments for '{}' command", cmd),
+            CommandErr::SyntaxError => write!(f, "ERR syntax error"),
+            CommandErr::WrongType => write!(f, "WRONGTYPE Operation against a key holding the wrong kind of value"),
+            CommandErr::NoAuth => write!(f, "NOAUTH Authentication required."),
+            CommandErr::NoPerm(msg) => write!(f, "NOPERM {}", msg),
+            CommandErr::Oom => write!(f, "OOM command not allowed when used memory > 'maxmemory'."),
+            CommandErr::ReadOnly => write!(f, "READONLY You can't write against a read only replica."),
+            CommandErr::Busy => write!(f, "BUSY Redis is busy running a script. You can only call SCRIPT KILL or FUNCTION KILL."),
+            CommandErr::NoProto => write!(f, "NOPROTO unsupported protocol version"),
+            CommandErr::BusyGroup => write!(f, "BUSYGROUP Consumer Group name already exists"),
+            CommandErr::NoGroup(msg) => write!(f, "NOGROUP {}", msg),
+            CommandErr::NotHll => write!(f, "WRONGTYPE Key is not a valid HyperLogLog string value."),
+            CommandErr::CorruptHll => write!(f, "INVALIDOBJ Corrupted HLL object detected"),
         }
     }
 }
 
+/// The start of a client argument echoed in an error, capped at 128 bytes like redis does.
+pub(crate) fn echoed(arg: &str) -> &str {
+    let mut end = arg.len().min(128);
+    while !arg.is_char_boundary(end) {
+        end -= 1;
+    }
+    &arg[..end]
+}
+
+impl From<CommandErr> for RespType {
+    fn from(e: CommandErr) -> Self {
+        RespType::Err(e.to_string())
+    }
+}
@@ -0,0 +1,486 @@
+use crate::{FromResp, RespType, ToResp};
+
+use super::string::MAX_STRING_LEN;
+use super::{Command, CommandContext, CommandErr, CommandFlag, CommandSpec};
+
+pub(super) fn commands() -> Vec<Box<dyn Command>> {
+    vec![
+        Box::new(SetBit),
+        Box::new(GetBit),
+        Box::new(BitCount),
+        Box::new(BitPos),
+        Box::new(BitOp),
+        Box::new(BitField { readonly: false }),
+        Box::new(BitField { readonly: true }),
+    ]
+}
+
+/// Parses a bit offset. BITFIELD also takes `#n`, meaning the n-th field of `bits` width.
+fn parse_bit_offset(arg: &RespType, bits: Option<u32>) -> Result<usize, CommandErr> {
+    let invalid = || CommandErr::InvalidArgs("bit offset is not an integer or out of range".to_string());
+    let raw = Vec::<u8>::from_resp(arg)?;
+    let offset = match (raw.strip_prefix(b"#"), bits) {
+        (Some(n), Some(bits)) => i64::from_resp(&RespType::BString(n.to_vec()))
+            .ok()
+            .and_then(|n| n.checked_mul(bits as i64)),
+        _ => i64::from_resp(&RespType::BString(raw)).ok(),
+    };
+    match offset {
+        Some(offset) if offset >= 0 && (offset as usize >> 3) < MAX_STRING_LEN => Ok(offset as usize),
+        _ => Err(invalid()),
+    }
+}
+
+/// Whether a range is given in bytes, the default, or in bits.
+fn parse_range_unit(arg: Option<&RespType>) -> Result<bool, CommandErr> {
+    let Some(arg) = arg else {
+        return Ok(false);
+    };
+    match String::from_resp(arg)?.to_ascii_uppercase().as_str() {
+        "BYTE" => Ok(false),
+        "BIT" => Ok(true),
+        _ => Err(CommandErr::SyntaxError),
+    }
+}
+
+/// A BITCOUNT or BITPOS range resolved against a string: the first and last byte, and
+/// masks of the bits in those bytes that fall outside the range.
+struct ByteRange {
+    first: usize,
+    last: usize,
+    first_mask: u8,
+    last_mask: u8,
+}
+
+/// Resolves `start..=end`, which count from the end when negative, against a string of
+/// `len` bytes. Returns `None` if the range is empty.
+fn resolve_range(start: i64, end: i64, len: usize, in_bits: bool) -> Option<ByteRange> {
+    let total = if in_bits { len as i64 * 8 } else { len as i64 };
+    let start = if start < 0 { (start + total).max(0) } else { start };
+    let end = if end < 0 { (end + total).max(0) } else { end.min(total - 1) };
+    if total == 0 || start > end {
+        return None;
+    }
+
+    if in_bits {
+        Some(ByteRange {
+            first: (start >> 3) as usize,
+            last: (end >> 3) as usize,
+            first_mask: !(0xffu8 >> (start & 7)),
+            last_mask: (1u8 << (7 - (end & 7))).wrapping_sub(1),
+        })
+    } else {
+        Some(ByteRange { first: start as usize, last: end as usize, first_mask: 0, last_mask: 0 })
+    }
+}
+
+/// Reads `bits` bits starting at bit `offset`, most significant first. Bits past the end of
+/// the string read as zero.
+fn read_bits(value: &[u8], offset: usize, bits: u32) -> u64 {
+    let mut n = 0u64;
+    for i in offset..offset + bits as usize {
+        let bit = value.get(i >> 3).map_or(0, |byte| (byte >> (7 - (i & 7))) & 1);
+        n = (n << 1) | bit as u64;
+    }
+    n
+}
+
+/// Writes the low `bits` bits of `n` at bit `offset`, growing the string as needed.
+fn write_bits(value: &mut Vec<u8>, offset: usize, bits: u32, n: u64) {
+    let needed = (offset + bits as usize).div_ceil(8);
+    if value.len() < needed {
+        value.resize(needed, 0);
+    }
+    for (j, i) in (offset..offset + bits as usize).enumerate() {
+        let bit = (n >> (bits as usize - 1 - j)) & 1;
+        let mask = 1u8 << (7 - (i & 7));
+        if bit == 1 {
+            value[i >> 3] |= mask;
+        } else {
+            value[i >> 3] &= !mask;
+        }
+    }
+}
+
+
+struct SetBit;
+
+impl Command for SetBit {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("setbit", 4)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+            .keys(1, 1, 1)
+            .acl(&["@bitmap"])
+            .docs("bitmap", "2.2.0", "O(1)", "Sets or clears the bit at offset of the string value. Creates the key if it doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let offset = parse_bit_offset(&parts[1], None)?;
+        let bit = match Vec::<u8>::from_resp(&parts[2])?.as_slice() {
+            b"0" => 0,
+            b"1" => 1,
+            _ => return Err(CommandErr::InvalidArgs("bit is not an integer or out of range".to_string())),
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        if storage.get(&k)?.is_none() {
+            storage.set(k.clone(), Vec::new());
+        }
+        let value = storage.get_mut(&k)?.unwrap();
+        let old = read_bits(value, offset, 1);
+        write_bits(value, offset, 1, bit);
+        Ok(RespType::Int(old as isize))
+    }
+}
+
+
+struct GetBit;
+
+impl Command for GetBit {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("getbit", 3)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@bitmap"])
+            .docs("bitmap", "2.2.0", "O(1)", "Returns a bit value by offset.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let offset = parse_bit_offset(&parts[1], None)?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let bit = storage.get(&k)?.map_or(0, |value| read_bits(value, offset, 1));
+        Ok(RespType::Int(bit as isize))
+    }
+}
+
+
+struct BitCount;
+
+impl Command for BitCount {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("bitcount", -2)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@bitmap"])
+            .docs("bitmap", "2.6.0", "O(N)", "Counts the number of set bits (population counting) in a string.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let (start, end, in_bits) = match parts.len() {
+            1 => (0, -1, false),
+            3 | 4 => (i64::from_resp(&parts[1])?, i64::from_resp(&parts[2])?, parse_range_unit(parts.get(3))?),
+            _ => return Err(CommandErr::SyntaxError),
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let value = storage.get(&k)?.map_or(&[][..], Vec::as_slice);
+        if start < 0 && end < 0 && start > end {
+            return Ok(RespType::Int(0));
+        }
+        let Some(range) = resolve_range(start, end, value.len(), in_bits) else {
+            return Ok(RespType::Int(0));
+        };
+
+        let count = value[range.first..=range.last].iter().map(|b| b.count_ones() as usize).sum::<usize>()
+            - (value[range.first] & range.first_mask).count_ones() as usize
+            - (value[range.last] & range.last_mask).count_ones() as usize;
+        Ok(count.to_resp())
+    }
+}
+
+
+struct BitPos;
+
+impl Command for BitPos {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("bitpos", -3)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@bitmap"])
+            .docs("bitmap", "2.8.7", "O(N)", "Finds the first set (1) or clear (0) bit in a string.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let bit = match i64::from_resp(&parts[1])? {
+            0 => false,
+            1 => true,
+            _ => return Err(CommandErr::InvalidArgs("The bit argument must be 1 or 0.".to_string())),
+        };
+        if parts.len() > 5 {
+            return Err(CommandErr::SyntaxError);
+        }
+        let start = parts.get(2).map(i64::from_resp).transpose()?.unwrap_or(0);
+        let end = parts.get(3).map(i64::from_resp).transpose()?;
+        let in_bits = parse_range_unit(parts.get(4))?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(value) = storage.get(&k)? else {
+            return Ok(RespType::Int(if bit { -1 } else { 0 }));
+        };
+        let Some(range) = resolve_range(start, end.unwrap_or(-1), value.len(), in_bits) else {
+            return Ok(RespType::Int(-1));
+        };
+
+        for (i, &byte) in value.iter().enumerate().take(range.last + 1).skip(range.first) {
+            let mut mask = 0;
+            if i == range.first {
+                mask |= range.first_mask;
+            }
+            if i == range.last {
+                mask |= range.last_mask;
+            }
+            // make the bits outside the range look like the opposite of what's searched for
+            let byte = if bit { byte & !mask } else { !(byte | mask) };
+            if byte != 0 {
+                return Ok((i * 8 + byte.leading_zeros() as usize).to_resp());
+            }
+        }
+
+        // past the end of the string every bit is clear, unless the range was bounded
+        if bit || end.is_some() {
+            return Ok(RespType::Int(-1));
+        }
+        Ok(((range.last + 1) * 8).to_resp())
+    }
+}
+
+
+struct BitOp;
+
+impl Command for BitOp {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("bitop", -4)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+            .keys(2, -1, 1)
+            .acl(&["@bitmap"])
+            .docs("bitmap", "2.6.0", "O(N)", "Performs bitwise operations on multiple strings, and stores the result.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let op = String::from_resp(&parts[0])?.to_ascii_uppercase();
+        let dest = Vec::<u8>::from_resp(&parts[1])?;
+        let keys = parts[2..].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let apply: fn(u8, u8) -> u8 = match op.as_str() {
+            "AND" => |a, b| a & b,
+            "OR" => |a, b| a | b,
+            "XOR" => |a, b| a ^ b,
+            "NOT" if keys.len() == 1 => |a, _| !a,
+            "NOT" => return Err(CommandErr::InvalidArgs("BITOP NOT must be called with a single source key.".to_string())),
+            _ => return Err(CommandErr::SyntaxError),
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let sources = keys
+            .iter()
+            .map(|k| Ok(storage.get(k)?.cloned().unwrap_or_default()))
+            .collect::<Result<Vec<_>, CommandErr>>()?;
+        let len = sources.iter().map(Vec::len).max().unwrap_or(0);
+
+        // shorter strings count as zero-padded up to the longest one
+        let result: Vec<u8> = if op == "NOT" {
+            sources[0].iter().map(|&b| apply(b, 0)).collect()
+        } else {
+            (0..len)
+                .map(|i| sources.iter().map(|s| s.get(i).copied().unwrap_or(0)).reduce(apply).unwrap_or(0))
+                .collect()
+        };
+
+        if result.is_empty() {
+            storage.del(&dest);
+        } else {
+            storage.set(dest, result);
+        }
+        Ok(len.to_resp())
+    }
+}
+
+
+/// What BITFIELD does when a SET or INCRBY doesn't fit the field.
+#[derive(Clone, Copy)]
+enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+/// A field type such as `i5` or `u16`.
+#[derive(Clone, Copy)]
+struct FieldType {
+    signed: bool,
+    bits: u32,
+}
+
+impl FieldType {
+    fn parse(arg: &RespType) -> Result<Self, CommandErr> {
+        let invalid = || {
+            CommandErr::InvalidArgs(
+                "Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.".to_string(),
+            )
+        };
+        let s = String::from_resp(arg)?;
+        let (signed, bits) = match s.as_bytes().first() {
+            Some(b'i' | b'I') => (true, &s[1..]),
+            Some(b'u' | b'U') => (false, &s[1..]),
+            _ => return Err(invalid()),
+        };
+        let bits: u32 = bits.parse().map_err(|_| invalid())?;
+        if bits == 0 || (signed && bits > 64) || (!signed && bits > 63) {
+            return Err(invalid());
+        }
+        Ok(Self { signed, bits })
+    }
+
+    fn min(&self) -> i128 {
+        if self.signed { -(1i128 << (self.bits - 1)) } else { 0 }
+    }
+
+    fn max(&self) -> i128 {
+        if self.signed { (1i128 << (self.bits - 1)) - 1 } else { (1i128 << self.bits) - 1 }
+    }
+
+    fn read(&self, value: &[u8], offset: usize) -> i64 {
+        let raw = read_bits(value, offset, self.bits);
+        if self.signed && self.bits < 64 && raw >> (self.bits - 1) & 1 == 1 {
+            // sign extend
+            (raw | (u64::MAX << self.bits)) as i64
+        } else {
+            raw as i64
+        }
+    }
+
+    /// Fits `n` into the field, or `None` if it doesn't fit and overflow is FAIL.
+    fn fit(&self, n: i128, overflow: Overflow) -> Option<i64> {
+        if n >= self.min() && n <= self.max() {
+            return Some(n as i64);
+        }
+        match overflow {
+            Overflow::Fail => None,
+            Overflow::Sat => Some(if n > self.max() { self.max() } else { self.min() } as i64),
+            Overflow::Wrap => {
+                let wrapped = n.rem_euclid(1i128 << self.bits);
+                Some(if wrapped > self.max() { wrapped - (1i128 << self.bits) } else { wrapped } as i64)
+            }
+        }
+    }
+}
+
+enum FieldOp {
+    Get(FieldType, usize),
+    Set(FieldType, usize, i64, Overflow),
+    IncrBy(FieldType, usize, i64, Overflow),
+}
+
+/// `BITFIELD` and its read-only variant `BITFIELD_RO`.
+struct BitField {
+    readonly: bool,
+}
+
+impl Command for BitField {
+    fn spec(&self) -> CommandSpec {
+        if self.readonly {
+            CommandSpec::new("bitfield_ro", -2)
+                .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+                .keys(1, 1, 1)
+                .acl(&["@bitmap"])
+                .docs("bitmap", "6.0.0", "O(1) for each subcommand specified", "Performs arbitrary read-only bitfield integer operations on strings.")
+        } else {
+            CommandSpec::new("bitfield", -2)
+                .flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+                .keys(1, 1, 1)
+                .acl(&["@bitmap"])
+                .docs("bitmap", "3.2.0", "O(1) for each subcommand specified", "Performs arbitrary bitfield integer operations on strings.")
+        }
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+
+        // every operation is validated before any of them runs
+        let mut ops = Vec::new();
+        let mut overflow = Overflow::Wrap;
+        let mut i = 1;
+        while i < parts.len() {
+            let sub = String::from_resp(&parts[i])?.to_ascii_uppercase();
+            let remaining = parts.len() - i - 1;
+            match sub.as_str() {
+                "GET" if remaining >= 2 => {
+                    let ty = FieldType::parse(&parts[i + 1])?;
+                    ops.push(FieldOp::Get(ty, parse_bit_offset(&parts[i + 2], Some(ty.bits))?));
+                    i += 3;
+                }
+                "SET" | "INCRBY" if remaining >= 3 => {
+                    if self.readonly {
+                        return Err(CommandErr::InvalidArgs("BITFIELD_RO only supports the GET subcommand".to_string()));
+                    }
+                    let ty = FieldType::parse(&parts[i + 1])?;
+                    let offset = parse_bit_offset(&parts[i + 2], Some(ty.bits))?;
+                    let n = i64::from_resp(&parts[i + 3])?;
+                    ops.push(if sub == "SET" {
+                        FieldOp::Set(ty, offset, n, overflow)
+                    } else {
+                        FieldOp::IncrBy(ty, offset, n, overflow)
+                    });
+                    i += 4;
+                }
+                "OVERFLOW" if remaining >= 1 => {
+                    overflow = match String::from_resp(&parts[i + 1])?.to_ascii_uppercase().as_str() {
+                        "WRAP" => Overflow::Wrap,
+                        "SAT" => Overflow::Sat,
+                        "FAIL" => Overflow::Fail,
+                        _ => return Err(CommandErr::InvalidArgs("Invalid OVERFLOW type specified".to_string())),
+                    };
+                    i += 2;
+                }
+                _ => return Err(CommandErr::SyntaxError),
+            }
+        }
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let writes = ops.iter().any(|op| !matches!(op, FieldOp::Get(..)));
+        if writes && storage.get(&k)?.is_none() {
+            storage.set(k.clone(), Vec::new());
+        }
+
+        let mut empty = Vec::new();
+        let value = match storage.get_mut(&k)? {
+            Some(value) => value,
+            None => &mut empty,
+        };
+        let replies = ops
+            .into_iter()
+            .map(|op| match op {
+                FieldOp::Get(ty, offset) => ty.read(value, offset).to_resp(),
+                FieldOp::Set(ty, offset, n, overflow) => {
+                    // an unsigned field is given its value as the u64 with the same bits,
+                    // so negative values overflow rather than underflow, like in redis
+                    let n = if ty.signed { n as i128 } else { n as u64 as i128 };
+                    let old = ty.read(value, offset);
+                    match ty.fit(n, overflow) {
+                        Some(n) => {
+                            write_bits(value, offset, ty.bits, n as u64);
+                            old.to_resp()
+                        }
+                        None => RespType::Null,
+                    }
+                }
+                FieldOp::IncrBy(ty, offset, by, overflow) => {
+                    let old = ty.read(value, offset);
+                    match ty.fit(old as i128 + by as i128, overflow) {
+                        Some(n) => {
+                            write_bits(value, offset, ty.bits, n as u64);
+                            n.to_resp()
+                        }
+                        None => RespType::Null,
+                    }
+                }
+            })
+            .collect();
+        Ok(RespType::Array(replies))
+    }
+}
@@ -0,0 +1,693 @@
+use crate::{FromResp, Protocol, RespType, ScoreRange, ToResp, ZSet};
+use crate::Direction;
+
+use super::zset::{add, store, AddOptions, Added};
+use super::{Command, CommandContext, CommandErr, CommandFlag, CommandSpec};
+
+pub(super) fn commands() -> Vec<Box<dyn Command>> {
+    vec![
+        Box::new(GeoAdd),
+        Box::new(GeoDist),
+        Box::new(GeoHashCmd),
+        Box::new(GeoPos),
+        Box::new(GeoSearch { store: false }),
+        Box::new(GeoSearch { store: true }),
+    ]
+}
+
+// Points are stored in a sorted set, scored by their 52 bit geohash: 26 bits of longitude
+// interleaved with 26 bits of latitude, so points close together have close scores and an
+// area can be searched as a few ranges of scores, like redis does.
+
+const LONG_MIN: f64 = -180.0;
+const LONG_MAX: f64 = 180.0;
+/// The latitudes of the square EPSG:900913 projection, beyond which points can't be indexed.
+const LAT_MIN: f64 = -85.05112878;
+const LAT_MAX: f64 = 85.05112878;
+const STEP_MAX: u8 = 26;
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+const MERCATOR_MAX: f64 = 20037726.37;
+const GEOALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// A geohash of `step` bits of each coordinate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct GeoHash {
+    bits: u64,
+    step: u8,
+}
+
+impl GeoHash {
+    /// A neighbor left out of a search.
+    const NONE: GeoHash = GeoHash { bits: 0, step: 0 };
+
+    /// The hash as a 52 bit score, the lowest of the points falling in its cell.
+    fn align52(self) -> u64 {
+        self.bits << (52 - self.step as u32 * 2)
+    }
+
+    /// The cell `dx` cells east and `dy` north, wrapping around. Longitude takes the odd bits.
+    fn moved(self, dx: i8, dy: i8) -> GeoHash {
+        let shift = 64 - self.step as u32 * 2;
+        let move_bits = |bits: u64, mask: u64, d: i8| {
+            let zz = !mask >> shift;
+            let moved = match d.cmp(&0) {
+                std::cmp::Ordering::Equal => return bits,
+                std::cmp::Ordering::Greater => bits.wrapping_add(zz + 1),
+                std::cmp::Ordering::Less => (bits | zz).wrapping_sub(zz + 1),
+            };
+            moved & (mask >> shift)
+        };
+        let x = move_bits(self.bits & 0xaaaa_aaaa_aaaa_aaaa, 0xaaaa_aaaa_aaaa_aaaa, dx);
+        let y = move_bits(self.bits & 0x5555_5555_5555_5555, 0x5555_5555_5555_5555, dy);
+        GeoHash { bits: x | y, step: self.step }
+    }
+}
+
+/// The cell a geohash stands for.
+struct Area {
+    long_min: f64,
+    long_max: f64,
+    lat_min: f64,
+    lat_max: f64,
+}
+
+/// Spreads the bits of `lat` to the even positions and those of `lon` to the odd ones.
+fn interleave64(lat: u32, lon: u32) -> u64 {
+    const B: [u64; 5] = [0x5555_5555_5555_5555, 0x3333_3333_3333_3333, 0x0f0f_0f0f_0f0f_0f0f, 0x00ff_00ff_00ff_00ff, 0x0000_ffff_0000_ffff];
+    const S: [u32; 5] = [1, 2, 4, 8, 16];
+    let spread = |mut v: u64| {
+        for i in (0..5).rev() {
+            v = (v | (v << S[i])) & B[i];
+        }
+        v
+    };
+    spread(lat as u64) | (spread(lon as u64) << 1)
+}
+
+/// The reverse of `interleave64`: latitude in the low 32 bits, longitude in the high ones.
+fn deinterleave64(interleaved: u64) -> u64 {
+    const B: [u64; 6] = [0x5555_5555_5555_5555, 0x3333_3333_3333_3333, 0x0f0f_0f0f_0f0f_0f0f, 0x00ff_00ff_00ff_00ff, 0x0000_ffff_0000_ffff, 0x0000_0000_ffff_ffff];
+    const S: [u32; 6] = [0, 1, 2, 4, 8, 16];
+    let squash = |mut v: u64| {
+        for i in 0..6 {
+            v = (v | (v >> S[i])) & B[i];
+        }
+        v
+    };
+    squash(interleaved) | (squash(interleaved >> 1) << 32)
+}
+
+/// Encodes a point within the latitudes `-lat_range..=lat_range`, which is 90 for standard
+/// geohashes. Fails for points outside the range that can be indexed.
+fn encode(lon: f64, lat: f64, step: u8, lat_range: f64) -> Option<GeoHash> {
+    if !(LONG_MIN..=LONG_MAX).contains(&lon) || !(LAT_MIN..=LAT_MAX).contains(&lat) {
+        return None;
+    }
+    let cells = (1u64 << step) as f64;
+    let lat_offset = (lat + lat_range) / (2.0 * lat_range) * cells;
+    let lon_offset = (lon - LONG_MIN) / (LONG_MAX - LONG_MIN) * cells;
+    Some(GeoHash { bits: interleave64(lat_offset as u32, lon_offset as u32), step })
+}
+
+fn decode(hash: GeoHash) -> Area {
+    let separated = deinterleave64(hash.bits);
+    let (lat, lon) = (separated as u32 as f64, (separated >> 32) as u32 as f64);
+    let cells = (1u64 << hash.step) as f64;
+    let (lat_scale, long_scale) = (LAT_MAX - LAT_MIN, LONG_MAX - LONG_MIN);
+    Area {
+        long_min: LONG_MIN + (lon / cells) * long_scale,
+        long_max: LONG_MIN + ((lon + 1.0) / cells) * long_scale,
+        lat_min: LAT_MIN + (lat / cells) * lat_scale,
+        lat_max: LAT_MIN + ((lat + 1.0) / cells) * lat_scale,
+    }
+}
+
+/// The longitude and latitude of a point stored with the score.
+fn decode_score(score: f64) -> (f64, f64) {
+    let area = decode(GeoHash { bits: score as u64, step: STEP_MAX });
+    let lon = ((area.long_min + area.long_max) / 2.0).clamp(LONG_MIN, LONG_MAX);
+    let lat = ((area.lat_min + area.lat_max) / 2.0).clamp(LAT_MIN, LAT_MAX);
+    (lon, lat)
+}
+
+fn lat_distance(lat1: f64, lat2: f64) -> f64 {
+    EARTH_RADIUS_IN_METERS * (lat2.to_radians() - lat1.to_radians()).abs()
+}
+
+/// The haversine distance in meters.
+fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    // on the same meridian it's just the distance in latitude
+    if v == 0.0 {
+        return lat_distance(lat1, lat2);
+    }
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+    2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+}
+
+/// Parses a longitude and latitude, checking they can be indexed.
+fn parse_lon_lat(lon: &RespType, lat: &RespType) -> Result<(f64, f64), CommandErr> {
+    let (lon, lat) = (f64::from_resp(lon)?, f64::from_resp(lat)?);
+    if !(LONG_MIN..=LONG_MAX).contains(&lon) || !(LAT_MIN..=LAT_MAX).contains(&lat) {
+        return Err(CommandErr::InvalidArgs(format!("invalid longitude,latitude pair {:.6},{:.6}", lon, lat)));
+    }
+    Ok((lon, lat))
+}
+
+/// Meters in the unit.
+fn parse_unit(arg: &RespType) -> Result<f64, CommandErr> {
+    match String::from_resp(arg)?.to_ascii_lowercase().as_str() {
+        "m" => Ok(1.0),
+        "km" => Ok(1000.0),
+        "ft" => Ok(0.3048),
+        "mi" => Ok(1609.34),
+        _ => Err(CommandErr::InvalidArgs("unsupported unit provided. please use M, KM, FT, MI".to_string())),
+    }
+}
+
+/// A distance with four decimals, rounded like redis does.
+fn distance_reply(d: f64) -> RespType {
+    let scaled = (d * 10_000.0).round_ties_even() as u64;
+    format!("{}.{:04}", scaled / 10_000, scaled % 10_000).to_resp()
+}
+
+/// A coordinate, a double on RESP3 and otherwise a string with up to 17 decimals.
+fn coord_reply(coord: f64, protocol: Protocol) -> RespType {
+    if protocol == Protocol::Resp3 {
+        return RespType::Double(coord);
+    }
+    let s = format!("{:.17}", coord);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0" } else { s }.to_resp()
+}
+
+fn coords_reply((lon, lat): (f64, f64), protocol: Protocol) -> RespType {
+    RespType::Array(vec![coord_reply(lon, protocol), coord_reply(lat, protocol)])
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ShapeKind {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+/// The area searched: a circle or a box around a point, its size in the unit of `conversion`
+/// meters.
+struct Shape {
+    lon: f64,
+    lat: f64,
+    conversion: f64,
+    kind: ShapeKind,
+}
+
+impl Shape {
+    /// The distance in meters of the point from the center, if it's within the shape.
+    fn contains(&self, lon: f64, lat: f64) -> Option<f64> {
+        match self.kind {
+            ShapeKind::Radius(radius) => {
+                let d = distance(self.lon, self.lat, lon, lat);
+                (d <= radius * self.conversion).then_some(d)
+            }
+            ShapeKind::Box { width, height } => {
+                // the distance in latitude is cheaper to check, so it goes first
+                if lat_distance(lat, self.lat) > height * self.conversion / 2.0 {
+                    return None;
+                }
+                if distance(lon, lat, self.lon, lat) > width * self.conversion / 2.0 {
+                    return None;
+                }
+                Some(distance(self.lon, self.lat, lon, lat))
+            }
+        }
+    }
+
+    /// The longitudes and latitudes bounding the shape, as `[min lon, min lat, max lon, max lat]`.
+    fn bounding_box(&self) -> [f64; 4] {
+        let (half_width, half_height) = match self.kind {
+            ShapeKind::Radius(radius) => (radius, radius),
+            ShapeKind::Box { width, height } => (width / 2.0, height / 2.0),
+        };
+        let (width, height) = (self.conversion * half_width, self.conversion * half_height);
+        let lat_delta = (height / EARTH_RADIUS_IN_METERS).to_degrees();
+        let long_delta_top = (width / EARTH_RADIUS_IN_METERS / (self.lat + lat_delta).to_radians().cos()).to_degrees();
+        let long_delta_bottom = (width / EARTH_RADIUS_IN_METERS / (self.lat - lat_delta).to_radians().cos()).to_degrees();
+        // the edge nearer the pole is the wider one, which is the other way in the south
+        let long_delta = if self.lat < 0.0 { long_delta_bottom } else { long_delta_top };
+        [self.lon - long_delta, self.lat - lat_delta, self.lon + long_delta, self.lat + lat_delta]
+    }
+
+    /// The cells covering the shape: the one of the center and its eight neighbors, those
+    /// the shape doesn't reach being `GeoHash::NONE`. Ported from redis'
+    /// `geohashCalculateAreasByShapeWGS84`.
+    fn search_cells(&self) -> [GeoHash; 9] {
+        let [min_lon, min_lat, max_lon, max_lat] = self.bounding_box();
+        let radius = match self.kind {
+            ShapeKind::Radius(radius) => radius,
+            ShapeKind::Box { width, height } => ((width / 2.0) * (width / 2.0) + (height / 2.0) * (height / 2.0)).sqrt(),
+        } * self.conversion;
+
+        let mut step = estimate_steps(radius, self.lat);
+        let cells_at = |step| {
+            let hash = encode(self.lon, self.lat, step, LAT_MAX).unwrap_or(GeoHash::NONE);
+            // in the order they're searched in: N, S, E, W, NE, NW, SE, SW
+            let moves = [(0, 1), (0, -1), (1, 0), (-1, 0), (1, 1), (-1, 1), (1, -1), (-1, -1)];
+            let mut cells = [hash; 9];
+            for (cell, (dx, dy)) in cells[1..].iter_mut().zip(moves) {
+                *cell = hash.moved(dx, dy);
+            }
+            cells
+        };
+        let mut cells = cells_at(step);
+
+        // near the edges of a cell, the neighbors may not be enough to cover the whole shape
+        let (north, south, east, west) = (decode(cells[1]), decode(cells[2]), decode(cells[3]), decode(cells[4]));
+        let too_small = north.lat_max < max_lat || south.lat_min > min_lat || east.long_max < max_lon || west.long_min > min_lon;
+        if step > 1 && too_small {
+            step -= 1;
+            cells = cells_at(step);
+        }
+
+        // leave out the neighbors the shape doesn't reach
+        if step >= 2 {
+            let area = decode(cells[0]);
+            let mut exclude = |indexes: [usize; 3]| indexes.iter().for_each(|&i| cells[i] = GeoHash::NONE);
+            if area.lat_min < min_lat {
+                exclude([2, 7, 8]);
+            }
+            if area.lat_max > max_lat {
+                exclude([1, 5, 6]);
+            }
+            if area.long_min < min_lon {
+                exclude([4, 6, 8]);
+            }
+            if area.long_max > max_lon {
+                exclude([3, 5, 7]);
+            }
+        }
+        cells
+    }
+}
+
+/// How many bits of each coordinate the cells to search a radius in should have.
+fn estimate_steps(mut range: f64, lat: f64) -> u8 {
+    if range == 0.0 {
+        return STEP_MAX;
+    }
+    let mut step: i32 = 1;
+    while range < MERCATOR_MAX {
+        range *= 2.0;
+        step += 1;
+    }
+    // make sure the range is covered in most cases, and more so towards the poles where
+    // cells get narrower
+    step -= 2;
+    if !(-66.0..=66.0).contains(&lat) {
+        step -= 1;
+        if !(-80.0..=80.0).contains(&lat) {
+            step -= 1;
+        }
+    }
+    step.clamp(1, STEP_MAX as i32) as u8
+}
+
+/// A point found by a search.
+struct Found {
+    member: Vec<u8>,
+    /// The distance from the center in meters.
+    dist: f64,
+    score: f64,
+    lon: f64,
+    lat: f64,
+}
+
+/// The points of the sorted set within the shape, stopping at `limit` points unless it's 0.
+fn search(zset: &ZSet, shape: &Shape, limit: usize) -> Vec<Found> {
+    let cells = shape.search_cells();
+    let mut found = Vec::new();
+    let mut last_searched = 0;
+    for (i, &cell) in cells.iter().enumerate() {
+        if cell == GeoHash::NONE {
+            continue;
+        }
+        // with a huge radius neighbors may be the same cell, which is searched once
+        if last_searched != 0 && cell == cells[last_searched] {
+            continue;
+        }
+        if limit != 0 && found.len() >= limit {
+            break;
+        }
+        let next = GeoHash { bits: cell.bits + 1, step: cell.step };
+        let range = ScoreRange { min: cell.align52() as f64, max: next.align52() as f64, min_exclusive: false, max_exclusive: true };
+        for (member, score) in zset.range_by_score(&range, Direction::Ascending, 0, usize::MAX) {
+            let (lon, lat) = decode_score(score);
+            if let Some(dist) = shape.contains(lon, lat) {
+                found.push(Found { member, dist, score, lon, lat });
+                if limit != 0 && found.len() >= limit {
+                    break;
+                }
+            }
+        }
+        last_searched = i;
+    }
+    found
+}
+
+
+/// `GEOADD key [NX | XX] [CH] longitude latitude member [longitude latitude member ...]`.
+struct GeoAdd;
+
+impl Command for GeoAdd {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("geoadd", -5)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+            .keys(1, 1, 1)
+            .acl(&["@geo"])
+            .docs("geo", "3.2.0", "O(log(N)) for each item added, where N is the number of elements in the sorted set.", "Adds one or more members to a geospatial index. The key is created if it doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let (mut opts, mut ch) = (AddOptions::default(), false);
+        let mut i = 1;
+        while let Some(arg) = parts.get(i) {
+            match String::from_resp(arg)?.to_ascii_uppercase().as_str() {
+                "NX" => opts.nx = true,
+                "XX" => opts.xx = true,
+                "CH" => ch = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        let triples = &parts[i..];
+        if triples.is_empty() || !triples.len().is_multiple_of(3) || (opts.nx && opts.xx) {
+            return Err(CommandErr::SyntaxError);
+        }
+        let points = triples
+            .chunks(3)
+            .map(|triple| {
+                let (lon, lat) = parse_lon_lat(&triple[0], &triple[1])?;
+                let score = encode(lon, lat, STEP_MAX, LAT_MAX).map_or(0, GeoHash::align52) as f64;
+                Ok((score, Vec::<u8>::from_resp(&triple[2])?))
+            })
+            .collect::<Result<Vec<_>, CommandErr>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        if storage.zset(&k)?.is_none() && opts.xx {
+            return Ok(RespType::Int(0));
+        }
+        let zset = storage.zset_or_create(&k)?;
+        let (mut added, mut changed) = (0, 0);
+        for (score, member) in points {
+            match add(zset, member, score, &opts)? {
+                Added::New(_) => added += 1,
+                Added::Changed(_) => changed += 1,
+                Added::Unchanged(_) | Added::Skipped => {}
+            }
+        }
+        storage.remove_if_empty(&k);
+        Ok(RespType::Int(if ch { added + changed } else { added }))
+    }
+}
+
+
+/// `GEODIST key member1 member2 [M | KM | FT | MI]`.
+struct GeoDist;
+
+impl Command for GeoDist {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("geodist", -4)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@geo"])
+            .docs("geo", "3.2.0", "O(1)", "Returns the distance between two members of a geospatial index.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let (m1, m2) = (Vec::<u8>::from_resp(&parts[1])?, Vec::<u8>::from_resp(&parts[2])?);
+        let conversion = match &parts[3..] {
+            [] => 1.0,
+            [unit] => parse_unit(unit)?,
+            _ => return Err(CommandErr::SyntaxError),
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(zset) = storage.zset(&k)? else {
+            return Ok(RespType::Null);
+        };
+        let (Some(s1), Some(s2)) = (zset.score(&m1), zset.score(&m2)) else {
+            return Ok(RespType::Null);
+        };
+        let ((lon1, lat1), (lon2, lat2)) = (decode_score(s1), decode_score(s2));
+        Ok(distance_reply(distance(lon1, lat1, lon2, lat2) / conversion))
+    }
+}
+
+
+/// `GEOHASH key [member ...]`, as standard 11 character geohash strings.
+struct GeoHashCmd;
+
+impl Command for GeoHashCmd {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("geohash", -2)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@geo"])
+            .docs("geo", "3.2.0", "O(1) for each member requested.", "Returns members from a geospatial index as geohash strings.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let members = parts[1..].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let zset = storage.zset(&k)?;
+        let hashes = members
+            .iter()
+            .map(|member| {
+                let Some(score) = zset.and_then(|zset| zset.score(member)) else {
+                    return RespType::Null;
+                };
+                // scores cover latitudes up to 85 degrees, where standard geohashes go to 90,
+                // so the point is encoded again
+                let (lon, lat) = decode_score(score);
+                let bits = encode(lon, lat, STEP_MAX, 90.0).map_or(0, |hash| hash.bits);
+                // 52 bits make 10 characters and a bit, so the last one is always '0'
+                let hash: Vec<u8> = (0..11)
+                    .map(|i| match i {
+                        10 => GEOALPHABET[0],
+                        _ => GEOALPHABET[((bits >> (52 - (i + 1) * 5)) & 0x1f) as usize],
+                    })
+                    .collect();
+                hash.to_resp()
+            })
+            .collect();
+        Ok(RespType::Array(hashes))
+    }
+}
+
+
+/// `GEOPOS key [member ...]`.
+struct GeoPos;
+
+impl Command for GeoPos {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("geopos", -2)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@geo"])
+            .docs("geo", "3.2.0", "O(1) for each member requested.", "Returns the longitude and latitude of members from a geospatial index.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let members = parts[1..].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let protocol = ctx.client.protocol;
+        let mut storage = ctx.storage.lock().unwrap();
+        let zset = storage.zset(&k)?;
+        let positions = members
+            .iter()
+            .map(|member| match zset.and_then(|zset| zset.score(member)) {
+                Some(score) => coords_reply(decode_score(score), protocol),
+                None => RespType::NullArray,
+            })
+            .collect();
+        Ok(RespType::Array(positions))
+    }
+}
+
+
+/// Where a search is centered.
+enum From {
+    Member(Vec<u8>),
+    LonLat(f64, f64),
+}
+
+/// `GEOSEARCH key FROMMEMBER member | FROMLONLAT longitude latitude BYRADIUS radius unit | BYBOX width height unit [ASC | DESC] [COUNT count [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]`,
+/// and `GEOSEARCHSTORE destination source ... [STOREDIST]` which stores the points found in
+/// a sorted set, scored by their distance with STOREDIST.
+struct GeoSearch {
+    store: bool,
+}
+
+impl Command for GeoSearch {
+    fn spec(&self) -> CommandSpec {
+        let complexity = "O(N+log(M)) where N is the number of elements in the grid-aligned bounding box area around the shape provided as the filter and M is the number of items inside the shape";
+        if self.store {
+            CommandSpec::new("geosearchstore", -8)
+                .flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+                .keys(1, 2, 1)
+                .docs("geo", "6.2.0", complexity, "Queries a geospatial index for members inside an area of a box or a circle, optionally stores the result.")
+        } else {
+            CommandSpec::new("geosearch", -7)
+                .flags(&[CommandFlag::Readonly])
+                .keys(1, 1, 1)
+                .docs("geo", "6.2.0", complexity, "Queries a geospatial index for members inside an area of a box or a circle.")
+        }
+        .acl(&["@geo"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let (dst, src, args) = if self.store {
+            (Some(Vec::<u8>::from_resp(&parts[0])?), Vec::<u8>::from_resp(&parts[1])?, &parts[2..])
+        } else {
+            (None, Vec::<u8>::from_resp(&parts[0])?, &parts[1..])
+        };
+
+        let (mut from, mut kind, mut conversion) = (None, None, 1.0);
+        let (mut withdist, mut withhash, mut withcoord, mut storedist) = (false, false, false, false);
+        let (mut sort, mut count, mut any) = (None, 0, false);
+        let mut i = 0;
+        while i < args.len() {
+            let remaining = args.len() - i - 1;
+            match String::from_resp(&args[i])?.to_ascii_uppercase().as_str() {
+                "WITHDIST" => withdist = true,
+                "WITHHASH" => withhash = true,
+                "WITHCOORD" => withcoord = true,
+                "ANY" => any = true,
+                "ASC" => sort = Some(Direction::Ascending),
+                "DESC" => sort = Some(Direction::Descending),
+                "STOREDIST" if self.store => storedist = true,
+                "COUNT" if remaining >= 1 => {
+                    count = match i64::from_resp(&args[i + 1])? {
+                        n if n > 0 => n as usize,
+                        _ => return Err(CommandErr::InvalidArgs("COUNT must be > 0".to_string())),
+                    };
+                    i += 1;
+                }
+                "FROMMEMBER" if remaining >= 1 && !matches!(from, Some(From::LonLat(..))) => {
+                    from = Some(From::Member(Vec::<u8>::from_resp(&args[i + 1])?));
+                    i += 1;
+                }
+                "FROMLONLAT" if remaining >= 2 && !matches!(from, Some(From::Member(_))) => {
+                    let (lon, lat) = parse_lon_lat(&args[i + 1], &args[i + 2])?;
+                    from = Some(From::LonLat(lon, lat));
+                    i += 2;
+                }
+                "BYRADIUS" if remaining >= 2 && !matches!(kind, Some(ShapeKind::Box { .. })) => {
+                    let radius = parse_size(&args[i + 1], "need numeric radius")?;
+                    if radius < 0.0 {
+                        return Err(CommandErr::InvalidArgs("radius cannot be negative".to_string()));
+                    }
+                    conversion = parse_unit(&args[i + 2])?;
+                    kind = Some(ShapeKind::Radius(radius));
+                    i += 2;
+                }
+                "BYBOX" if remaining >= 3 && !matches!(kind, Some(ShapeKind::Radius(_))) => {
+                    let width = parse_size(&args[i + 1], "need numeric width")?;
+                    let height = parse_size(&args[i + 2], "need numeric height")?;
+                    if width < 0.0 || height < 0.0 {
+                        return Err(CommandErr::InvalidArgs("height or width cannot be negative".to_string()));
+                    }
+                    conversion = parse_unit(&args[i + 3])?;
+                    kind = Some(ShapeKind::Box { width, height });
+                    i += 3;
+                }
+                _ => return Err(CommandErr::SyntaxError),
+            }
+            i += 1;
+        }
+
+        let name = if self.store { "geosearchstore" } else { "geosearch" };
+        if self.store && (withdist || withhash || withcoord) {
+            return Err(CommandErr::InvalidArgs("GEOSEARCHSTORE is not compatible with WITHDIST, WITHHASH and WITHCOORD options".to_string()));
+        }
+        let Some(from) = from else {
+            return Err(CommandErr::InvalidArgs(format!("exactly one of FROMMEMBER or FROMLONLAT can be specified for {}", name)));
+        };
+        let Some(kind) = kind else {
+            return Err(CommandErr::InvalidArgs(format!("exactly one of BYRADIUS and BYBOX can be specified for {}", name)));
+        };
+        if any && count == 0 {
+            return Err(CommandErr::InvalidArgs("the ANY argument requires COUNT argument".to_string()));
+        }
+
+        let protocol = ctx.client.protocol;
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(zset) = storage.zset(&src)? else {
+            return Ok(match dst {
+                Some(dst) => {
+                    storage.del(&dst);
+                    RespType::Int(0)
+                }
+                None => RespType::Array(Vec::new()),
+            });
+        };
+        let (lon, lat) = match from {
+            From::Member(member) => {
+                let score = zset
+                    .score(&member)
+                    .ok_or_else(|| CommandErr::InvalidArgs("could not decode requested zset member".to_string()))?;
+                decode_score(score)
+            }
+            From::LonLat(lon, lat) => (lon, lat),
+        };
+        let shape = Shape { lon, lat, conversion, kind };
+
+        let mut found = search(zset, &shape, if any { count } else { 0 });
+        // the nearest points are the ones wanted when there's a COUNT
+        if count != 0 && !any && sort.is_none() {
+            sort = Some(Direction::Ascending);
+        }
+        match sort {
+            Some(Direction::Ascending) => found.sort_by(|a, b| a.dist.total_cmp(&b.dist)),
+            Some(Direction::Descending) => found.sort_by(|a, b| b.dist.total_cmp(&a.dist)),
+            None => {}
+        }
+        if count != 0 {
+            found.truncate(count);
+        }
+
+        if let Some(dst) = dst {
+            let elements = found.into_iter().map(|p| (p.member, if storedist { p.dist / conversion } else { p.score }));
+            return Ok(store(&mut storage, &dst, elements)?.to_resp());
+        }
+        let replies = found
+            .into_iter()
+            .map(|p| {
+                if !(withdist || withhash || withcoord) {
+                    return p.member.to_resp();
+                }
+                let mut reply = vec![p.member.to_resp()];
+                if withdist {
+                    reply.push(distance_reply(p.dist / conversion));
+                }
+                if withhash {
+                    reply.push(RespType::Int(p.score as isize));
+                }
+                if withcoord {
+                    reply.push(coords_reply((p.lon, p.lat), protocol));
+                }
+                RespType::Array(reply)
+            })
+            .collect();
+        Ok(RespType::Array(replies))
+    }
+}
+
+fn parse_size(arg: &RespType, error: &str) -> Result<f64, CommandErr> {
+    f64::from_resp(arg).map_err(|_| CommandErr::InvalidArgs(error.to_string()))
+}
@@ -0,0 +1,759 @@
+use crate::storage::now_ms;
+use crate::{format_double, FromResp, Protocol, RespType, ToResp};
+
+use super::string::{parse_expire_at, parse_f64, parse_i64, Expiry};
+use super::{Command, CommandContext, CommandErr, CommandFlag, CommandSpec, ScanArgs};
+
+pub(super) fn commands() -> Vec<Box<dyn Command>> {
+    vec![
+        Box::new(HSet { name: "hset" }),
+        Box::new(HSet { name: "hmset" }),
+        Box::new(HSetNx),
+        Box::new(HGet),
+        Box::new(HMGet),
+        Box::new(HDel),
+        Box::new(HExists),
+        Box::new(HLen),
+        Box::new(HKeys),
+        Box::new(HVals),
+        Box::new(HGetAll),
+        Box::new(HIncrBy),
+        Box::new(HIncrByFloat),
+        Box::new(HStrLen),
+        Box::new(HRandField),
+        Box::new(HScan),
+        Box::new(HExpire { name: "hexpire", millis: false, absolute: false }),
+        Box::new(HExpire { name: "hpexpire", millis: true, absolute: false }),
+        Box::new(HExpire { name: "hexpireat", millis: false, absolute: true }),
+        Box::new(HExpire { name: "hpexpireat", millis: true, absolute: true }),
+        Box::new(HTtl { millis: false }),
+        Box::new(HTtl { millis: true }),
+        Box::new(HExpireTime { millis: false }),
+        Box::new(HExpireTime { millis: true }),
+        Box::new(HPersist),
+        Box::new(HGetEx),
+    ]
+}
+
+/// Latest field expiry redis accepts, in unix milliseconds.
+const MAX_FIELD_EXPIRE_MS: i64 = (1 << 48) - 1;
+
+/// Field/value pairs given to `HSET`-style commands.
+type Pairs = Vec<(Vec<u8>, Vec<u8>)>;
+
+fn parse_pairs(cmd: &str, parts: &[RespType]) -> Result<Pairs, CommandErr> {
+    if parts.is_empty() || !parts.len().is_multiple_of(2) {
+        return Err(CommandErr::WrongArity(cmd.to_string()));
+    }
+    parts
+        .chunks(2)
+        .map(|pair| Ok((Vec::<u8>::from_resp(&pair[0])?, Vec::<u8>::from_resp(&pair[1])?)))
+        .collect()
+}
+
+/// Parses the `FIELDS numfields field...` arguments that end the field expiry commands.
+fn parse_fields(parts: &[RespType]) -> Result<Vec<Vec<u8>>, CommandErr> {
+    let missing = || CommandErr::InvalidArgs("Mandatory argument FIELDS is missing or not at the right position".to_string());
+    let [opt, numfields, fields @ ..] = parts else {
+        return Err(missing());
+    };
+    if !String::from_resp(opt)?.eq_ignore_ascii_case("FIELDS") {
+        return Err(missing());
+    }
+    let numfields = i64::from_resp(numfields)?;
+    if numfields <= 0 {
+        return Err(CommandErr::InvalidArgs("Parameter `numFields` should be greater than 0".to_string()));
+    }
+    if numfields as usize != fields.len() {
+        return Err(CommandErr::InvalidArgs("The `numfields` parameter must match the number of arguments".to_string()));
+    }
+    fields.iter().map(|f| Ok(Vec::<u8>::from_resp(f)?)).collect()
+}
+
+/// Replies with field/value pairs, as pairs on RESP3 and flattened on RESP2.
+fn pairs_reply<'a>(pairs: impl Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>, protocol: Protocol) -> RespType {
+    match protocol {
+        Protocol::Resp3 => RespType::Array(pairs.map(|(f, v)| RespType::Array(vec![f.to_resp(), v.to_resp()])).collect()),
+        _ => RespType::Array(pairs.flat_map(|(f, v)| [f.to_resp(), v.to_resp()]).collect()),
+    }
+}
+
+
+/// `HSET`, and the deprecated `HMSET` which only differs in its reply.
+struct HSet {
+    name: &'static str,
+}
+
+impl Command for HSet {
+    fn spec(&self) -> CommandSpec {
+        let spec = if self.name == "hset" {
+            CommandSpec::new("hset", -4)
+                .docs("hash", "2.0.0", "O(1) for each field/value pair added, so O(N) to add N field/value pairs when the command is called with multiple field/value pairs.", "Creates or modifies the value of a field in a hash.")
+        } else {
+            CommandSpec::new("hmset", -4)
+                .docs("hash", "2.0.0", "O(N) where N is the number of fields being set.", "Sets the values of multiple fields.")
+        };
+        spec.flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let pairs = parse_pairs(self.name, &parts[1..])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let hash = storage.hash_or_create(&k)?;
+        let added = pairs.into_iter().map(|(f, v)| hash.insert(f, v)).filter(|&added| added).count();
+
+        if self.name == "hset" {
+            Ok(added.to_resp())
+        } else {
+            Ok(RespType::String("OK".to_string()))
+        }
+    }
+}
+
+
+struct HSetNx;
+
+impl Command for HSetNx {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hsetnx", 4)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "2.0.0", "O(1)", "Sets the value of a field in a hash only when the field doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let field = Vec::<u8>::from_resp(&parts[1])?;
+        let value = Vec::<u8>::from_resp(&parts[2])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        if storage.hash(&k)?.is_some_and(|hash| hash.contains(&field)) {
+            return Ok(RespType::Int(0));
+        }
+        storage.hash_or_create(&k)?.insert(field, value);
+        Ok(RespType::Int(1))
+    }
+}
+
+
+struct HGet;
+
+impl Command for HGet {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hget", 3)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "2.0.0", "O(1)", "Returns the value of a field in a hash.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let field = Vec::<u8>::from_resp(&parts[1])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        Ok(storage.hash(&k)?.and_then(|hash| hash.get(&field)).to_resp())
+    }
+}
+
+
+struct HMGet;
+
+impl Command for HMGet {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hmget", -3)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "2.0.0", "O(N) where N is the number of fields being requested.", "Returns the values of all fields in a hash.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let fields = parts[1..].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let hash = storage.hash(&k)?;
+        Ok(RespType::Array(fields.iter().map(|f| hash.and_then(|hash| hash.get(f)).to_resp()).collect()))
+    }
+}
+
+
+struct HDel;
+
+impl Command for HDel {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hdel", -3)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "2.0.0", "O(N) where N is the number of fields to be removed.", "Deletes one or more fields and their values from a hash. Deletes the hash if no fields remain.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let fields = parts[1..].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(hash) = storage.hash_mut(&k)? else {
+            return Ok(RespType::Int(0));
+        };
+        let removed = fields.iter().filter(|f| hash.remove(f)).count();
+        storage.remove_if_empty(&k);
+        Ok(removed.to_resp())
+    }
+}
+
+
+struct HExists;
+
+impl Command for HExists {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hexists", 3)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "2.0.0", "O(1)", "Determines whether a field exists in a hash.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let field = Vec::<u8>::from_resp(&parts[1])?;
+
+        let exists = ctx.storage.lock().unwrap().hash(&k)?.is_some_and(|hash| hash.contains(&field));
+        Ok(RespType::Int(exists as isize))
+    }
+}
+
+
+struct HLen;
+
+impl Command for HLen {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hlen", 2)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "2.0.0", "O(1)", "Returns the number of fields in a hash.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let len = ctx.storage.lock().unwrap().hash(&k)?.map_or(0, |hash| hash.len());
+        Ok(len.to_resp())
+    }
+}
+
+
+struct HKeys;
+
+impl Command for HKeys {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hkeys", 2)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "2.0.0", "O(N) where N is the size of the hash.", "Returns all fields in a hash.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let fields = storage.hash(&k)?.into_iter().flat_map(|hash| hash.iter().map(|(f, _)| f.to_resp()));
+        Ok(RespType::Array(fields.collect()))
+    }
+}
+
+
+struct HVals;
+
+impl Command for HVals {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hvals", 2)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "2.0.0", "O(N) where N is the size of the hash.", "Returns all values in a hash.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let values = storage.hash(&k)?.into_iter().flat_map(|hash| hash.iter().map(|(_, v)| v.to_resp()));
+        Ok(RespType::Array(values.collect()))
+    }
+}
+
+
+struct HGetAll;
+
+impl Command for HGetAll {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hgetall", 2)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "2.0.0", "O(N) where N is the size of the hash.", "Returns all fields and values in a hash.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let pairs = storage.hash(&k)?.into_iter().flat_map(|hash| hash.iter().map(|(f, v)| (f.to_resp(), v.to_resp())));
+        Ok(RespType::Map(pairs.collect()))
+    }
+}
+
+
+struct HIncrBy;
+
+impl Command for HIncrBy {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hincrby", 4)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "2.0.0", "O(1)", "Increments the integer value of a field in a hash by a number. Uses 0 as initial value if the field doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let field = Vec::<u8>::from_resp(&parts[1])?;
+        let by = i64::from_resp(&parts[2])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let current = match storage.hash(&k)?.and_then(|hash| hash.get(&field)) {
+            Some(v) => parse_i64(v).ok_or_else(|| CommandErr::InvalidArgs("hash value is not an integer".to_string()))?,
+            None => 0,
+        };
+        let new = current
+            .checked_add(by)
+            .ok_or_else(|| CommandErr::InvalidArgs("increment or decrement would overflow".to_string()))?;
+
+        storage.hash_or_create(&k)?.insert_keep_ttl(field, new.to_string().into_bytes());
+        Ok(new.to_resp())
+    }
+}
+
+
+struct HIncrByFloat;
+
+impl Command for HIncrByFloat {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hincrbyfloat", 4)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "2.6.0", "O(1)", "Increments the floating point value of a field by a number. Uses 0 as initial value if the field doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let field = Vec::<u8>::from_resp(&parts[1])?;
+        let by = f64::from_resp(&parts[2])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let current = match storage.hash(&k)?.and_then(|hash| hash.get(&field)) {
+            Some(v) => parse_f64(v).ok_or_else(|| CommandErr::InvalidArgs("hash value is not a float".to_string()))?,
+            None => 0.0,
+        };
+        let new = current + by;
+        if !new.is_finite() {
+            return Err(CommandErr::InvalidArgs("increment would produce NaN or Infinity".to_string()));
+        }
+
+        let new = format_double(new).into_bytes();
+        storage.hash_or_create(&k)?.insert_keep_ttl(field, new.clone());
+        Ok(RespType::BString(new))
+    }
+}
+
+
+struct HStrLen;
+
+impl Command for HStrLen {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hstrlen", 3)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "3.2.0", "O(1)", "Returns the length of the value of a field.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let field = Vec::<u8>::from_resp(&parts[1])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let len = storage.hash(&k)?.and_then(|hash| hash.get(&field)).map_or(0, Vec::len);
+        Ok(len.to_resp())
+    }
+}
+
+
+/// `HRANDFIELD key [count [WITHVALUES]]`. A positive count returns distinct fields, a
+/// negative one may return the same field several times.
+struct HRandField;
+
+impl Command for HRandField {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hrandfield", -2)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "6.2.0", "O(N) where N is the number of fields returned", "Returns one or more random fields from a hash.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let (count, with_values) = match parts {
+            [_] => (None, false),
+            [_, count] => (Some(i64::from_resp(count)?), false),
+            [_, count, opt] if String::from_resp(opt)?.eq_ignore_ascii_case("WITHVALUES") => {
+                let count = i64::from_resp(count)?;
+                // the reply would hold twice as many elements
+                if count.unsigned_abs() > i64::MAX as u64 / 2 {
+                    return Err(CommandErr::InvalidArgs("value is out of range".to_string()));
+                }
+                (Some(count), true)
+            }
+            _ => return Err(CommandErr::SyntaxError),
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let hash = storage.hash(&k)?;
+        let mut rng = rand::thread_rng();
+        let Some(count) = count else {
+            return Ok(hash.and_then(|hash| hash.random(&mut rng)).map(|(f, _)| f).to_resp());
+        };
+        let Some(hash) = hash else {
+            return Ok(RespType::Array(Vec::new()));
+        };
+
+        let picked: Vec<(&Vec<u8>, &Vec<u8>)> = if count < 0 {
+            (0..count.unsigned_abs()).filter_map(|_| hash.random(&mut rng)).collect()
+        } else if count as usize >= hash.len() {
+            hash.iter().collect()
+        } else {
+            let pairs: Vec<_> = hash.iter().collect();
+            rand::seq::index::sample(&mut rng, pairs.len(), count as usize).into_iter().map(|i| pairs[i]).collect()
+        };
+
+        if with_values {
+            Ok(pairs_reply(picked.into_iter(), ctx.client.protocol))
+        } else {
+            Ok(RespType::Array(picked.into_iter().map(|(f, _)| f.to_resp()).collect()))
+        }
+    }
+}
+
+
+/// `HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]`.
+struct HScan;
+
+impl Command for HScan {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hscan", -3)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "2.8.0", "O(1) for every call. O(N) for a complete iteration, including enough command calls for the cursor to return back to 0. N is the number of elements inside the collection.", "Iterates over fields and values of a hash.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let args = ScanArgs::parse(&parts[1..], true)?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(hash) = storage.hash(&k)? else {
+            return Ok(ScanArgs::reply(0, Vec::new()));
+        };
+
+        let mut found = Vec::new();
+        let next = hash.scan(args.cursor, args.count, |f, v| {
+            if args.matches(f) {
+                found.push(f.to_resp());
+                if !args.novalues {
+                    found.push(v.to_resp());
+                }
+            }
+        });
+        Ok(ScanArgs::reply(next, found))
+    }
+}
+
+
+/// `HEXPIRE`, `HPEXPIRE`, `HEXPIREAT` and `HPEXPIREAT`. Each field gets its own result:
+/// -2 if there's no such field, 0 if the condition wasn't met, 1 if the expiry was set
+/// and 2 if the field was deleted as the time already passed.
+struct HExpire {
+    name: &'static str,
+    millis: bool,
+    absolute: bool,
+}
+
+impl Command for HExpire {
+    fn spec(&self) -> CommandSpec {
+        let summary = match (self.millis, self.absolute) {
+            (false, false) => "Set expiry for hash field using relative time to expire (seconds)",
+            (true, false) => "Set expiry for hash field using relative time to expire (milliseconds)",
+            (false, true) => "Set expiry for hash field using an absolute Unix timestamp (seconds)",
+            (true, true) => "Set expiry for hash field using an absolute Unix timestamp (milliseconds)",
+        };
+        CommandSpec::new(self.name, -6)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "7.4.0", "O(N) where N is the number of specified fields", summary)
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let when = i64::from_resp(&parts[1])?;
+
+        let cond = String::from_resp(&parts[2])?.to_ascii_uppercase();
+        let (cond, rest) = match cond.as_str() {
+            "NX" | "XX" | "GT" | "LT" => (Some(cond), &parts[3..]),
+            _ => (None, &parts[2..]),
+        };
+        let fields = parse_fields(rest)?;
+
+        let invalid = || CommandErr::InvalidArgs(format!("invalid expire time in '{}' command", self.name));
+        if when < 0 {
+            return Err(invalid());
+        }
+        let when_ms = if self.millis { Some(when) } else { when.checked_mul(1000) };
+        let at = if self.absolute {
+            when_ms
+        } else {
+            when_ms.and_then(|ms| ms.checked_add(now_ms() as i64))
+        }
+        .filter(|&at| at <= MAX_FIELD_EXPIRE_MS)
+        .ok_or_else(invalid)? as u64;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(hash) = storage.hash_mut(&k)? else {
+            return Ok(RespType::Array(vec![RespType::Int(-2); fields.len()]));
+        };
+
+        let now = now_ms();
+        let results = fields
+            .iter()
+            .map(|field| {
+                let Some(current) = hash.expire_time(field) else {
+                    return -2;
+                };
+                // a field without an expiry counts as having an infinite ttl for GT and LT
+                let skip = match (cond.as_deref(), current) {
+                    (Some("NX"), current) => current.is_some(),
+                    (Some("XX"), current) => current.is_none(),
+                    (Some("GT"), current) => current.is_none_or(|current| at <= current),
+                    (Some("LT"), current) => current.is_some_and(|current| at >= current),
+                    _ => false,
+                };
+                if skip {
+                    0
+                } else if at <= now {
+                    hash.remove(field);
+                    2
+                } else {
+                    hash.expire_at(field, at);
+                    1
+                }
+            })
+            .map(RespType::Int)
+            .collect();
+
+        storage.remove_if_empty(&k);
+        storage.sync_field_expiry(&k);
+        Ok(RespType::Array(results))
+    }
+}
+
+
+/// `HTTL` and `HPTTL`: the remaining time to live of each field, -1 if it has no expiry
+/// and -2 if there's no such field.
+struct HTtl {
+    millis: bool,
+}
+
+impl Command for HTtl {
+    fn spec(&self) -> CommandSpec {
+        let spec = if self.millis {
+            CommandSpec::new("hpttl", -5).docs("hash", "7.4.0", "O(N) where N is the number of specified fields", "Returns the TTL in milliseconds of a hash field.")
+        } else {
+            CommandSpec::new("httl", -5).docs("hash", "7.4.0", "O(N) where N is the number of specified fields", "Returns the TTL in seconds of a hash field.")
+        };
+        spec.flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let fields = parse_fields(&parts[1..])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let hash = storage.hash(&k)?;
+        let now = now_ms();
+        let ttls = fields.iter().map(|field| {
+            let ttl = match hash.and_then(|hash| hash.expire_time(field)) {
+                None => -2,
+                Some(None) => -1,
+                Some(Some(at)) => {
+                    let ttl = at.saturating_sub(now) as i64;
+                    // rounded up like redis does, so a field about to expire doesn't read 0
+                    if self.millis { ttl } else { (ttl + 999) / 1000 }
+                }
+            };
+            ttl.to_resp()
+        });
+        Ok(RespType::Array(ttls.collect()))
+    }
+}
+
+
+/// `HEXPIRETIME` and `HPEXPIRETIME`.
+struct HExpireTime {
+    millis: bool,
+}
+
+impl Command for HExpireTime {
+    fn spec(&self) -> CommandSpec {
+        let spec = if self.millis {
+            CommandSpec::new("hpexpiretime", -5).docs("hash", "7.4.0", "O(N) where N is the number of specified fields", "Returns the expiration time of a hash field as a Unix timestamp, in msec.")
+        } else {
+            CommandSpec::new("hexpiretime", -5).docs("hash", "7.4.0", "O(N) where N is the number of specified fields", "Returns the expiration time of a hash field as a Unix timestamp, in seconds.")
+        };
+        spec.flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let fields = parse_fields(&parts[1..])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let hash = storage.hash(&k)?;
+        let times = fields.iter().map(|field| {
+            let time = match hash.and_then(|hash| hash.expire_time(field)) {
+                None => -2,
+                Some(None) => -1,
+                Some(Some(at)) if self.millis => at as i64,
+                Some(Some(at)) => (at / 1000) as i64,
+            };
+            time.to_resp()
+        });
+        Ok(RespType::Array(times.collect()))
+    }
+}
+
+
+/// `HPERSIST`: 1 for each field whose expiry was removed, -1 if it had none and -2 if
+/// there's no such field.
+struct HPersist;
+
+impl Command for HPersist {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hpersist", -5)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "7.4.0", "O(N) where N is the number of specified fields", "Removes the expiration time for each specified field")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let fields = parse_fields(&parts[1..])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(hash) = storage.hash_mut(&k)? else {
+            return Ok(RespType::Array(vec![RespType::Int(-2); fields.len()]));
+        };
+        let results = fields.iter().map(|field| {
+            let result = if hash.persist(field) {
+                1
+            } else if hash.contains(field) {
+                -1
+            } else {
+                -2
+            };
+            RespType::Int(result)
+        });
+        Ok(RespType::Array(results.collect()))
+    }
+}
+
+
+/// `HGETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+/// PXAT unix-time-milliseconds | PERSIST] FIELDS numfields field...`, which returns the
+/// values of the fields after changing their expiry.
+struct HGetEx;
+
+impl Command for HGetEx {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("hgetex", -5)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hash"])
+            .docs("hash", "8.0.0", "O(N) where N is the number of specified fields", "Get the value of one or more fields of a given hash key, and optionally set their expiration.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+
+        // `Keep` leaves the expiry alone, `Clear` is PERSIST
+        let opt = String::from_resp(&parts[1])?.to_ascii_uppercase();
+        let (expiry, rest) = match opt.as_str() {
+            "PERSIST" => (Expiry::Clear, &parts[2..]),
+            "EX" | "PX" | "EXAT" | "PXAT" => {
+                let arg = parts.get(2).ok_or(CommandErr::SyntaxError)?;
+                let at = parse_expire_at("hgetex", arg, opt.starts_with('P'), opt.ends_with("AT"))?;
+                (Expiry::At(at), &parts[3..])
+            }
+            _ => (Expiry::Keep, &parts[1..]),
+        };
+        let fields = parse_fields(rest)?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(hash) = storage.hash_mut(&k)? else {
+            return Ok(RespType::Array(vec![RespType::Null; fields.len()]));
+        };
+
+        let now = now_ms();
+        let values = fields
+            .iter()
+            .map(|field| {
+                let value = hash.get(field).cloned();
+                if value.is_some() {
+                    match expiry {
+                        Expiry::Clear => {
+                            hash.persist(field);
+                        }
+                        Expiry::Keep => {}
+                        Expiry::At(at) if at <= now => {
+                            hash.remove(field);
+                        }
+                        Expiry::At(at) => {
+                            hash.expire_at(field, at);
+                        }
+                    }
+                }
+                value.to_resp()
+            })
+            .collect();
+
+        storage.remove_if_empty(&k);
+        storage.sync_field_expiry(&k);
+        Ok(RespType::Array(values))
+    }
+}
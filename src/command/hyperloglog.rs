@@ -0,0 +1,515 @@
+use crate::{FromResp, RespType, ToResp};
+
+use super::{Command, CommandContext, CommandErr, CommandFlag, CommandSpec};
+
+pub(super) fn commands() -> Vec<Box<dyn Command>> {
+    vec![Box::new(PfAdd), Box::new(PfCount), Box::new(PfMerge)]
+}
+
+// HyperLogLogs are strings laid out exactly like redis lays them out, so they can be moved
+// between the two with DUMP/RESTORE or GET/SET: a 16 byte header, `HYLL`, the encoding, three
+// unused bytes and the cached cardinality, followed by the registers.
+
+/// Bits of the hash picking the register, so there are 2^14 registers.
+const P: u32 = 14;
+const REGISTERS: usize = 1 << P;
+/// Bits of the hash left to count the run of zeros in.
+const Q: u32 = 64 - P;
+const REGISTER_BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+const HEADER_LEN: usize = 16;
+const DENSE_LEN: usize = HEADER_LEN + (REGISTERS * REGISTER_BITS).div_ceil(8);
+const MAGIC: &[u8] = b"HYLL";
+const ENCODING_AT: usize = 4;
+const CARD_AT: usize = 8;
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+
+// The sparse encoding is a run-length encoding of the registers in three opcodes:
+// ZERO `00xxxxxx` for up to 64 zero registers, XZERO `01xxxxxx yyyyyyyy` for up to 16384,
+// and VAL `1vvvvvxx` for up to 4 registers set to a value of up to 32.
+const SPARSE_VAL_MAX_VALUE: u8 = 32;
+const SPARSE_VAL_MAX_LEN: usize = 4;
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+
+/// 0.5 / ln(2), the bias correction of the estimator.
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+/// MurmurHash64A, with redis' seed, which is what decides the register an element sets.
+fn murmur_hash64a(data: &[u8]) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    let mut h = 0xadc8_3b19u64 ^ (data.len() as u64).wrapping_mul(M);
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &b) in tail.iter().enumerate() {
+            h ^= (b as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// The register an element falls in, and the length of the run of zeros in the rest of
+/// its hash, plus one.
+fn pattern(element: &[u8]) -> (usize, u8) {
+    let hash = murmur_hash64a(element);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // the extra bit bounds the run when the hash is all zeros
+    let rest = (hash >> P) | (1 << Q);
+    (index, rest.trailing_zeros() as u8 + 1)
+}
+
+/// An empty HyperLogLog, in the sparse encoding.
+fn new_hll() -> Vec<u8> {
+    let mut hll = MAGIC.to_vec();
+    hll.extend_from_slice(&[SPARSE, 0, 0, 0]);
+    hll.extend_from_slice(&[0; 8]);
+    hll.extend_from_slice(&xzero_op(REGISTERS));
+    hll
+}
+
+/// Checks that the string is laid out like a HyperLogLog.
+fn check(hll: &[u8]) -> Result<(), CommandErr> {
+    let valid = hll.len() >= HEADER_LEN
+        && hll.starts_with(MAGIC)
+        && match hll[ENCODING_AT] {
+            DENSE => hll.len() == DENSE_LEN,
+            SPARSE => true,
+            _ => false,
+        };
+    valid.then_some(()).ok_or(CommandErr::NotHll)
+}
+
+/// The cardinality cached in the header, unless it was invalidated by a change since.
+fn cached_count(hll: &[u8]) -> Option<u64> {
+    let card = u64::from_le_bytes(hll[CARD_AT..HEADER_LEN].try_into().unwrap());
+    (card >> 63 == 0).then_some(card)
+}
+
+fn invalidate_cache(hll: &mut [u8]) {
+    hll[HEADER_LEN - 1] |= 1 << 7;
+}
+
+fn dense_get(registers: &[u8], index: usize) -> u8 {
+    let bit = index * REGISTER_BITS;
+    let (byte, shift) = (bit / 8, bit % 8);
+    let b0 = registers[byte] as u16;
+    let b1 = registers.get(byte + 1).copied().unwrap_or(0) as u16;
+    (((b0 >> shift) | (b1 << (8 - shift))) & REGISTER_MAX as u16) as u8
+}
+
+fn dense_set(registers: &mut [u8], index: usize, value: u8) {
+    let bit = index * REGISTER_BITS;
+    let (byte, shift) = (bit / 8, bit % 8);
+    let value = value as u16;
+    registers[byte] &= !((REGISTER_MAX as u16) << shift) as u8;
+    registers[byte] |= (value << shift) as u8;
+    if let Some(next) = registers.get_mut(byte + 1) {
+        *next &= !((REGISTER_MAX as u16) >> (8 - shift)) as u8;
+        *next |= (value >> (8 - shift)) as u8;
+    }
+}
+
+fn is_zero_op(op: u8) -> bool {
+    op & 0xc0 == 0
+}
+
+fn is_xzero_op(op: u8) -> bool {
+    op & 0xc0 == 0x40
+}
+
+fn is_val_op(op: u8) -> bool {
+    op & 0x80 != 0
+}
+
+fn val_op(value: u8, len: usize) -> u8 {
+    0x80 | ((value - 1) << 2) | (len - 1) as u8
+}
+
+fn val_op_value(op: u8) -> u8 {
+    ((op >> 2) & 0x1f) + 1
+}
+
+fn zero_op(len: usize) -> u8 {
+    (len - 1) as u8
+}
+
+fn xzero_op(len: usize) -> [u8; 2] {
+    let len = len - 1;
+    [0x40 | (len >> 8) as u8, (len & 0xff) as u8]
+}
+
+/// The sparse opcode at `at`: how many registers it covers, and its length in bytes.
+/// `None` if it's cut short.
+fn sparse_op(sparse: &[u8], at: usize) -> Option<(usize, usize)> {
+    let op = sparse[at];
+    if is_zero_op(op) {
+        Some(((op & 0x3f) as usize + 1, 1))
+    } else if is_val_op(op) {
+        Some(((op & 0x3) as usize + 1, 1))
+    } else {
+        let low = *sparse.get(at + 1)?;
+        Some(((((op & 0x3f) as usize) << 8 | low as usize) + 1, 2))
+    }
+}
+
+/// Calls `f` for each run of registers of the sparse encoding with the value they're set to,
+/// failing if the runs don't cover exactly all the registers.
+fn sparse_runs(sparse: &[u8], mut f: impl FnMut(usize, usize, u8)) -> Result<(), CommandErr> {
+    let (mut at, mut index) = (0, 0);
+    while at < sparse.len() {
+        let (len, op_len) = sparse_op(sparse, at).ok_or(CommandErr::CorruptHll)?;
+        let value = if is_val_op(sparse[at]) { val_op_value(sparse[at]) } else { 0 };
+        if index + len > REGISTERS {
+            return Err(CommandErr::CorruptHll);
+        }
+        f(index, len, value);
+        index += len;
+        at += op_len;
+    }
+    if index != REGISTERS {
+        return Err(CommandErr::CorruptHll);
+    }
+    Ok(())
+}
+
+/// Converts a sparse HyperLogLog to the dense encoding, keeping its header.
+fn sparse_to_dense(hll: &mut Vec<u8>) -> Result<(), CommandErr> {
+    if hll[ENCODING_AT] == DENSE {
+        return Ok(());
+    }
+    let mut dense = vec![0; DENSE_LEN];
+    dense[..HEADER_LEN].copy_from_slice(&hll[..HEADER_LEN]);
+    dense[ENCODING_AT] = DENSE;
+    let registers = &mut dense[HEADER_LEN..];
+    sparse_runs(&hll[HEADER_LEN..], |index, len, value| {
+        if value > 0 {
+            (index..index + len).for_each(|i| dense_set(registers, i, value));
+        }
+    })?;
+    *hll = dense;
+    Ok(())
+}
+
+/// Raises the register to `value`, returning whether it was lower.
+fn set_register(hll: &mut Vec<u8>, index: usize, value: u8, sparse_max_bytes: usize) -> Result<bool, CommandErr> {
+    if hll[ENCODING_AT] == DENSE {
+        let registers = &mut hll[HEADER_LEN..];
+        if dense_get(registers, index) >= value {
+            return Ok(false);
+        }
+        dense_set(registers, index, value);
+        return Ok(true);
+    }
+    if value > SPARSE_VAL_MAX_VALUE {
+        return promote(hll, index, value);
+    }
+
+    // find the opcode covering the register, and the one before it
+    let (mut at, mut first, mut span, mut prev) = (HEADER_LEN, 0, 0, None);
+    while at < hll.len() {
+        let Some((len, op_len)) = sparse_op(hll, at) else {
+            return Err(CommandErr::CorruptHll);
+        };
+        span = len;
+        if index < first + span {
+            break;
+        }
+        prev = Some(at);
+        at += op_len;
+        first += span;
+    }
+    if span == 0 || at >= hll.len() {
+        return Err(CommandErr::CorruptHll);
+    }
+
+    // the simple cases are updated in place, the others split the opcode in up to three,
+    // the worst being XZERO-VAL-XZERO
+    let op = hll[at];
+    if is_val_op(op) && val_op_value(op) >= value {
+        return Ok(false);
+    }
+    if span == 1 && !is_xzero_op(op) {
+        hll[at] = val_op(value, 1);
+    } else {
+        let last = first + span - 1;
+        let mut seq = Vec::with_capacity(5);
+        let run = |seq: &mut Vec<u8>, len: usize| {
+            if is_val_op(op) {
+                seq.push(val_op(val_op_value(op), len));
+            } else if len > SPARSE_ZERO_MAX_LEN {
+                seq.extend_from_slice(&xzero_op(len));
+            } else {
+                seq.push(zero_op(len));
+            }
+        };
+        if index != first {
+            run(&mut seq, index - first);
+        }
+        seq.push(val_op(value, 1));
+        if index != last {
+            run(&mut seq, last - index);
+        }
+        let old_len = if is_xzero_op(op) { 2 } else { 1 };
+        if seq.len() > old_len && hll.len() + seq.len() - old_len > sparse_max_bytes {
+            return promote(hll, index, value);
+        }
+        hll.splice(at..at + old_len, seq);
+    }
+
+    // adjacent VAL opcodes of the same value may be merged now, so look at a few from the
+    // one before the change on
+    let mut at = prev.unwrap_or(HEADER_LEN);
+    let mut scans = 5;
+    while at < hll.len() && scans > 0 {
+        scans -= 1;
+        let op = hll[at];
+        if is_xzero_op(op) {
+            at += 2;
+            continue;
+        }
+        if is_zero_op(op) {
+            at += 1;
+            continue;
+        }
+        if let Some(&next) = hll.get(at + 1).filter(|&&next| is_val_op(next)) {
+            let len = (op & 0x3) as usize + (next & 0x3) as usize + 2;
+            if val_op_value(op) == val_op_value(next) && len <= SPARSE_VAL_MAX_LEN {
+                hll[at + 1] = val_op(val_op_value(op), len);
+                hll.remove(at);
+                // and try merging the result with the one after it
+                continue;
+            }
+        }
+        at += 1;
+    }
+    Ok(true)
+}
+
+/// Converts to the dense encoding to set a register the sparse one can't hold.
+fn promote(hll: &mut Vec<u8>, index: usize, value: u8) -> Result<bool, CommandErr> {
+    sparse_to_dense(hll)?;
+    dense_set(&mut hll[HEADER_LEN..], index, value);
+    Ok(true)
+}
+
+/// Raises each register of `max` to the one of the HyperLogLog where that's higher.
+fn merge(max: &mut [u8], hll: &[u8]) -> Result<(), CommandErr> {
+    let registers = &hll[HEADER_LEN..];
+    if hll[ENCODING_AT] == DENSE {
+        for (i, max) in max.iter_mut().enumerate() {
+            *max = (*max).max(dense_get(registers, i));
+        }
+        return Ok(());
+    }
+    sparse_runs(registers, |index, len, value| {
+        for max in &mut max[index..index + len] {
+            *max = (*max).max(value);
+        }
+    })
+}
+
+/// Estimates the cardinality from how many registers hold each value, with the estimator of
+/// "New cardinality estimation algorithms for HyperLogLog sketches" by Otmar Ertl, as redis does.
+fn estimate(histogram: &[u32; 64]) -> u64 {
+    let m = REGISTERS as f64;
+    let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+    for &count in histogram[1..=Q as usize].iter().rev() {
+        z += count as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let (mut y, mut z) = (1.0, x);
+    loop {
+        x *= x;
+        let prev = z;
+        z += x * y;
+        y += y;
+        if prev == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let (mut y, mut z) = (1.0, 1.0 - x);
+    loop {
+        x = x.sqrt();
+        let prev = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if prev == z {
+            return z / 3.0;
+        }
+    }
+}
+
+fn count(hll: &[u8]) -> Result<u64, CommandErr> {
+    let mut histogram = [0u32; 64];
+    let registers = &hll[HEADER_LEN..];
+    if hll[ENCODING_AT] == DENSE {
+        (0..REGISTERS).for_each(|i| histogram[dense_get(registers, i) as usize] += 1);
+    } else {
+        sparse_runs(registers, |_, len, value| histogram[value as usize] += len as u32)?;
+    }
+    Ok(estimate(&histogram))
+}
+
+fn count_registers(registers: &[u8]) -> u64 {
+    let mut histogram = [0u32; 64];
+    registers.iter().for_each(|&value| histogram[value as usize] += 1);
+    estimate(&histogram)
+}
+
+
+/// `PFADD key [element ...]`, replying 1 if an estimate may have changed.
+struct PfAdd;
+
+impl Command for PfAdd {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("pfadd", -2)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@hyperloglog"])
+            .docs("hyperloglog", "2.8.9", "O(1) to add every element.", "Adds elements to a HyperLogLog key. Creates the key if it doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let elements = parts[1..].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let sparse_max_bytes = storage.limits().hll_sparse_max_bytes;
+        let mut updated = false;
+        if storage.get(&k)?.is_none() {
+            storage.set(k.clone(), new_hll());
+            updated = true;
+        }
+        let hll = storage.get_mut(&k)?.unwrap();
+        check(hll)?;
+        for element in &elements {
+            let (index, value) = pattern(element);
+            updated |= set_register(hll, index, value, sparse_max_bytes)?;
+        }
+        if updated {
+            invalidate_cache(hll);
+        }
+        Ok(RespType::Int(updated as isize))
+    }
+}
+
+
+/// `PFCOUNT key [key ...]`, the estimated cardinality of the union of the HyperLogLogs.
+/// With a single key the estimate is cached in its header.
+struct PfCount;
+
+impl Command for PfCount {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("pfcount", -2)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, -1, 1)
+            .acl(&["@hyperloglog"])
+            .docs("hyperloglog", "2.8.9", "O(1) with a very small average constant time when called with a single key. O(N) with N being the number of keys, and much bigger constant times, when called with multiple keys.", "Returns the approximated cardinality of the set(s) observed by the HyperLogLog key(s).")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let keys = parts.iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        if let [k] = keys.as_slice() {
+            let Some(hll) = storage.get_mut(k)? else {
+                return Ok(RespType::Int(0));
+            };
+            check(hll)?;
+            let card = match cached_count(hll) {
+                Some(card) => card,
+                None => {
+                    let card = count(hll)?;
+                    hll[CARD_AT..HEADER_LEN].copy_from_slice(&card.to_le_bytes());
+                    card
+                }
+            };
+            return Ok(card.to_resp());
+        }
+
+        let mut max = vec![0; REGISTERS];
+        for k in &keys {
+            let Some(hll) = storage.get(k)? else {
+                continue;
+            };
+            check(hll)?;
+            merge(&mut max, hll)?;
+        }
+        Ok(count_registers(&max).to_resp())
+    }
+}
+
+
+/// `PFMERGE destkey [sourcekey ...]`, which merges into what `destkey` held. The result is
+/// dense if any of the HyperLogLogs was.
+struct PfMerge;
+
+impl Command for PfMerge {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("pfmerge", -2)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+            .keys(1, -1, 1)
+            .acl(&["@hyperloglog"])
+            .docs("hyperloglog", "2.8.9", "O(N) to merge N HyperLogLogs, but with high constant times.", "Merges one or more HyperLogLog values into a single key.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let keys = parts.iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let sparse_max_bytes = storage.limits().hll_sparse_max_bytes;
+        let (mut max, mut dense) = (vec![0; REGISTERS], false);
+        for k in &keys {
+            let Some(hll) = storage.get(k)? else {
+                continue;
+            };
+            check(hll)?;
+            dense |= hll[ENCODING_AT] == DENSE;
+            merge(&mut max, hll)?;
+        }
+
+        let dst = &keys[0];
+        if storage.get(dst)?.is_none() {
+            storage.set(dst.clone(), new_hll());
+        }
+        let hll = storage.get_mut(dst)?.unwrap();
+        if dense {
+            sparse_to_dense(hll)?;
+        }
+        for (index, &value) in max.iter().enumerate().filter(|(_, &value)| value > 0) {
+            set_register(hll, index, value, sparse_max_bytes)?;
+        }
+        invalidate_cache(hll);
+        Ok(RespType::String("OK".to_string()))
+    }
+}
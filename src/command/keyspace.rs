@@ -0,0 +1,219 @@
+use crate::storage::now_ms;
+use crate::{FromResp, RespType, ToResp};
+
+use super::{Command, CommandContext, CommandErr, CommandFlag, CommandSpec};
+
+pub(super) fn commands() -> Vec<Box<dyn Command>> {
+    vec![
+        Box::new(Del),
+        Box::new(Expire { name: "expire", millis: false, absolute: false }),
+        Box::new(Expire { name: "pexpire", millis: true, absolute: false }),
+        Box::new(Expire { name: "expireat", millis: false, absolute: true }),
+        Box::new(Expire { name: "pexpireat", millis: true, absolute: true }),
+        Box::new(ExpireTime { millis: false }),
+        Box::new(ExpireTime { millis: true }),
+        Box::new(Ttl { millis: false }),
+        Box::new(Ttl { millis: true }),
+        Box::new(Persist),
+        Box::new(Type),
+    ]
+}
+
+
+struct Del;
+
+impl Command for Del {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("del", -2)
+            .flags(&[CommandFlag::Write])
+            .keys(1, -1, 1)
+            .acl(&["@keyspace"])
+            .docs("generic", "1.0.0", "O(N) where N is the number of keys that will be removed.", "Deletes one or more keys.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let keys = parts.iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let c = keys.iter().filter(|k| storage.del(k)).count();
+
+        Ok(c.to_resp())
+    }
+}
+
+
+/// `EXPIRE`, `PEXPIRE`, `EXPIREAT` and `PEXPIREAT`, which only differ in how the time is given.
+struct Expire {
+    name: &'static str,
+    millis: bool,
+    absolute: bool,
+}
+
+impl Command for Expire {
+    fn spec(&self) -> CommandSpec {
+        let (since, summary) = match (self.millis, self.absolute) {
+            (false, false) => ("1.0.0", "Sets the expiration time of a key in seconds."),
+            (true, false) => ("2.6.0", "Sets the expiration time of a key in milliseconds."),
+            (false, true) => ("1.2.0", "Sets the expiration time of a key to a Unix timestamp."),
+            (true, true) => ("2.6.0", "Sets the expiration time of a key to a Unix milliseconds timestamp."),
+        };
+        CommandSpec::new(self.name, -3)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@keyspace"])
+            .docs("generic", since, "O(1)", summary)
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let key = Vec::<u8>::from_resp(&parts[0])?;
+        let when = i64::from_resp(&parts[1])?;
+
+        let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+        for opt in &parts[2..] {
+            let opt = String::from_resp(opt)?;
+            match opt.to_ascii_uppercase().as_str() {
+                "NX" => nx = true,
+                "XX" => xx = true,
+                "GT" => gt = true,
+                "LT" => lt = true,
+                _ => return Err(CommandErr::InvalidArgs(format!("Unsupported option {}", opt))),
+            }
+        }
+        if nx && (xx || gt || lt) {
+            return Err(CommandErr::InvalidArgs("NX and XX, GT or LT options at the same time are not compatible".to_string()));
+        }
+        if gt && lt {
+            return Err(CommandErr::InvalidArgs("GT and LT options at the same time are not compatible".to_string()));
+        }
+
+        let invalid = || CommandErr::InvalidArgs(format!("invalid expire time in '{}' command", self.name));
+        let when_ms = if self.millis { Some(when) } else { when.checked_mul(1000) };
+        let at = if self.absolute {
+            when_ms
+        } else {
+            when_ms.and_then(|ms| ms.checked_add(now_ms() as i64))
+        }
+        .ok_or_else(invalid)?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(current) = storage.expire_time(&key) else {
+            return Ok(RespType::Int(0));
+        };
+
+        // a key without an expiry counts as having an infinite ttl for GT and LT
+        let skip = match current {
+            Some(_) if nx => true,
+            None if xx || gt => true,
+            Some(current) => (gt && at <= current as i64) || (lt && at >= current as i64),
+            None => false,
+        };
+        if skip {
+            return Ok(RespType::Int(0));
+        }
+
+        storage.expire_at(&key, at.max(0) as u64);
+        Ok(RespType::Int(1))
+    }
+}
+
+
+/// `TTL` and `PTTL`.
+struct Ttl {
+    millis: bool,
+}
+
+impl Command for Ttl {
+    fn spec(&self) -> CommandSpec {
+        let spec = if self.millis {
+            CommandSpec::new("pttl", 2).docs("generic", "2.6.0", "O(1)", "Returns the expiration time in milliseconds of a key.")
+        } else {
+            CommandSpec::new("ttl", 2).docs("generic", "1.0.0", "O(1)", "Returns the expiration time in seconds of a key.")
+        };
+        spec.flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@keyspace"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let key = Vec::<u8>::from_resp(&parts[0])?;
+
+        let ttl = match ctx.storage.lock().unwrap().expire_time(&key) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(at)) => {
+                let ttl = at.saturating_sub(now_ms()) as i64;
+                if self.millis { ttl } else { (ttl + 500) / 1000 }
+            }
+        };
+        Ok(ttl.to_resp())
+    }
+}
+
+
+/// `EXPIRETIME` and `PEXPIRETIME`.
+struct ExpireTime {
+    millis: bool,
+}
+
+impl Command for ExpireTime {
+    fn spec(&self) -> CommandSpec {
+        let spec = if self.millis {
+            CommandSpec::new("pexpiretime", 2).docs("generic", "7.0.0", "O(1)", "Returns the expiration time of a key as a Unix milliseconds timestamp.")
+        } else {
+            CommandSpec::new("expiretime", 2).docs("generic", "7.0.0", "O(1)", "Returns the expiration time of a key as a Unix timestamp.")
+        };
+        spec.flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@keyspace"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let key = Vec::<u8>::from_resp(&parts[0])?;
+
+        let time = match ctx.storage.lock().unwrap().expire_time(&key) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(at)) if self.millis => at as i64,
+            Some(Some(at)) => (at / 1000) as i64,
+        };
+        Ok(time.to_resp())
+    }
+}
+
+
+struct Persist;
+
+impl Command for Persist {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("persist", 2)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@keyspace"])
+            .docs("generic", "2.2.0", "O(1)", "Removes the expiration time of a key.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let key = Vec::<u8>::from_resp(&parts[0])?;
+        let persisted = ctx.storage.lock().unwrap().persist(&key);
+        Ok(RespType::Int(persisted as isize))
+    }
+}
+
+
+struct Type;
+
+impl Command for Type {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("type", 2)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@keyspace"])
+            .docs("generic", "1.0.0", "O(1)", "Determines the type of value stored at a key.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let key = Vec::<u8>::from_resp(&parts[0])?;
+        let name = ctx.storage.lock().unwrap().get_value(&key).map_or("none", |v| v.type_name());
+        Ok(RespType::String(name.to_string()))
+    }
+}
@@ -0,0 +1,681 @@
+use std::collections::VecDeque;
+
+use crate::{FromResp, RespType, Storage, ToResp};
+
+use super::{numkeys_positions, parse_timeout, Command, CommandContext, CommandErr, CommandFlag, CommandSpec};
+
+pub(super) fn commands() -> Vec<Box<dyn Command>> {
+    vec![
+        Box::new(Push { end: End::Left, only_existing: false }),
+        Box::new(Push { end: End::Right, only_existing: false }),
+        Box::new(Push { end: End::Left, only_existing: true }),
+        Box::new(Push { end: End::Right, only_existing: true }),
+        Box::new(Pop { end: End::Left }),
+        Box::new(Pop { end: End::Right }),
+        Box::new(LLen),
+        Box::new(LRange),
+        Box::new(LIndex),
+        Box::new(LSet),
+        Box::new(LRem),
+        Box::new(LTrim),
+        Box::new(LInsert),
+        Box::new(LPos),
+        Box::new(LMove),
+        Box::new(RPopLPush),
+        Box::new(LMPop),
+        Box::new(BPop { end: End::Left }),
+        Box::new(BPop { end: End::Right }),
+        Box::new(BLMove),
+        Box::new(BLMPop),
+    ]
+}
+
+/// Which end of a list an element is pushed to or popped from.
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum End {
+    Left,
+    Right,
+}
+
+impl End {
+    pub(super) fn parse(arg: &RespType) -> Result<Self, CommandErr> {
+        match String::from_resp(arg)?.to_ascii_uppercase().as_str() {
+            "LEFT" => Ok(End::Left),
+            "RIGHT" => Ok(End::Right),
+            _ => Err(CommandErr::SyntaxError),
+        }
+    }
+
+    fn push(self, list: &mut VecDeque<Vec<u8>>, elem: Vec<u8>) {
+        match self {
+            End::Left => list.push_front(elem),
+            End::Right => list.push_back(elem),
+        }
+    }
+
+    fn pop(self, list: &mut VecDeque<Vec<u8>>) -> Option<Vec<u8>> {
+        match self {
+            End::Left => list.pop_front(),
+            End::Right => list.pop_back(),
+        }
+    }
+}
+
+/// Resolves a redis list range, where negative indexes count from the end, to the
+/// indexes it covers. Returns `None` if the range is empty.
+pub(super) fn resolve_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { (start + len).max(0) } else { start };
+    let stop = if stop < 0 { stop + len } else { stop.min(len - 1) };
+    if start > stop || start >= len {
+        return None;
+    }
+    Some((start as usize, stop as usize))
+}
+
+/// Resolves an index that counts from the end when negative.
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    (index >= 0 && index < len as i64).then_some(index as usize)
+}
+
+/// Parses a `numkeys` argument followed by that many keys, returning the keys and
+/// the arguments after them.
+pub(super) fn parse_numkeys(parts: &[RespType]) -> Result<(Vec<Vec<u8>>, &[RespType]), CommandErr> {
+    let numkeys = i64::from_resp(&parts[0])?;
+    if numkeys <= 0 {
+        return Err(CommandErr::InvalidArgs("numkeys should be greater than 0".to_string()));
+    }
+    let numkeys = numkeys as usize;
+    if numkeys >= parts.len() {
+        return Err(CommandErr::InvalidArgs("Number of keys can't be greater than number of args".to_string()));
+    }
+    let keys = parts[1..=numkeys].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+    Ok((keys, &parts[numkeys + 1..]))
+}
+
+/// Parses the `COUNT` option of LMPOP-style commands.
+pub(super) fn parse_mpop_count(opts: &[RespType]) -> Result<usize, CommandErr> {
+    match opts {
+        [] => Ok(1),
+        [opt, count] if String::from_resp(opt)?.eq_ignore_ascii_case("COUNT") => match i64::from_resp(count)? {
+            n if n > 0 => Ok(n as usize),
+            _ => Err(CommandErr::InvalidArgs("count should be greater than 0".to_string())),
+        },
+        _ => Err(CommandErr::SyntaxError),
+    }
+}
+
+/// Pops an element from `src` and pushes it to `dst`, which may be the same list. The type
+/// of `dst` is checked before anything is popped.
+pub(super) fn move_element(storage: &mut Storage, src: &[u8], dst: &[u8], from: End, to: End) -> Result<Option<Vec<u8>>, CommandErr> {
+    storage.list(dst)?;
+    let Some(elem) = storage.list_mut(src)?.and_then(|list| from.pop(list)) else {
+        return Ok(None);
+    };
+    storage.remove_if_empty(src);
+    to.push(storage.list_or_create(dst)?, elem.clone());
+    Ok(Some(elem))
+}
+
+/// The key an LMPOP-style pop took elements from, along with those elements.
+pub(super) type Popped = (Vec<u8>, Vec<Vec<u8>>);
+
+/// Pops up to `count` elements from the first non-empty list of `keys`, returning that key
+/// along with the elements.
+pub(super) fn mpop(storage: &mut Storage, keys: &[Vec<u8>], end: End, count: usize) -> Result<Option<Popped>, CommandErr> {
+    for key in keys {
+        let Some(list) = storage.list_mut(key)? else {
+            continue;
+        };
+        let popped: Vec<Vec<u8>> = (0..count).map_while(|_| end.pop(list)).collect();
+        storage.remove_if_empty(key);
+        return Ok(Some((key.clone(), popped)));
+    }
+    Ok(None)
+}
+
+/// The reply of LMPOP-style commands: the key popped from and its elements.
+pub(super) fn mpop_reply(popped: Option<Popped>) -> RespType {
+    match popped {
+        Some((key, elems)) => RespType::Array(vec![key.to_resp(), elems.to_resp()]),
+        None => RespType::NullArray,
+    }
+}
+
+
+/// `LPUSH`, `RPUSH`, and `LPUSHX`/`RPUSHX` which only push to lists that exist.
+struct Push {
+    end: End,
+    only_existing: bool,
+}
+
+impl Command for Push {
+    fn spec(&self) -> CommandSpec {
+        let spec = match (self.end, self.only_existing) {
+            (End::Left, false) => CommandSpec::new("lpush", -3)
+                .docs("list", "1.0.0", "O(1) for each element added, so O(N) to add N elements when the command is called with multiple arguments.", "Prepends one or more elements to a list. Creates the key if it doesn't exist."),
+            (End::Right, false) => CommandSpec::new("rpush", -3)
+                .docs("list", "1.0.0", "O(1) for each element added, so O(N) to add N elements when the command is called with multiple arguments.", "Appends one or more elements to a list. Creates the key if it doesn't exist."),
+            (End::Left, true) => CommandSpec::new("lpushx", -3)
+                .docs("list", "2.2.0", "O(1) for each element added, so O(N) to add N elements when the command is called with multiple arguments.", "Prepends one or more elements to a list only when the list exists."),
+            (End::Right, true) => CommandSpec::new("rpushx", -3)
+                .docs("list", "2.2.0", "O(1) for each element added, so O(N) to add N elements when the command is called with multiple arguments.", "Appends an element to a list only when the list exists."),
+        };
+        spec.flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@list"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let elems = parts[1..].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let list = if self.only_existing {
+            match storage.list_mut(&k)? {
+                Some(list) => list,
+                None => return Ok(RespType::Int(0)),
+            }
+        } else {
+            storage.list_or_create(&k)?
+        };
+        for elem in elems {
+            self.end.push(list, elem);
+        }
+        Ok(list.len().to_resp())
+    }
+}
+
+
+/// `LPOP` and `RPOP`.
+struct Pop {
+    end: End,
+}
+
+impl Command for Pop {
+    fn spec(&self) -> CommandSpec {
+        let spec = match self.end {
+            End::Left => CommandSpec::new("lpop", -2)
+                .docs("list", "1.0.0", "O(N) where N is the number of elements returned", "Returns the first elements in a list after removing it. Deletes the list if the last element was popped."),
+            End::Right => CommandSpec::new("rpop", -2)
+                .docs("list", "1.0.0", "O(N) where N is the number of elements returned", "Returns and removes the last elements of the list. Deletes the list if the last element was popped."),
+        };
+        spec.flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@list"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let count = match parts {
+            [_] => None,
+            [_, count] => match i64::from_resp(count)? {
+                n if n >= 0 => Some(n as usize),
+                _ => return Err(CommandErr::InvalidArgs("value is out of range, must be positive".to_string())),
+            },
+            _ => return Err(CommandErr::SyntaxError),
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(list) = storage.list_mut(&k)? else {
+            return Ok(if count.is_some() { RespType::NullArray } else { RespType::Null });
+        };
+        let reply = match count {
+            None => self.end.pop(list).to_resp(),
+            Some(count) => (0..count).map_while(|_| self.end.pop(list)).collect::<Vec<_>>().to_resp(),
+        };
+        storage.remove_if_empty(&k);
+        Ok(reply)
+    }
+}
+
+
+struct LLen;
+
+impl Command for LLen {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("llen", 2)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@list"])
+            .docs("list", "1.0.0", "O(1)", "Returns the length of a list.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let len = ctx.storage.lock().unwrap().list(&k)?.map_or(0, VecDeque::len);
+        Ok(len.to_resp())
+    }
+}
+
+
+struct LRange;
+
+impl Command for LRange {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("lrange", 4)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@list"])
+            .docs("list", "1.0.0", "O(S+N) where S is the distance of start offset from HEAD for small lists, from nearest end (HEAD or TAIL) for large lists; and N is the number of elements in the specified range.", "Returns a range of elements from a list.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let start = i64::from_resp(&parts[1])?;
+        let stop = i64::from_resp(&parts[2])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(list) = storage.list(&k)? else {
+            return Ok(RespType::Array(Vec::new()));
+        };
+        let Some((start, stop)) = resolve_range(start, stop, list.len()) else {
+            return Ok(RespType::Array(Vec::new()));
+        };
+        Ok(RespType::Array(list.range(start..=stop).map(|elem| elem.to_resp()).collect()))
+    }
+}
+
+
+struct LIndex;
+
+impl Command for LIndex {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("lindex", 3)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@list"])
+            .docs("list", "1.0.0", "O(N) where N is the number of elements to traverse to get to the element at index. This makes asking for the first or the last element of the list O(1).", "Returns an element from a list by its index.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let index = i64::from_resp(&parts[1])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let elem = storage
+            .list(&k)?
+            .and_then(|list| resolve_index(index, list.len()).map(|i| &list[i]));
+        Ok(elem.to_resp())
+    }
+}
+
+
+struct LSet;
+
+impl Command for LSet {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("lset", 4)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+            .keys(1, 1, 1)
+            .acl(&["@list"])
+            .docs("list", "1.0.0", "O(N) where N is the length of the list. Setting either the first or the last element of the list is O(1).", "Sets the value of an element in a list by its index.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let index = i64::from_resp(&parts[1])?;
+        let elem = Vec::<u8>::from_resp(&parts[2])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(list) = storage.list_mut(&k)? else {
+            return Err(CommandErr::InvalidArgs("no such key".to_string()));
+        };
+        let Some(i) = resolve_index(index, list.len()) else {
+            return Err(CommandErr::InvalidArgs("index out of range".to_string()));
+        };
+        list[i] = elem;
+        Ok(RespType::String("OK".to_string()))
+    }
+}
+
+
+struct LRem;
+
+impl Command for LRem {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("lrem", 4)
+            .flags(&[CommandFlag::Write])
+            .keys(1, 1, 1)
+            .acl(&["@list"])
+            .docs("list", "1.0.0", "O(N+M) where N is the length of the list and M is the number of elements removed.", "Removes elements from a list. Deletes the list if the last element was removed.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let count = i64::from_resp(&parts[1])?;
+        let elem = Vec::<u8>::from_resp(&parts[2])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(list) = storage.list_mut(&k)? else {
+            return Ok(RespType::Int(0));
+        };
+
+        // a negative count removes matches starting from the tail
+        let limit = if count == 0 { usize::MAX } else { count.unsigned_abs() as usize };
+        let mut removed = 0;
+        if count >= 0 {
+            let mut i = 0;
+            while i < list.len() && removed < limit {
+                if list[i] == elem {
+                    list.remove(i);
+                    removed += 1;
+                } else {
+                    i += 1;
+                }
+            }
+        } else {
+            let mut i = list.len();
+            while i > 0 && removed < limit {
+                i -= 1;
+                if list[i] == elem {
+                    list.remove(i);
+                    removed += 1;
+                }
+            }
+        }
+
+        storage.remove_if_empty(&k);
+        Ok(removed.to_resp())
+    }
+}
+
+
+struct LTrim;
+
+impl Command for LTrim {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("ltrim", 4)
+            .flags(&[CommandFlag::Write])
+            .keys(1, 1, 1)
+            .acl(&["@list"])
+            .docs("list", "1.0.0", "O(N) where N is the number of elements to be removed by the operation.", "Removes elements from both ends a list. Deletes the list if all elements were trimmed.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let start = i64::from_resp(&parts[1])?;
+        let stop = i64::from_resp(&parts[2])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        if let Some(list) = storage.list_mut(&k)? {
+            match resolve_range(start, stop, list.len()) {
+                Some((start, stop)) => {
+                    list.truncate(stop + 1);
+                    list.drain(..start);
+                }
+                None => list.clear(),
+            }
+            storage.remove_if_empty(&k);
+        }
+        Ok(RespType::String("OK".to_string()))
+    }
+}
+
+
+struct LInsert;
+
+impl Command for LInsert {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("linsert", 5)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+            .keys(1, 1, 1)
+            .acl(&["@list"])
+            .docs("list", "2.2.0", "O(N) where N is the number of elements to traverse before seeing the value pivot. This means that inserting somewhere on the left end on the list (head) can be considered O(1) and inserting somewhere on the right end (tail) is O(N).", "Inserts an element before or after another element in a list.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let after = match String::from_resp(&parts[1])?.to_ascii_uppercase().as_str() {
+            "BEFORE" => false,
+            "AFTER" => true,
+            _ => return Err(CommandErr::SyntaxError),
+        };
+        let pivot = Vec::<u8>::from_resp(&parts[2])?;
+        let elem = Vec::<u8>::from_resp(&parts[3])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(list) = storage.list_mut(&k)? else {
+            return Ok(RespType::Int(0));
+        };
+        let Some(i) = list.iter().position(|e| *e == pivot) else {
+            return Ok(RespType::Int(-1));
+        };
+        list.insert(if after { i + 1 } else { i }, elem);
+        Ok(list.len().to_resp())
+    }
+}
+
+
+struct LPos;
+
+impl Command for LPos {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("lpos", -3)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@list"])
+            .docs("list", "6.0.6", "O(N) where N is the number of elements in the list, for the average case. When searching for elements near the head or the tail of the list, or when the MAXLEN option is provided, the command may run in constant time.", "Returns the index of matching elements in a list.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let elem = Vec::<u8>::from_resp(&parts[1])?;
+
+        let (mut rank, mut count, mut maxlen) = (1i64, None, 0usize);
+        for opt in parts[2..].chunks(2) {
+            let [name, value] = opt else {
+                return Err(CommandErr::SyntaxError);
+            };
+            let value = i64::from_resp(value)?;
+            match String::from_resp(name)?.to_ascii_uppercase().as_str() {
+                "RANK" if value == 0 => {
+                    return Err(CommandErr::InvalidArgs("RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list".to_string()));
+                }
+                "RANK" if value == i64::MIN => {
+                    return Err(CommandErr::InvalidArgs(format!("value is out of range, value must between {} and {}", -i64::MAX, i64::MAX)));
+                }
+                "RANK" => rank = value,
+                "COUNT" if value < 0 => return Err(CommandErr::InvalidArgs("COUNT can't be negative".to_string())),
+                "COUNT" => count = Some(value as usize),
+                "MAXLEN" if value < 0 => return Err(CommandErr::InvalidArgs("MAXLEN can't be negative".to_string())),
+                "MAXLEN" => maxlen = value as usize,
+                _ => return Err(CommandErr::SyntaxError),
+            }
+        }
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let list = storage.list(&k)?;
+        let len = list.map_or(0, VecDeque::len);
+        let scan = if maxlen == 0 { len } else { maxlen.min(len) };
+        let indexes: Box<dyn Iterator<Item = usize>> = if rank > 0 {
+            Box::new(0..scan)
+        } else {
+            Box::new((len - scan..len).rev())
+        };
+
+        // COUNT 0 means every match
+        let wanted = match count {
+            Some(0) => usize::MAX,
+            Some(n) => n,
+            None => 1,
+        };
+        let matches: Vec<usize> = indexes
+            .filter(|&i| list.is_some_and(|list| list[i] == elem))
+            .skip(rank.unsigned_abs() as usize - 1)
+            .take(wanted)
+            .collect();
+
+        match count {
+            Some(_) => Ok(matches.to_resp()),
+            None => Ok(matches.first().to_resp()),
+        }
+    }
+}
+
+
+struct LMove;
+
+impl Command for LMove {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("lmove", 5)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+            .keys(1, 2, 1)
+            .acl(&["@list"])
+            .docs("list", "6.2.0", "O(1)", "Returns an element after popping it from one list and pushing it to another. Deletes the list if the last element was moved.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let src = Vec::<u8>::from_resp(&parts[0])?;
+        let dst = Vec::<u8>::from_resp(&parts[1])?;
+        let from = End::parse(&parts[2])?;
+        let to = End::parse(&parts[3])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        Ok(move_element(&mut storage, &src, &dst, from, to)?.to_resp())
+    }
+}
+
+
+struct RPopLPush;
+
+impl Command for RPopLPush {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("rpoplpush", 3)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+            .keys(1, 2, 1)
+            .acl(&["@list"])
+            .docs("list", "1.2.0", "O(1)", "Returns the last element of a list after removing and pushing it to another list. Deletes the list if the last element was popped.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let src = Vec::<u8>::from_resp(&parts[0])?;
+        let dst = Vec::<u8>::from_resp(&parts[1])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        Ok(move_element(&mut storage, &src, &dst, End::Right, End::Left)?.to_resp())
+    }
+}
+
+
+struct LMPop;
+
+impl Command for LMPop {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("lmpop", -4)
+            .flags(&[CommandFlag::Write, CommandFlag::MovableKeys])
+            .acl(&["@list"])
+            .docs("list", "7.0.0", "O(N+M) where N is the number of provided keys and M is the number of elements returned.", "Returns multiple elements from a list after removing them. Deletes the list if the last element was popped.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let (keys, rest) = parse_numkeys(parts)?;
+        let Some((end, opts)) = rest.split_first() else {
+            return Err(CommandErr::SyntaxError);
+        };
+        let end = End::parse(end)?;
+        let count = parse_mpop_count(opts)?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        Ok(mpop_reply(mpop(&mut storage, &keys, end, count)?))
+    }
+
+    fn key_positions(&self, _spec: &CommandSpec, argv: &[RespType]) -> Vec<usize> {
+        numkeys_positions(argv, 1)
+    }
+}
+
+
+/// `BLPOP` and `BRPOP`.
+struct BPop {
+    end: End,
+}
+
+impl Command for BPop {
+    fn spec(&self) -> CommandSpec {
+        let spec = match self.end {
+            End::Left => CommandSpec::new("blpop", -3)
+                .docs("list", "2.0.0", "O(N) where N is the number of provided keys.", "Removes and returns the first element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped."),
+            End::Right => CommandSpec::new("brpop", -3)
+                .docs("list", "2.0.0", "O(N) where N is the number of provided keys.", "Removes and returns the last element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped."),
+        };
+        spec.flags(&[CommandFlag::Write, CommandFlag::Blocking])
+            .keys(1, -2, 1)
+            .acl(&["@list"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let (timeout, keys) = parts.split_last().unwrap();
+        let keys = keys.iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+        let deadline = parse_timeout(timeout)?;
+
+        let popped = mpop(&mut ctx.storage.lock().unwrap(), &keys, self.end, 1)?;
+        match popped {
+            Some((key, mut elems)) => Ok(RespType::Array(vec![key.to_resp(), elems.remove(0).to_resp()])),
+            None => {
+                ctx.block(keys, deadline);
+                Ok(RespType::NullArray)
+            }
+        }
+    }
+}
+
+
+struct BLMove;
+
+impl Command for BLMove {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("blmove", 6)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Blocking])
+            .keys(1, 2, 1)
+            .acl(&["@list"])
+            .docs("list", "6.2.0", "O(1)", "Pops an element from a list, pushes it to another list and returns it. Blocks until an element is available otherwise. Deletes the list if the last element was moved.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let src = Vec::<u8>::from_resp(&parts[0])?;
+        let dst = Vec::<u8>::from_resp(&parts[1])?;
+        let from = End::parse(&parts[2])?;
+        let to = End::parse(&parts[3])?;
+        let deadline = parse_timeout(&parts[4])?;
+
+        let moved = move_element(&mut ctx.storage.lock().unwrap(), &src, &dst, from, to)?;
+        if moved.is_none() {
+            ctx.block(vec![src], deadline);
+        }
+        Ok(moved.to_resp())
+    }
+}
+
+
+struct BLMPop;
+
+impl Command for BLMPop {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("blmpop", -5)
+            .flags(&[CommandFlag::Write, CommandFlag::Blocking, CommandFlag::MovableKeys])
+            .acl(&["@list"])
+            .docs("list", "7.0.0", "O(N+M) where N is the number of provided keys and M is the number of elements returned.", "Pops the first element from one of multiple lists. Blocks until an element is available otherwise. Deletes the list if the last element was popped.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let deadline = parse_timeout(&parts[0])?;
+        let (keys, rest) = parse_numkeys(&parts[1..])?;
+        let Some((end, opts)) = rest.split_first() else {
+            return Err(CommandErr::SyntaxError);
+        };
+        let end = End::parse(end)?;
+        let count = parse_mpop_count(opts)?;
+
+        let popped = mpop(&mut ctx.storage.lock().unwrap(), &keys, end, count)?;
+        if popped.is_none() {
+            ctx.block(keys, deadline);
+        }
+        Ok(mpop_reply(popped))
+    }
+
+    fn key_positions(&self, _spec: &CommandSpec, argv: &[RespType]) -> Vec<usize> {
+        numkeys_positions(argv, 2)
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::HashSet;
+
+use crate::{FromResp, RespType, Set, Storage, ToResp};
+
+use super::list::parse_numkeys;
+use super::{numkeys_positions, Command, CommandContext, CommandErr, CommandFlag, CommandSpec, ScanArgs};
+
+pub(super) fn commands() -> Vec<Box<dyn Command>> {
+    vec![
+        Box::new(SAdd),
+        Box::new(SRem),
+        Box::new(SMembers),
+        Box::new(SIsMember),
+        Box::new(SMIsMember),
+        Box::new(SCard),
+        Box::new(SPop),
+        Box::new(SRandMember),
+        Box::new(SMove),
+        Box::new(SScan),
+        Box::new(SetOpCmd { op: SetOp::Inter, store: false }),
+        Box::new(SetOpCmd { op: SetOp::Union, store: false }),
+        Box::new(SetOpCmd { op: SetOp::Diff, store: false }),
+        Box::new(SetOpCmd { op: SetOp::Inter, store: true }),
+        Box::new(SetOpCmd { op: SetOp::Union, store: true }),
+        Box::new(SetOpCmd { op: SetOp::Diff, store: true }),
+        Box::new(SInterCard),
+    ]
+}
+
+#[derive(Clone, Copy)]
+enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// Copies of the sets at `keys`, `None` for keys that don't exist. Every key is type
+/// checked, even when the result is already known to be empty.
+fn load_sets(storage: &mut Storage, keys: &[Vec<u8>]) -> Result<Vec<Option<Set>>, CommandErr> {
+    keys.iter().map(|k| Ok(storage.get_set(k)?.cloned())).collect()
+}
+
+/// Applies `op` to the sets, a missing key counting as an empty set, and stops once
+/// `limit` members were found.
+fn combine(sets: &[Option<Set>], op: SetOp, limit: usize) -> Vec<Vec<u8>> {
+    match op {
+        SetOp::Inter => {
+            let Some(mut sets) = sets.iter().map(Option::as_ref).collect::<Option<Vec<&Set>>>() else {
+                return Vec::new();
+            };
+            // checking the members of the smallest set against the others is the least work
+            sets.sort_by_key(|set| set.len());
+            let (smallest, rest) = sets.split_first().expect("commands take at least one key");
+            smallest.iter().filter(|m| rest.iter().all(|set| set.contains(m))).take(limit).collect()
+        }
+        SetOp::Union => {
+            let mut seen = HashSet::new();
+            sets.iter().flatten().flat_map(Set::iter).filter(|m| seen.insert(m.clone())).take(limit).collect()
+        }
+        SetOp::Diff => {
+            let Some((Some(first), rest)) = sets.split_first() else {
+                return Vec::new();
+            };
+            first.iter().filter(|m| rest.iter().flatten().all(|set| !set.contains(m))).take(limit).collect()
+        }
+    }
+}
+
+fn set_reply(members: impl IntoIterator<Item = Vec<u8>>) -> RespType {
+    RespType::Set(members.into_iter().map(|m| m.to_resp()).collect())
+}
+
+
+struct SAdd;
+
+impl Command for SAdd {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("sadd", -3)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@set"])
+            .docs("set", "1.0.0", "O(1) for each element added, so O(N) to add N elements when the command is called with multiple arguments.", "Adds one or more members to a set. Creates the key if it doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let members = parts[1..].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let set = storage.get_set_or_create(&k)?;
+        let added = members.into_iter().map(|m| set.insert(m)).filter(|&added| added).count();
+        Ok(added.to_resp())
+    }
+}
+
+
+struct SRem;
+
+impl Command for SRem {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("srem", -3)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@set"])
+            .docs("set", "1.0.0", "O(N) where N is the number of members to be removed.", "Removes one or more members from a set. Deletes the set if the last member was removed.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let members = parts[1..].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(set) = storage.get_set_mut(&k)? else {
+            return Ok(RespType::Int(0));
+        };
+        let removed = members.iter().filter(|m| set.remove(m)).count();
+        storage.remove_if_empty(&k);
+        Ok(removed.to_resp())
+    }
+}
+
+
+struct SMembers;
+
+impl Command for SMembers {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("smembers", 2)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@set"])
+            .docs("set", "1.0.0", "O(N) where N is the set cardinality.", "Returns all members of a set.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        Ok(set_reply(storage.get_set(&k)?.into_iter().flat_map(Set::iter)))
+    }
+}
+
+
+struct SIsMember;
+
+impl Command for SIsMember {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("sismember", 3)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@set"])
+            .docs("set", "1.0.0", "O(1)", "Determines whether a member belongs to a set.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let member = Vec::<u8>::from_resp(&parts[1])?;
+
+        let is_member = ctx.storage.lock().unwrap().get_set(&k)?.is_some_and(|set| set.contains(&member));
+        Ok(RespType::Int(is_member as isize))
+    }
+}
+
+
+struct SMIsMember;
+
+impl Command for SMIsMember {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("smismember", -3)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@set"])
+            .docs("set", "6.2.0", "O(N) where N is the number of elements being checked for membership", "Determines whether multiple members belong to a set.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let members = parts[1..].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let set = storage.get_set(&k)?;
+        let results = members.iter().map(|m| RespType::Int(set.is_some_and(|set| set.contains(m)) as isize));
+        Ok(RespType::Array(results.collect()))
+    }
+}
+
+
+struct SCard;
+
+impl Command for SCard {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("scard", 2)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@set"])
+            .docs("set", "1.0.0", "O(1)", "Returns the number of members in a set.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let len = ctx.storage.lock().unwrap().get_set(&k)?.map_or(0, Set::len);
+        Ok(len.to_resp())
+    }
+}
+
+
+struct SPop;
+
+impl Command for SPop {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("spop", -2)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@set"])
+            .docs("set", "1.0.0", "Without the count argument O(1), otherwise O(N) where N is the value of the passed count.", "Returns one or more random members from a set after removing them. Deletes the set if the last member was popped.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let count = match parts {
+            [_] => None,
+            [_, count] => match i64::from_resp(count)? {
+                n if n >= 0 => Some(n as usize),
+                _ => return Err(CommandErr::InvalidArgs("value is out of range, must be positive".to_string())),
+            },
+            _ => return Err(CommandErr::SyntaxError),
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(set) = storage.get_set_mut(&k)? else {
+            return Ok(if count.is_some() { RespType::Set(Vec::new()) } else { RespType::Null });
+        };
+        let mut rng = rand::thread_rng();
+        let reply = match count {
+            None => set.pop_random(&mut rng).to_resp(),
+            Some(count) => set_reply((0..count).map_while(|_| set.pop_random(&mut rng)).collect::<Vec<_>>()),
+        };
+        storage.remove_if_empty(&k);
+        Ok(reply)
+    }
+}
+
+
+/// `SRANDMEMBER key [count]`. A positive count returns distinct members, a negative one
+/// may return the same member several times.
+struct SRandMember;
+
+impl Command for SRandMember {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("srandmember", -2)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@set"])
+            .docs("set", "1.0.0", "Without the count argument O(1), otherwise O(N) where N is the absolute value of the passed count.", "Get one or multiple random members from a set")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let count = match parts {
+            [_] => None,
+            [_, count] => Some(i64::from_resp(count)?),
+            _ => return Err(CommandErr::SyntaxError),
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let set = storage.get_set(&k)?;
+        let mut rng = rand::thread_rng();
+        let Some(count) = count else {
+            return Ok(set.and_then(|set| set.random(&mut rng)).to_resp());
+        };
+        let Some(set) = set else {
+            return Ok(RespType::Array(Vec::new()));
+        };
+
+        let picked: Vec<Vec<u8>> = if count < 0 {
+            (0..count.unsigned_abs()).filter_map(|_| set.random(&mut rng)).collect()
+        } else if count as usize >= set.len() {
+            set.iter().collect()
+        } else {
+            let members: Vec<_> = set.iter().collect();
+            rand::seq::index::sample(&mut rng, members.len(), count as usize).into_iter().map(|i| members[i].clone()).collect()
+        };
+        Ok(picked.to_resp())
+    }
+}
+
+
+struct SMove;
+
+impl Command for SMove {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("smove", 4)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 2, 1)
+            .acl(&["@set"])
+            .docs("set", "1.0.0", "O(1)", "Moves a member from one set to another.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let src = Vec::<u8>::from_resp(&parts[0])?;
+        let dst = Vec::<u8>::from_resp(&parts[1])?;
+        let member = Vec::<u8>::from_resp(&parts[2])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        storage.get_set(&dst)?;
+        let Some(set) = storage.get_set_mut(&src)? else {
+            return Ok(RespType::Int(0));
+        };
+        if src == dst {
+            return Ok(RespType::Int(set.contains(&member) as isize));
+        }
+        if !set.remove(&member) {
+            return Ok(RespType::Int(0));
+        }
+        storage.remove_if_empty(&src);
+        storage.get_set_or_create(&dst)?.insert(member);
+        Ok(RespType::Int(1))
+    }
+}
+
+
+/// `SSCAN key cursor [MATCH pattern] [COUNT count]`.
+struct SScan;
+
+impl Command for SScan {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("sscan", -3)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@set"])
+            .docs("set", "2.8.0", "O(1) for every call. O(N) for a complete iteration, including enough command calls for the cursor to return back to 0. N is the number of elements inside the collection.", "Iterates over members of a set.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let args = ScanArgs::parse(&parts[1..], false)?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(set) = storage.get_set(&k)? else {
+            return Ok(ScanArgs::reply(0, Vec::new()));
+        };
+
+        let mut found = Vec::new();
+        let next = set.scan(args.cursor, args.count, |m| {
+            if args.matches(m) {
+                found.push(m.to_resp());
+            }
+        });
+        Ok(ScanArgs::reply(next, found))
+    }
+}
+
+
+/// `SINTER`, `SUNION` and `SDIFF`, and their `*STORE` variants which store the result
+/// in a destination key instead of replying with it.
+struct SetOpCmd {
+    op: SetOp,
+    store: bool,
+}
+
+impl Command for SetOpCmd {
+    fn spec(&self) -> CommandSpec {
+        let spec = match (self.op, self.store) {
+            (SetOp::Inter, false) => CommandSpec::new("sinter", -2)
+                .docs("set", "1.0.0", "O(N*M) worst case where N is the cardinality of the smallest set and M is the number of sets.", "Returns the intersect of multiple sets."),
+            (SetOp::Union, false) => CommandSpec::new("sunion", -2)
+                .docs("set", "1.0.0", "O(N) where N is the total number of elements in all given sets.", "Returns the union of multiple sets."),
+            (SetOp::Diff, false) => CommandSpec::new("sdiff", -2)
+                .docs("set", "1.0.0", "O(N) where N is the total number of elements in all given sets.", "Returns the difference of multiple sets."),
+            (SetOp::Inter, true) => CommandSpec::new("sinterstore", -3)
+                .docs("set", "1.0.0", "O(N*M) worst case where N is the cardinality of the smallest set and M is the number of sets.", "Stores the intersect of multiple sets in a key."),
+            (SetOp::Union, true) => CommandSpec::new("sunionstore", -3)
+                .docs("set", "1.0.0", "O(N) where N is the total number of elements in all given sets.", "Stores the union of multiple sets in a key."),
+            (SetOp::Diff, true) => CommandSpec::new("sdiffstore", -3)
+                .docs("set", "1.0.0", "O(N) where N is the total number of elements in all given sets.", "Stores the difference of multiple sets in a key."),
+        };
+        if self.store {
+            spec.flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+                .keys(1, -1, 1)
+                .acl(&["@set"])
+        } else {
+            spec.flags(&[CommandFlag::Readonly])
+                .keys(1, -1, 1)
+                .acl(&["@set"])
+        }
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let keys = parts.iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+        let (dst, keys) = if self.store { (Some(&keys[0]), &keys[1..]) } else { (None, &keys[..]) };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let sets = load_sets(&mut storage, keys)?;
+        let members = combine(&sets, self.op, usize::MAX);
+
+        let Some(dst) = dst else {
+            return Ok(set_reply(members));
+        };
+        // the destination is overwritten whatever it held, and deleted for an empty result
+        storage.del(dst);
+        let len = members.len();
+        if len > 0 {
+            let set = storage.get_set_or_create(dst)?;
+            for m in members {
+                set.insert(m);
+            }
+        }
+        Ok(len.to_resp())
+    }
+}
+
+
+/// `SINTERCARD numkeys key [key ...] [LIMIT limit]`.
+struct SInterCard;
+
+impl Command for SInterCard {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("sintercard", -3)
+            .flags(&[CommandFlag::Readonly, CommandFlag::MovableKeys])
+            .acl(&["@set"])
+            .docs("set", "7.0.0", "O(N*M) worst case where N is the cardinality of the smallest set and M is the number of sets.", "Returns the number of members of the intersect of multiple sets.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let (keys, opts) = parse_numkeys(parts)?;
+        let limit = match opts {
+            [] => 0,
+            [opt, limit] if String::from_resp(opt)?.eq_ignore_ascii_case("LIMIT") => match i64::from_resp(limit)? {
+                n if n >= 0 => n as usize,
+                _ => return Err(CommandErr::InvalidArgs("LIMIT can't be negative".to_string())),
+            },
+            _ => return Err(CommandErr::SyntaxError),
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let sets = load_sets(&mut storage, &keys)?;
+        // a limit of 0 means no limit
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        Ok(combine(&sets, SetOp::Inter, limit).len().to_resp())
+    }
+
+    fn key_positions(&self, _spec: &CommandSpec, argv: &[RespType]) -> Vec<usize> {
+        numkeys_positions(argv, 1)
+    }
+}
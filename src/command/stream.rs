@@ -0,0 +1,1031 @@
+use std::time::{Duration, Instant};
+
+use crate::{now_ms, Fields, FromResp, Protocol, RespType, StreamEntry, StreamId, ToResp, Trim};
+
+use super::{echoed, Command, CommandContext, CommandErr, CommandFlag, CommandSpec};
+
+pub(super) fn commands() -> Vec<Box<dyn Command>> {
+    vec![
+        Box::new(XAdd),
+        Box::new(XLen),
+        Box::new(XRange { rev: false }),
+        Box::new(XRange { rev: true }),
+        Box::new(XDel),
+        Box::new(XTrim),
+        Box::new(XRead { group: false }),
+        Box::new(XRead { group: true }),
+        Box::new(XGroup),
+        Box::new(XAck),
+        Box::new(XPending),
+        Box::new(XClaim),
+        Box::new(XAutoClaim),
+        Box::new(XInfo),
+    ]
+}
+
+fn invalid_id() -> CommandErr {
+    CommandErr::InvalidArgs("Invalid stream ID specified as stream command argument".to_string())
+}
+
+fn parse_u64(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+/// Parses an ID written `ms-seq`, or just `ms` in which case the sequence number is `missing_seq`.
+pub(super) fn parse_id(arg: &RespType, missing_seq: u64) -> Result<StreamId, CommandErr> {
+    let bytes = arg.as_bytes().ok_or_else(invalid_id)?;
+    let id = match bytes.iter().position(|&b| b == b'-') {
+        Some(dash) => parse_u64(&bytes[..dash]).zip(parse_u64(&bytes[dash + 1..])),
+        None => parse_u64(bytes).map(|ms| (ms, missing_seq)),
+    };
+    id.map(|(ms, seq)| StreamId::new(ms, seq)).ok_or_else(invalid_id)
+}
+
+/// Parses the start of an `XRANGE`-style interval: `-`, an ID, or an ID prefixed with `(`
+/// to leave it out.
+fn parse_range_start(arg: &RespType) -> Result<StreamId, CommandErr> {
+    match arg.as_bytes() {
+        Some(b"-") => Ok(StreamId::MIN),
+        Some([b'(', id @ ..]) => parse_id(&RespType::BString(id.to_vec()), 0)?
+            .next()
+            .ok_or_else(|| CommandErr::InvalidArgs("invalid start ID for the interval".to_string())),
+        _ => parse_id(arg, 0),
+    }
+}
+
+/// Parses the end of an `XRANGE`-style interval, where an ID without a sequence number
+/// includes the whole millisecond.
+fn parse_range_end(arg: &RespType) -> Result<StreamId, CommandErr> {
+    match arg.as_bytes() {
+        Some(b"+") => Ok(StreamId::MAX),
+        Some([b'(', id @ ..]) => parse_id(&RespType::BString(id.to_vec()), u64::MAX)?
+            .prev()
+            .ok_or_else(|| CommandErr::InvalidArgs("invalid end ID for the interval".to_string())),
+        _ => parse_id(arg, u64::MAX),
+    }
+}
+
+pub(super) fn id_reply(id: StreamId) -> RespType {
+    id.to_string().to_resp()
+}
+
+/// An entry as its ID followed by a flat list of its fields and values.
+pub(super) fn entry_reply(entry: StreamEntry) -> RespType {
+    let fields = entry.fields.into_iter().flat_map(|(f, v)| [f.to_resp(), v.to_resp()]).collect();
+    RespType::Array(vec![id_reply(entry.id), RespType::Array(fields)])
+}
+
+/// The trimming options of `XADD` and `XTRIM`: `MAXLEN | MINID [= | ~] threshold [LIMIT count]`.
+struct TrimArgs {
+    trim: Trim,
+    approx: bool,
+    limit: Option<usize>,
+}
+
+impl TrimArgs {
+    /// Parses the options at `parts[*i]`, which is `MAXLEN` or `MINID`, and moves `i` past them.
+    fn parse(parts: &[RespType], i: &mut usize) -> Result<Self, CommandErr> {
+        let strategy = String::from_resp(&parts[*i])?.to_ascii_uppercase();
+        *i += 1;
+        let approx = match parts.get(*i).and_then(RespType::as_bytes) {
+            Some(op @ (b"~" | b"=")) => {
+                *i += 1;
+                op == b"~"
+            }
+            _ => false,
+        };
+        let threshold = parts.get(*i).ok_or(CommandErr::SyntaxError)?;
+        let trim = match strategy.as_str() {
+            "MAXLEN" => match i64::from_resp(threshold)? {
+                n if n >= 0 => Trim::MaxLen(n as usize),
+                _ => return Err(CommandErr::InvalidArgs("The MAXLEN argument must be >= 0.".to_string())),
+            },
+            _ => Trim::MinId(parse_id(threshold, 0)?),
+        };
+        *i += 1;
+
+        let mut limit = None;
+        if parts.get(*i).and_then(RespType::as_bytes).is_some_and(|opt| opt.eq_ignore_ascii_case(b"LIMIT")) {
+            let count = parts.get(*i + 1).ok_or(CommandErr::SyntaxError)?;
+            limit = match i64::from_resp(count)? {
+                n if n >= 0 => Some(n as usize),
+                _ => return Err(CommandErr::InvalidArgs("The LIMIT argument must be >= 0.".to_string())),
+            };
+            if !approx {
+                return Err(CommandErr::InvalidArgs("syntax error, LIMIT cannot be used without the special ~ option".to_string()));
+            }
+            *i += 2;
+        }
+        Ok(Self { trim, approx, limit })
+    }
+}
+
+/// The ID `XADD` is given: `*`, `ms-*` or a whole ID.
+enum NewId {
+    Auto,
+    AutoSeq(u64),
+    Given(StreamId),
+}
+
+
+/// `XADD key [NOMKSTREAM] [MAXLEN | MINID [= | ~] threshold [LIMIT count]] * | id field value [field value ...]`.
+struct XAdd;
+
+impl Command for XAdd {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("xadd", -5)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@stream"])
+            .docs("stream", "5.0.0", "O(1) when adding a new entry, O(N) when trimming where N being the number of entries evicted.", "Appends a new message to a stream. Creates the key if it doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+
+        let (mut nomkstream, mut trim) = (false, None);
+        let mut i = 1;
+        while let Some(opt) = parts.get(i).and_then(RespType::as_bytes) {
+            if opt.eq_ignore_ascii_case(b"NOMKSTREAM") {
+                nomkstream = true;
+                i += 1;
+            } else if opt.eq_ignore_ascii_case(b"MAXLEN") || opt.eq_ignore_ascii_case(b"MINID") {
+                if trim.is_some() {
+                    return Err(CommandErr::InvalidArgs("syntax error, MAXLEN and MINID options at the same time are not compatible".to_string()));
+                }
+                trim = Some(TrimArgs::parse(parts, &mut i)?);
+            } else {
+                break;
+            }
+        }
+
+        let Some((id, fields)) = parts.get(i..).and_then(|rest| rest.split_first()) else {
+            return Err(CommandErr::WrongArity("xadd".to_string()));
+        };
+        if fields.is_empty() || !fields.len().is_multiple_of(2) {
+            return Err(CommandErr::WrongArity("xadd".to_string()));
+        }
+        let id = match id.as_bytes() {
+            Some(b"*") => NewId::Auto,
+            Some(bytes) if bytes.ends_with(b"-*") => NewId::AutoSeq(parse_u64(&bytes[..bytes.len() - 2]).ok_or_else(invalid_id)?),
+            _ => match parse_id(id, 0)? {
+                StreamId::MIN => return Err(CommandErr::InvalidArgs("The ID specified in XADD must be greater than 0-0".to_string())),
+                id => NewId::Given(id),
+            },
+        };
+        let fields = fields
+            .chunks(2)
+            .map(|pair| Ok((Vec::<u8>::from_resp(&pair[0])?, Vec::<u8>::from_resp(&pair[1])?)))
+            .collect::<Result<Vec<_>, CommandErr>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        if storage.stream(&k)?.is_none() && nomkstream {
+            return Ok(RespType::Null);
+        }
+        let stream = storage.stream_or_create(&k)?;
+        let too_small = || CommandErr::InvalidArgs("The ID specified in XADD is equal or smaller than the target stream top item".to_string());
+        let id = match id {
+            NewId::Auto => stream
+                .next_id(now_ms())
+                .ok_or_else(|| CommandErr::InvalidArgs("The stream has exhausted the last possible ID, unable to add more items".to_string()))?,
+            NewId::AutoSeq(ms) => stream.next_id_at(ms).ok_or_else(too_small)?,
+            NewId::Given(id) => id,
+        };
+        if !stream.append(id, fields) {
+            // never the case for a stream that was just created, so none is left empty
+            return Err(too_small());
+        }
+        if let Some(TrimArgs { trim, approx, limit }) = trim {
+            stream.trim(trim, approx, limit);
+        }
+        Ok(id_reply(id))
+    }
+}
+
+
+struct XLen;
+
+impl Command for XLen {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("xlen", 2)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@stream"])
+            .docs("stream", "5.0.0", "O(1)", "Return the number of messages in a stream.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let len = ctx.storage.lock().unwrap().stream(&k)?.map_or(0, |stream| stream.len());
+        Ok(len.to_resp())
+    }
+}
+
+
+/// `XRANGE key start end [COUNT count]`, and `XREVRANGE key end start [COUNT count]`
+/// which returns the entries from last to first.
+struct XRange {
+    rev: bool,
+}
+
+impl Command for XRange {
+    fn spec(&self) -> CommandSpec {
+        if self.rev {
+            CommandSpec::new("xrevrange", -4)
+                .docs("stream", "5.0.0", "O(N) with N being the number of elements returned. If N is constant (e.g. always asking for the first 10 elements with COUNT), you can consider it O(1).", "Returns the messages from a stream within a range of IDs in reverse order.")
+        } else {
+            CommandSpec::new("xrange", -4)
+                .docs("stream", "5.0.0", "O(N) with N being the number of elements being returned. If N is constant (e.g. always asking for the first 10 elements with COUNT), you can consider it O(1).", "Returns the messages from a stream within a range of IDs.")
+        }
+        .flags(&[CommandFlag::Readonly])
+        .keys(1, 1, 1)
+        .acl(&["@stream"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let (start, end) = if self.rev { (&parts[2], &parts[1]) } else { (&parts[1], &parts[2]) };
+        let (start, end) = (parse_range_start(start)?, parse_range_end(end)?);
+        let count = match &parts[3..] {
+            [] => None,
+            // a negative count is taken as 0, which replies with a null array like in redis
+            [opt, count] if String::from_resp(opt)?.eq_ignore_ascii_case("COUNT") => Some(i64::from_resp(count)?.max(0) as usize),
+            _ => return Err(CommandErr::SyntaxError),
+        };
+        if count == Some(0) {
+            return Ok(RespType::NullArray);
+        }
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(stream) = storage.stream(&k)? else {
+            return Ok(RespType::Array(Vec::new()));
+        };
+        let count = count.unwrap_or(usize::MAX);
+        let entries: Vec<RespType> = if self.rev {
+            stream.range(start, end).rev().take(count).map(entry_reply).collect()
+        } else {
+            stream.range(start, end).take(count).map(entry_reply).collect()
+        };
+        Ok(RespType::Array(entries))
+    }
+}
+
+
+struct XDel;
+
+impl Command for XDel {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("xdel", -3)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@stream"])
+            .docs("stream", "5.0.0", "O(1) for each single item to delete in the stream, regardless of the stream size.", "Returns the number of messages after removing them from a stream.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let ids = parts[1..].iter().map(|id| parse_id(id, 0)).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(stream) = storage.stream_mut(&k)? else {
+            return Ok(RespType::Int(0));
+        };
+        Ok(ids.into_iter().filter(|&id| stream.remove(id)).count().to_resp())
+    }
+}
+
+
+/// `XTRIM key MAXLEN | MINID [= | ~] threshold [LIMIT count]`.
+struct XTrim;
+
+impl Command for XTrim {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("xtrim", -4)
+            .flags(&[CommandFlag::Write])
+            .keys(1, 1, 1)
+            .acl(&["@stream"])
+            .docs("stream", "5.0.0", "O(N), with N being the number of evicted entries. Constant times are very small however, since entries are organized in macro nodes containing multiple entries that can be released with a single deallocation.", "Deletes messages from the beginning of a stream.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let strategy = String::from_resp(&parts[1])?.to_ascii_uppercase();
+        if strategy != "MAXLEN" && strategy != "MINID" {
+            return Err(CommandErr::SyntaxError);
+        }
+        let mut i = 1;
+        let TrimArgs { trim, approx, limit } = TrimArgs::parse(parts, &mut i)?;
+        if i != parts.len() {
+            return Err(CommandErr::SyntaxError);
+        }
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(stream) = storage.stream_mut(&k)? else {
+            return Ok(RespType::Int(0));
+        };
+        Ok(stream.trim(trim, approx, limit).to_resp())
+    }
+}
+
+
+/// `XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]`, and
+/// `XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK] STREAMS key [key ...] id [id ...]`
+/// which reads on behalf of a consumer of a group.
+///
+/// `$` as an `XREAD` ID stands for the last ID of the stream, so only new entries are read.
+/// `>` as an `XREADGROUP` ID reads the entries not delivered to the group yet, while any other
+/// ID reads again the consumer's pending entries after it.
+struct XRead {
+    group: bool,
+}
+
+impl Command for XRead {
+    fn spec(&self) -> CommandSpec {
+        if self.group {
+            CommandSpec::new("xreadgroup", -7)
+                .flags(&[CommandFlag::Write, CommandFlag::Blocking, CommandFlag::MovableKeys])
+                .docs("stream", "5.0.0", "For each stream mentioned: O(M) with M being the number of elements returned. If M is constant (e.g. always asking for the first 10 elements with COUNT), you can consider it O(1). On the other side when XREADGROUP blocks, XADD will pay the O(N) time in order to serve the N clients blocked on the stream getting new data.", "Returns new or historical messages from a stream for a consumer in a group. Blocks until a message is available otherwise.")
+        } else {
+            CommandSpec::new("xread", -4)
+                .flags(&[CommandFlag::Readonly, CommandFlag::Blocking, CommandFlag::MovableKeys])
+                .docs("stream", "5.0.0", "For each stream mentioned: O(N) with N being the number of elements being returned, it means that XREAD-ing with a fixed COUNT is O(1). Note that when the BLOCK option is used, XADD will pay O(M) time in order to serve the M clients blocked on the stream getting new data.", "Returns messages from multiple streams with IDs greater than the ones requested. Blocks until a message is available otherwise.")
+        }
+        .acl(&["@stream"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let (mut count, mut block, mut noack, mut group, mut streams_at) = (usize::MAX, None, false, None, None);
+        let mut i = 0;
+        while i < parts.len() {
+            let opt = String::from_resp(&parts[i])?.to_ascii_uppercase();
+            match opt.as_str() {
+                "STREAMS" => {
+                    streams_at = Some(i + 1);
+                    break;
+                }
+                "COUNT" if i + 1 < parts.len() => {
+                    // like in redis, a count of 0 or less means no limit
+                    count = match i64::from_resp(&parts[i + 1])? {
+                        n if n > 0 => n as usize,
+                        _ => usize::MAX,
+                    };
+                    i += 2;
+                }
+                "BLOCK" if i + 1 < parts.len() => {
+                    block = match i64::from_resp(&parts[i + 1])? {
+                        ms if ms < 0 => return Err(CommandErr::InvalidArgs("timeout is negative".to_string())),
+                        0 => Some(None),
+                        ms => Some(Some(Instant::now() + Duration::from_millis(ms as u64))),
+                    };
+                    i += 2;
+                }
+                "GROUP" if i + 2 < parts.len() => {
+                    if !self.group {
+                        return Err(CommandErr::InvalidArgs("The GROUP option is only supported by XREADGROUP. You called XREAD instead.".to_string()));
+                    }
+                    group = Some((Vec::<u8>::from_resp(&parts[i + 1])?, Vec::<u8>::from_resp(&parts[i + 2])?));
+                    i += 3;
+                }
+                "NOACK" if self.group => {
+                    noack = true;
+                    i += 1;
+                }
+                _ => return Err(CommandErr::SyntaxError),
+            }
+        }
+        let Some(streams_at) = streams_at else {
+            return Err(CommandErr::SyntaxError);
+        };
+        let streams = &parts[streams_at..];
+        if streams.is_empty() || !streams.len().is_multiple_of(2) {
+            let (name, id) = if self.group { ("xreadgroup", ">") } else { ("xread", "$") };
+            return Err(CommandErr::InvalidArgs(format!("Unbalanced '{}' list of streams: for each stream key an ID or '{}' must be specified.", name, id)));
+        }
+        if self.group && group.is_none() {
+            return Err(CommandErr::InvalidArgs("Missing GROUP option for XREADGROUP".to_string()));
+        }
+        let (keys, ids) = streams.split_at(streams.len() / 2);
+        let keys = keys.iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        // the ID to read after, or none to read what the group wasn't delivered yet
+        let mut after = Vec::with_capacity(ids.len());
+        for (k, id) in keys.iter().zip(ids) {
+            let stream = storage.stream(k)?;
+            if let Some((g, _)) = &group {
+                if stream.and_then(|stream| stream.group(g)).is_none() {
+                    return Err(CommandErr::NoGroup(format!(
+                        "No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                        String::from_utf8_lossy(k),
+                        String::from_utf8_lossy(g)
+                    )));
+                }
+            }
+            after.push(match id.as_bytes() {
+                Some(b"$") if self.group => return Err(CommandErr::InvalidArgs("The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set.".to_string())),
+                Some(b"$") => Some(stream.map_or(StreamId::MIN, |stream| stream.last_id())),
+                Some(b">") if self.group => None,
+                Some(b">") => return Err(CommandErr::InvalidArgs("The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option.".to_string())),
+                _ => Some(parse_id(id, 0)?),
+            });
+        }
+
+        let now = now_ms();
+        let mut found = Vec::new();
+        for (k, &after) in keys.iter().zip(&after) {
+            let Some(stream) = storage.stream_mut(k)? else {
+                continue;
+            };
+            let entries: Vec<RespType> = match (&group, after) {
+                (Some((g, consumer)), None) => {
+                    let entries = stream.read_group(g, consumer, count, noack, now).unwrap_or_default();
+                    entries.into_iter().map(entry_reply).collect()
+                }
+                (Some((g, consumer)), Some(id)) => {
+                    // the consumer's history is replied even when there's none
+                    let entries = stream.read_pending(g, consumer, id, count, now).unwrap_or_default();
+                    found.push((k.to_resp(), RespType::Array(entries.into_iter().map(pending_reply).collect())));
+                    continue;
+                }
+                (None, Some(id)) => match id.next() {
+                    Some(start) => stream.range(start, StreamId::MAX).take(count).map(entry_reply).collect(),
+                    None => Vec::new(),
+                },
+                (None, None) => Vec::new(),
+            };
+            if !entries.is_empty() {
+                found.push((k.to_resp(), RespType::Array(entries)));
+            }
+        }
+        drop(storage);
+
+        if !found.is_empty() {
+            return Ok(match ctx.client.protocol {
+                Protocol::Resp3 => RespType::Map(found),
+                _ => RespType::Array(found.into_iter().map(|(k, entries)| RespType::Array(vec![k, entries])).collect()),
+            });
+        }
+        if let Some(deadline) = block {
+            if self.group {
+                ctx.block(keys, deadline);
+            } else {
+                // run again with the IDs `$` stood for now, rather than whatever is last then
+                let mut command = vec!["XREAD".to_resp()];
+                command.extend_from_slice(&parts[..streams_at]);
+                command.extend(keys.iter().map(|k| k.to_resp()));
+                command.extend(after.iter().flatten().map(|&id| id_reply(id)));
+                ctx.block_rerunning(keys, deadline, RespType::Array(command));
+            }
+        }
+        Ok(RespType::NullArray)
+    }
+
+    fn key_positions(&self, _spec: &CommandSpec, argv: &[RespType]) -> Vec<usize> {
+        let Some(at) = argv.iter().position(|arg| arg.as_bytes().is_some_and(|a| a.eq_ignore_ascii_case(b"STREAMS"))) else {
+            return Vec::new();
+        };
+        (at + 1..at + 1 + (argv.len() - at - 1) / 2).collect()
+    }
+}
+
+
+/// `XGROUP CREATE | SETID | DESTROY | CREATECONSUMER | DELCONSUMER key group ...`, which
+/// manages the consumer groups of a stream.
+struct XGroup;
+
+impl Command for XGroup {
+    fn spec(&self) -> CommandSpec {
+        let sub = |name, arity, flags, since, complexity, summary| {
+            CommandSpec::new(name, arity)
+                .flags(flags)
+                .keys(2, 2, 1)
+                .acl(&["@stream"])
+                .docs("stream", since, complexity, summary)
+        };
+        CommandSpec::new("xgroup", -2)
+            .flags(&[CommandFlag::Write])
+            .keys(2, 2, 1)
+            .acl(&["@stream"])
+            .docs("stream", "5.0.0", "Depends on subcommand.", "A container for consumer groups commands.")
+            .subcommand(sub("xgroup|create", -5, &[CommandFlag::Write, CommandFlag::DenyOom], "5.0.0", "O(1)", "Creates a consumer group."))
+            .subcommand(sub("xgroup|setid", -5, &[CommandFlag::Write], "5.0.0", "O(1)", "Sets the last-delivered ID of a consumer group."))
+            .subcommand(sub("xgroup|destroy", 4, &[CommandFlag::Write], "5.0.0", "O(N) where N is the number of entries in the group's pending entries list (PEL).", "Destroys a consumer group."))
+            .subcommand(sub("xgroup|createconsumer", 5, &[CommandFlag::Write, CommandFlag::DenyOom], "6.2.0", "O(1)", "Creates a consumer in a consumer group."))
+            .subcommand(sub("xgroup|delconsumer", 5, &[CommandFlag::Write], "5.0.0", "O(1)", "Deletes a consumer from a consumer group."))
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let sub = subcommand(self.spec(), parts)?;
+        let k = Vec::<u8>::from_resp(&parts[1])?;
+        let g = Vec::<u8>::from_resp(&parts[2])?;
+
+        // CREATE and SETID take `id | $ [ENTRIESREAD entries-read]`, and CREATE also MKSTREAM
+        let (mut mkstream, mut entries_read) = (false, None);
+        if sub == "CREATE" || sub == "SETID" {
+            let mut i = 4;
+            while i < parts.len() {
+                let opt = String::from_resp(&parts[i])?.to_ascii_uppercase();
+                match opt.as_str() {
+                    "MKSTREAM" if sub == "CREATE" => {
+                        mkstream = true;
+                        i += 1;
+                    }
+                    "ENTRIESREAD" if i + 1 < parts.len() => {
+                        entries_read = match i64::from_resp(&parts[i + 1])? {
+                            -1 => None,
+                            n if n >= 0 => Some(n as u64),
+                            _ => return Err(CommandErr::InvalidArgs("value for ENTRIESREAD must be positive or -1".to_string())),
+                        };
+                        i += 2;
+                    }
+                    _ => return Err(CommandErr::SyntaxError),
+                }
+            }
+        }
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let stream = match storage.stream_mut(&k)? {
+            Some(stream) => stream,
+            None if mkstream => storage.stream_or_create(&k)?,
+            None => return Err(CommandErr::InvalidArgs("The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.".to_string())),
+        };
+        let no_group = || {
+            CommandErr::NoGroup(format!("No such consumer group '{}' for key name '{}'", String::from_utf8_lossy(&g), String::from_utf8_lossy(&k)))
+        };
+        let last_id = match parts.get(3).and_then(RespType::as_bytes) {
+            Some(b"$") => stream.last_id(),
+            _ => StreamId::MIN,
+        };
+        let id = |arg: &RespType| match arg.as_bytes() {
+            Some(b"$") => Ok(last_id),
+            _ => parse_id(arg, 0),
+        };
+
+        match sub.as_str() {
+            "CREATE" => {
+                let id = id(&parts[3])?;
+                if !stream.create_group(&g, id, entries_read) {
+                    return Err(CommandErr::BusyGroup);
+                }
+                Ok(RespType::String("OK".to_string()))
+            }
+            "SETID" => {
+                let id = id(&parts[3])?;
+                let group = stream.group_mut(&g).ok_or_else(no_group)?;
+                group.last_id = id;
+                group.entries_read = entries_read;
+                Ok(RespType::String("OK".to_string()))
+            }
+            "DESTROY" => Ok(RespType::Int(stream.destroy_group(&g) as isize)),
+            "CREATECONSUMER" => {
+                let consumer = Vec::<u8>::from_resp(&parts[3])?;
+                let group = stream.group_mut(&g).ok_or_else(no_group)?;
+                Ok(RespType::Int(group.create_consumer(&consumer, now_ms()) as isize))
+            }
+            _ => {
+                let consumer = Vec::<u8>::from_resp(&parts[3])?;
+                let group = stream.group_mut(&g).ok_or_else(no_group)?;
+                Ok(group.delete_consumer(&consumer).unwrap_or(0).to_resp())
+            }
+        }
+    }
+}
+
+
+/// `XACK key group id [id ...]`.
+struct XAck;
+
+impl Command for XAck {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("xack", -4)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@stream"])
+            .docs("stream", "5.0.0", "O(1) for each message ID processed.", "Returns the number of messages that were successfully acknowledged by the consumer group member of a stream.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let g = Vec::<u8>::from_resp(&parts[1])?;
+        let ids = parts[2..].iter().map(|id| parse_id(id, 0)).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(group) = storage.stream_mut(&k)?.and_then(|stream| stream.group_mut(&g)) else {
+            return Ok(RespType::Int(0));
+        };
+        Ok(ids.into_iter().filter(|&id| group.ack(id)).count().to_resp())
+    }
+}
+
+
+/// `XPENDING key group [[IDLE min-idle-time] start end count [consumer]]`: a summary of the
+/// group's pending entries, or with a range, the entries themselves.
+struct XPending;
+
+impl Command for XPending {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("xpending", -3)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@stream"])
+            .docs("stream", "5.0.0", "O(N) with N being the number of elements returned, so asking for a small fixed number of entries per call is O(1). O(M), where M is the total number of entries scanned when used with the IDLE filter. When the command returns just the summary and the list of consumers is small, it runs in O(1) time; otherwise, an additional O(N) time for iterating every consumer.", "Returns the information and entries from a stream consumer group's pending entries list.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let g = Vec::<u8>::from_resp(&parts[1])?;
+        let (mut min_idle, mut i) = (0, 2);
+        if parts.len() > 3 && String::from_resp(&parts[2])?.eq_ignore_ascii_case("IDLE") {
+            min_idle = i64::from_resp(&parts[3])?.max(0) as u64;
+            i = 4;
+        }
+        let range = match &parts[i..] {
+            [] if i == 2 => None,
+            [start, end, count, consumer @ ..] if consumer.len() <= 1 => Some((
+                parse_range_start(start)?,
+                parse_range_end(end)?,
+                i64::from_resp(count)?.max(0) as usize,
+                consumer.first().map(Vec::<u8>::from_resp).transpose()?,
+            )),
+            _ => return Err(CommandErr::SyntaxError),
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(group) = storage.stream(&k)?.and_then(|stream| stream.group(&g)) else {
+            return Err(no_such_key_or_group(&k, &g));
+        };
+
+        let Some((start, end, count, consumer)) = range else {
+            let (Some((first, _)), Some((last, _))) = (group.pending.first_key_value(), group.pending.last_key_value()) else {
+                return Ok(RespType::Array(vec![RespType::Int(0), RespType::Null, RespType::Null, RespType::NullArray]));
+            };
+            let consumers = group
+                .consumers
+                .iter()
+                .filter(|(_, consumer)| !consumer.pending.is_empty())
+                .map(|(name, consumer)| RespType::Array(vec![name.to_resp(), consumer.pending.len().to_string().to_resp()]))
+                .collect();
+            return Ok(RespType::Array(vec![group.pending.len().to_resp(), id_reply(*first), id_reply(*last), RespType::Array(consumers)]));
+        };
+
+        if start > end {
+            return Ok(RespType::Array(Vec::new()));
+        }
+        let ids: Box<dyn Iterator<Item = &StreamId>> = match &consumer {
+            Some(name) => match group.consumers.get(name) {
+                Some(consumer) => Box::new(consumer.pending.range(start..=end)),
+                None => return Ok(RespType::Array(Vec::new())),
+            },
+            None => Box::new(group.pending.range(start..=end).map(|(id, _)| id)),
+        };
+        let now = now_ms();
+        let entries = ids
+            .filter_map(|id| Some((id, group.pending.get(id)?)))
+            .map(|(&id, pending)| (id, pending, now.saturating_sub(pending.delivered_at)))
+            .filter(|&(_, _, idle)| idle >= min_idle)
+            .take(count)
+            .map(|(id, pending, idle)| {
+                RespType::Array(vec![id_reply(id), pending.consumer.to_resp(), idle.to_resp(), pending.delivery_count.to_resp()])
+            })
+            .collect();
+        Ok(RespType::Array(entries))
+    }
+}
+
+
+/// `XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-milliseconds] [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID lastid]`.
+struct XClaim;
+
+impl Command for XClaim {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("xclaim", -6)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@stream"])
+            .docs("stream", "5.0.0", "O(log N) with N being the number of messages in the PEL of the consumer group.", "Changes, or acquires, ownership of a message in a consumer group, as if the message was delivered a consumer group member.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let g = Vec::<u8>::from_resp(&parts[1])?;
+        let consumer = Vec::<u8>::from_resp(&parts[2])?;
+        let min_idle = parse_min_idle(&parts[3], "XCLAIM")?;
+        let mut i = 4;
+        let mut ids = Vec::new();
+        while let Some(id) = parts.get(i).and_then(|id| parse_id(id, 0).ok()) {
+            ids.push(id);
+            i += 1;
+        }
+        if ids.is_empty() {
+            return Err(invalid_id());
+        }
+
+        let now = now_ms();
+        let (mut delivered_at, mut retry_count, mut force, mut justid, mut last_id) = (now, None, false, false, None);
+        while i < parts.len() {
+            let opt = String::from_resp(&parts[i])?;
+            let invalid = |name: &str| CommandErr::InvalidArgs(format!("Invalid {} option argument for XCLAIM", name));
+            let arg = parts.get(i + 1);
+            match opt.to_ascii_uppercase().as_str() {
+                "FORCE" => force = true,
+                "JUSTID" => justid = true,
+                "IDLE" if arg.is_some() => {
+                    let idle = arg.and_then(|arg| i64::from_resp(arg).ok()).ok_or_else(|| invalid("IDLE"))?;
+                    delivered_at = (now as i64).saturating_sub(idle).clamp(0, now as i64) as u64;
+                    i += 1;
+                }
+                "TIME" if arg.is_some() => {
+                    let time = arg.and_then(|arg| i64::from_resp(arg).ok()).ok_or_else(|| invalid("TIME"))?;
+                    // a time in the future is taken as now, like in redis
+                    delivered_at = if (0..=now as i64).contains(&time) { time as u64 } else { now };
+                    i += 1;
+                }
+                "RETRYCOUNT" if arg.is_some() => {
+                    let count = arg.and_then(|arg| i64::from_resp(arg).ok()).filter(|&n| n >= 0).ok_or_else(|| invalid("RETRYCOUNT"))?;
+                    retry_count = Some(count as u64);
+                    i += 1;
+                }
+                "LASTID" if arg.is_some() => {
+                    last_id = Some(parse_id(&parts[i + 1], 0)?);
+                    i += 1;
+                }
+                _ => return Err(CommandErr::InvalidArgs(format!("Unrecognized XCLAIM option '{}'", opt))),
+            }
+            i += 1;
+        }
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(stream) = storage.stream_mut(&k)? else {
+            return Err(no_such_key_or_group(&k, &g));
+        };
+        let claimed = stream.with_group(&g, |stream, group| {
+            group.touch_consumer(&consumer, now);
+            let mut claimed = Vec::new();
+            for id in ids {
+                let exists = stream.get(id).is_some();
+                let idle = match group.pending.get(&id) {
+                    Some(pending) => now.saturating_sub(pending.delivered_at),
+                    // FORCE claims entries no consumer was delivered, as if they were just now
+                    None if force && exists => 0,
+                    None => continue,
+                };
+                if idle < min_idle {
+                    continue;
+                }
+                if !exists {
+                    // deleted from the stream since, so there's nothing left to process
+                    group.ack(id);
+                    continue;
+                }
+                let pending = group.assign(id, &consumer, delivered_at);
+                match retry_count {
+                    Some(count) => pending.delivery_count = count,
+                    None if !justid => pending.delivery_count += 1,
+                    None => {}
+                }
+                claimed.push(id);
+            }
+            if !claimed.is_empty() {
+                group.touch_consumer(&consumer, now).active_at = Some(now);
+            }
+            if let Some(last_id) = last_id.filter(|&id| id > group.last_id) {
+                group.last_id = last_id;
+            }
+            claimed
+        });
+        let Some(claimed) = claimed else {
+            return Err(no_such_key_or_group(&k, &g));
+        };
+
+        let replies = claimed
+            .into_iter()
+            .map(|id| match stream.get(id) {
+                Some(entry) if !justid => entry_reply(entry),
+                _ => id_reply(id),
+            })
+            .collect();
+        Ok(RespType::Array(replies))
+    }
+}
+
+
+/// `XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]`, which claims
+/// the pending entries idle long enough, scanning the PEL from `start`. Replies with the ID to
+/// carry on scanning from, or `0-0` when done, the claimed entries, and the IDs of the
+/// deleted entries it found and removed from the PEL.
+struct XAutoClaim;
+
+impl Command for XAutoClaim {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("xautoclaim", -6)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@stream"])
+            .docs("stream", "6.2.0", "O(1) if COUNT is small.", "Changes, or acquires, ownership of messages in a consumer group, as if the messages were delivered to as consumer group member.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let g = Vec::<u8>::from_resp(&parts[1])?;
+        let consumer = Vec::<u8>::from_resp(&parts[2])?;
+        let min_idle = parse_min_idle(&parts[3], "XAUTOCLAIM")?;
+        let start = parse_range_start(&parts[4])?;
+        let (mut count, mut justid) = (100, false);
+        let mut i = 5;
+        while i < parts.len() {
+            let opt = String::from_resp(&parts[i])?.to_ascii_uppercase();
+            match opt.as_str() {
+                "JUSTID" => justid = true,
+                "COUNT" if i + 1 < parts.len() => {
+                    count = match i64::from_resp(&parts[i + 1])? {
+                        n if n >= 1 && n <= i64::MAX / ATTEMPTS_PER_COUNT as i64 => n as usize,
+                        _ => return Err(CommandErr::InvalidArgs("COUNT must be > 0".to_string())),
+                    };
+                    i += 1;
+                }
+                _ => return Err(CommandErr::SyntaxError),
+            }
+            i += 1;
+        }
+
+        let now = now_ms();
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(stream) = storage.stream_mut(&k)? else {
+            return Err(no_such_key_or_group(&k, &g));
+        };
+        let scanned = stream.with_group(&g, |stream, group| {
+            group.touch_consumer(&consumer, now);
+            let (mut claimed, mut deleted) = (Vec::new(), Vec::new());
+            let mut attempts = count * ATTEMPTS_PER_COUNT;
+            let mut from = Some(start);
+            let next = loop {
+                let Some(id) = from.and_then(|from| group.pending.range(from..).next().map(|(&id, _)| id)) else {
+                    break StreamId::MIN;
+                };
+                if attempts == 0 || claimed.len() == count {
+                    break id;
+                }
+                attempts -= 1;
+                from = id.next();
+                if now.saturating_sub(group.pending[&id].delivered_at) < min_idle {
+                    continue;
+                }
+                if stream.get(id).is_none() {
+                    group.ack(id);
+                    deleted.push(id);
+                    continue;
+                }
+                let pending = group.assign(id, &consumer, now);
+                if !justid {
+                    pending.delivery_count += 1;
+                }
+                claimed.push(id);
+            };
+            if !claimed.is_empty() {
+                group.touch_consumer(&consumer, now).active_at = Some(now);
+            }
+            (next, claimed, deleted)
+        });
+        let Some((next, claimed, deleted)) = scanned else {
+            return Err(no_such_key_or_group(&k, &g));
+        };
+
+        let claimed = claimed
+            .into_iter()
+            .map(|id| match stream.get(id) {
+                Some(entry) if !justid => entry_reply(entry),
+                _ => id_reply(id),
+            })
+            .collect();
+        Ok(RespType::Array(vec![
+            id_reply(next),
+            RespType::Array(claimed),
+            RespType::Array(deleted.into_iter().map(id_reply).collect()),
+        ]))
+    }
+}
+
+/// How many PEL entries `XAUTOCLAIM` looks at, at most, for each entry it's asked to claim.
+const ATTEMPTS_PER_COUNT: usize = 10;
+
+
+/// `XINFO STREAM key | GROUPS key | CONSUMERS key group`.
+struct XInfo;
+
+impl Command for XInfo {
+    fn spec(&self) -> CommandSpec {
+        let sub = |name, arity, complexity, summary| {
+            CommandSpec::new(name, arity)
+                .flags(&[CommandFlag::Readonly])
+                .keys(2, 2, 1)
+                .acl(&["@stream"])
+                .docs("stream", "5.0.0", complexity, summary)
+        };
+        CommandSpec::new("xinfo", -2)
+            .flags(&[CommandFlag::Readonly])
+            .keys(2, 2, 1)
+            .acl(&["@stream"])
+            .docs("stream", "5.0.0", "Depends on subcommand.", "A container for stream introspection commands.")
+            .subcommand(sub("xinfo|consumers", 4, "O(1)", "Returns a list of the consumers in a consumer group."))
+            .subcommand(sub("xinfo|groups", 3, "O(1)", "Returns a list of the consumer groups of a stream."))
+            .subcommand(sub("xinfo|stream", 3, "O(1)", "Returns information about a stream."))
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let sub = subcommand(self.spec(), parts)?;
+        let k = Vec::<u8>::from_resp(&parts[1])?;
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(stream) = storage.stream(&k)? else {
+            return Err(CommandErr::InvalidArgs("no such key".to_string()));
+        };
+
+        let map = |fields: Vec<(&str, RespType)>| RespType::Map(fields.into_iter().map(|(name, value)| (name.to_resp(), value)).collect());
+        match sub.as_str() {
+            "STREAM" => Ok(map(vec![
+                ("length", stream.len().to_resp()),
+                // nodes hang off a B-tree rather than a radix tree, so both are approximated by
+                // how many nodes there are
+                ("radix-tree-keys", stream.node_count().to_resp()),
+                ("radix-tree-nodes", stream.node_count().to_resp()),
+                ("last-generated-id", id_reply(stream.last_id())),
+                ("max-deleted-entry-id", id_reply(stream.max_deleted_id())),
+                ("entries-added", stream.entries_added().to_resp()),
+                ("recorded-first-entry-id", id_reply(stream.first_entry().map_or(StreamId::MIN, |entry| entry.id))),
+                ("groups", stream.groups().count().to_resp()),
+                ("first-entry", stream.first_entry().map_or(RespType::Null, entry_reply)),
+                ("last-entry", stream.last_entry().map_or(RespType::Null, entry_reply)),
+            ])),
+            "GROUPS" => Ok(RespType::Array(
+                stream
+                    .groups()
+                    .map(|(name, group)| {
+                        map(vec![
+                            ("name", name.to_resp()),
+                            ("consumers", group.consumers.len().to_resp()),
+                            ("pending", group.pending.len().to_resp()),
+                            ("last-delivered-id", id_reply(group.last_id)),
+                            ("entries-read", group.entries_read.to_resp()),
+                            ("lag", stream.lag(group).to_resp()),
+                        ])
+                    })
+                    .collect(),
+            )),
+            _ => {
+                let g = Vec::<u8>::from_resp(&parts[2])?;
+                let group = stream.group(&g).ok_or_else(|| {
+                    CommandErr::NoGroup(format!("No such consumer group '{}' for key name '{}'", String::from_utf8_lossy(&g), String::from_utf8_lossy(&k)))
+                })?;
+                let now = now_ms();
+                Ok(RespType::Array(
+                    group
+                        .consumers
+                        .iter()
+                        .map(|(name, consumer)| {
+                            map(vec![
+                                ("name", name.to_resp()),
+                                ("pending", consumer.pending.len().to_resp()),
+                                ("idle", now.saturating_sub(consumer.seen_at).to_resp()),
+                                ("inactive", consumer.active_at.map_or(-1, |at| now.saturating_sub(at) as i64).to_resp()),
+                            ])
+                        })
+                        .collect(),
+                ))
+            }
+        }
+    }
+}
+
+/// The name of the subcommand `parts[0]` of a container command like `XGROUP`, uppercased,
+/// after checking the arguments against its spec.
+fn subcommand(container: CommandSpec, parts: &[RespType]) -> Result<String, CommandErr> {
+    let sub = String::from_resp(&parts[0])?;
+    let name = format!("{}|{}", container.name, sub.to_ascii_lowercase());
+    let Some(spec) = container.subcommands.iter().find(|spec| spec.name == name) else {
+        return Err(CommandErr::InvalidArgs(format!("unknown subcommand '{}'. Try {} HELP.", echoed(&sub), container.name.to_uppercase())));
+    };
+    if !spec.accepts_argc(parts.len() + 1) {
+        return Err(CommandErr::WrongArity(name));
+    }
+    Ok(sub.to_ascii_uppercase())
+}
+
+fn no_such_key_or_group(k: &[u8], g: &[u8]) -> CommandErr {
+    CommandErr::NoGroup(format!("No such key '{}' or consumer group '{}'", String::from_utf8_lossy(k), String::from_utf8_lossy(g)))
+}
+
+/// The minimum idle time of `XCLAIM` and `XAUTOCLAIM`, where a negative one is taken as 0.
+fn parse_min_idle(arg: &RespType, cmd: &str) -> Result<u64, CommandErr> {
+    let min_idle = i64::from_resp(arg).map_err(|_| CommandErr::InvalidArgs(format!("Invalid min-idle-time argument for {}", cmd)))?;
+    Ok(min_idle.max(0) as u64)
+}
+
+/// A pending entry delivered again, with a null in place of its fields if it was deleted.
+fn pending_reply((id, fields): (StreamId, Option<Fields>)) -> RespType {
+    match fields {
+        Some(fields) => entry_reply(StreamEntry { id, fields }),
+        None => RespType::Array(vec![id_reply(id), RespType::NullArray]),
+    }
+}
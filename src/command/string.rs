@@ -0,0 +1,706 @@
+use crate::storage::now_ms;
+use crate::{format_double, FromResp, RespType, ToResp};
+
+use super::{Command, CommandContext, CommandErr, CommandFlag, CommandSpec};
+
+pub(super) fn commands() -> Vec<Box<dyn Command>> {
+    vec![
+        Box::new(Set),
+        Box::new(Get),
+        Box::new(SetNx),
+        Box::new(SetEx { millis: false }),
+        Box::new(SetEx { millis: true }),
+        Box::new(GetSet),
+        Box::new(GetEx),
+        Box::new(GetDel),
+        Box::new(IncrBy { name: "incr" }),
+        Box::new(IncrBy { name: "decr" }),
+        Box::new(IncrBy { name: "incrby" }),
+        Box::new(IncrBy { name: "decrby" }),
+        Box::new(IncrByFloat),
+        Box::new(Append),
+        Box::new(StrLen),
+        Box::new(GetRange),
+        Box::new(SetRange),
+        Box::new(MGet),
+        Box::new(MSet { nx: false }),
+        Box::new(MSet { nx: true }),
+        Box::new(Lcs),
+    ]
+}
+
+/// Largest string a value may grow to, the same as redis' default `proto-max-bulk-len`.
+pub(super) const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// Parses a stored value as an integer as strictly as redis does: no sign other than a
+/// leading minus, no leading zeros and no surrounding spaces.
+pub(super) fn parse_i64(bytes: &[u8]) -> Option<i64> {
+    let digits = bytes.strip_prefix(b"-").unwrap_or(bytes);
+    match digits {
+        [b'1'..=b'9', ..] => std::str::from_utf8(bytes).ok()?.parse().ok(),
+        b"0" if digits.len() == bytes.len() => Some(0),
+        _ => None,
+    }
+}
+
+pub(super) fn parse_f64(bytes: &[u8]) -> Option<f64> {
+    let s = std::str::from_utf8(bytes).ok()?;
+    if s.starts_with(char::is_whitespace) || s.ends_with(char::is_whitespace) {
+        return None;
+    }
+    s.parse::<f64>().ok().filter(|f| !f.is_nan())
+}
+
+/// What a write does to the key's expiry.
+pub(super) enum Expiry {
+    /// Drop the expiry, which is what a plain write does.
+    Clear,
+    Keep,
+    At(u64),
+}
+
+/// Parses an `EX`/`PX`/`EXAT`/`PXAT` argument into a unix time in milliseconds. Unlike
+/// `EXPIRE`, these only take positive times.
+pub(super) fn parse_expire_at(cmd: &str, arg: &RespType, millis: bool, absolute: bool) -> Result<u64, CommandErr> {
+    let when = i64::from_resp(arg)?;
+    let invalid = || CommandErr::InvalidArgs(format!("invalid expire time in '{}' command", cmd));
+    if when <= 0 {
+        return Err(invalid());
+    }
+
+    let when_ms = if millis { Some(when) } else { when.checked_mul(1000) };
+    let at = if absolute {
+        when_ms
+    } else {
+        when_ms.and_then(|ms| ms.checked_add(now_ms() as i64))
+    };
+    at.map(|at| at as u64).ok_or_else(invalid)
+}
+
+
+struct Set;
+
+impl Command for Set {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("set", -3)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+            .keys(1, 1, 1)
+            .acl(&["@string"])
+            .docs("string", "1.0.0", "O(1)", "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let v = Vec::<u8>::from_resp(&parts[1])?;
+
+        let (mut nx, mut xx, mut get) = (false, false, false);
+        let mut expiry: Option<(String, Expiry)> = None;
+        let mut i = 2;
+        while i < parts.len() {
+            let opt = String::from_resp(&parts[i])?.to_ascii_uppercase();
+            // an option may be repeated, but not combined with one it contradicts
+            let conflicts = |expiry: &Option<(String, Expiry)>| matches!(expiry, Some((prev, _)) if *prev != opt);
+            match opt.as_str() {
+                "NX" if !xx => nx = true,
+                "XX" if !nx => xx = true,
+                "GET" => get = true,
+                "KEEPTTL" if !conflicts(&expiry) => expiry = Some((opt, Expiry::Keep)),
+                "EX" | "PX" | "EXAT" | "PXAT" if !conflicts(&expiry) && i + 1 < parts.len() => {
+                    let millis = opt.starts_with('P');
+                    let absolute = opt.ends_with("AT");
+                    let at = parse_expire_at("set", &parts[i + 1], millis, absolute)?;
+                    expiry = Some((opt, Expiry::At(at)));
+                    i += 1;
+                }
+                _ => return Err(CommandErr::SyntaxError),
+            }
+            i += 1;
+        }
+        let expiry = expiry.map_or(Expiry::Clear, |(_, expiry)| expiry);
+
+        // the checks and the write happen under one lock, so SET NX is a safe lock primitive
+        let mut storage = ctx.storage.lock().unwrap();
+        let old = if get { storage.get(&k)?.cloned() } else { None };
+        let exists = storage.exists(&k);
+        if (nx && exists) || (xx && !exists) {
+            return Ok(if get { old.to_resp() } else { RespType::Null });
+        }
+
+        match expiry {
+            Expiry::Keep => storage.set_keep_ttl(k.clone(), v),
+            _ => storage.set(k.clone(), v),
+        }
+        if let Expiry::At(at) = expiry {
+            storage.expire_at(&k, at);
+        }
+
+        Ok(if get { old.to_resp() } else { RespType::String("OK".to_string()) })
+    }
+}
+
+struct Get;
+
+impl Command for Get {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("get", 2)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@string"])
+            .docs("string", "1.0.0", "O(1)", "Returns the string value of a key.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+
+        let value = ctx.storage.lock().unwrap().get(&k)?.cloned();
+
+        Ok(value.to_resp())
+    }
+}
+
+
+struct SetNx;
+
+impl Command for SetNx {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("setnx", 3)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@string"])
+            .docs("string", "1.0.0", "O(1)", "Set the string value of a key only when the key doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let v = Vec::<u8>::from_resp(&parts[1])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        if storage.exists(&k) {
+            return Ok(RespType::Int(0));
+        }
+        storage.set(k, v);
+        Ok(RespType::Int(1))
+    }
+}
+
+
+/// `SETEX` and `PSETEX`.
+struct SetEx {
+    millis: bool,
+}
+
+impl Command for SetEx {
+    fn spec(&self) -> CommandSpec {
+        let spec = if self.millis {
+            CommandSpec::new("psetex", 4).docs("string", "2.6.0", "O(1)", "Sets both string value and expiration time in milliseconds of a key. The key is created if it doesn't exist.")
+        } else {
+            CommandSpec::new("setex", 4).docs("string", "2.0.0", "O(1)", "Sets the string value and expiration time of a key. Creates the key if it doesn't exist.")
+        };
+        spec.flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+            .keys(1, 1, 1)
+            .acl(&["@string"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let name = if self.millis { "psetex" } else { "setex" };
+        let at = parse_expire_at(name, &parts[1], self.millis, false)?;
+        let v = Vec::<u8>::from_resp(&parts[2])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        storage.set(k.clone(), v);
+        storage.expire_at(&k, at);
+        Ok(RespType::String("OK".to_string()))
+    }
+}
+
+
+struct GetSet;
+
+impl Command for GetSet {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("getset", 3)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@string"])
+            .docs("string", "1.0.0", "O(1)", "Returns the previous string value of a key after setting it to a new value.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let v = Vec::<u8>::from_resp(&parts[1])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let old = storage.get(&k)?.cloned();
+        storage.set(k, v);
+        Ok(old.to_resp())
+    }
+}
+
+
+struct GetEx;
+
+impl Command for GetEx {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("getex", -2)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@string"])
+            .docs("string", "6.2.0", "O(1)", "Returns the string value of a key after setting its expiration time.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+
+        // `Keep` leaves the expiry alone, `Clear` is PERSIST
+        let expiry = match &parts[1..] {
+            [] => Expiry::Keep,
+            [opt] if String::from_resp(opt)?.eq_ignore_ascii_case("PERSIST") => Expiry::Clear,
+            [opt, arg] => {
+                let opt = String::from_resp(opt)?.to_ascii_uppercase();
+                match opt.as_str() {
+                    "EX" | "PX" | "EXAT" | "PXAT" => {
+                        Expiry::At(parse_expire_at("getex", arg, opt.starts_with('P'), opt.ends_with("AT"))?)
+                    }
+                    _ => return Err(CommandErr::SyntaxError),
+                }
+            }
+            _ => return Err(CommandErr::SyntaxError),
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let value = storage.get(&k)?.cloned();
+        if value.is_some() {
+            match expiry {
+                Expiry::Clear => {
+                    storage.persist(&k);
+                }
+                Expiry::Keep => {}
+                Expiry::At(at) => {
+                    storage.expire_at(&k, at);
+                }
+            }
+        }
+        Ok(value.to_resp())
+    }
+}
+
+
+struct GetDel;
+
+impl Command for GetDel {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("getdel", 2)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@string"])
+            .docs("string", "6.2.0", "O(1)", "Returns the string value of a key after deleting the key.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let value = storage.get(&k)?.cloned();
+        storage.del(&k);
+        Ok(value.to_resp())
+    }
+}
+
+
+/// `INCR`, `DECR`, `INCRBY` and `DECRBY`.
+struct IncrBy {
+    name: &'static str,
+}
+
+impl Command for IncrBy {
+    fn spec(&self) -> CommandSpec {
+        let (arity, summary) = match self.name {
+            "incr" => (2, "Increments the integer value of a key by one. Uses 0 as initial value if the key doesn't exist."),
+            "decr" => (2, "Decrements the integer value of a key by one. Uses 0 as initial value if the key doesn't exist."),
+            "incrby" => (3, "Increments the integer value of a key by a number. Uses 0 as initial value if the key doesn't exist."),
+            _ => (3, "Decrements a number from the integer value of a key. Uses 0 as initial value if the key doesn't exist."),
+        };
+        CommandSpec::new(self.name, arity)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@string"])
+            .docs("string", "1.0.0", "O(1)", summary)
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let by = match parts.get(1) {
+            Some(by) => i64::from_resp(by)?,
+            None => 1,
+        };
+        let delta = if self.name.starts_with("decr") {
+            by.checked_neg().ok_or_else(|| CommandErr::InvalidArgs("decrement would overflow".to_string()))?
+        } else {
+            by
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let current = match storage.get(&k)? {
+            Some(v) => parse_i64(v).ok_or_else(|| CommandErr::InvalidArgs("value is not an integer or out of range".to_string()))?,
+            None => 0,
+        };
+        let new = current
+            .checked_add(delta)
+            .ok_or_else(|| CommandErr::InvalidArgs("increment or decrement would overflow".to_string()))?;
+
+        storage.set_keep_ttl(k, new.to_string().into_bytes());
+        Ok(new.to_resp())
+    }
+}
+
+
+struct IncrByFloat;
+
+impl Command for IncrByFloat {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("incrbyfloat", 3)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@string"])
+            .docs("string", "2.6.0", "O(1)", "Increment the floating point value of a key by a number. Uses 0 as initial value if the key doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let by = f64::from_resp(&parts[1])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let current = match storage.get(&k)? {
+            Some(v) => parse_f64(v).ok_or_else(|| CommandErr::InvalidArgs("value is not a valid float".to_string()))?,
+            None => 0.0,
+        };
+        let new = current + by;
+        if !new.is_finite() {
+            return Err(CommandErr::InvalidArgs("increment would produce NaN or Infinity".to_string()));
+        }
+
+        let new = format_double(new).into_bytes();
+        storage.set_keep_ttl(k, new.clone());
+        Ok(RespType::BString(new))
+    }
+}
+
+
+struct Append;
+
+impl Command for Append {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("append", 3)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@string"])
+            .docs("string", "2.0.0", "O(1). The amortized time complexity is O(1) assuming the appended value is small and the already present value is of any size, since the dynamic string library used by Redis will double the free space available on every reallocation.", "Appends a string to the value of a key. Creates the key if it doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let suffix = Vec::<u8>::from_resp(&parts[1])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let len = match storage.get_mut(&k)? {
+            Some(v) => {
+                if v.len() + suffix.len() > MAX_STRING_LEN {
+                    return Err(CommandErr::InvalidArgs("string exceeds maximum allowed size (proto-max-bulk-len)".to_string()));
+                }
+                v.extend_from_slice(&suffix);
+                v.len()
+            }
+            None => {
+                let len = suffix.len();
+                storage.set(k, suffix);
+                len
+            }
+        };
+        Ok(len.to_resp())
+    }
+}
+
+
+struct StrLen;
+
+impl Command for StrLen {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("strlen", 2)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@string"])
+            .docs("string", "2.2.0", "O(1)", "Returns the length of a string value.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let len = ctx.storage.lock().unwrap().get(&k)?.map_or(0, Vec::len);
+        Ok(len.to_resp())
+    }
+}
+
+
+struct GetRange;
+
+impl Command for GetRange {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("getrange", 4)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@string"])
+            .docs("string", "2.4.0", "O(N) where N is the length of the returned string. The complexity is ultimately determined by the returned length, but because creating a substring from an existing string is very cheap, it can be considered O(1) for small strings.", "Returns a substring of the string stored at a key.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let start = i64::from_resp(&parts[1])?;
+        let end = i64::from_resp(&parts[2])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let value = storage.get(&k)?.map_or(&[][..], Vec::as_slice);
+        let len = value.len() as i64;
+
+        if start < 0 && end < 0 && start > end {
+            return Ok(RespType::BString(Vec::new()));
+        }
+        let start = if start < 0 { (start + len).max(0) } else { start };
+        let end = if end < 0 { (end + len).max(0) } else { end.min(len - 1) };
+        if len == 0 || start > end {
+            return Ok(RespType::BString(Vec::new()));
+        }
+        Ok(RespType::BString(value[start as usize..=end as usize].to_vec()))
+    }
+}
+
+
+struct SetRange;
+
+impl Command for SetRange {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("setrange", 4)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+            .keys(1, 1, 1)
+            .acl(&["@string"])
+            .docs("string", "2.2.0", "O(1), not counting the time taken to copy the new string in place. Usually, this string is very small so the amortized complexity is O(1). Otherwise, complexity is O(M) with M being the length of the value argument.", "Overwrites a part of a string value with another by an offset. Creates the key if it doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let offset = i64::from_resp(&parts[1])?;
+        let patch = Vec::<u8>::from_resp(&parts[2])?;
+
+        if offset < 0 {
+            return Err(CommandErr::InvalidArgs("offset is out of range".to_string()));
+        }
+        let offset = offset as usize;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let current_len = storage.get(&k)?.map_or(0, Vec::len);
+        // an empty patch changes nothing, and doesn't create the key either
+        if patch.is_empty() {
+            return Ok(current_len.to_resp());
+        }
+        if offset.saturating_add(patch.len()) > MAX_STRING_LEN {
+            return Err(CommandErr::InvalidArgs("string exceeds maximum allowed size (proto-max-bulk-len)".to_string()));
+        }
+
+        if storage.get(&k)?.is_none() {
+            storage.set(k.clone(), Vec::new());
+        }
+        let value = storage.get_mut(&k)?.unwrap();
+        if value.len() < offset + patch.len() {
+            value.resize(offset + patch.len(), 0);
+        }
+        value[offset..offset + patch.len()].copy_from_slice(&patch);
+        Ok(value.len().to_resp())
+    }
+}
+
+
+struct MGet;
+
+impl Command for MGet {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("mget", -2)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, -1, 1)
+            .acl(&["@string"])
+            .docs("string", "1.0.0", "O(N) where N is the number of keys to retrieve.", "Atomically returns the string values of one or more keys.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let keys = parts.iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        // keys holding another type read as missing rather than failing the whole reply
+        let values: Vec<Option<Vec<u8>>> = keys.iter().map(|k| storage.get(k).ok().flatten().cloned()).collect();
+        Ok(values.to_resp())
+    }
+}
+
+
+/// `MSET` and `MSETNX`.
+struct MSet {
+    nx: bool,
+}
+
+impl Command for MSet {
+    fn spec(&self) -> CommandSpec {
+        let spec = if self.nx {
+            CommandSpec::new("msetnx", -3).docs("string", "1.0.1", "O(N) where N is the number of keys to set.", "Atomically modifies the string values of one or more keys only when all keys don't exist.")
+        } else {
+            CommandSpec::new("mset", -3).docs("string", "1.0.1", "O(N) where N is the number of keys to set.", "Atomically creates or modifies the string values of one or more keys.")
+        };
+        spec.flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+            .keys(1, -1, 2)
+            .acl(&["@string"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        if !parts.len().is_multiple_of(2) {
+            return Err(CommandErr::WrongArity(if self.nx { "msetnx" } else { "mset" }.to_string()));
+        }
+        let pairs = parts
+            .chunks(2)
+            .map(|pair| Ok((Vec::<u8>::from_resp(&pair[0])?, Vec::<u8>::from_resp(&pair[1])?)))
+            .collect::<Result<Vec<_>, CommandErr>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        if self.nx && pairs.iter().any(|(k, _)| storage.exists(k)) {
+            return Ok(RespType::Int(0));
+        }
+        for (k, v) in pairs {
+            storage.set(k, v);
+        }
+        Ok(if self.nx { RespType::Int(1) } else { RespType::String("OK".to_string()) })
+    }
+}
+
+
+struct Lcs;
+
+impl Command for Lcs {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("lcs", -3)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 2, 1)
+            .acl(&["@string"])
+            .docs("string", "7.0.0", "O(N*M) where N and M are the lengths of s1 and s2, respectively", "Finds the longest common substring.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let ka = Vec::<u8>::from_resp(&parts[0])?;
+        let kb = Vec::<u8>::from_resp(&parts[1])?;
+
+        let (mut get_len, mut get_idx, mut with_match_len, mut min_match_len) = (false, false, false, 0);
+        let mut i = 2;
+        while i < parts.len() {
+            let opt = String::from_resp(&parts[i])?;
+            match opt.to_ascii_uppercase().as_str() {
+                "LEN" => get_len = true,
+                "IDX" => get_idx = true,
+                "WITHMATCHLEN" => with_match_len = true,
+                "MINMATCHLEN" if i + 1 < parts.len() => {
+                    min_match_len = i64::from_resp(&parts[i + 1])?.max(0) as usize;
+                    i += 1;
+                }
+                _ => return Err(CommandErr::SyntaxError),
+            }
+            i += 1;
+        }
+        if get_len && get_idx {
+            return Err(CommandErr::InvalidArgs("If you want both the length and indexes, please just use IDX.".to_string()));
+        }
+
+        let (a, b) = {
+            let mut storage = ctx.storage.lock().unwrap();
+            let mut string_at = |k| match storage.get(k) {
+                Ok(v) => Ok(v.cloned().unwrap_or_default()),
+                Err(_) => Err(CommandErr::InvalidArgs("The specified keys must contain string values".to_string())),
+            };
+            (string_at(&ka)?, string_at(&kb)?)
+        };
+
+        // table[i][j] is the length of the LCS of a[..i] and b[..j], which is refused when it
+        // would be too large, the same limit as redis
+        let width = b.len() + 1;
+        let cells = (a.len() + 1).checked_mul(width).filter(|&n| n < (u32::MAX / 4) as usize);
+        let Some(cells) = cells else {
+            return Err(CommandErr::InvalidArgs("String too long for LCS".to_string()));
+        };
+        let mut table = vec![0u32; cells];
+        for i in 1..=a.len() {
+            for j in 1..=b.len() {
+                table[i * width + j] = if a[i - 1] == b[j - 1] {
+                    table[(i - 1) * width + j - 1] + 1
+                } else {
+                    table[(i - 1) * width + j].max(table[i * width + j - 1])
+                };
+            }
+        }
+        let lcs_len = table[a.len() * width + b.len()] as usize;
+        if get_len {
+            return Ok(lcs_len.to_resp());
+        }
+
+        // walk the table back from the end, collecting the LCS and the ranges it's made of,
+        // the same way redis does so matches come out in the same order
+        let mut lcs = vec![0u8; lcs_len];
+        let mut matches = Vec::new();
+        let (mut i, mut j, mut idx) = (a.len(), b.len(), lcs_len);
+        let mut range: Option<(usize, usize, usize, usize)> = None;
+        while i > 0 && j > 0 {
+            let mut emit = false;
+            if a[i - 1] == b[j - 1] {
+                lcs[idx - 1] = a[i - 1];
+                range = match range {
+                    None => Some((i - 1, i - 1, j - 1, j - 1)),
+                    Some((a_start, a_end, b_start, b_end)) if a_start == i && b_start == j => {
+                        Some((a_start - 1, a_end, b_start - 1, b_end))
+                    }
+                    Some(r) => {
+                        emit = true;
+                        Some(r)
+                    }
+                };
+                if let Some((a_start, _, b_start, _)) = range {
+                    emit |= a_start == 0 || b_start == 0;
+                }
+                idx -= 1;
+                i -= 1;
+                j -= 1;
+            } else {
+                if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                    i -= 1;
+                } else {
+                    j -= 1;
+                }
+                emit = range.is_some();
+            }
+
+            if emit {
+                let (a_start, a_end, b_start, b_end) = range.take().unwrap();
+                let match_len = a_end - a_start + 1;
+                if match_len >= min_match_len {
+                    let mut m = vec![
+                        RespType::Array(vec![a_start.to_resp(), a_end.to_resp()]),
+                        RespType::Array(vec![b_start.to_resp(), b_end.to_resp()]),
+                    ];
+                    if with_match_len {
+                        m.push(match_len.to_resp());
+                    }
+                    matches.push(RespType::Array(m));
+                }
+            }
+        }
+
+        if get_idx {
+            return Ok(RespType::Map(vec![
+                (RespType::BString(b"matches".to_vec()), RespType::Array(matches)),
+                (RespType::BString(b"len".to_vec()), lcs_len.to_resp()),
+            ]));
+        }
+        Ok(RespType::BString(lcs))
+    }
+}
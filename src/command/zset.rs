@@ -0,0 +1,888 @@
+use std::collections::HashMap;
+
+use crate::{format_double, FromResp, Protocol, RespType, ToResp};
+use crate::{Direction, LexBound, LexRange, ScoreRange, Storage, Value, ZSet};
+
+use super::list::{parse_mpop_count, parse_numkeys, resolve_range};
+use super::string::parse_f64;
+use super::{numkeys_positions, parse_timeout, Command, CommandContext, CommandErr, CommandFlag, CommandSpec, ScanArgs};
+
+pub(super) fn commands() -> Vec<Box<dyn Command>> {
+    vec![
+        Box::new(ZAdd),
+        Box::new(ZIncrBy),
+        Box::new(ZRem),
+        Box::new(ZScore),
+        Box::new(ZMScore),
+        Box::new(ZCard),
+        Box::new(ZCount),
+        Box::new(ZLexCount),
+        Box::new(ZRank { direction: Direction::Ascending }),
+        Box::new(ZRank { direction: Direction::Descending }),
+        Box::new(ZRange),
+        Box::new(ZRangeStore),
+        Box::new(ZPop { direction: Direction::Ascending }),
+        Box::new(ZPop { direction: Direction::Descending }),
+        Box::new(BZPop { direction: Direction::Ascending }),
+        Box::new(BZPop { direction: Direction::Descending }),
+        Box::new(ZMPop),
+        Box::new(BZMPop),
+        Box::new(ZStoreOp { inter: false }),
+        Box::new(ZStoreOp { inter: true }),
+        Box::new(ZScan),
+    ]
+}
+
+/// A member along with its score.
+type Scored = (Vec<u8>, f64);
+
+fn nan_error() -> CommandErr {
+    CommandErr::InvalidArgs("resulting score is not a number (NaN)".to_string())
+}
+
+/// Conditions of `ZADD`, which `ZINCRBY` adds with none of.
+#[derive(Default)]
+pub(super) struct AddOptions {
+    pub(super) nx: bool,
+    pub(super) xx: bool,
+    pub(super) gt: bool,
+    pub(super) lt: bool,
+    pub(super) incr: bool,
+}
+
+/// What `add` did to an element.
+pub(super) enum Added {
+    New(f64),
+    Changed(f64),
+    Unchanged(f64),
+    /// One of the conditions didn't hold, so nothing was written.
+    Skipped,
+}
+
+/// Sets the member's score, or adds `score` to it with `incr`, as far as the options allow.
+pub(super) fn add(zset: &mut ZSet, member: Vec<u8>, score: f64, opts: &AddOptions) -> Result<Added, CommandErr> {
+    match zset.score(&member) {
+        Some(_) if opts.nx => Ok(Added::Skipped),
+        Some(current) => {
+            let new = if opts.incr { current + score } else { score };
+            if new.is_nan() {
+                return Err(nan_error());
+            }
+            if (opts.lt && new >= current) || (opts.gt && new <= current) {
+                return Ok(Added::Skipped);
+            }
+            if new == current {
+                return Ok(Added::Unchanged(new));
+            }
+            zset.insert(member, new);
+            Ok(Added::Changed(new))
+        }
+        None if opts.xx => Ok(Added::Skipped),
+        None => {
+            zset.insert(member, score);
+            Ok(Added::New(score))
+        }
+    }
+}
+
+/// Parses a score range end, which is exclusive when prefixed with `(`.
+fn parse_score_bound(arg: &RespType) -> Option<(f64, bool)> {
+    match arg.as_bytes()? {
+        [b'(', score @ ..] => parse_f64(score).map(|score| (score, true)),
+        score => parse_f64(score).map(|score| (score, false)),
+    }
+}
+
+fn parse_score_range(min: &RespType, max: &RespType) -> Result<ScoreRange, CommandErr> {
+    match (parse_score_bound(min), parse_score_bound(max)) {
+        (Some((min, min_exclusive)), Some((max, max_exclusive))) => Ok(ScoreRange { min, max, min_exclusive, max_exclusive }),
+        _ => Err(CommandErr::InvalidArgs("min or max is not a float".to_string())),
+    }
+}
+
+fn parse_lex_bound(arg: &RespType) -> Option<LexBound> {
+    match arg.as_bytes()? {
+        b"-" => Some(LexBound::NegInf),
+        b"+" => Some(LexBound::PosInf),
+        [b'[', member @ ..] => Some(LexBound::Inclusive(member.to_vec())),
+        [b'(', member @ ..] => Some(LexBound::Exclusive(member.to_vec())),
+        _ => None,
+    }
+}
+
+fn parse_lex_range(min: &RespType, max: &RespType) -> Result<LexRange, CommandErr> {
+    match (parse_lex_bound(min), parse_lex_bound(max)) {
+        (Some(min), Some(max)) => Ok(LexRange { min, max }),
+        _ => Err(CommandErr::InvalidArgs("min or max not valid string range item".to_string())),
+    }
+}
+
+/// The elements, followed by their scores when `withscores`. RESP3 clients get each
+/// member paired with its score rather than a flat list.
+fn scored_reply(elements: Vec<Scored>, withscores: bool, protocol: Protocol) -> RespType {
+    let elements = elements.into_iter();
+    match (withscores, protocol) {
+        (false, _) => RespType::Array(elements.map(|(m, _)| m.to_resp()).collect()),
+        (true, Protocol::Resp3) => RespType::Array(elements.map(|(m, s)| RespType::Array(vec![m.to_resp(), s.to_resp()])).collect()),
+        (true, _) => RespType::Array(elements.flat_map(|(m, s)| [m.to_resp(), s.to_resp()]).collect()),
+    }
+}
+
+/// What `ZRANGE` and `ZRANGESTORE` select elements by.
+enum Range {
+    Rank(i64, i64),
+    Score(ScoreRange),
+    Lex(LexRange),
+}
+
+/// The arguments `ZRANGE` and `ZRANGESTORE` share:
+/// `start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count]`.
+struct RangeQuery {
+    range: Range,
+    direction: Direction,
+    offset: i64,
+    /// Negative for no limit.
+    count: i64,
+    withscores: bool,
+}
+
+impl RangeQuery {
+    /// Parses the query from `args`, which start with the range. `WITHSCORES` is only
+    /// accepted when `allow_withscores`.
+    fn parse(args: &[RespType], allow_withscores: bool) -> Result<Self, CommandErr> {
+        #[derive(PartialEq)]
+        enum By {
+            Rank,
+            Score,
+            Lex,
+        }
+
+        let (mut by, mut rev, mut limit, mut withscores) = (By::Rank, false, None, false);
+        let mut i = 2;
+        while i < args.len() {
+            match String::from_resp(&args[i])?.to_ascii_uppercase().as_str() {
+                "WITHSCORES" if allow_withscores => withscores = true,
+                "BYSCORE" => by = By::Score,
+                "BYLEX" => by = By::Lex,
+                "REV" => rev = true,
+                "LIMIT" if i + 2 < args.len() => {
+                    limit = Some((i64::from_resp(&args[i + 1])?, i64::from_resp(&args[i + 2])?));
+                    i += 2;
+                }
+                _ => return Err(CommandErr::SyntaxError),
+            }
+            i += 1;
+        }
+
+        if limit.is_some() && by == By::Rank {
+            return Err(CommandErr::InvalidArgs("syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX".to_string()));
+        }
+        if withscores && by == By::Lex {
+            return Err(CommandErr::InvalidArgs("syntax error, WITHSCORES not supported in combination with BYLEX".to_string()));
+        }
+
+        // reversed score and lex ranges are written from max to min
+        let (min, max) = if rev && by != By::Rank { (&args[1], &args[0]) } else { (&args[0], &args[1]) };
+        let range = match by {
+            By::Rank => Range::Rank(i64::from_resp(min)?, i64::from_resp(max)?),
+            By::Score => Range::Score(parse_score_range(min, max)?),
+            By::Lex => Range::Lex(parse_lex_range(min, max)?),
+        };
+        let (offset, count) = limit.unwrap_or((0, -1));
+        let direction = if rev { Direction::Descending } else { Direction::Ascending };
+        Ok(Self { range, direction, offset, count, withscores })
+    }
+
+    fn select(&self, zset: &ZSet) -> Vec<Scored> {
+        // like in redis, a negative offset selects nothing and a negative count everything
+        if self.offset < 0 {
+            return Vec::new();
+        }
+        let offset = self.offset as usize;
+        let count = if self.count < 0 { usize::MAX } else { self.count as usize };
+        match &self.range {
+            Range::Rank(start, stop) => match resolve_range(*start, *stop, zset.len()) {
+                Some((start, stop)) => zset.range_by_rank(start, stop, self.direction),
+                None => Vec::new(),
+            },
+            Range::Score(range) => zset.range_by_score(range, self.direction, offset, count),
+            Range::Lex(range) => zset.range_by_lex(range, self.direction, offset, count),
+        }
+    }
+}
+
+/// Parses the `MIN | MAX` argument of ZMPOP-style commands.
+fn parse_min_max(arg: &RespType) -> Result<Direction, CommandErr> {
+    match String::from_resp(arg)?.to_ascii_uppercase().as_str() {
+        "MIN" => Ok(Direction::Ascending),
+        "MAX" => Ok(Direction::Descending),
+        _ => Err(CommandErr::SyntaxError),
+    }
+}
+
+/// The key a ZMPOP-style pop took elements from, along with those elements.
+type Popped = (Vec<u8>, Vec<Scored>);
+
+/// Pops up to `count` elements from the first non-empty sorted set of `keys`, returning
+/// that key along with the elements.
+fn zmpop(storage: &mut Storage, keys: &[Vec<u8>], direction: Direction, count: usize) -> Result<Option<Popped>, CommandErr> {
+    for key in keys {
+        let Some(zset) = storage.zset_mut(key)? else {
+            continue;
+        };
+        let popped: Vec<Scored> = (0..count).map_while(|_| zset.pop(direction)).collect();
+        storage.remove_if_empty(key);
+        return Ok(Some((key.clone(), popped)));
+    }
+    Ok(None)
+}
+
+/// The reply of ZMPOP-style commands: the key popped from and its elements, each paired
+/// with its score whatever the protocol.
+fn zmpop_reply(popped: Option<Popped>) -> RespType {
+    match popped {
+        Some((key, elements)) => RespType::Array(vec![key.to_resp(), scored_reply(elements, true, Protocol::Resp3)]),
+        None => RespType::NullArray,
+    }
+}
+
+/// How `ZUNIONSTORE` and `ZINTERSTORE` combine the scores of a member found in several inputs.
+#[derive(Clone, Copy)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // adding opposite infinities gives 0 rather than NaN, like in redis
+            Aggregate::Sum => Some(a + b).filter(|s| !s.is_nan()).unwrap_or(0.0),
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+/// The elements of the sorted sets at `keys`, or of plain sets whose members all score 1.
+/// Missing keys count as empty.
+fn load_scored(storage: &mut Storage, keys: &[Vec<u8>]) -> Result<Vec<HashMap<Vec<u8>, f64>>, CommandErr> {
+    keys.iter()
+        .map(|k| match storage.get_value(k) {
+            Some(Value::ZSet(zset)) => Ok(zset.iter().collect()),
+            Some(Value::Set(set)) => Ok(set.iter().map(|m| (m, 1.0)).collect()),
+            Some(_) => Err(CommandErr::WrongType),
+            None => Ok(HashMap::new()),
+        })
+        .collect()
+}
+
+/// Replaces whatever `dst` held with a sorted set of the elements, deleting it if there
+/// are none. Returns how many elements were stored.
+pub(super) fn store(storage: &mut Storage, dst: &[u8], elements: impl IntoIterator<Item = Scored>) -> Result<usize, CommandErr> {
+    storage.del(dst);
+    let zset = storage.zset_or_create(dst)?;
+    for (member, score) in elements {
+        zset.insert(member, score);
+    }
+    let len = zset.len();
+    storage.remove_if_empty(dst);
+    Ok(len)
+}
+
+
+/// `ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]`.
+struct ZAdd;
+
+impl Command for ZAdd {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("zadd", -4)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@sortedset"])
+            .docs("sorted-set", "1.2.0", "O(log(N)) for each item added, where N is the number of elements in the sorted set.", "Adds one or more members to a sorted set, or updates their scores. Creates the key if it doesn't exist.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+
+        let (mut opts, mut ch) = (AddOptions::default(), false);
+        let mut i = 1;
+        while let Some(arg) = parts.get(i) {
+            match String::from_resp(arg)?.to_ascii_uppercase().as_str() {
+                "NX" => opts.nx = true,
+                "XX" => opts.xx = true,
+                "GT" => opts.gt = true,
+                "LT" => opts.lt = true,
+                "CH" => ch = true,
+                "INCR" => opts.incr = true,
+                _ => break,
+            }
+            i += 1;
+        }
+
+        let pairs = &parts[i..];
+        if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+            return Err(CommandErr::SyntaxError);
+        }
+        if opts.nx && opts.xx {
+            return Err(CommandErr::InvalidArgs("XX and NX options at the same time are not compatible".to_string()));
+        }
+        if [opts.nx, opts.gt, opts.lt].iter().filter(|&&set| set).count() > 1 {
+            return Err(CommandErr::InvalidArgs("GT, LT, and/or NX options at the same time are not compatible".to_string()));
+        }
+        if opts.incr && pairs.len() > 2 {
+            return Err(CommandErr::InvalidArgs("INCR option supports a single increment-element pair".to_string()));
+        }
+        let elements = pairs
+            .chunks(2)
+            .map(|pair| Ok((f64::from_resp(&pair[0])?, Vec::<u8>::from_resp(&pair[1])?)))
+            .collect::<Result<Vec<_>, CommandErr>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        if storage.zset(&k)?.is_none() && opts.xx {
+            return Ok(if opts.incr { RespType::Null } else { RespType::Int(0) });
+        }
+        let zset = storage.zset_or_create(&k)?;
+        let (mut added, mut changed, mut last) = (0, 0, None);
+        let result = elements.into_iter().try_for_each(|(score, member)| {
+            last = match add(zset, member, score, &opts)? {
+                Added::New(score) => {
+                    added += 1;
+                    Some(score)
+                }
+                Added::Changed(score) => {
+                    changed += 1;
+                    Some(score)
+                }
+                Added::Unchanged(score) => Some(score),
+                Added::Skipped => None,
+            };
+            Ok::<_, CommandErr>(())
+        });
+        storage.remove_if_empty(&k);
+        result?;
+
+        if opts.incr {
+            return Ok(last.to_resp());
+        }
+        Ok(RespType::Int(if ch { added + changed } else { added }))
+    }
+}
+
+
+struct ZIncrBy;
+
+impl Command for ZIncrBy {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("zincrby", 4)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@sortedset"])
+            .docs("sorted-set", "1.2.0", "O(log(N)) where N is the number of elements in the sorted set.", "Increments the score of a member in a sorted set.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let increment = f64::from_resp(&parts[1])?;
+        let member = Vec::<u8>::from_resp(&parts[2])?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let zset = storage.zset_or_create(&k)?;
+        let result = add(zset, member, increment, &AddOptions { incr: true, ..AddOptions::default() });
+        storage.remove_if_empty(&k);
+        match result? {
+            Added::New(score) | Added::Changed(score) | Added::Unchanged(score) => Ok(score.to_resp()),
+            Added::Skipped => unreachable!("ZINCRBY has no conditions"),
+        }
+    }
+}
+
+
+struct ZRem;
+
+impl Command for ZRem {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("zrem", -3)
+            .flags(&[CommandFlag::Write, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@sortedset"])
+            .docs("sorted-set", "1.2.0", "O(M*log(N)) with N being the number of elements in the sorted set and M the number of elements to be removed.", "Removes one or more members from a sorted set. Deletes the sorted set if all members were removed.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let members = parts[1..].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(zset) = storage.zset_mut(&k)? else {
+            return Ok(RespType::Int(0));
+        };
+        let removed = members.iter().filter(|m| zset.remove(m)).count();
+        storage.remove_if_empty(&k);
+        Ok(removed.to_resp())
+    }
+}
+
+
+struct ZScore;
+
+impl Command for ZScore {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("zscore", 3)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@sortedset"])
+            .docs("sorted-set", "1.2.0", "O(1)", "Returns the score of a member in a sorted set.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let member = Vec::<u8>::from_resp(&parts[1])?;
+
+        let score = ctx.storage.lock().unwrap().zset(&k)?.and_then(|zset| zset.score(&member));
+        Ok(score.to_resp())
+    }
+}
+
+
+struct ZMScore;
+
+impl Command for ZMScore {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("zmscore", -3)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@sortedset"])
+            .docs("sorted-set", "6.2.0", "O(N) where N is the number of members being requested.", "Returns the score of one or more members in a sorted set.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let members = parts[1..].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let zset = storage.zset(&k)?;
+        let scores = members.iter().map(|m| zset.and_then(|zset| zset.score(m)).to_resp());
+        Ok(RespType::Array(scores.collect()))
+    }
+}
+
+
+struct ZCard;
+
+impl Command for ZCard {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("zcard", 2)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@sortedset"])
+            .docs("sorted-set", "1.2.0", "O(1)", "Returns the number of members in a sorted set.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let len = ctx.storage.lock().unwrap().zset(&k)?.map_or(0, ZSet::len);
+        Ok(len.to_resp())
+    }
+}
+
+
+/// `ZCOUNT key min max`.
+struct ZCount;
+
+impl Command for ZCount {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("zcount", 4)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@sortedset"])
+            .docs("sorted-set", "2.0.0", "O(log(N)) with N being the number of elements in the sorted set.", "Returns the count of members in a sorted set that have scores within a range.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let range = parse_score_range(&parts[1], &parts[2])?;
+
+        let count = ctx.storage.lock().unwrap().zset(&k)?.map_or(0, |zset| zset.count_in_score_range(&range));
+        Ok(count.to_resp())
+    }
+}
+
+
+/// `ZLEXCOUNT key min max`.
+struct ZLexCount;
+
+impl Command for ZLexCount {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("zlexcount", 4)
+            .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+            .keys(1, 1, 1)
+            .acl(&["@sortedset"])
+            .docs("sorted-set", "2.8.9", "O(log(N)) with N being the number of elements in the sorted set.", "Returns the number of members in a sorted set within a lexicographical range.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let range = parse_lex_range(&parts[1], &parts[2])?;
+
+        let count = ctx.storage.lock().unwrap().zset(&k)?.map_or(0, |zset| zset.count_in_lex_range(&range));
+        Ok(count.to_resp())
+    }
+}
+
+
+/// `ZRANK` and `ZREVRANK`, which count ranks from the highest score.
+struct ZRank {
+    direction: Direction,
+}
+
+impl Command for ZRank {
+    fn spec(&self) -> CommandSpec {
+        match self.direction {
+            Direction::Ascending => CommandSpec::new("zrank", -3)
+                .docs("sorted-set", "2.0.0", "O(log(N))", "Returns the index of a member in a sorted set ordered by ascending scores."),
+            Direction::Descending => CommandSpec::new("zrevrank", -3)
+                .docs("sorted-set", "2.0.0", "O(log(N))", "Returns the index of a member in a sorted set ordered by descending scores."),
+        }
+        .flags(&[CommandFlag::Readonly, CommandFlag::Fast])
+        .keys(1, 1, 1)
+        .acl(&["@sortedset"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let member = Vec::<u8>::from_resp(&parts[1])?;
+        let withscore = match &parts[2..] {
+            [] => false,
+            [opt] if String::from_resp(opt)?.eq_ignore_ascii_case("WITHSCORE") => true,
+            _ => return Err(CommandErr::SyntaxError),
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let found = storage.zset(&k)?.and_then(|zset| Some((zset.rank(&member, self.direction)?, zset.score(&member)?)));
+        Ok(match found {
+            Some((rank, score)) if withscore => RespType::Array(vec![rank.to_resp(), score.to_resp()]),
+            Some((rank, _)) => rank.to_resp(),
+            None if withscore => RespType::NullArray,
+            None => RespType::Null,
+        })
+    }
+}
+
+
+/// `ZRANGE key start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count] [WITHSCORES]`.
+struct ZRange;
+
+impl Command for ZRange {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("zrange", -4)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@sortedset"])
+            .docs("sorted-set", "1.2.0", "O(log(N)+M) with N being the number of elements in the sorted set and M the number of elements returned.", "Returns members in a sorted set within a range of indexes.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let query = RangeQuery::parse(&parts[1..], true)?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let elements = storage.zset(&k)?.map(|zset| query.select(zset)).unwrap_or_default();
+        Ok(scored_reply(elements, query.withscores, ctx.client.protocol))
+    }
+}
+
+
+/// `ZRANGESTORE dst src min max [BYSCORE | BYLEX] [REV] [LIMIT offset count]`.
+struct ZRangeStore;
+
+impl Command for ZRangeStore {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("zrangestore", -5)
+            .flags(&[CommandFlag::Write, CommandFlag::DenyOom])
+            .keys(1, 2, 1)
+            .acl(&["@sortedset"])
+            .docs("sorted-set", "6.2.0", "O(log(N)+M) with N being the number of elements in the sorted set and M the number of elements stored into the destination key.", "Stores a range of members from sorted set in a key.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let dst = Vec::<u8>::from_resp(&parts[0])?;
+        let src = Vec::<u8>::from_resp(&parts[1])?;
+        let query = RangeQuery::parse(&parts[2..], false)?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let elements = storage.zset(&src)?.map(|zset| query.select(zset)).unwrap_or_default();
+        Ok(store(&mut storage, &dst, elements)?.to_resp())
+    }
+}
+
+
+/// `ZPOPMIN` and `ZPOPMAX`, which pop the elements with the lowest or highest scores.
+struct ZPop {
+    direction: Direction,
+}
+
+impl Command for ZPop {
+    fn spec(&self) -> CommandSpec {
+        match self.direction {
+            Direction::Ascending => CommandSpec::new("zpopmin", -2)
+                .docs("sorted-set", "5.0.0", "O(log(N)*M) with N being the number of elements in the sorted set, and M being the number of elements popped.", "Returns the lowest-scoring members from a sorted set after removing them. Deletes the sorted set if the last member was popped."),
+            Direction::Descending => CommandSpec::new("zpopmax", -2)
+                .docs("sorted-set", "5.0.0", "O(log(N)*M) with N being the number of elements in the sorted set, and M being the number of elements popped.", "Returns the highest-scoring members from a sorted set after removing them. Deletes the sorted set if the last member was popped."),
+        }
+        .flags(&[CommandFlag::Write, CommandFlag::Fast])
+        .keys(1, 1, 1)
+        .acl(&["@sortedset"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let count = match parts {
+            [_] => None,
+            [_, count] => match i64::from_resp(count)? {
+                n if n >= 0 => Some(n as usize),
+                _ => return Err(CommandErr::InvalidArgs("value is out of range, must be positive".to_string())),
+            },
+            _ => return Err(CommandErr::SyntaxError),
+        };
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let popped: Vec<Scored> = match storage.zset_mut(&k)? {
+            Some(zset) => (0..count.unwrap_or(1)).map_while(|_| zset.pop(self.direction)).collect(),
+            None => Vec::new(),
+        };
+        storage.remove_if_empty(&k);
+        // without a count the element is a flat member and score in any protocol
+        let protocol = if count.is_some() { ctx.client.protocol } else { Protocol::Resp2 };
+        Ok(scored_reply(popped, true, protocol))
+    }
+}
+
+
+/// `BZPOPMIN` and `BZPOPMAX`.
+struct BZPop {
+    direction: Direction,
+}
+
+impl Command for BZPop {
+    fn spec(&self) -> CommandSpec {
+        match self.direction {
+            Direction::Ascending => CommandSpec::new("bzpopmin", -3)
+                .docs("sorted-set", "5.0.0", "O(log(N)) with N being the number of elements in the sorted set.", "Removes and returns the member with the lowest score from one or more sorted sets. Blocks until a member is available otherwise. Deletes the sorted set if the last element was popped."),
+            Direction::Descending => CommandSpec::new("bzpopmax", -3)
+                .docs("sorted-set", "5.0.0", "O(log(N)) with N being the number of elements in the sorted set.", "Removes and returns the member with the highest score from one or more sorted sets. Blocks until a member is available otherwise. Deletes the sorted set if the last element was popped."),
+        }
+        .flags(&[CommandFlag::Write, CommandFlag::Fast, CommandFlag::Blocking])
+        .keys(1, -2, 1)
+        .acl(&["@sortedset"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let (timeout, keys) = parts.split_last().unwrap();
+        let keys = keys.iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+        let deadline = parse_timeout(timeout)?;
+
+        let popped = zmpop(&mut ctx.storage.lock().unwrap(), &keys, self.direction, 1)?;
+        match popped {
+            Some((key, mut elements)) => {
+                let (member, score) = elements.remove(0);
+                Ok(RespType::Array(vec![key.to_resp(), member.to_resp(), score.to_resp()]))
+            }
+            None => {
+                ctx.block(keys, deadline);
+                Ok(RespType::NullArray)
+            }
+        }
+    }
+}
+
+
+/// `ZMPOP numkeys key [key ...] MIN | MAX [COUNT count]`.
+struct ZMPop;
+
+impl Command for ZMPop {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("zmpop", -4)
+            .flags(&[CommandFlag::Write, CommandFlag::MovableKeys])
+            .acl(&["@sortedset"])
+            .docs("sorted-set", "7.0.0", "O(K) + O(M*log(N)) where K is the number of provided keys, N being the number of elements in the sorted set, and M being the number of elements popped.", "Returns the highest- or lowest-scoring members from one or more sorted sets after removing them. Deletes the sorted set if the last member was popped.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let (keys, rest) = parse_numkeys(parts)?;
+        let Some((direction, opts)) = rest.split_first() else {
+            return Err(CommandErr::SyntaxError);
+        };
+        let direction = parse_min_max(direction)?;
+        let count = parse_mpop_count(opts)?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        Ok(zmpop_reply(zmpop(&mut storage, &keys, direction, count)?))
+    }
+
+    fn key_positions(&self, _spec: &CommandSpec, argv: &[RespType]) -> Vec<usize> {
+        numkeys_positions(argv, 1)
+    }
+}
+
+
+/// `BZMPOP timeout numkeys key [key ...] MIN | MAX [COUNT count]`.
+struct BZMPop;
+
+impl Command for BZMPop {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("bzmpop", -5)
+            .flags(&[CommandFlag::Write, CommandFlag::Blocking, CommandFlag::MovableKeys])
+            .acl(&["@sortedset"])
+            .docs("sorted-set", "7.0.0", "O(K) + O(M*log(N)) where K is the number of provided keys, N being the number of elements in the sorted set, and M being the number of elements popped.", "Removes and returns a member by score from one or more sorted sets. Blocks until a member is available otherwise. Deletes the sorted set if the last element was popped.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let deadline = parse_timeout(&parts[0])?;
+        let (keys, rest) = parse_numkeys(&parts[1..])?;
+        let Some((direction, opts)) = rest.split_first() else {
+            return Err(CommandErr::SyntaxError);
+        };
+        let direction = parse_min_max(direction)?;
+        let count = parse_mpop_count(opts)?;
+
+        let popped = zmpop(&mut ctx.storage.lock().unwrap(), &keys, direction, count)?;
+        if popped.is_none() {
+            ctx.block(keys, deadline);
+        }
+        Ok(zmpop_reply(popped))
+    }
+
+    fn key_positions(&self, _spec: &CommandSpec, argv: &[RespType]) -> Vec<usize> {
+        numkeys_positions(argv, 2)
+    }
+}
+
+
+/// `ZUNIONSTORE` and `ZINTERSTORE`:
+/// `dst numkeys key [key ...] [WEIGHTS weight [weight ...]] [AGGREGATE SUM | MIN | MAX]`.
+/// Plain sets may be given too, their members scoring 1.
+struct ZStoreOp {
+    inter: bool,
+}
+
+impl Command for ZStoreOp {
+    fn spec(&self) -> CommandSpec {
+        if self.inter {
+            CommandSpec::new("zinterstore", -4)
+                .docs("sorted-set", "2.0.0", "O(N*K)+O(M*log(M)) worst case with N being the smallest input sorted set, K being the number of input sorted sets and M being the number of elements in the resulting sorted set.", "Stores the intersect of multiple sorted sets in a key.")
+        } else {
+            CommandSpec::new("zunionstore", -4)
+                .docs("sorted-set", "2.0.0", "O(N)+O(M log(M)) with N being the sum of the sizes of the input sorted sets, and M being the number of elements in the resulting sorted set.", "Stores the union of multiple sorted sets in a key.")
+        }
+        .flags(&[CommandFlag::Write, CommandFlag::DenyOom, CommandFlag::MovableKeys])
+        .acl(&["@sortedset"])
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let dst = Vec::<u8>::from_resp(&parts[0])?;
+        let numkeys = i64::from_resp(&parts[1])?;
+        if numkeys < 1 {
+            let name = if self.inter { "zinterstore" } else { "zunionstore" };
+            return Err(CommandErr::InvalidArgs(format!("at least 1 input key is needed for '{}' command", name)));
+        }
+        let numkeys = numkeys as usize;
+        if numkeys > parts.len() - 2 {
+            return Err(CommandErr::SyntaxError);
+        }
+        let keys = parts[2..2 + numkeys].iter().map(Vec::<u8>::from_resp).collect::<Result<Vec<_>, _>>()?;
+
+        let (mut weights, mut aggregate) = (vec![1.0; numkeys], Aggregate::Sum);
+        let opts = &parts[2 + numkeys..];
+        let mut i = 0;
+        while i < opts.len() {
+            match String::from_resp(&opts[i])?.to_ascii_uppercase().as_str() {
+                "WEIGHTS" if i + numkeys < opts.len() => {
+                    for (weight, arg) in weights.iter_mut().zip(&opts[i + 1..]) {
+                        *weight = f64::from_resp(arg)
+                            .map_err(|_| CommandErr::InvalidArgs("weight value is not a float".to_string()))?;
+                    }
+                    i += numkeys;
+                }
+                "AGGREGATE" if i + 1 < opts.len() => {
+                    aggregate = match String::from_resp(&opts[i + 1])?.to_ascii_uppercase().as_str() {
+                        "SUM" => Aggregate::Sum,
+                        "MIN" => Aggregate::Min,
+                        "MAX" => Aggregate::Max,
+                        _ => return Err(CommandErr::SyntaxError),
+                    };
+                    i += 1;
+                }
+                _ => return Err(CommandErr::SyntaxError),
+            }
+            i += 1;
+        }
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let inputs = load_scored(&mut storage, &keys)?;
+        // a weight of 0 on an infinite score gives 0 rather than NaN, like in redis
+        let weighted = |score: f64, weight: f64| Some(score * weight).filter(|s| !s.is_nan()).unwrap_or(0.0);
+
+        let mut result: HashMap<Vec<u8>, f64> = HashMap::new();
+        if self.inter {
+            let smallest = inputs.iter().min_by_key(|input| input.len()).expect("numkeys is at least 1");
+            for member in smallest.keys() {
+                let scores = inputs.iter().zip(&weights).map(|(input, &w)| input.get(member).map(|&s| weighted(s, w)));
+                if let Some(scores) = scores.collect::<Option<Vec<f64>>>() {
+                    let score = scores.into_iter().reduce(|a, b| aggregate.apply(a, b)).expect("numkeys is at least 1");
+                    result.insert(member.clone(), score);
+                }
+            }
+        } else {
+            for (input, &w) in inputs.iter().zip(&weights) {
+                for (member, &s) in input {
+                    let s = weighted(s, w);
+                    result.entry(member.clone()).and_modify(|acc| *acc = aggregate.apply(*acc, s)).or_insert(s);
+                }
+            }
+        }
+        Ok(store(&mut storage, &dst, result)?.to_resp())
+    }
+
+    fn key_positions(&self, _spec: &CommandSpec, argv: &[RespType]) -> Vec<usize> {
+        let mut positions = vec![1];
+        positions.extend(numkeys_positions(argv, 2));
+        positions
+    }
+}
+
+
+/// `ZSCAN key cursor [MATCH pattern] [COUNT count]`.
+struct ZScan;
+
+impl Command for ZScan {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec::new("zscan", -3)
+            .flags(&[CommandFlag::Readonly])
+            .keys(1, 1, 1)
+            .acl(&["@sortedset"])
+            .docs("sorted-set", "2.8.0", "O(1) for every call. O(N) for a complete iteration, including enough command calls for the cursor to return back to 0. N is the number of elements inside the collection.", "Iterates over members and scores of a sorted set.")
+    }
+
+    fn execute(&self, parts: &[RespType], ctx: &mut CommandContext) -> Result<RespType, CommandErr> {
+        let k = Vec::<u8>::from_resp(&parts[0])?;
+        let args = ScanArgs::parse(&parts[1..], false)?;
+
+        let mut storage = ctx.storage.lock().unwrap();
+        let Some(zset) = storage.zset(&k)? else {
+            return Ok(ScanArgs::reply(0, Vec::new()));
+        };
+
+        let mut found = Vec::new();
+        let next = zset.scan(args.cursor, args.count, |m, score| {
+            if args.matches(m) {
+                // scores are bulk strings here whatever the protocol, like in redis
+                found.push(m.to_resp());
+                found.push(format_double(score).to_resp());
+            }
+        });
+        Ok(ScanArgs::reply(next, found))
+    }
+}
@@ -1,53 +1,253 @@
-use std::collections::LinkedList;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
-use std::io::{BufRead, Cursor, Read, Seek};
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, Write};
 use std::str::FromStr;
 
-#[derive(Debug, PartialEq)]
+#[cfg(feature = "serde")]
+mod bridge;
+#[cfg(feature = "serde")]
+pub use bridge::{from_resp, to_resp};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum RespType {
-    BString(String),
+    BString(Vec<u8>),
     String(String),
     Err(String),
     Int(isize),
     Array(Vec<RespType>),
-    Null
+    /// Null bulk string, `$-1` in RESP2.
+    Null,
+    /// Null array, `*-1` in RESP2.
+    NullArray,
+    // RESP3 types, downgraded to their closest RESP2 form on RESP2 connections
+    Map(Vec<(RespType, RespType)>),
+    Set(Vec<RespType>),
+    Double(f64),
+    Boolean(bool),
+    BigNumber(String),
+    /// Format (e.g. `txt` or `mkd`) and the text itself.
+    Verbatim(String, Vec<u8>),
+    /// Out-of-band attributes and the reply they annotate.
+    Attribute(Vec<(RespType, RespType)>, Box<RespType>),
+    Push(Vec<RespType>),
+}
+
+/// Protocol version negotiated for a connection with `HELLO`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
 }
 
 impl RespType {
-    pub fn serialize(&self) -> String {
+    /// Serializes the frame for a RESP2 connection.
+    pub fn serialize(&self) -> Vec<u8> {
+        self.serialize_with(Protocol::Resp2)
+    }
+
+    pub fn serialize_with(&self, proto: Protocol) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_to(&mut out, proto).expect("writing to a Vec can't fail");
+        out
+    }
+
+    /// Writes the frame straight into `w`, without building intermediate strings.
+    ///
+    /// Pass a reused `Vec<u8>` or a `BufWriter`, as each frame is written in several small pieces.
+    pub fn write_to<W: Write>(&self, w: &mut W, proto: Protocol) -> io::Result<()> {
+        let resp3 = proto == Protocol::Resp3;
         match self {
             RespType::BString(s) => {
-                let len = s.len();
-                format!("${}\r\n{}\r\n", len, s)
+                write_blob(w, b'$', s)
             },
             RespType::String(s) => {
-                format!("+{}\r\n", s)
+                write!(w, "+{}\r\n", s)
             },
             RespType::Err(e) => {
-                format!("-{}\r\n", e)
+                // errors may echo what the client sent, and a line break in there would end
+                // the reply early and have the rest read as another one
+                write!(w, "-{}\r\n", e.replace(['\r', '\n'], " "))
             },
             RespType::Int(i) => {
-                format!(":{}\r\n", i)
+                write!(w, ":{}\r\n", i)
             },
             RespType::Array(vec) => {
-                let serialized_simple: String = vec.iter().map(|s| {
-                    s.serialize()
-                }).collect();
-                let agg_len = vec.len();
-                format!("*{}\r\n{}", agg_len, serialized_simple)
+                write_agg(w, b'*', vec, proto)
+            },
+            RespType::Null | RespType::NullArray if resp3 => {
+                w.write_all(b"_\r\n")
             },
             RespType::Null => {
-                format!("$-1\r\n")
+                w.write_all(b"$-1\r\n")
+            },
+            RespType::NullArray => {
+                w.write_all(b"*-1\r\n")
+            },
+            RespType::Map(pairs) => {
+                if resp3 {
+                    write!(w, "%{}\r\n", pairs.len())?;
+                } else {
+                    write!(w, "*{}\r\n", pairs.len() * 2)?;
+                }
+                write_pairs(w, pairs, proto)
+            },
+            RespType::Set(vec) => {
+                write_agg(w, if resp3 { b'~' } else { b'*' }, vec, proto)
+            },
+            RespType::Push(vec) => {
+                write_agg(w, if resp3 { b'>' } else { b'*' }, vec, proto)
+            },
+            RespType::Double(d) => {
+                let d = DoubleFmt(*d);
+                if resp3 {
+                    write!(w, ",{}\r\n", d)
+                } else {
+                    write!(w, "${}\r\n{}\r\n", d.display_len(), d)
+                }
+            },
+            RespType::Boolean(b) if resp3 => {
+                write!(w, "#{}\r\n", if *b { 't' } else { 'f' })
+            },
+            RespType::Boolean(b) => {
+                write!(w, ":{}\r\n", *b as u8)
+            },
+            RespType::BigNumber(n) if resp3 => {
+                write!(w, "({}\r\n", n)
+            },
+            RespType::BigNumber(n) => {
+                write_blob(w, b'$', n.as_bytes())
+            },
+            RespType::Verbatim(format, text) if resp3 => {
+                write!(w, "={}\r\n{}:", format.len() + 1 + text.len(), format)?;
+                w.write_all(text)?;
+                w.write_all(b"\r\n")
+            },
+            RespType::Verbatim(_, text) => {
+                write_blob(w, b'$', text)
+            },
+            RespType::Attribute(attrs, reply) if resp3 => {
+                write!(w, "|{}\r\n", attrs.len())?;
+                write_pairs(w, attrs, proto)?;
+                reply.write_to(w, proto)
+            },
+            RespType::Attribute(_, reply) => {
+                reply.write_to(w, proto)
             },
         }
     }
-} 
+}
+
+fn write_blob<W: Write>(w: &mut W, prefix: u8, blob: &[u8]) -> io::Result<()> {
+    write!(w, "{}{}\r\n", prefix as char, blob.len())?;
+    w.write_all(blob)?;
+    w.write_all(b"\r\n")
+}
+
+fn write_agg<W: Write>(w: &mut W, prefix: u8, vec: &[RespType], proto: Protocol) -> io::Result<()> {
+    write!(w, "{}{}\r\n", prefix as char, vec.len())?;
+    for s in vec {
+        s.write_to(w, proto)?;
+    }
+    Ok(())
+}
+
+fn write_pairs<W: Write>(w: &mut W, pairs: &[(RespType, RespType)], proto: Protocol) -> io::Result<()> {
+    for (k, v) in pairs {
+        k.write_to(w, proto)?;
+        v.write_to(w, proto)?;
+    }
+    Ok(())
+}
+
+/// Displays a double the way Redis replies with it, e.g. `1.5`, `3`, `inf` or `nan`.
+struct DoubleFmt(f64);
+
+impl DoubleFmt {
+    /// Length of the formatted double, needed up front for the bulk string header.
+    fn display_len(&self) -> usize {
+        struct Counter(usize);
+        impl std::fmt::Write for Counter {
+            fn write_str(&mut self, s: &str) -> std::fmt::Result {
+                self.0 += s.len();
+                Ok(())
+            }
+        }
+        let mut counter = Counter(0);
+        let _ = std::fmt::Write::write_fmt(&mut counter, format_args!("{}", self));
+        counter.0
+    }
+}
+
+impl std::fmt::Display for DoubleFmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let d = self.0;
+        if d.is_nan() {
+            write!(f, "nan")
+        } else if d.is_infinite() {
+            write!(f, "{}", if d > 0.0 { "inf" } else { "-inf" })
+        } else {
+            write!(f, "{}", d)
+        }
+    }
+}
+
+/// Formats a double the way Redis replies with it, e.g. `1.5`, `3`, `inf` or `nan`.
+pub fn format_double(d: f64) -> String {
+    DoubleFmt(d).to_string()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RespErr {
+    Protocol(String),
+    /// A frame couldn't be converted to the requested Rust type.
+    Conversion(String),
+}
+
+impl std::fmt::Display for RespErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RespErr::Protocol(msg) => write!(f, "Protocol error: {}", msg),
+            RespErr::Conversion(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl Error for RespErr {}
+
+/// Bounds on what the parser accepts, so a malformed or hostile peer can't make
+/// it buffer or allocate without limit.
+#[derive(Debug, Clone)]
+pub struct RespLimits {
+    /// Largest bulk string payload, in bytes.
+    pub max_bulk_len: usize,
+    /// Most elements in an array, set, push or map frame.
+    pub max_multibulk_len: usize,
+    /// How deeply aggregates may nest inside each other.
+    pub max_depth: usize,
+    /// Longest inline command or header line, in bytes.
+    pub max_inline_len: usize,
+}
+
+impl Default for RespLimits {
+    fn default() -> Self {
+        // same defaults as redis' proto-max-bulk-len and PROTO_INLINE_MAX_SIZE
+        Self {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: i32::MAX as usize,
+            max_depth: 128,
+            max_inline_len: 64 * 1024,
+        }
+    }
+}
 
 pub struct Resp {}
 
-enum ProcessMode {
-    Collect,
-    Return,
+impl Default for Resp {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Resp {
@@ -55,135 +255,758 @@ impl Resp {
         Self {}
     }
 
-    pub fn parse_line(&self, s: &str) -> Result<RespType, Box<dyn Error>> {
-        let mut mode = ProcessMode::Return;
-        let mut c = Cursor::new(s.as_bytes());
-        struct AggStack {
-            i: u8,
-            len: u8,
-            agg: RespType,
+    /// Parses a single complete frame. Use `RespDecoder` when reading from a socket,
+    /// where frames may be split across reads or several may arrive at once.
+    pub fn parse_line<T: AsRef<[u8]>>(&self, s: T) -> Result<RespType, Box<dyn Error>> {
+        match parse_frame(s.as_ref(), &RespLimits::default())? {
+            Some((frame, _)) => Ok(frame),
+            None => Err(Box::new(RespErr::Protocol("incomplete frame".to_string()))),
         }
-        let mut agg_stack: LinkedList<AggStack> = LinkedList::new();
+    }
+}
 
-        loop {
-            if (c.position() as usize) >= c.get_ref().len() {
+/// Incremental decoder keeping a per-connection buffer.
+///
+/// Bytes are appended with `feed` as they come off the socket and `next_frame`
+/// yields every complete frame in order, returning `Ok(None)` once the remaining
+/// bytes don't form a whole frame yet.
+pub struct RespDecoder {
+    buf: Vec<u8>,
+    // start of the unconsumed bytes, so consumed frames aren't shifted out one by one
+    pos: usize,
+    limits: RespLimits,
+    progress: ScanProgress,
+}
+
+/// How much of the frame at the front of the buffer is known to have arrived, so a frame
+/// split across many reads is only decoded once it's whole, rather than again on each read.
+#[derive(Default)]
+struct ScanProgress {
+    /// Bytes past `pos` holding whole elements of the frame.
+    scanned: usize,
+    /// How many elements each aggregate the scan is inside still expects.
+    open: Vec<usize>,
+}
+
+impl Default for RespDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RespDecoder {
+    pub fn new() -> Self {
+        Self::with_limits(RespLimits::default())
+    }
+
+    pub fn with_limits(limits: RespLimits) -> Self {
+        Self { buf: Vec::new(), pos: 0, limits, progress: ScanProgress::default() }
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn next_frame(&mut self) -> Result<Option<RespType>, RespErr> {
+        let buf = &self.buf[self.pos..];
+        // inline commands are bounded by `max_inline_len`, so only RESP frames need scanning
+        if buf.first().is_some_and(|b| TYPE_BYTES.contains(b)) && !scan_frame(buf, &self.limits, &mut self.progress)? {
+            return Ok(None);
+        }
+        self.progress = ScanProgress::default();
+        match parse_frame(buf, &self.limits)? {
+            Some((frame, consumed)) => {
+                self.pos += consumed;
+                if self.pos == self.buf.len() {
+                    self.buf.clear();
+                    self.pos = 0;
+                }
+                Ok(Some(frame))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Number of bytes received but not yet decoded into a frame.
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.pos = 0;
+        self.progress = ScanProgress::default();
+    }
+}
+
+/// Walks the headers of the frame at the start of `buf` from where `progress` left off,
+/// returning whether the whole frame has arrived. Malformed headers past the limits are
+/// errors right away; anything else amiss is reported as complete, for `parse_frame` to
+/// reject.
+fn scan_frame(buf: &[u8], limits: &RespLimits, progress: &mut ScanProgress) -> Result<bool, RespErr> {
+    loop {
+        if progress.scanned > 0 && progress.open.is_empty() {
+            return Ok(true);
+        }
+        let mut parser = FrameParser { buf, pos: progress.scanned, depth: 0, limits };
+        let Some(&type_byte) = buf.get(parser.pos) else {
+            return Ok(false);
+        };
+        if !TYPE_BYTES.contains(&type_byte) || progress.open.len() >= limits.max_depth {
+            return Ok(true);
+        }
+        parser.pos += 1;
+
+        let aggregate = |parser: &mut FrameParser| parser.read_length("multibulk", limits.max_multibulk_len);
+        let children = match type_byte {
+            b'*' | b'~' | b'>' => aggregate(&mut parser)?.map(|len| len.max(0) as usize),
+            b'%' => aggregate(&mut parser)?.map(|len| len.max(0) as usize * 2),
+            b'|' => aggregate(&mut parser)?.map(|len| len.max(0) as usize * 2 + 1),
+            b'$' | b'=' => match parser.read_length("bulk", limits.max_bulk_len)? {
+                Some(len) if len < 0 => Some(0),
+                Some(len) if buf.len() < parser.pos + len as usize + 2 => None,
+                Some(len) => {
+                    parser.pos += len as usize + 2;
+                    Some(0)
+                }
+                None => None,
+            },
+            _ => parser.read_line()?.map(|_| 0),
+        };
+        let Some(children) = children else {
+            return Ok(false);
+        };
+        progress.scanned = parser.pos;
+
+        if children > 0 {
+            progress.open.push(children);
+            continue;
+        }
+        // a whole element, which may be the last one some aggregates were waiting for
+        while let Some(left) = progress.open.last_mut() {
+            *left -= 1;
+            if *left > 0 {
                 break;
-            };
-            let mut next_byte = [0u8; 1]; // Buffer to hold a single byte
-            c.read_exact(&mut next_byte)?; // Read the first byte
-            let mut curr_type: Option<RespType> = None;
-            println!("next byte: {:?}", char::from_u32(next_byte[0] as u32));
-            
-            match next_byte[0] {
-                b'*' => {
-                    println!("processing arr");
-                    let len: u8 = self.read_line_and_delim(&mut c)?;
-                    agg_stack.push_front(AggStack {
-                        i: 0,
-                        len,
-                        agg: RespType::Array(Vec::new()),
-                    });
-                    println!("added new agg collector");
-                    mode = ProcessMode::Collect;
-                }
-
-                b'+' => {
-                    println!("processing simple string");
-                    curr_type = Some(RespType::String(self.read_line_and_delim(&mut c)?));
-                }
-
-                b'$' => {
-                    let length: isize = self.read_line_and_delim(&mut c)?;
-                    println!("length of bulk string: {}", length);
-                    if length == -1 { // indicates a null type
-                        println!("processing null");
-                        curr_type = Some(RespType::Null);
-                    } else {
-                        println!("processing bulk string");
-                        let mut s = vec![0u8; length as usize];
-                        c.read_exact(&mut s)?;
+            }
+            progress.open.pop();
+        }
+    }
+}
+
+/// Decodes one frame from the start of `buf`, returning it with the number of bytes it spans,
+/// or `None` if `buf` holds only part of a frame.
+fn parse_frame(buf: &[u8], limits: &RespLimits) -> Result<Option<(RespType, usize)>, RespErr> {
+    let mut parser = FrameParser { buf, pos: 0, depth: 0, limits };
+    Ok(parser.parse_top()?.map(|frame| (frame, parser.pos)))
+}
+
+const TYPE_BYTES: &[u8] = b"*+-$:~>%|=,(#_";
+
+/// Splits an inline command the way redis-cli and `sdssplitargs` do: arguments are separated
+/// by whitespace and may be wrapped in double quotes (with `\n`, `\xff`-style escapes) or
+/// single quotes (where only `\'` is an escape).
+fn split_inline_args(line: &[u8]) -> Result<Vec<Vec<u8>>, RespErr> {
+    let unbalanced = || RespErr::Protocol("unbalanced quotes in request".to_string());
+    let mut args = Vec::new();
+    let mut i = 0;
+
+    loop {
+        while i < line.len() && line[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i == line.len() {
+            return Ok(args);
+        }
 
+        let mut arg = Vec::new();
+        match line[i] {
+            b'"' => {
+                i += 1;
+                loop {
+                    match line.get(i) {
+                        None => return Err(unbalanced()),
+                        Some(b'"') => break,
+                        Some(b'\\') if line.get(i + 1) == Some(&b'x')
+                            && line.len() > i + 3
+                            && line[i + 2].is_ascii_hexdigit()
+                            && line[i + 3].is_ascii_hexdigit() =>
                         {
-                            let mut t = String::new();
-                            c.read_line(&mut t)?; // skips the delimiter after string
+                            let hex = std::str::from_utf8(&line[i + 2..i + 4]).unwrap();
+                            arg.push(u8::from_str_radix(hex, 16).unwrap());
+                            i += 3;
                         }
-
-                        curr_type = Some(RespType::BString(String::from_utf8(s).unwrap()));
+                        Some(b'\\') if i + 1 < line.len() => {
+                            i += 1;
+                            arg.push(match line[i] {
+                                b'n' => b'\n',
+                                b'r' => b'\r',
+                                b't' => b'\t',
+                                b'b' => 0x08,
+                                b'a' => 0x07,
+                                c => c,
+                            });
+                        }
+                        Some(&c) => arg.push(c),
+                    }
+                    i += 1;
+                }
+                i += 1;
+                // the closing quote must be followed by a space or end the line
+                if i < line.len() && !line[i].is_ascii_whitespace() {
+                    return Err(unbalanced());
+                }
+            }
+            b'\'' => {
+                i += 1;
+                loop {
+                    match line.get(i) {
+                        None => return Err(unbalanced()),
+                        Some(b'\'') => break,
+                        Some(b'\\') if line.get(i + 1) == Some(&b'\'') => {
+                            arg.push(b'\'');
+                            i += 1;
+                        }
+                        Some(&c) => arg.push(c),
                     }
+                    i += 1;
                 }
+                i += 1;
+                if i < line.len() && !line[i].is_ascii_whitespace() {
+                    return Err(unbalanced());
+                }
+            }
+            _ => {
+                while i < line.len() && !line[i].is_ascii_whitespace() {
+                    arg.push(line[i]);
+                    i += 1;
+                }
+            }
+        }
+        args.push(arg);
+    }
+}
+
+struct FrameParser<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    depth: usize,
+    limits: &'a RespLimits,
+}
 
-                b':' => {
-                    println!("processing integer");
-                    curr_type = Some(RespType::Int(self.read_line_and_delim(&mut c)?))
+fn invalid_multibulk() -> RespErr {
+    RespErr::Protocol("invalid multibulk length".to_string())
+}
+
+// Bails out with `Ok(None)` when the buffer ends before the frame does.
+macro_rules! need {
+    ($e:expr) => {
+        match $e {
+            Some(v) => v,
+            None => return Ok(None),
+        }
+    };
+}
+
+impl<'a> FrameParser<'a> {
+    /// Parses a request, which is either a RESP frame or an inline command such as
+    /// `SET foo "bar baz"` typed into telnet or netcat.
+    fn parse_top(&mut self) -> Result<Option<RespType>, RespErr> {
+        loop {
+            let first = *need!(self.buf.get(self.pos));
+            if TYPE_BYTES.contains(&first) {
+                return self.parse();
+            }
+
+            // inline commands end at '\n', with or without the '\r'
+            let rest = &self.buf[self.pos..];
+            let Some(end) = rest.iter().position(|&b| b == b'\n') else {
+                if rest.len() > self.limits.max_inline_len {
+                    return Err(RespErr::Protocol("too big inline request".to_string()));
                 }
+                return Ok(None);
+            };
+            self.pos += end + 1;
+            let line = rest[..end].strip_suffix(b"\r").unwrap_or(&rest[..end]);
 
-                // b'-' => {
-                //
-                // }
-                _ => {
-                    // Err(Box::from("Failed to parse"))
-                    println!("failed to parse type.... aborting parser...");
-                    break;
+            let args = split_inline_args(line)?;
+            // blank lines are skipped, like redis does
+            if !args.is_empty() {
+                return Ok(Some(RespType::Array(args.into_iter().map(RespType::BString).collect())));
+            }
+        }
+    }
+
+    fn parse(&mut self) -> Result<Option<RespType>, RespErr> {
+        let type_byte = *need!(self.buf.get(self.pos));
+        self.pos += 1;
+
+        let frame = match type_byte {
+            b'*' => match need!(self.read_items()?) {
+                Some(items) => RespType::Array(items),
+                None => RespType::NullArray,
+            },
+            b'~' => RespType::Set(need!(self.read_items()?).ok_or_else(invalid_multibulk)?),
+            b'>' => RespType::Push(need!(self.read_items()?).ok_or_else(invalid_multibulk)?),
+            b'%' => RespType::Map(need!(self.read_pairs()?)),
+            b'|' => {
+                let attrs = need!(self.read_pairs()?);
+                let reply = need!(self.parse_nested()?);
+                RespType::Attribute(attrs, Box::new(reply))
+            }
+            b'+' => RespType::String(need!(self.read_text()?)),
+            b'-' => RespType::Err(need!(self.read_text()?)),
+            b'$' => match need!(self.read_length("bulk", self.limits.max_bulk_len)?) {
+                -1 => RespType::Null,
+                len => RespType::BString(need!(self.read_blob(len as usize)?)),
+            },
+            b'=' => {
+                let len = need!(self.read_length("bulk", self.limits.max_bulk_len)?);
+                if len < 0 {
+                    return Err(RespErr::Protocol("invalid bulk length".to_string()));
+                }
+                let blob = need!(self.read_blob(len as usize)?);
+                if blob.len() < 4 || blob[3] != b':' {
+                    return Err(RespErr::Protocol("invalid verbatim string".to_string()));
                 }
+                let format = String::from_utf8_lossy(&blob[..3]).into_owned();
+                RespType::Verbatim(format, blob[4..].to_vec())
             }
+            b':' => RespType::Int(need!(self.read_number()?)),
+            b',' => RespType::Double(need!(self.read_number()?)),
+            b'(' => {
+                let n = need!(self.read_text()?);
+                let digits = n.strip_prefix(['-', '+']).unwrap_or(&n);
+                if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(RespErr::Protocol(format!("invalid big number '{}'", n)));
+                }
+                RespType::BigNumber(n)
+            }
+            b'#' => match need!(self.read_line()?) {
+                b"t" => RespType::Boolean(true),
+                b"f" => RespType::Boolean(false),
+                _ => return Err(RespErr::Protocol("invalid boolean".to_string())),
+            },
+            b'_' => {
+                if !need!(self.read_line()?).is_empty() {
+                    return Err(RespErr::Protocol("invalid null".to_string()));
+                }
+                RespType::Null
+            }
+            b => {
+                return Err(RespErr::Protocol(format!(
+                    "unexpected type byte '{}'",
+                    (b as char).escape_debug()
+                )));
+            }
+        };
 
-            if let ProcessMode::Return = mode {
-                return if let Some(t) = curr_type {
-                    Ok(t)
-                } else {
-                    Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "Failed to parse type",
-                    )))
-                };
-            } else {
-                let curr_agg = agg_stack.front_mut().unwrap();
-
-                if let Some(t) = curr_type {
-                    if let RespType::Array(v) = &mut curr_agg.agg {
-                        curr_agg.i += 1;
-                        println!("added value to agg collector {:?}", t);
-                        v.push(t);
+        Ok(Some(frame))
+    }
+
+    /// Parses an element of an aggregate, enforcing the nesting limit.
+    fn parse_nested(&mut self) -> Result<Option<RespType>, RespErr> {
+        if self.depth >= self.limits.max_depth {
+            return Err(RespErr::Protocol("too many nested aggregates".to_string()));
+        }
+        self.depth += 1;
+        let frame = self.parse();
+        self.depth -= 1;
+        frame
+    }
+
+    /// Reads the elements of an aggregate, where a `-1` length yields `Some(None)`.
+    fn read_items(&mut self) -> Result<Option<Option<Vec<RespType>>>, RespErr> {
+        let len = need!(self.read_length("multibulk", self.limits.max_multibulk_len)?);
+        if len < 0 {
+            return Ok(Some(None));
+        }
+        // the length is client-controlled, so grow as elements actually arrive
+        let mut items = Vec::with_capacity((len as usize).min(1024));
+        for _ in 0..len {
+            items.push(need!(self.parse_nested()?));
+        }
+        Ok(Some(Some(items)))
+    }
+
+    fn read_pairs(&mut self) -> Result<Option<Vec<(RespType, RespType)>>, RespErr> {
+        let len = need!(self.read_length("multibulk", self.limits.max_multibulk_len)?);
+        if len < 0 {
+            return Err(invalid_multibulk());
+        }
+        let mut pairs = Vec::with_capacity((len as usize).min(1024));
+        for _ in 0..len {
+            let k = need!(self.parse_nested()?);
+            let v = need!(self.parse_nested()?);
+            pairs.push((k, v));
+        }
+        Ok(Some(pairs))
+    }
+
+    /// Reads a `<len>\r\n` header, where `-1` is the only negative length allowed.
+    fn read_length(&mut self, kind: &str, max: usize) -> Result<Option<isize>, RespErr> {
+        let line = need!(self.read_line()?);
+        let invalid = || RespErr::Protocol(format!("invalid {} length", kind));
+        let len: isize = std::str::from_utf8(line)
+            .ok()
+            .and_then(|l| l.parse().ok())
+            .ok_or_else(invalid)?;
+        if len < -1 || (len > 0 && len as usize > max) {
+            return Err(invalid());
+        }
+        Ok(Some(len))
+    }
+
+    /// Reads a length-prefixed payload along with its trailing `\r\n`.
+    fn read_blob(&mut self, len: usize) -> Result<Option<Vec<u8>>, RespErr> {
+        let end = self.pos + len;
+        if self.buf.len() < end + 2 {
+            return Ok(None);
+        }
+        if &self.buf[end..end + 2] != b"\r\n" {
+            return Err(RespErr::Protocol("expected '\\r\\n' after bulk string".to_string()));
+        }
+        let blob = self.buf[self.pos..end].to_vec();
+        self.pos = end + 2;
+        Ok(Some(blob))
+    }
+
+    /// Returns the bytes up to the next `\r\n` and moves past the delimiter.
+    fn read_line(&mut self) -> Result<Option<&'a [u8]>, RespErr> {
+        let rest = &self.buf[self.pos..];
+        let Some(end) = rest.windows(2).position(|w| w == b"\r\n") else {
+            if rest.len() > self.limits.max_inline_len {
+                return Err(RespErr::Protocol("too big line".to_string()));
+            }
+            return Ok(None);
+        };
+        self.pos += end + 2;
+        Ok(Some(&rest[..end]))
+    }
+
+    fn read_text(&mut self) -> Result<Option<String>, RespErr> {
+        let line = need!(self.read_line()?);
+        String::from_utf8(line.to_vec())
+            .map(Some)
+            .map_err(|_| RespErr::Protocol("line is not valid utf-8".to_string()))
+    }
+
+    fn read_number<T: FromStr>(&mut self) -> Result<Option<T>, RespErr> {
+        let line = need!(self.read_text()?);
+        line.parse::<T>()
+            .map(Some)
+            .map_err(|_| RespErr::Protocol(format!("invalid number '{}'", line)))
+    }
+}
+
+/// Conversion from a RESP frame into a Rust type.
+///
+/// Commands use it to read their arguments, which always arrive as bulk strings, so
+/// numbers are parsed out of strings as well as read from integer frames.
+pub trait FromResp: Sized {
+    fn from_resp(resp: &RespType) -> Result<Self, RespErr>;
+
+    /// Lets `Vec<u8>` decode from a bulk string rather than an array of integers.
+    #[doc(hidden)]
+    fn from_resp_bytes(_bytes: &[u8]) -> Option<Vec<Self>> {
+        None
+    }
+}
+
+/// Conversion from a Rust type into a RESP frame.
+pub trait ToResp {
+    fn to_resp(&self) -> RespType;
+
+    /// Lets `[u8]` encode as a bulk string rather than an array of integers.
+    #[doc(hidden)]
+    fn slice_to_resp(items: &[Self]) -> RespType
+    where
+        Self: Sized,
+    {
+        RespType::Array(items.iter().map(ToResp::to_resp).collect())
+    }
+}
+
+fn conversion_err<T>(resp: &RespType, into: &str) -> Result<T, RespErr> {
+    Err(RespErr::Conversion(format!("can't convert {:?} into {}", resp, into)))
+}
+
+impl RespType {
+    /// The payload of a string-like frame.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            RespType::BString(b) | RespType::Verbatim(_, b) => Some(b),
+            RespType::String(s) | RespType::BigNumber(s) => Some(s.as_bytes()),
+            _ => None,
+        }
+    }
+}
+
+impl FromResp for RespType {
+    fn from_resp(resp: &RespType) -> Result<Self, RespErr> {
+        Ok(resp.clone())
+    }
+}
+
+impl ToResp for RespType {
+    fn to_resp(&self) -> RespType {
+        self.clone()
+    }
+}
+
+macro_rules! int_resp {
+    ($($t:ty),*) => {$(
+        impl FromResp for $t {
+            fn from_resp(resp: &RespType) -> Result<Self, RespErr> {
+                let not_int = || RespErr::Conversion("value is not an integer or out of range".to_string());
+                match resp {
+                    RespType::Int(i) => <$t>::try_from(*i).map_err(|_| not_int()),
+                    RespType::Boolean(b) => Ok(*b as $t),
+                    other => {
+                        let bytes = other.as_bytes().ok_or_else(not_int)?;
+                        std::str::from_utf8(bytes)
+                            .ok()
+                            .and_then(|s| s.parse::<$t>().ok())
+                            .ok_or_else(not_int)
                     }
                 }
+            }
+        }
+
+        impl ToResp for $t {
+            fn to_resp(&self) -> RespType {
+                match isize::try_from(*self) {
+                    Ok(i) => RespType::Int(i),
+                    Err(_) => RespType::BigNumber(self.to_string()),
+                }
+            }
+        }
+    )*};
+}
 
-                // check if the current array loop is finished
-                if curr_agg.i == curr_agg.len {
-                    if agg_stack.len() == 1 {
-                        // we finished the main agg
-                        break;
-                    } else {
-                        // we finished processing a nested agg
-                        let curr_agg = agg_stack.pop_front();
-                        let prev_agg = agg_stack.front_mut().unwrap();
+int_resp!(i8, i16, i32, i64, isize, u16, u32, u64, usize);
 
-                        if let RespType::Array(vec) = &mut prev_agg.agg {
-                            vec.push(curr_agg.unwrap().agg);
-                            prev_agg.i += 1;
-                        }
+// u8 gets its own impl so that `Vec<u8>` maps to a bulk string
+impl FromResp for u8 {
+    fn from_resp(resp: &RespType) -> Result<Self, RespErr> {
+        let i = i64::from_resp(resp)?;
+        u8::try_from(i).map_err(|_| RespErr::Conversion("value is not an integer or out of range".to_string()))
+    }
+
+    fn from_resp_bytes(bytes: &[u8]) -> Option<Vec<Self>> {
+        Some(bytes.to_vec())
+    }
+}
+
+impl ToResp for u8 {
+    fn to_resp(&self) -> RespType {
+        RespType::Int(*self as isize)
+    }
+
+    fn slice_to_resp(items: &[Self]) -> RespType {
+        RespType::BString(items.to_vec())
+    }
+}
+
+/// Parses a float the way Redis does, accepting `inf`, `+inf` and `-inf` but not `nan`.
+fn parse_float(bytes: &[u8]) -> Option<f64> {
+    let s = std::str::from_utf8(bytes).ok()?;
+    match s.to_ascii_lowercase().as_str() {
+        "inf" | "+inf" | "infinity" | "+infinity" => Some(f64::INFINITY),
+        "-inf" | "-infinity" => Some(f64::NEG_INFINITY),
+        _ => s.parse::<f64>().ok().filter(|f| f.is_finite()),
+    }
+}
+
+macro_rules! float_resp {
+    ($($t:ty),*) => {$(
+        impl FromResp for $t {
+            fn from_resp(resp: &RespType) -> Result<Self, RespErr> {
+                let not_float = || RespErr::Conversion("value is not a valid float".to_string());
+                match resp {
+                    RespType::Double(d) => Ok(*d as $t),
+                    RespType::Int(i) => Ok(*i as $t),
+                    other => other
+                        .as_bytes()
+                        .and_then(parse_float)
+                        .map(|f| f as $t)
+                        .ok_or_else(not_float),
+                }
+            }
+        }
+
+        impl ToResp for $t {
+            fn to_resp(&self) -> RespType {
+                RespType::Double(*self as f64)
+            }
+        }
+    )*};
+}
+
+float_resp!(f32, f64);
+
+impl FromResp for bool {
+    fn from_resp(resp: &RespType) -> Result<Self, RespErr> {
+        match resp {
+            RespType::Boolean(b) => Ok(*b),
+            RespType::Int(0) => Ok(false),
+            RespType::Int(1) => Ok(true),
+            other => conversion_err(other, "bool"),
+        }
+    }
+}
+
+impl ToResp for bool {
+    fn to_resp(&self) -> RespType {
+        RespType::Boolean(*self)
+    }
+}
+
+impl FromResp for String {
+    fn from_resp(resp: &RespType) -> Result<Self, RespErr> {
+        match resp.as_bytes() {
+            Some(bytes) => String::from_utf8(bytes.to_vec())
+                .map_err(|_| RespErr::Conversion("string is not valid utf-8".to_string())),
+            None => conversion_err(resp, "String"),
+        }
+    }
+}
+
+impl ToResp for String {
+    fn to_resp(&self) -> RespType {
+        RespType::BString(self.as_bytes().to_vec())
+    }
+}
+
+impl ToResp for str {
+    fn to_resp(&self) -> RespType {
+        RespType::BString(self.as_bytes().to_vec())
+    }
+}
+
+impl<T: ToResp + ?Sized> ToResp for &T {
+    fn to_resp(&self) -> RespType {
+        (**self).to_resp()
+    }
+}
+
+impl<T: FromResp> FromResp for Option<T> {
+    fn from_resp(resp: &RespType) -> Result<Self, RespErr> {
+        match resp {
+            RespType::Null | RespType::NullArray => Ok(None),
+            other => T::from_resp(other).map(Some),
+        }
+    }
+}
+
+impl<T: ToResp> ToResp for Option<T> {
+    fn to_resp(&self) -> RespType {
+        match self {
+            Some(v) => v.to_resp(),
+            None => RespType::Null,
+        }
+    }
+}
+
+impl<T: FromResp> FromResp for Vec<T> {
+    fn from_resp(resp: &RespType) -> Result<Self, RespErr> {
+        match resp {
+            RespType::Array(items) | RespType::Set(items) | RespType::Push(items) => {
+                items.iter().map(T::from_resp).collect()
+            }
+            // a null array is an empty list, like redis clients read it
+            RespType::Null | RespType::NullArray => Ok(Vec::new()),
+            other => match other.as_bytes().and_then(T::from_resp_bytes) {
+                Some(v) => Ok(v),
+                None => conversion_err(other, "Vec"),
+            },
+        }
+    }
+}
+
+impl<T: ToResp> ToResp for [T] {
+    fn to_resp(&self) -> RespType {
+        T::slice_to_resp(self)
+    }
+}
+
+impl<T: ToResp> ToResp for Vec<T> {
+    fn to_resp(&self) -> RespType {
+        T::slice_to_resp(self)
+    }
+}
+
+macro_rules! tuple_resp {
+    ($len:expr; $($name:ident $idx:tt),+) => {
+        impl<$($name: FromResp),+> FromResp for ($($name,)+) {
+            fn from_resp(resp: &RespType) -> Result<Self, RespErr> {
+                match resp {
+                    RespType::Array(items) if items.len() == $len => {
+                        Ok(($($name::from_resp(&items[$idx])?,)+))
                     }
-                    // if le
+                    other => conversion_err(other, concat!("a tuple of ", $len)),
                 }
             }
         }
 
-        Ok(agg_stack.pop_front().unwrap().agg)
+        impl<$($name: ToResp),+> ToResp for ($($name,)+) {
+            fn to_resp(&self) -> RespType {
+                RespType::Array(vec![$(self.$idx.to_resp()),+])
+            }
+        }
+    };
+}
+
+tuple_resp!(1; A 0);
+tuple_resp!(2; A 0, B 1);
+tuple_resp!(3; A 0, B 1, C 2);
+tuple_resp!(4; A 0, B 1, C 2, D 3);
+
+/// Reads the key/value pairs of a RESP3 map, or of a RESP2 array flattened as `k1 v1 k2 v2 ...`.
+fn map_pairs(resp: &RespType) -> Result<Vec<(&RespType, &RespType)>, RespErr> {
+    match resp {
+        RespType::Map(pairs) => Ok(pairs.iter().map(|(k, v)| (k, v)).collect()),
+        RespType::Array(items) if items.len() % 2 == 0 => {
+            Ok(items.chunks(2).map(|kv| (&kv[0], &kv[1])).collect())
+        }
+        other => conversion_err(other, "a map"),
     }
+}
 
-    fn read_line_and_delim<T: FromStr>(&self, c: &mut Cursor<&[u8]>) -> Result<T, Box< dyn Error>>
-    where
-        <T as FromStr>::Err: std::fmt::Debug,
-    {
-        let mut line = String::new();
-        c.read_line(&mut line)
-        .map_err(|e| Box::new(e) as Box<dyn Error>)?;
+impl<K, V, S> FromResp for HashMap<K, V, S>
+where
+    K: FromResp + Eq + Hash,
+    V: FromResp,
+    S: BuildHasher + Default,
+{
+    fn from_resp(resp: &RespType) -> Result<Self, RespErr> {
+        map_pairs(resp)?
+            .into_iter()
+            .map(|(k, v)| Ok((K::from_resp(k)?, V::from_resp(v)?)))
+            .collect()
+    }
+}
 
-        println!("line: {}", line.escape_debug());
+impl<K: ToResp, V: ToResp, S> ToResp for HashMap<K, V, S> {
+    fn to_resp(&self) -> RespType {
+        RespType::Map(self.iter().map(|(k, v)| (k.to_resp(), v.to_resp())).collect())
+    }
+}
 
-        // Trim and parse, converting parse error into a string
-        line.trim_end_matches("\r\n")
-            .parse::<T>()
-            .map_err(|e| Box::<dyn Error>::from(format!("Parse error: {:?}", e))) // Convert parse error to a string
+impl<K: FromResp + Ord, V: FromResp> FromResp for BTreeMap<K, V> {
+    fn from_resp(resp: &RespType) -> Result<Self, RespErr> {
+        map_pairs(resp)?
+            .into_iter()
+            .map(|(k, v)| Ok((K::from_resp(k)?, V::from_resp(v)?)))
+            .collect()
     }
 }
 
+impl<K: ToResp, V: ToResp> ToResp for BTreeMap<K, V> {
+    fn to_resp(&self) -> RespType {
+        RespType::Map(self.iter().map(|(k, v)| (k.to_resp(), v.to_resp())).collect())
+    }
+}
@@ -0,0 +1,492 @@
+//! Maps serde's data model onto RESP frames, so any `Serialize`/`Deserialize` type can be
+//! written as or read from a reply. Structs and maps become RESP3 maps, sequences become
+//! arrays, and numbers are also read back out of bulk strings, as Redis stores them.
+
+use serde::de::{self, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize};
+
+use super::{map_pairs, FromResp, RespErr, RespType};
+
+impl ser::Error for RespErr {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        RespErr::Conversion(msg.to_string())
+    }
+}
+
+impl de::Error for RespErr {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        RespErr::Conversion(msg.to_string())
+    }
+}
+
+/// Serializes `value` into a RESP frame.
+pub fn to_resp<T: Serialize + ?Sized>(value: &T) -> Result<RespType, RespErr> {
+    value.serialize(Serializer)
+}
+
+/// Deserializes a `T` out of a RESP frame.
+pub fn from_resp<'de, T: de::Deserialize<'de>>(resp: &'de RespType) -> Result<T, RespErr> {
+    T::deserialize(Deserializer(resp))
+}
+
+fn bulk(s: &str) -> RespType {
+    RespType::BString(s.as_bytes().to_vec())
+}
+
+struct Serializer;
+
+macro_rules! serialize_int {
+    ($($method:ident: $t:ty),*) => {$(
+        fn $method(self, v: $t) -> Result<RespType, RespErr> {
+            Ok(match isize::try_from(v) {
+                Ok(i) => RespType::Int(i),
+                Err(_) => RespType::BigNumber(v.to_string()),
+            })
+        }
+    )*};
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = RespType;
+    type Error = RespErr;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    serialize_int!(
+        serialize_i8: i8, serialize_i16: i16, serialize_i32: i32, serialize_i64: i64,
+        serialize_u8: u8, serialize_u16: u16, serialize_u32: u32, serialize_u64: u64
+    );
+
+    fn serialize_bool(self, v: bool) -> Result<RespType, RespErr> {
+        Ok(RespType::Boolean(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<RespType, RespErr> {
+        Ok(RespType::Double(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<RespType, RespErr> {
+        Ok(RespType::Double(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<RespType, RespErr> {
+        Ok(bulk(v.encode_utf8(&mut [0; 4])))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<RespType, RespErr> {
+        Ok(bulk(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<RespType, RespErr> {
+        Ok(RespType::BString(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<RespType, RespErr> {
+        Ok(RespType::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<RespType, RespErr> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<RespType, RespErr> {
+        Ok(RespType::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<RespType, RespErr> {
+        Ok(RespType::Null)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<RespType, RespErr> {
+        Ok(bulk(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<RespType, RespErr> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<RespType, RespErr> {
+        Ok(RespType::Map(vec![(bulk(variant), value.serialize(self)?)]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, RespErr> {
+        Ok(SeqSerializer { variant: None, items: Vec::with_capacity(len.unwrap_or(0)) })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, RespErr> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SeqSerializer, RespErr> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, RespErr> {
+        Ok(SeqSerializer { variant: Some(variant), items: Vec::with_capacity(len) })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapSerializer, RespErr> {
+        Ok(MapSerializer { variant: None, pairs: Vec::with_capacity(len.unwrap_or(0)), key: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<MapSerializer, RespErr> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<MapSerializer, RespErr> {
+        Ok(MapSerializer { variant: Some(variant), pairs: Vec::with_capacity(len), key: None })
+    }
+}
+
+/// Wraps the frame of an enum variant in a single-entry map keyed by the variant's name.
+fn wrap_variant(variant: Option<&'static str>, frame: RespType) -> RespType {
+    match variant {
+        Some(variant) => RespType::Map(vec![(bulk(variant), frame)]),
+        None => frame,
+    }
+}
+
+struct SeqSerializer {
+    variant: Option<&'static str>,
+    items: Vec<RespType>,
+}
+
+impl SeqSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RespErr> {
+        self.items.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<RespType, RespErr> {
+        Ok(wrap_variant(self.variant, RespType::Array(self.items)))
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = RespType;
+    type Error = RespErr;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RespErr> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RespType, RespErr> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = RespType;
+    type Error = RespErr;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RespErr> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RespType, RespErr> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = RespType;
+    type Error = RespErr;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RespErr> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RespType, RespErr> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = RespType;
+    type Error = RespErr;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RespErr> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RespType, RespErr> {
+        self.finish()
+    }
+}
+
+struct MapSerializer {
+    variant: Option<&'static str>,
+    pairs: Vec<(RespType, RespType)>,
+    key: Option<RespType>,
+}
+
+impl MapSerializer {
+    fn finish(self) -> Result<RespType, RespErr> {
+        Ok(wrap_variant(self.variant, RespType::Map(self.pairs)))
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = RespType;
+    type Error = RespErr;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), RespErr> {
+        self.key = Some(key.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RespErr> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| RespErr::Conversion("map value serialized before its key".to_string()))?;
+        self.pairs.push((key, value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<RespType, RespErr> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = RespType;
+    type Error = RespErr;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), RespErr> {
+        self.pairs.push((bulk(key), value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<RespType, RespErr> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+    type Ok = RespType;
+    type Error = RespErr;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), RespErr> {
+        self.pairs.push((bulk(key), value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<RespType, RespErr> {
+        self.finish()
+    }
+}
+
+struct Deserializer<'de>(&'de RespType);
+
+macro_rules! deserialize_num {
+    ($($method:ident: $t:ty => $visit:ident),*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespErr> {
+            visitor.$visit(<$t>::from_resp(self.0)?)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = RespErr;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespErr> {
+        match self.0 {
+            RespType::BString(b) | RespType::Verbatim(_, b) => match std::str::from_utf8(b) {
+                Ok(s) => visitor.visit_borrowed_str(s),
+                Err(_) => visitor.visit_borrowed_bytes(b),
+            },
+            RespType::String(s) | RespType::BigNumber(s) => visitor.visit_borrowed_str(s),
+            RespType::Err(e) => Err(RespErr::Conversion(e.clone())),
+            RespType::Int(i) => visitor.visit_i64(*i as i64),
+            RespType::Double(d) => visitor.visit_f64(*d),
+            RespType::Boolean(b) => visitor.visit_bool(*b),
+            RespType::Null | RespType::NullArray => visitor.visit_none(),
+            RespType::Array(items) | RespType::Set(items) | RespType::Push(items) => {
+                visitor.visit_seq(SeqDeserializer(items.iter()))
+            }
+            RespType::Map(_) => self.deserialize_map(visitor),
+            RespType::Attribute(_, reply) => Deserializer(reply).deserialize_any(visitor),
+        }
+    }
+
+    deserialize_num!(
+        deserialize_i8: i8 => visit_i8, deserialize_i16: i16 => visit_i16,
+        deserialize_i32: i32 => visit_i32, deserialize_i64: i64 => visit_i64,
+        deserialize_u8: u8 => visit_u8, deserialize_u16: u16 => visit_u16,
+        deserialize_u32: u32 => visit_u32, deserialize_u64: u64 => visit_u64,
+        deserialize_f32: f32 => visit_f32, deserialize_f64: f64 => visit_f64
+    );
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespErr> {
+        match self.0.as_bytes() {
+            Some(b"1") | Some(b"true") => visitor.visit_bool(true),
+            Some(b"0") | Some(b"false") => visitor.visit_bool(false),
+            _ => visitor.visit_bool(bool::from_resp(self.0)?),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespErr> {
+        match self.0 {
+            RespType::Null | RespType::NullArray => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespErr> {
+        match self.0.as_bytes() {
+            Some(b) => visitor.visit_borrowed_bytes(b),
+            None => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespErr> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, RespErr> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespErr> {
+        visitor.visit_map(MapDeserializer { pairs: map_pairs(self.0)?.into_iter(), value: None })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, RespErr> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, RespErr> {
+        match self.0 {
+            RespType::Map(pairs) if pairs.len() == 1 => {
+                visitor.visit_enum(EnumDeserializer { variant: &pairs[0].0, value: Some(&pairs[0].1) })
+            }
+            other => visitor.visit_enum(EnumDeserializer { variant: other, value: None }),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        char str string unit unit_struct seq tuple tuple_struct identifier ignored_any
+    }
+}
+
+struct SeqDeserializer<'de>(std::slice::Iter<'de, RespType>);
+
+impl<'de> SeqAccess<'de> for SeqDeserializer<'de> {
+    type Error = RespErr;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, RespErr> {
+        self.0.next().map(|item| seed.deserialize(Deserializer(item))).transpose()
+    }
+}
+
+struct MapDeserializer<'de> {
+    pairs: std::vec::IntoIter<(&'de RespType, &'de RespType)>,
+    value: Option<&'de RespType>,
+}
+
+impl<'de> MapAccess<'de> for MapDeserializer<'de> {
+    type Error = RespErr;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, RespErr> {
+        match self.pairs.next() {
+            Some((k, v)) => {
+                self.value = Some(v);
+                seed.deserialize(Deserializer(k)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, RespErr> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| RespErr::Conversion("map value read before its key".to_string()))?;
+        seed.deserialize(Deserializer(value))
+    }
+}
+
+struct EnumDeserializer<'de> {
+    variant: &'de RespType,
+    value: Option<&'de RespType>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer<'de> {
+    type Error = RespErr;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), RespErr> {
+        let variant = seed.deserialize(Deserializer(self.variant))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for EnumDeserializer<'de> {
+    type Error = RespErr;
+
+    fn unit_variant(self) -> Result<(), RespErr> {
+        match self.value {
+            None | Some(RespType::Null) | Some(RespType::NullArray) => Ok(()),
+            Some(other) => Err(RespErr::Conversion(format!("expected a unit variant, got {:?}", other))),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, RespErr> {
+        match self.value {
+            Some(value) => seed.deserialize(Deserializer(value)),
+            None => seed.deserialize(().into_deserializer()),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, RespErr> {
+        match self.value {
+            Some(value) => de::Deserializer::deserialize_seq(Deserializer(value), visitor),
+            None => Err(RespErr::Conversion("expected a tuple variant".to_string())),
+        }
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, RespErr> {
+        match self.value {
+            Some(value) => de::Deserializer::deserialize_map(Deserializer(value), visitor),
+            None => Err(RespErr::Conversion("expected a struct variant".to_string())),
+        }
+    }
+}
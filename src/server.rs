@@ -1,37 +1,61 @@
 // TODO: create a multi-threaded web server that accepts redis client requests and runs them as commands.
 
 use std::{
-    io::{Read, Write},
+    collections::{HashMap, VecDeque},
+    io::{self, Read, Write},
     net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
     sync::{
         atomic::{AtomicBool, Ordering},
         mpsc::{channel, Receiver, Sender},
-        Arc, Mutex, MutexGuard,
+        Arc, Mutex,
     },
     thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Context};
 
-use crate::{CommandHandler, Resp, RespType, Storage};
+use crate::{BlockState, CommandHandler, EncodingLimits, RespDecoder, RespLimits, RespType, Storage};
 
 pub struct Server {
     pool: Option<ThreadPool>,
     running: Arc<AtomicBool>,
     address: Option<SocketAddr>,
-    storage: Arc<Mutex<Storage>>
+    storage: Arc<Mutex<Storage>>,
+    limits: RespLimits,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Server {
     pub fn new() -> Self {
+        Self::with_limits(RespLimits::default())
+    }
+
+    /// Creates a server whose connections reject requests exceeding `limits`.
+    pub fn with_limits(limits: RespLimits) -> Self {
         let running = Arc::new(AtomicBool::new(false));
         Self {
             pool: None,
             running: Arc::clone(&running),
             address: None,
-            storage: Arc::new(Mutex::new(Storage::new()))
+            storage: Arc::new(Mutex::new(Storage::new())),
+            limits,
+        }
+    }
+
+    /// Makes collections switch from their compact encoding at `limits` instead of the defaults.
+    pub fn with_encoding_limits(self, limits: EncodingLimits) -> Self {
+        Self {
+            storage: Arc::new(Mutex::new(Storage::with_limits(limits))),
+            ..self
         }
     }
+
     pub fn listen<A: ToSocketAddrs>(&mut self, addr: A) -> anyhow::Result<()> {
         let socket_addr = addr
             .to_socket_addrs()?
@@ -41,19 +65,26 @@ impl Server {
         self.address = Some(socket_addr);
         let listener = TcpListener::bind(addr).context("Failed to bind address")?;
         self.running.store(true, Ordering::SeqCst);
-        self.pool = Some(ThreadPool::build(10, Arc::clone(&self.running)));
+        let pool = ThreadPool::build(10, Arc::clone(&self.running));
+        let shared = Arc::new(Shared {
+            storage: Arc::clone(&self.storage),
+            blocked: Mutex::new(BlockedClients::default()),
+            jobs: pool.sender.clone(),
+        });
+        self.pool = Some(pool);
+        spawn_active_expire(Arc::clone(&self.storage), Arc::clone(&self.running));
+        spawn_block_timeouts(Arc::clone(&shared), Arc::clone(&self.running));
 
         for stream in listener.incoming() {
-            let storage = Arc::clone(&self.storage);
             match stream {
                 Ok(stream) => {
-                    self.pool.as_ref().unwrap().execute(Box::new(move || {
-                        let res = handle_client(stream, storage);
-                        // let res = stream.write_all("+OK\r\n".as_bytes());
-                        if let Err(e) = res {
-                            println!("Failed to write to client: {e}");
-                        }
-                    }));
+                    let conn = Connection {
+                        stream: Arc::new(stream),
+                        decoder: RespDecoder::with_limits(self.limits.clone()),
+                        handler: CommandHandler::new(Arc::clone(&self.storage)),
+                        out: Vec::new(),
+                    };
+                    shared.schedule(conn);
                 }
                 Err(e) => {
                     println!("Failed to connect to client {e}");
@@ -73,68 +104,311 @@ impl Server {
     }
 }
 
-fn handle_client(mut stream: TcpStream, storage: Arc<Mutex<Storage>>) -> anyhow::Result<()> {
-    let mut buf = [0; 1024];
+/// Reclaims expired keys nobody reads anymore, which lazy expiry alone would keep forever.
+fn spawn_active_expire(storage: Arc<Mutex<Storage>>, running: Arc<AtomicBool>) {
+    std::thread::spawn(move || {
+        while running.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(100));
+            storage.lock().unwrap().active_expire_cycle(Duration::from_millis(25));
+        }
+    });
+}
+
+/// Replies to blocked clients whose timeout passed, and drops the ones that disconnected.
+fn spawn_block_timeouts(shared: Arc<Shared>, running: Arc<AtomicBool>) {
+    std::thread::spawn(move || {
+        while running.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(10));
+            shared.expire_blocked();
+        }
+    });
+}
+
+/// A client connection along with its state, so it can be picked up by any worker.
+struct Connection {
+    /// Shared so the timeout sweep can check the socket of a parked client without holding
+    /// the lock on the blocked clients.
+    stream: Arc<TcpStream>,
+    decoder: RespDecoder,
+    handler: CommandHandler,
+    /// replies to every frame decoded from one read are serialized into this buffer and
+    /// written back together; it's cleared rather than dropped so its allocation is reused
+    out: Vec<u8>,
+}
 
-    loop {
-        let read_buf: Vec<u8> = match stream.read(&mut buf) {
-            Ok(0) => break,
-            Ok(n) => {
-                let mut valid = Vec::new();
-                valid.extend_from_slice(&buf[..n]); // Append only the received bytes
+impl Connection {
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.out.is_empty() {
+            self.stream.as_ref().write_all(&self.out)?;
+            self.out.clear();
+        }
+        Ok(())
+    }
 
-                // Debug print: Show raw bytes and their escaped representation
-                println!("Raw received: {:?}", &valid);
-                println!("Received (escaped): {:?}", String::from_utf8_lossy(&valid));
+    /// Writes as much of the pending replies as the socket takes without blocking, the
+    /// rest is left for `flush`.
+    #[cfg(unix)]
+    fn flush_nowait(&mut self) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
 
-                valid
-            },
-            Err(e) => {
-                anyhow::bail!(format!("Failed to read from client {e}"));
+        let mut sent = 0;
+        while sent < self.out.len() {
+            let rest = &self.out[sent..];
+            let n = unsafe {
+                libc::send(self.stream.as_raw_fd(), rest.as_ptr().cast(), rest.len(), libc::MSG_DONTWAIT)
+            };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::WouldBlock => break,
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(err),
+                }
             }
-        };
+            sent += n as usize;
+        }
+        self.out.drain(..sent);
+        Ok(())
+    }
 
-        if !read_buf.ends_with(b"\r\n") {
-            let c = String::from_utf8_lossy(&read_buf);
-            println!("skipping current read as the cmd delim is not reached..");
-            println!("read: {}", c.escape_debug());
-            continue;
-        };
+    #[cfg(not(unix))]
+    fn flush_nowait(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+}
+
+/// Whether the peer hung up, checked without consuming anything it sent. The socket stays
+/// blocking, so this is safe while a worker may be reading from it.
+#[cfg(unix)]
+fn peer_closed(stream: &TcpStream) -> bool {
+    use std::os::fd::AsRawFd;
+
+    let mut byte = 0u8;
+    let flags = libc::MSG_PEEK | libc::MSG_DONTWAIT;
+    let n = unsafe { libc::recv(stream.as_raw_fd(), (&mut byte as *mut u8).cast(), 1, flags) };
+    match n {
+        0 => true,
+        n if n > 0 => false,
+        _ => io::Error::last_os_error().kind() != io::ErrorKind::WouldBlock,
+    }
+}
+
+/// Without a non-blocking peek, hung up clients are noticed when their reply fails instead.
+#[cfg(not(unix))]
+fn peer_closed(_stream: &TcpStream) -> bool {
+    false
+}
+
+/// A connection waiting for a blocking command to be served.
+struct Parked {
+    conn: Connection,
+    state: BlockState,
+}
+
+/// Connections parked on blocking commands. They don't hold a worker while they wait;
+/// a write to one of their keys or their timeout hands them back to the pool.
+#[derive(Default)]
+struct BlockedClients {
+    clients: HashMap<u64, Parked>,
+    /// Ids of the clients waiting on each key, in the order they blocked, so the
+    /// longest waiting client is served first.
+    waiting: HashMap<Vec<u8>, VecDeque<u64>>,
+}
+
+impl BlockedClients {
+    fn unpark(&mut self, id: u64, storage: &Mutex<Storage>) -> Option<Parked> {
+        let parked = self.clients.remove(&id)?;
+        let mut storage = storage.lock().unwrap();
+        for key in &parked.state.keys {
+            storage.unwatch(key);
+            if let Some(queue) = self.waiting.get_mut(key) {
+                queue.retain(|&waiting| waiting != id);
+                if queue.is_empty() {
+                    self.waiting.remove(key);
+                }
+            }
+        }
+        Some(parked)
+    }
+}
 
-        let cmd: std::borrow::Cow<'_, str> = String::from_utf8_lossy(&read_buf);
-        let mut cmd_handler = CommandHandler::new(Arc::clone(&storage));
-        let req_resp = Resp::new().parse_line(&cmd);
-        match req_resp {
-            Ok(parsed) => {
-                let res = cmd_handler.handle_cmd(parsed);
-                match res {
-                    Ok(resp_response) => {
-                        println!("Writing to client {:?}" , resp_response);
-                         stream.write_all(resp_response.serialize().as_bytes())?;
+/// State every connection job shares.
+struct Shared {
+    storage: Arc<Mutex<Storage>>,
+    /// Lock before `storage` when both are needed.
+    blocked: Mutex<BlockedClients>,
+    jobs: Sender<Job>,
+}
+
+impl Shared {
+    /// Hands the connection to the pool to serve it until it closes or blocks.
+    fn schedule(self: &Arc<Self>, conn: Connection) {
+        let shared = Arc::clone(self);
+        let job: Job = Box::new(move || {
+            if let Err(e) = shared.serve(conn) {
+                println!("Failed to write to client: {e}");
+            }
+        });
+        if let Err(e) = self.jobs.send(job) {
+            eprintln!("Failed to send task to worker thread: {}", e);
+        }
+    }
+
+    fn serve(self: &Arc<Self>, mut conn: Connection) -> anyhow::Result<()> {
+        let mut buf = [0; 16 * 1024];
+
+        loop {
+            loop {
+                match conn.decoder.next_frame() {
+                    Ok(Some(parsed)) => {
+                        let res = conn.handler.handle_cmd(parsed);
+                        self.serve_ready_clients();
+
+                        if let Some(state) = conn.handler.take_blocked() {
+                            // frames after the blocking command wait for it to be served
+                            conn.flush()?;
+                            self.park(conn, state);
+                            return Ok(());
+                        }
+                        match res {
+                            Ok(resp_response) => {
+                                resp_response.write_to(&mut conn.out, conn.handler.protocol())?;
+                            },
+                            Err(e) => {
+                                RespType::from(e).write_to(&mut conn.out, conn.handler.protocol())?;
+                            },
+                        }
                     },
+                    Ok(None) => break,
                     Err(e) => {
-                        println!("Error: Writing to client {:?}" , e);
-                        //  stream.write_all(format!("Failed to exec command {e}").as_bytes())?; 
-                        stream.write_all(RespType::Err(format!("Failed to exec command {e}")).serialize().as_bytes())?;
-                    },
+                        // like redis, reply with the protocol error and drop the client,
+                        // as there's no telling where the next frame starts
+                        RespType::Err(format!("ERR {e}")).write_to(&mut conn.out, conn.handler.protocol())?;
+                        conn.flush()?;
+                        return Ok(());
+                    }
+                }
+            }
+            conn.flush()?;
+
+            let n = match conn.stream.as_ref().read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(n) => n,
+                Err(e) => {
+                    anyhow::bail!(format!("Failed to read from client {e}"));
                 }
-                
-            },
-            Err(e) => {
-                println!("Failed to parse cmd {}", e);
-            } 
-        
+            };
+            conn.decoder.feed(&buf[..n]);
+        }
+    }
+
+    fn park(self: &Arc<Self>, conn: Connection, state: BlockState) {
+        let id = conn.handler.client_id();
+        {
+            let mut blocked = self.blocked.lock().unwrap();
+            let mut storage = self.storage.lock().unwrap();
+            for key in &state.keys {
+                storage.watch(key);
+                blocked.waiting.entry(key.clone()).or_default().push_back(id);
+                // a write may have landed between the command finding nothing and now
+                storage.signal_ready(key);
+            }
+            drop(storage);
+            blocked.clients.insert(id, Parked { conn, state });
         }
-        
+        self.serve_ready_clients();
     }
 
-    Ok(())
+    /// Runs the commands of clients blocked on keys that were written to, oldest waiter
+    /// first, and reschedules the ones that got served.
+    fn serve_ready_clients(self: &Arc<Self>) {
+        loop {
+            // serving one client can make more keys ready, e.g. BLMOVE pushing to its destination
+            let ready = self.storage.lock().unwrap().take_ready_keys();
+            if ready.is_empty() {
+                return;
+            }
+
+            let mut served = Vec::new();
+            let mut blocked = self.blocked.lock().unwrap();
+            for key in ready {
+                let Some(queue) = blocked.waiting.get(&key) else {
+                    continue;
+                };
+                for id in queue.iter().copied().collect::<Vec<_>>() {
+                    let Some(parked) = blocked.clients.get_mut(&id) else {
+                        continue;
+                    };
+                    let res = parked.conn.handler.handle_cmd(parked.state.command.clone());
+                    if parked.conn.handler.take_blocked().is_some() {
+                        continue;
+                    }
+
+                    let Some(Parked { mut conn, .. }) = blocked.unpark(id, &self.storage) else {
+                        continue;
+                    };
+                    let protocol = conn.handler.protocol();
+                    let written = match res {
+                        Ok(reply) => reply.write_to(&mut conn.out, protocol),
+                        Err(e) => RespType::from(e).write_to(&mut conn.out, protocol),
+                    };
+                    if written.is_ok() {
+                        served.push(conn);
+                    }
+                }
+            }
+            drop(blocked);
+            self.reply_and_schedule(served);
+        }
+    }
+
+    /// Sends clients that were unparked their replies and hands them back to the pool. The
+    /// write doesn't block, a client that stopped reading would otherwise hold up whoever
+    /// woke it up; what doesn't fit in the socket is written by its worker.
+    fn reply_and_schedule(self: &Arc<Self>, conns: Vec<Connection>) {
+        for mut conn in conns {
+            if conn.flush_nowait().is_ok() {
+                self.schedule(conn);
+            }
+        }
+    }
+
+    fn expire_blocked(self: &Arc<Self>) {
+        let now = Instant::now();
+        // the sockets are checked after letting go of the lock, as that takes a syscall each
+        let parked: Vec<(u64, Arc<TcpStream>)> = {
+            let blocked = self.blocked.lock().unwrap();
+            blocked.clients.iter().map(|(&id, parked)| (id, Arc::clone(&parked.conn.stream))).collect()
+        };
+        let closed: Vec<u64> = parked.into_iter().filter(|(_, stream)| peer_closed(stream)).map(|(id, _)| id).collect();
+
+        let mut expired = Vec::new();
+        let mut blocked = self.blocked.lock().unwrap();
+        for id in closed {
+            blocked.unpark(id, &self.storage);
+        }
+        let timed_out: Vec<u64> = blocked
+            .clients
+            .iter()
+            .filter(|(_, parked)| parked.state.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(&id, _)| id)
+            .collect();
+        for id in timed_out {
+            let Some(Parked { mut conn, state }) = blocked.unpark(id, &self.storage) else {
+                continue;
+            };
+            if state.timeout_reply.write_to(&mut conn.out, conn.handler.protocol()).is_ok() {
+                expired.push(conn);
+            }
+        }
+        drop(blocked);
+        self.reply_and_schedule(expired);
+    }
 }
 
 struct ThreadPool {
     workers: Vec<Worker>,
-    pool_size: usize,
-    running: Arc<AtomicBool>,
     sender: Sender<Job>,
 }
 
@@ -147,14 +421,8 @@ impl ThreadPool {
 
         let rx = Arc::new(Mutex::new(rx));
 
-        let workers: Vec<Worker> = vec![0; pool_size]
-            .iter()
-            .enumerate()
-            .map(|(i, _)| {
-                let rx = Arc::clone(&rx);
-                let running = Arc::clone(&running);
-                return Worker::new(i, rx, Arc::clone(&running));
-            })
+        let workers: Vec<Worker> = (0..pool_size)
+            .map(|i| Worker::new(i, Arc::clone(&rx), Arc::clone(&running)))
             .collect();
 
         println!("spawned {} workers", workers.len());
@@ -162,20 +430,11 @@ impl ThreadPool {
         Self {
             workers,
             sender: tx,
-            pool_size,
-            running,
-        }
-    }
-
-    fn execute(&self, task: Job) {
-        if let Err(e) = self.sender.send(task) {
-            eprintln!("Failed to send task to worker thread: {}", e);
         }
     }
 }
 
 struct Worker {
-    id: usize,
     thread: Option<JoinHandle<()>>,
 }
 
@@ -198,7 +457,6 @@ impl Worker {
         });
 
         Self {
-            id,
             thread: Some(thread),
         }
     }
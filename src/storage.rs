@@ -1,28 +1,552 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+
+mod dict;
+mod hash;
+mod set;
+mod stream;
+mod zset;
+
+pub use dict::Dict;
+pub use hash::Hash;
+pub use set::Set;
+pub use stream::{Consumer, ConsumerGroup, Fields, PendingEntry, Stream, StreamEntry, StreamId, Trim};
+pub use zset::{Direction, LexBound, LexRange, ScoreRange, ZSet};
+
+/// Volatile keys sampled per round of the active expire cycle.
+const ACTIVE_EXPIRE_SAMPLE: usize = 20;
+/// A round expiring more than this share of its sample is followed by another one,
+/// as there are likely many more expired keys left.
+const ACTIVE_EXPIRE_REPEAT_PERCENT: usize = 25;
+
+/// Current unix time in milliseconds, which expiry deadlines are stored as.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Sizes up to which collections keep their compact encoding, named after the redis
+/// config options they mirror.
+#[derive(Debug, Clone)]
+pub struct EncodingLimits {
+    /// Most fields a hash may have before it's converted to a hash table.
+    pub hash_max_listpack_entries: usize,
+    /// Longest field or value, in bytes, a compact hash may hold.
+    pub hash_max_listpack_value: usize,
+    /// Most members a set of integers may have before it's converted to a hash table.
+    pub set_max_intset_entries: usize,
+    /// Most entries packed together in a node of a stream.
+    pub stream_node_max_entries: usize,
+    /// Largest size, in bytes, a HyperLogLog may grow to in its sparse encoding.
+    pub hll_sparse_max_bytes: usize,
+}
+
+impl Default for EncodingLimits {
+    fn default() -> Self {
+        Self {
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            set_max_intset_entries: 512,
+            stream_node_max_entries: 100,
+            hll_sparse_max_bytes: 3000,
+        }
+    }
+}
+
+/// A stored value, which is one of the redis data types.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(Vec<u8>),
+    List(VecDeque<Vec<u8>>),
+    Hash(Hash),
+    Set(Set),
+    ZSet(ZSet),
+    Stream(Stream),
+}
+
+impl Value {
+    /// The name `TYPE` reports for the value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Str(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+
+    /// Whether the value is a collection with nothing left in it, in which case
+    /// the key is removed like in redis.
+    fn is_empty_collection(&self) -> bool {
+        match self {
+            Value::Str(_) => false,
+            Value::List(list) => list.is_empty(),
+            Value::Hash(hash) => hash.is_empty(),
+            Value::Set(set) => set.is_empty(),
+            Value::ZSet(zset) => zset.is_empty(),
+            // streams are kept when emptied, so their last ID isn't lost
+            Value::Stream(_) => false,
+        }
+    }
+}
+
+/// Returned when a key holds a different type than the operation works on.
+#[derive(Debug, PartialEq)]
+pub struct WrongType;
 
 pub struct Storage {
-    items: HashMap<String, String>
+    items: HashMap<Vec<u8>, Value>,
+    /// Expiry deadline of each volatile key, with its index in `volatile`.
+    expires: HashMap<Vec<u8>, (u64, usize)>,
+    /// Keys that have an expiry, so the active expire cycle can sample them at random.
+    volatile: Vec<Vec<u8>>,
+    /// Keys blocked clients wait on, with how many clients wait on each.
+    watched: HashMap<Vec<u8>, usize>,
+    /// Watched keys that were written to since the last `take_ready_keys`.
+    ready: Vec<Vec<u8>>,
+    /// Hashes with expiring fields, ordered by when their next field expires, so the
+    /// active expire cycle can find them without sampling.
+    field_expiry: BTreeSet<(u64, Vec<u8>)>,
+    /// When each hash in `field_expiry` is queued for.
+    field_expiry_at: HashMap<Vec<u8>, u64>,
+    limits: EncodingLimits,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Storage {
 
     pub fn new() -> Self {
-        Self {items: HashMap::new()}
+        Self::with_limits(EncodingLimits::default())
+    }
+
+    /// Creates a storage whose collections switch encodings at `limits`.
+    pub fn with_limits(limits: EncodingLimits) -> Self {
+        Self {
+            items: HashMap::new(),
+            expires: HashMap::new(),
+            volatile: Vec::new(),
+            watched: HashMap::new(),
+            ready: Vec::new(),
+            field_expiry: BTreeSet::new(),
+            field_expiry_at: HashMap::new(),
+            limits,
+        }
+    }
+
+    pub fn limits(&self) -> &EncodingLimits {
+        &self.limits
     }
 
-    pub fn set(&mut self, k: &str, v: &str) {
-        self.items.insert(k.to_string(), v.to_string());
+    /// Sets a string value, replacing whatever the key held and discarding its expiry.
+    pub fn set(&mut self, k: Vec<u8>, v: Vec<u8>) {
+        self.set_value(k, Value::Str(v));
     }
 
-    pub fn get(&self, k: &str) -> Option<&String> {
+    /// Sets a string value, leaving the key's expiry as it was.
+    pub fn set_keep_ttl(&mut self, k: Vec<u8>, v: Vec<u8>) {
+        self.expire_if_needed(&k);
+        self.items.insert(k, Value::Str(v));
+    }
+
+    /// Sets a value of any type, discarding any expiry the key had.
+    pub fn set_value(&mut self, k: Vec<u8>, v: Value) {
+        self.remove_expire(&k);
+        self.items.insert(k, v);
+    }
+
+    pub fn get_value(&mut self, k: &[u8]) -> Option<&Value> {
+        self.expire_if_needed(k);
         self.items.get(k)
     }
 
-    pub fn exists(&self, k: &str) -> bool {
+    /// The string stored at the key, or `WrongType` if the key holds another type.
+    pub fn get(&mut self, k: &[u8]) -> Result<Option<&Vec<u8>>, WrongType> {
+        match self.get_value(k) {
+            Some(Value::Str(v)) => Ok(Some(v)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// The string to change in place, keeping the key's expiry.
+    pub fn get_mut(&mut self, k: &[u8]) -> Result<Option<&mut Vec<u8>>, WrongType> {
+        self.expire_if_needed(k);
+        match self.items.get_mut(k) {
+            Some(Value::Str(v)) => Ok(Some(v)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn list(&mut self, k: &[u8]) -> Result<Option<&VecDeque<Vec<u8>>>, WrongType> {
+        match self.get_value(k) {
+            Some(Value::List(list)) => Ok(Some(list)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn list_mut(&mut self, k: &[u8]) -> Result<Option<&mut VecDeque<Vec<u8>>>, WrongType> {
+        self.expire_if_needed(k);
+        match self.items.get_mut(k) {
+            Some(Value::List(list)) => Ok(Some(list)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// The list at the key, creating an empty one if the key doesn't exist. Callers are
+    /// expected to add to it, so clients blocked on the key are signaled.
+    pub fn list_or_create(&mut self, k: &[u8]) -> Result<&mut VecDeque<Vec<u8>>, WrongType> {
+        self.expire_if_needed(k);
+        self.signal_ready(k);
+        if !self.items.contains_key(k) {
+            self.items.insert(k.to_vec(), Value::List(VecDeque::new()));
+        }
+        match self.items.get_mut(k) {
+            Some(Value::List(list)) => Ok(list),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn hash(&mut self, k: &[u8]) -> Result<Option<&Hash>, WrongType> {
+        match self.get_value(k) {
+            Some(Value::Hash(hash)) => Ok(Some(hash)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn hash_mut(&mut self, k: &[u8]) -> Result<Option<&mut Hash>, WrongType> {
+        self.expire_if_needed(k);
+        match self.items.get_mut(k) {
+            Some(Value::Hash(hash)) => Ok(Some(hash)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// The hash at the key, creating an empty one if the key doesn't exist.
+    pub fn hash_or_create(&mut self, k: &[u8]) -> Result<&mut Hash, WrongType> {
+        self.expire_if_needed(k);
+        if !self.items.contains_key(k) {
+            self.items.insert(k.to_vec(), Value::Hash(Hash::new(&self.limits)));
+        }
+        match self.items.get_mut(k) {
+            Some(Value::Hash(hash)) => Ok(hash),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn get_set(&mut self, k: &[u8]) -> Result<Option<&Set>, WrongType> {
+        match self.get_value(k) {
+            Some(Value::Set(set)) => Ok(Some(set)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_set_mut(&mut self, k: &[u8]) -> Result<Option<&mut Set>, WrongType> {
+        self.expire_if_needed(k);
+        match self.items.get_mut(k) {
+            Some(Value::Set(set)) => Ok(Some(set)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// The set at the key, creating an empty one if the key doesn't exist.
+    pub fn get_set_or_create(&mut self, k: &[u8]) -> Result<&mut Set, WrongType> {
+        self.expire_if_needed(k);
+        if !self.items.contains_key(k) {
+            self.items.insert(k.to_vec(), Value::Set(Set::new(&self.limits)));
+        }
+        match self.items.get_mut(k) {
+            Some(Value::Set(set)) => Ok(set),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn zset(&mut self, k: &[u8]) -> Result<Option<&ZSet>, WrongType> {
+        match self.get_value(k) {
+            Some(Value::ZSet(zset)) => Ok(Some(zset)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn zset_mut(&mut self, k: &[u8]) -> Result<Option<&mut ZSet>, WrongType> {
+        self.expire_if_needed(k);
+        match self.items.get_mut(k) {
+            Some(Value::ZSet(zset)) => Ok(Some(zset)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// The sorted set at the key, creating an empty one if the key doesn't exist. Callers
+    /// are expected to add to it, so clients blocked on the key are signaled.
+    pub fn zset_or_create(&mut self, k: &[u8]) -> Result<&mut ZSet, WrongType> {
+        self.expire_if_needed(k);
+        self.signal_ready(k);
+        if !self.items.contains_key(k) {
+            self.items.insert(k.to_vec(), Value::ZSet(ZSet::new()));
+        }
+        match self.items.get_mut(k) {
+            Some(Value::ZSet(zset)) => Ok(zset),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn stream(&mut self, k: &[u8]) -> Result<Option<&Stream>, WrongType> {
+        match self.get_value(k) {
+            Some(Value::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn stream_mut(&mut self, k: &[u8]) -> Result<Option<&mut Stream>, WrongType> {
+        self.expire_if_needed(k);
+        match self.items.get_mut(k) {
+            Some(Value::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// The stream at the key, creating an empty one if the key doesn't exist. Callers are
+    /// expected to add to it, so clients blocked on the key are signaled.
+    pub fn stream_or_create(&mut self, k: &[u8]) -> Result<&mut Stream, WrongType> {
+        self.expire_if_needed(k);
+        self.signal_ready(k);
+        if !self.items.contains_key(k) {
+            self.items.insert(k.to_vec(), Value::Stream(Stream::new(&self.limits)));
+        }
+        match self.items.get_mut(k) {
+            Some(Value::Stream(stream)) => Ok(stream),
+            _ => Err(WrongType),
+        }
+    }
+
+    /// Queues the hash at the key for active expiry of its fields. Call it after giving
+    /// a field of the hash an expiry.
+    pub fn sync_field_expiry(&mut self, k: &[u8]) {
+        let next = match self.items.get(k) {
+            Some(Value::Hash(hash)) => hash.next_expire(),
+            _ => None,
+        };
+        let queued = self.field_expiry_at.get(k).copied();
+        if queued == next {
+            return;
+        }
+        if let Some(at) = queued {
+            self.field_expiry.remove(&(at, k.to_vec()));
+            self.field_expiry_at.remove(k);
+        }
+        if let Some(at) = next {
+            self.field_expiry.insert((at, k.to_vec()));
+            self.field_expiry_at.insert(k.to_vec(), at);
+        }
+    }
+
+    /// Deletes the key if it holds a collection that's been emptied.
+    pub fn remove_if_empty(&mut self, k: &[u8]) {
+        if self.items.get(k).is_some_and(Value::is_empty_collection) {
+            self.del(k);
+        }
+    }
+
+    pub fn exists(&mut self, k: &[u8]) -> bool {
+        self.expire_if_needed(k);
         self.items.contains_key(k)
     }
 
-    pub fn del(&mut self, k: &str) -> bool {
+    pub fn del(&mut self, k: &[u8]) -> bool {
+        self.expire_if_needed(k);
+        self.remove_expire(k);
         self.items.remove(k).is_some()
     }
-}
\ No newline at end of file
+
+    /// Notes that a client blocks on the key, so writes to it are reported by `take_ready_keys`.
+    pub fn watch(&mut self, k: &[u8]) {
+        *self.watched.entry(k.to_vec()).or_insert(0) += 1;
+    }
+
+    pub fn unwatch(&mut self, k: &[u8]) {
+        if let Some(n) = self.watched.get_mut(k) {
+            *n -= 1;
+            if *n == 0 {
+                self.watched.remove(k);
+            }
+        }
+    }
+
+    /// Records that a watched key may now serve the clients blocked on it.
+    pub fn signal_ready(&mut self, k: &[u8]) {
+        if self.watched.contains_key(k) && !self.ready.iter().any(|r| r == k) {
+            self.ready.push(k.to_vec());
+        }
+    }
+
+    /// Watched keys written to since the last call, in the order they were first written.
+    pub fn take_ready_keys(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.ready)
+    }
+
+    /// Number of keys, including expired ones that haven't been reclaimed yet.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Number of keys with an expiry set.
+    pub fn volatile_len(&self) -> usize {
+        self.volatile.len()
+    }
+
+    /// Makes the key expire at the unix time `at_ms`, deleting it right away if that's
+    /// already passed. Returns false if the key doesn't exist.
+    pub fn expire_at(&mut self, k: &[u8], at_ms: u64) -> bool {
+        if !self.exists(k) {
+            return false;
+        }
+        if at_ms <= now_ms() {
+            self.del(k);
+            return true;
+        }
+
+        match self.expires.get_mut(k) {
+            Some((deadline, _)) => *deadline = at_ms,
+            None => {
+                self.expires.insert(k.to_vec(), (at_ms, self.volatile.len()));
+                self.volatile.push(k.to_vec());
+            }
+        }
+        true
+    }
+
+    /// The key's expiry as a unix time in milliseconds: `None` if the key doesn't exist and
+    /// `Some(None)` if it never expires.
+    pub fn expire_time(&mut self, k: &[u8]) -> Option<Option<u64>> {
+        if !self.exists(k) {
+            return None;
+        }
+        Some(self.expires.get(k).map(|(deadline, _)| *deadline))
+    }
+
+    /// Removes the key's expiry, returning whether it had one.
+    pub fn persist(&mut self, k: &[u8]) -> bool {
+        self.exists(k) && self.remove_expire(k)
+    }
+
+    /// Deletes a few expired keys, sampling volatile keys at random like redis does.
+    /// Keeps sampling while many of the sampled keys turn out expired, for at most `budget`,
+    /// then removes the expired fields of hashes with fields due. Returns how many keys
+    /// were deleted, including hashes left without fields.
+    pub fn active_expire_cycle(&mut self, budget: Duration) -> usize {
+        let started = Instant::now();
+        let mut rng = rand::thread_rng();
+        let mut deleted = 0;
+
+        loop {
+            let now = now_ms();
+            let sample = ACTIVE_EXPIRE_SAMPLE.min(self.volatile.len());
+            let mut expired = 0;
+
+            for _ in 0..sample {
+                if self.volatile.is_empty() {
+                    break;
+                }
+                let key = &self.volatile[rng.gen_range(0..self.volatile.len())];
+                if self.expires[key].0 <= now {
+                    let key = key.clone();
+                    self.del(&key);
+                    expired += 1;
+                }
+            }
+            deleted += expired;
+
+            if sample == 0
+                || expired * 100 <= sample * ACTIVE_EXPIRE_REPEAT_PERCENT
+                || started.elapsed() >= budget
+            {
+                break;
+            }
+        }
+
+        // hashes are queued by when their next field expires, so only due ones are visited
+        let now = now_ms();
+        while let Some((at, key)) = self.field_expiry.first().cloned() {
+            if at > now || started.elapsed() >= budget {
+                break;
+            }
+            self.field_expiry.pop_first();
+            self.field_expiry_at.remove(&key);
+            if self.expire_fields(&key, now) {
+                deleted += 1;
+            }
+        }
+        deleted
+    }
+
+    /// Deletes the key if its expiry has passed, or the fields of a hash whose expiry has,
+    /// so reads never see them.
+    fn expire_if_needed(&mut self, k: &[u8]) {
+        let now = now_ms();
+        let expired = matches!(self.expires.get(k), Some((deadline, _)) if *deadline <= now);
+        if expired {
+            self.remove_expire(k);
+            self.items.remove(k);
+            return;
+        }
+
+        let fields_due = matches!(self.items.get(k), Some(Value::Hash(hash)) if hash.next_expire().is_some_and(|at| at <= now));
+        if fields_due {
+            self.expire_fields(k, now);
+        }
+    }
+
+    /// Removes the expired fields of the hash at the key, deleting the key if none are
+    /// left. Returns whether the key was deleted.
+    fn expire_fields(&mut self, k: &[u8], now: u64) -> bool {
+        let emptied = match self.items.get_mut(k) {
+            Some(Value::Hash(hash)) => {
+                hash.remove_expired(now);
+                hash.is_empty()
+            }
+            _ => false,
+        };
+        if emptied {
+            self.remove_expire(k);
+            self.items.remove(k);
+        }
+        self.sync_field_expiry(k);
+        emptied
+    }
+
+    fn remove_expire(&mut self, k: &[u8]) -> bool {
+        let Some((_, idx)) = self.expires.remove(k) else {
+            return false;
+        };
+        self.volatile.swap_remove(idx);
+        if let Some(moved) = self.volatile.get(idx) {
+            self.expires.get_mut(moved).unwrap().1 = idx;
+        }
+        true
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use rand::Rng;
+
+/// Fewest buckets a dict shrinks down to.
+const MIN_BUCKETS: usize = 4;
+
+/// A chained hash table keyed by byte strings, which collections switch to once they
+/// outgrow their compact encoding.
+///
+/// It's built like the redis dict rather than on `HashMap` so it can be scanned with a
+/// cursor: an entry that's in the dict for the whole scan is returned at least once, even
+/// if the dict is resized between calls.
+#[derive(Debug, Clone)]
+pub struct Dict<V> {
+    /// Always a power of two, so a hash is masked to its bucket.
+    buckets: Vec<Vec<(Vec<u8>, V)>>,
+    len: usize,
+    hasher: RandomState,
+}
+
+impl<V> Default for Dict<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> Dict<V> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let n = capacity.next_power_of_two().max(MIN_BUCKETS);
+        Self {
+            buckets: (0..n).map(|_| Vec::new()).collect(),
+            len: 0,
+            hasher: RandomState::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, k: &[u8]) -> Option<&V> {
+        self.buckets[self.bucket(k)].iter().find(|(key, _)| key == k).map(|(_, v)| v)
+    }
+
+    pub fn get_mut(&mut self, k: &[u8]) -> Option<&mut V> {
+        let i = self.bucket(k);
+        self.buckets[i].iter_mut().find(|(key, _)| key == k).map(|(_, v)| v)
+    }
+
+    pub fn contains_key(&self, k: &[u8]) -> bool {
+        self.get(k).is_some()
+    }
+
+    /// Inserts the entry, returning the value it replaced.
+    pub fn insert(&mut self, k: Vec<u8>, v: V) -> Option<V> {
+        if let Some(old) = self.get_mut(&k) {
+            return Some(std::mem::replace(old, v));
+        }
+        if self.len >= self.buckets.len() {
+            self.resize(self.buckets.len() * 2);
+        }
+        let i = self.bucket(&k);
+        self.buckets[i].push((k, v));
+        self.len += 1;
+        None
+    }
+
+    pub fn remove(&mut self, k: &[u8]) -> Option<V> {
+        let i = self.bucket(k);
+        let pos = self.buckets[i].iter().position(|(key, _)| key == k)?;
+        let (_, v) = self.buckets[i].swap_remove(pos);
+        self.len -= 1;
+        if self.buckets.len() > MIN_BUCKETS && self.len * 8 < self.buckets.len() {
+            self.resize(self.len.next_power_of_two().max(MIN_BUCKETS));
+        }
+        Some(v)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &V)> {
+        self.buckets.iter().flatten().map(|(k, v)| (k, v))
+    }
+
+    /// A random entry. Entries sharing a bucket with fewer others are a bit more likely
+    /// to be picked, like with redis' `dictGetFairRandomKey`'s fallback.
+    pub fn random(&self, rng: &mut impl Rng) -> Option<(&Vec<u8>, &V)> {
+        if self.is_empty() {
+            return None;
+        }
+        loop {
+            let bucket = &self.buckets[rng.gen_range(0..self.buckets.len())];
+            if !bucket.is_empty() {
+                let (k, v) = &bucket[rng.gen_range(0..bucket.len())];
+                return Some((k, v));
+            }
+        }
+    }
+
+    /// Calls `f` with every entry of the bucket `cursor` points to and returns the cursor
+    /// of the next bucket, or 0 once the whole dict was visited.
+    ///
+    /// The cursor is advanced on its reversed bits, so buckets that a resize splits or
+    /// merges are always visited after the ones they came from, see redis' `dictScan`.
+    pub fn scan(&self, cursor: u64, mut f: impl FnMut(&Vec<u8>, &V)) -> u64 {
+        let mask = (self.buckets.len() - 1) as u64;
+        for (k, v) in &self.buckets[(cursor & mask) as usize] {
+            f(k, v);
+        }
+        let next = (cursor | !mask).reverse_bits().wrapping_add(1);
+        next.reverse_bits()
+    }
+
+    /// Scans buckets from `cursor` until at least `count` entries were visited, returning
+    /// the cursor to continue from (0 when done). The buckets looked at are bounded like in
+    /// redis, so a sparse dict can't make one call take too long.
+    pub fn scan_bounded(&self, mut cursor: u64, count: usize, mut f: impl FnMut(&Vec<u8>, &V)) -> u64 {
+        let mut visited = 0;
+        let mut buckets_left = count.saturating_mul(10);
+        loop {
+            cursor = self.scan(cursor, |k, v| {
+                visited += 1;
+                f(k, v);
+            });
+            buckets_left -= 1;
+            if cursor == 0 || visited >= count || buckets_left == 0 {
+                return cursor;
+            }
+        }
+    }
+
+    fn bucket(&self, k: &[u8]) -> usize {
+        (self.hasher.hash_one(k) as usize) & (self.buckets.len() - 1)
+    }
+
+    fn resize(&mut self, n: usize) {
+        let old = std::mem::replace(&mut self.buckets, (0..n).map(|_| Vec::new()).collect());
+        for (k, v) in old.into_iter().flatten() {
+            let i = self.bucket(&k);
+            self.buckets[i].push((k, v));
+        }
+    }
+}
+
+impl<V: PartialEq> PartialEq for Dict<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use rand::Rng;
+
+use super::{Dict, EncodingLimits};
+
+/// A hash value. Small hashes are kept as a flat list of field/value pairs, which is
+/// compact and fast enough to search at that size; once a hash grows past the
+/// `hash_max_listpack_*` limits it's converted to a `Dict` for good.
+///
+/// Fields may expire on their own. Expired fields aren't removed until `remove_expired`
+/// is called, which `Storage` does before handing out the hash.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hash {
+    entries: Entries,
+    /// Expiry deadline, as a unix time in milliseconds, of the fields that have one.
+    expires: HashMap<Vec<u8>, u64>,
+    /// No field expires before this. It may be earlier than the actual soonest deadline
+    /// after fields are persisted or removed, which only costs a needless check.
+    next_expire: Option<u64>,
+    max_compact_entries: usize,
+    max_compact_value: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entries {
+    Compact(Vec<(Vec<u8>, Vec<u8>)>),
+    Table(Dict<Vec<u8>>),
+}
+
+impl Hash {
+    pub fn new(limits: &EncodingLimits) -> Self {
+        Self {
+            entries: Entries::Compact(Vec::new()),
+            expires: HashMap::new(),
+            next_expire: None,
+            max_compact_entries: limits.hash_max_listpack_entries,
+            max_compact_value: limits.hash_max_listpack_value,
+        }
+    }
+
+    /// The encoding name redis' `OBJECT ENCODING` would report.
+    pub fn encoding(&self) -> &'static str {
+        match self.entries {
+            Entries::Compact(_) => "listpack",
+            Entries::Table(_) => "hashtable",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.entries {
+            Entries::Compact(pairs) => pairs.len(),
+            Entries::Table(dict) => dict.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&Vec<u8>> {
+        match &self.entries {
+            Entries::Compact(pairs) => pairs.iter().find(|(f, _)| f == field).map(|(_, v)| v),
+            Entries::Table(dict) => dict.get(field),
+        }
+    }
+
+    pub fn contains(&self, field: &[u8]) -> bool {
+        self.get(field).is_some()
+    }
+
+    /// Sets the field, discarding any expiry it had. Returns whether it's a new field.
+    pub fn insert(&mut self, field: Vec<u8>, value: Vec<u8>) -> bool {
+        self.expires.remove(&field);
+        self.insert_keep_ttl(field, value)
+    }
+
+    /// Sets the field, leaving its expiry as it was.
+    pub fn insert_keep_ttl(&mut self, field: Vec<u8>, value: Vec<u8>) -> bool {
+        if field.len() > self.max_compact_value || value.len() > self.max_compact_value {
+            self.convert();
+        }
+        let added = match &mut self.entries {
+            Entries::Compact(pairs) => match pairs.iter_mut().find(|(f, _)| *f == field) {
+                Some((_, v)) => {
+                    *v = value;
+                    false
+                }
+                None => {
+                    pairs.push((field, value));
+                    true
+                }
+            },
+            Entries::Table(dict) => dict.insert(field, value).is_none(),
+        };
+        if self.len() > self.max_compact_entries {
+            self.convert();
+        }
+        added
+    }
+
+    /// Removes the field, returning whether it was there.
+    pub fn remove(&mut self, field: &[u8]) -> bool {
+        self.expires.remove(field);
+        match &mut self.entries {
+            Entries::Compact(pairs) => match pairs.iter().position(|(f, _)| f == field) {
+                Some(i) => {
+                    pairs.remove(i);
+                    true
+                }
+                None => false,
+            },
+            Entries::Table(dict) => dict.remove(field).is_some(),
+        }
+    }
+
+    /// Makes the field expire at the unix time `at_ms`. Returns false if there's no such field.
+    pub fn expire_at(&mut self, field: &[u8], at_ms: u64) -> bool {
+        if !self.contains(field) {
+            return false;
+        }
+        self.expires.insert(field.to_vec(), at_ms);
+        self.next_expire = Some(self.next_expire.map_or(at_ms, |next| next.min(at_ms)));
+        true
+    }
+
+    /// The field's expiry: `None` if there's no such field and `Some(None)` if it never expires.
+    pub fn expire_time(&self, field: &[u8]) -> Option<Option<u64>> {
+        self.contains(field).then(|| self.expires.get(field).copied())
+    }
+
+    /// Removes the field's expiry, returning whether it had one.
+    pub fn persist(&mut self, field: &[u8]) -> bool {
+        self.expires.remove(field).is_some()
+    }
+
+    /// The time from which some field may have expired, if any field has an expiry.
+    pub fn next_expire(&self) -> Option<u64> {
+        self.next_expire
+    }
+
+    /// Removes the fields whose expiry is at or before `now_ms`, returning how many.
+    pub fn remove_expired(&mut self, now_ms: u64) -> usize {
+        if self.next_expire.is_none_or(|next| next > now_ms) {
+            return 0;
+        }
+        let expired: Vec<Vec<u8>> = self
+            .expires
+            .iter()
+            .filter(|(_, &at)| at <= now_ms)
+            .map(|(field, _)| field.clone())
+            .collect();
+        for field in &expired {
+            self.remove(field);
+        }
+        self.next_expire = self.expires.values().min().copied();
+        expired.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &Vec<u8>)> {
+        let (compact, table) = match &self.entries {
+            Entries::Compact(pairs) => (Some(pairs.iter().map(|(f, v)| (f, v))), None),
+            Entries::Table(dict) => (None, Some(dict.iter())),
+        };
+        compact.into_iter().flatten().chain(table.into_iter().flatten())
+    }
+
+    pub fn random(&self, rng: &mut impl Rng) -> Option<(&Vec<u8>, &Vec<u8>)> {
+        match &self.entries {
+            Entries::Compact(pairs) if pairs.is_empty() => None,
+            Entries::Compact(pairs) => {
+                let (f, v) = &pairs[rng.gen_range(0..pairs.len())];
+                Some((f, v))
+            }
+            Entries::Table(dict) => dict.random(rng),
+        }
+    }
+
+    /// Calls `f` with the entries of a few buckets starting at `cursor`, until at least
+    /// `count` entries were visited, and returns the cursor to continue from (0 when done).
+    /// A compact hash is small enough to be visited whole in one call.
+    pub fn scan(&self, cursor: u64, count: usize, mut f: impl FnMut(&Vec<u8>, &Vec<u8>)) -> u64 {
+        match &self.entries {
+            Entries::Compact(pairs) => {
+                pairs.iter().for_each(|(k, v)| f(k, v));
+                0
+            }
+            Entries::Table(dict) => dict.scan_bounded(cursor, count, f),
+        }
+    }
+
+    fn convert(&mut self) {
+        if let Entries::Compact(pairs) = &mut self.entries {
+            let mut dict = Dict::with_capacity(pairs.len());
+            for (f, v) in pairs.drain(..) {
+                dict.insert(f, v);
+            }
+            self.entries = Entries::Table(dict);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use rand::Rng;
+
+use super::{Dict, EncodingLimits};
+
+/// A set value. While every member is an integer and there are at most
+/// `set_max_intset_entries` of them, the set is kept as a sorted array of integers like
+/// redis' intset; otherwise it's converted to a `Dict` for good.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Set {
+    members: Members,
+    max_intset_entries: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Members {
+    /// Sorted, so members are found with a binary search.
+    Ints(Vec<i64>),
+    Table(Dict<()>),
+}
+
+/// The member as an integer, if it's the canonical way of writing one, so it can be
+/// turned back into the same bytes.
+fn as_int(member: &[u8]) -> Option<i64> {
+    let i: i64 = std::str::from_utf8(member).ok()?.parse().ok()?;
+    (i.to_string().as_bytes() == member).then_some(i)
+}
+
+impl Set {
+    pub fn new(limits: &EncodingLimits) -> Self {
+        Self {
+            members: Members::Ints(Vec::new()),
+            max_intset_entries: limits.set_max_intset_entries,
+        }
+    }
+
+    /// The encoding name redis' `OBJECT ENCODING` would report.
+    pub fn encoding(&self) -> &'static str {
+        match self.members {
+            Members::Ints(_) => "intset",
+            Members::Table(_) => "hashtable",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.members {
+            Members::Ints(ints) => ints.len(),
+            Members::Table(dict) => dict.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match &self.members {
+            Members::Ints(ints) => as_int(member).is_some_and(|i| ints.binary_search(&i).is_ok()),
+            Members::Table(dict) => dict.contains_key(member),
+        }
+    }
+
+    /// Adds the member, returning whether it wasn't there yet.
+    pub fn insert(&mut self, member: Vec<u8>) -> bool {
+        if let Members::Ints(ints) = &mut self.members {
+            if let Some(i) = as_int(&member) {
+                let Err(pos) = ints.binary_search(&i) else {
+                    return false;
+                };
+                ints.insert(pos, i);
+                if ints.len() > self.max_intset_entries {
+                    self.convert();
+                }
+                return true;
+            }
+            self.convert();
+        }
+        match &mut self.members {
+            Members::Table(dict) => dict.insert(member, ()).is_none(),
+            Members::Ints(_) => unreachable!("the set was converted"),
+        }
+    }
+
+    /// Removes the member, returning whether it was there.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match &mut self.members {
+            Members::Ints(ints) => match as_int(member).map(|i| ints.binary_search(&i)) {
+                Some(Ok(pos)) => {
+                    ints.remove(pos);
+                    true
+                }
+                _ => false,
+            },
+            Members::Table(dict) => dict.remove(member).is_some(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        let (ints, table) = match &self.members {
+            Members::Ints(ints) => (Some(ints.iter().map(|i| i.to_string().into_bytes())), None),
+            Members::Table(dict) => (None, Some(dict.iter().map(|(m, _)| m.clone()))),
+        };
+        ints.into_iter().flatten().chain(table.into_iter().flatten())
+    }
+
+    pub fn random(&self, rng: &mut impl Rng) -> Option<Vec<u8>> {
+        match &self.members {
+            Members::Ints(ints) if ints.is_empty() => None,
+            Members::Ints(ints) => Some(ints[rng.gen_range(0..ints.len())].to_string().into_bytes()),
+            Members::Table(dict) => dict.random(rng).map(|(m, _)| m.clone()),
+        }
+    }
+
+    /// Removes and returns a random member.
+    pub fn pop_random(&mut self, rng: &mut impl Rng) -> Option<Vec<u8>> {
+        let member = self.random(rng)?;
+        self.remove(&member);
+        Some(member)
+    }
+
+    /// Calls `f` with the members of a few buckets starting at `cursor`, until at least
+    /// `count` members were visited, and returns the cursor to continue from (0 when done).
+    /// An intset is small enough to be visited whole in one call.
+    pub fn scan(&self, cursor: u64, count: usize, mut f: impl FnMut(&[u8])) -> u64 {
+        match &self.members {
+            Members::Ints(ints) => {
+                ints.iter().for_each(|i| f(i.to_string().as_bytes()));
+                0
+            }
+            Members::Table(dict) => dict.scan_bounded(cursor, count, |m, _| f(m)),
+        }
+    }
+
+    fn convert(&mut self) {
+        if let Members::Ints(ints) = &self.members {
+            let mut dict = Dict::with_capacity(ints.len());
+            for i in ints {
+                dict.insert(i.to_string().into_bytes(), ());
+            }
+            self.members = Members::Table(dict);
+        }
+    }
+}
@@ -0,0 +1,608 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use super::EncodingLimits;
+
+/// The ID of a stream entry: the unix time in milliseconds it was added at, and a
+/// sequence number telling apart entries added in the same millisecond.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId { ms: u64::MAX, seq: u64::MAX };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// The smallest ID greater than this one, if there is one.
+    pub fn next(self) -> Option<Self> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(Self { seq, ..self }),
+            None => Some(Self { ms: self.ms.checked_add(1)?, seq: 0 }),
+        }
+    }
+
+    /// The greatest ID smaller than this one, if there is one.
+    pub fn prev(self) -> Option<Self> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(Self { seq, ..self }),
+            None => Some(Self { ms: self.ms.checked_sub(1)?, seq: u64::MAX }),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// The field/value pairs of a stream entry, in the order they were given.
+pub type Fields = Vec<(Vec<u8>, Vec<u8>)>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamEntry {
+    pub id: StreamId,
+    pub fields: Fields,
+}
+
+/// What a stream is trimmed to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trim {
+    /// Keep at most this many entries.
+    MaxLen(usize),
+    /// Remove the entries with smaller IDs than this.
+    MinId(StreamId),
+}
+
+/// An entry delivered to a consumer of a group, which it hasn't acknowledged yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEntry {
+    pub consumer: Vec<u8>,
+    /// When it was last delivered, as a unix time in milliseconds.
+    pub delivered_at: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Consumer {
+    /// When the consumer last tried to read or claim entries, as a unix time in milliseconds.
+    pub seen_at: u64,
+    /// When it last actually read or claimed entries, if it ever did.
+    pub active_at: Option<u64>,
+    /// The IDs of its entries in the group's pending entries list.
+    pub pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    fn new(now_ms: u64) -> Self {
+        Self { seen_at: now_ms, active_at: None, pending: BTreeSet::new() }
+    }
+}
+
+/// A consumer group: where in the stream it's up to, and which of the entries it handed
+/// out to its consumers haven't been acknowledged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsumerGroup {
+    /// The ID of the last entry delivered to any consumer.
+    pub last_id: StreamId,
+    /// How many entries of the stream the group read, when that's known.
+    pub entries_read: Option<u64>,
+    /// The pending entries list, or PEL, of the whole group.
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+
+impl ConsumerGroup {
+    fn new(last_id: StreamId, entries_read: Option<u64>) -> Self {
+        Self { last_id, entries_read, pending: BTreeMap::new(), consumers: BTreeMap::new() }
+    }
+
+    /// Adds the consumer, returning false if it already existed.
+    pub fn create_consumer(&mut self, name: &[u8], now_ms: u64) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+        self.consumers.insert(name.to_vec(), Consumer::new(now_ms));
+        true
+    }
+
+    /// The consumer, created if needed, noted as seen at `now_ms`.
+    pub fn touch_consumer(&mut self, name: &[u8], now_ms: u64) -> &mut Consumer {
+        let consumer = self.consumers.entry(name.to_vec()).or_insert_with(|| Consumer::new(now_ms));
+        consumer.seen_at = now_ms;
+        consumer
+    }
+
+    /// Removes the consumer along with its pending entries, returning how many it had.
+    pub fn delete_consumer(&mut self, name: &[u8]) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+        for id in &consumer.pending {
+            self.pending.remove(id);
+        }
+        Some(consumer.pending.len())
+    }
+
+    /// Hands the entry to the consumer, which must exist, as delivered at `at_ms`. An entry
+    /// that was pending already, for this or another consumer, is taken over.
+    pub fn assign(&mut self, id: StreamId, consumer: &[u8], at_ms: u64) -> &mut PendingEntry {
+        if let Some(old) = self.pending.get(&id).map(|p| p.consumer.clone()) {
+            if old != consumer {
+                if let Some(old) = self.consumers.get_mut(&old) {
+                    old.pending.remove(&id);
+                }
+            }
+        }
+        if let Some(consumer) = self.consumers.get_mut(consumer) {
+            consumer.pending.insert(id);
+        }
+        let pending = self.pending.entry(id).or_insert_with(|| PendingEntry {
+            consumer: consumer.to_vec(),
+            delivered_at: at_ms,
+            delivery_count: 0,
+        });
+        pending.consumer = consumer.to_vec();
+        pending.delivered_at = at_ms;
+        pending
+    }
+
+    /// Removes the entry from the pending entries list, returning whether it was there.
+    pub fn ack(&mut self, id: StreamId) -> bool {
+        let Some(pending) = self.pending.remove(&id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&pending.consumer) {
+            consumer.pending.remove(&id);
+        }
+        true
+    }
+}
+
+/// Up to `stream_node_max_entries` entries packed in one buffer, like redis' listpacks.
+///
+/// The buffer starts with the master fields, those of the node's first entry. Each entry
+/// follows as a flag, its ID as the milliseconds since the master ID and its sequence
+/// number, then only its values when its fields are the master fields, and its field/value
+/// pairs otherwise. Numbers and lengths are varints.
+#[derive(Debug, Clone, PartialEq)]
+struct Node {
+    master_id: StreamId,
+    last_id: StreamId,
+    len: usize,
+    buf: Vec<u8>,
+}
+
+/// The entry's fields are the master fields, so only its values are stored.
+const SAME_FIELDS: u8 = 1;
+
+fn put_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// Reads the buffer a node was packed into, which is always well formed.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn varint(&mut self) -> u64 {
+        let mut n = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.buf[self.pos];
+            self.pos += 1;
+            n |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return n;
+            }
+            shift += 7;
+        }
+    }
+
+    fn bytes(&mut self) -> &'a [u8] {
+        let len = self.varint() as usize;
+        self.pos += len;
+        &self.buf[self.pos - len..self.pos]
+    }
+}
+
+impl Node {
+    fn new(first: StreamEntry) -> Self {
+        let mut buf = Vec::new();
+        put_varint(&mut buf, first.fields.len() as u64);
+        for (field, _) in &first.fields {
+            put_bytes(&mut buf, field);
+        }
+        let mut node = Self { master_id: first.id, last_id: first.id, len: 0, buf };
+        node.push(first);
+        node
+    }
+
+    /// Packs the entries again after some were removed, keeping the master ID and fields.
+    fn repack(&mut self, entries: Vec<StreamEntry>) {
+        let mut reader = Reader { buf: &self.buf, pos: 0 };
+        for _ in 0..reader.varint() {
+            reader.bytes();
+        }
+        self.buf.truncate(reader.pos);
+        self.len = 0;
+        for entry in entries {
+            self.push(entry);
+        }
+    }
+
+    fn push(&mut self, entry: StreamEntry) {
+        let mut reader = Reader { buf: &self.buf, pos: 0 };
+        let master_len = reader.varint() as usize;
+        let same = entry.fields.len() == master_len && entry.fields.iter().all(|(field, _)| reader.bytes() == field.as_slice());
+
+        self.buf.push(if same { SAME_FIELDS } else { 0 });
+        put_varint(&mut self.buf, entry.id.ms - self.master_id.ms);
+        put_varint(&mut self.buf, entry.id.seq);
+        if same {
+            for (_, value) in &entry.fields {
+                put_bytes(&mut self.buf, value);
+            }
+        } else {
+            put_varint(&mut self.buf, entry.fields.len() as u64);
+            for (field, value) in &entry.fields {
+                put_bytes(&mut self.buf, field);
+                put_bytes(&mut self.buf, value);
+            }
+        }
+        self.len += 1;
+        self.last_id = entry.id;
+    }
+
+    /// Unpacks the entries in order.
+    fn entries(&self) -> Vec<StreamEntry> {
+        let mut reader = Reader { buf: &self.buf, pos: 0 };
+        let master_fields: Vec<&[u8]> = (0..reader.varint()).map(|_| reader.bytes()).collect();
+        let mut entries = Vec::with_capacity(self.len);
+        while reader.pos < self.buf.len() {
+            let flags = reader.buf[reader.pos];
+            reader.pos += 1;
+            let id = StreamId::new(self.master_id.ms + reader.varint(), reader.varint());
+            let fields = if flags & SAME_FIELDS != 0 {
+                master_fields.iter().map(|field| (field.to_vec(), reader.bytes().to_vec())).collect()
+            } else {
+                (0..reader.varint()).map(|_| (reader.bytes().to_vec(), reader.bytes().to_vec())).collect()
+            };
+            entries.push(StreamEntry { id, fields });
+        }
+        entries
+    }
+}
+
+/// A stream value: entries ordered by ID, which only ever grows.
+///
+/// Like redis packs entries in listpacks hanging off a radix tree, entries are packed in
+/// nodes kept in a B-tree by the ID they started at. Approximate trimming only removes
+/// whole nodes, which is what makes it cheap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stream {
+    nodes: BTreeMap<StreamId, Node>,
+    len: usize,
+    last_id: StreamId,
+    max_deleted_id: StreamId,
+    /// How many entries were ever added, including deleted ones.
+    entries_added: u64,
+    node_max_entries: usize,
+    groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new(limits: &EncodingLimits) -> Self {
+        Self {
+            nodes: BTreeMap::new(),
+            len: 0,
+            last_id: StreamId::MIN,
+            max_deleted_id: StreamId::MIN,
+            entries_added: 0,
+            node_max_entries: limits.stream_node_max_entries.max(1),
+            groups: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The ID of the last entry added, which may have been deleted since.
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// The greatest ID of the entries deleted with `remove`.
+    pub fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    /// How many nodes the entries are packed in.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn first_entry(&self) -> Option<StreamEntry> {
+        self.nodes.values().next().and_then(|node| node.entries().into_iter().next())
+    }
+
+    pub fn last_entry(&self) -> Option<StreamEntry> {
+        self.nodes.values().next_back().and_then(|node| node.entries().pop())
+    }
+
+    pub fn get(&self, id: StreamId) -> Option<StreamEntry> {
+        let node = self.nodes.range(..=id).next_back()?.1;
+        node.entries().into_iter().find(|e| e.id == id)
+    }
+
+    /// The ID an entry added at `now_ms` gets when the caller leaves it to the stream:
+    /// the current time, or the last ID's time if the clock went back, with the next
+    /// sequence number. `None` once the greatest possible ID was used.
+    pub fn next_id(&self, now_ms: u64) -> Option<StreamId> {
+        if now_ms > self.last_id.ms {
+            Some(StreamId::new(now_ms, 0))
+        } else {
+            self.last_id.next()
+        }
+    }
+
+    /// The ID an entry added at the given time gets when the caller leaves the sequence
+    /// number to the stream. `None` if that time is before the last ID's.
+    pub fn next_id_at(&self, ms: u64) -> Option<StreamId> {
+        match ms.cmp(&self.last_id.ms) {
+            std::cmp::Ordering::Greater => Some(StreamId::new(ms, 0)),
+            std::cmp::Ordering::Equal => self.last_id.seq.checked_add(1).map(|seq| StreamId::new(ms, seq)),
+            std::cmp::Ordering::Less => None,
+        }
+    }
+
+    /// Appends an entry, returning false without adding it unless `id` is greater than
+    /// the last ID.
+    pub fn append(&mut self, id: StreamId, fields: Fields) -> bool {
+        if id <= self.last_id {
+            return false;
+        }
+        let entry = StreamEntry { id, fields };
+        match self.nodes.values_mut().next_back() {
+            Some(node) if node.len < self.node_max_entries => node.push(entry),
+            _ => {
+                self.nodes.insert(id, Node::new(entry));
+            }
+        }
+        self.len += 1;
+        self.last_id = id;
+        self.entries_added += 1;
+        true
+    }
+
+    /// Removes the entry, returning whether it was there.
+    pub fn remove(&mut self, id: StreamId) -> bool {
+        let Some((&start, node)) = self.nodes.range_mut(..=id).next_back() else {
+            return false;
+        };
+        let mut entries = node.entries();
+        let Ok(i) = entries.binary_search_by_key(&id, |e| e.id) else {
+            return false;
+        };
+        entries.remove(i);
+        if entries.is_empty() {
+            self.nodes.remove(&start);
+        } else {
+            node.repack(entries);
+        }
+        self.len -= 1;
+        self.max_deleted_id = self.max_deleted_id.max(id);
+        true
+    }
+
+    /// The entries with IDs from `start` to `end`, both included, in order.
+    pub fn range(&self, start: StreamId, end: StreamId) -> impl DoubleEndedIterator<Item = StreamEntry> + '_ {
+        // the node holding `start` may have started before it
+        let from = self.nodes.range(..=start).next_back().map_or(start, |(&id, _)| id);
+        let nodes = if from <= end { Some(self.nodes.range(from..=end)) } else { None };
+        nodes
+            .into_iter()
+            .flatten()
+            .flat_map(|(_, node)| node.entries())
+            .filter(move |e| e.id >= start && e.id <= end)
+    }
+
+    /// Removes entries from the start of the stream until it's trimmed to `trim`, returning
+    /// how many were removed. An `approx` trim only removes whole nodes, so it may leave a
+    /// few more entries than asked for, and at most `limit` entries, which defaults to 100
+    /// nodes worth; a limit of 0 means no limit.
+    pub fn trim(&mut self, trim: Trim, approx: bool, limit: Option<usize>) -> usize {
+        let limit = match limit {
+            Some(0) => usize::MAX,
+            Some(limit) => limit,
+            None if approx => self.node_max_entries.saturating_mul(100),
+            None => usize::MAX,
+        };
+
+        let mut removed = 0;
+        while let Some(mut first) = self.nodes.first_entry() {
+            let node = first.get_mut();
+            let whole = match trim {
+                Trim::MaxLen(max) => self.len - node.len >= max,
+                Trim::MinId(min) => node.last_id < min,
+            };
+            if whole {
+                if removed + node.len > limit {
+                    break;
+                }
+                removed += node.len;
+                self.len -= node.len;
+                first.remove();
+                continue;
+            }
+
+            if !approx {
+                let mut entries = node.entries();
+                let n = match trim {
+                    Trim::MaxLen(max) => self.len.saturating_sub(max),
+                    Trim::MinId(min) => entries.partition_point(|e| e.id < min),
+                };
+                if n > 0 {
+                    node.repack(entries.split_off(n));
+                }
+                removed += n;
+                self.len -= n;
+            }
+            break;
+        }
+        removed
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = (&Vec<u8>, &ConsumerGroup)> {
+        self.groups.iter()
+    }
+
+    pub fn group(&self, name: &[u8]) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &[u8]) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// Adds a group that has read up to `last_id`, returning false if it already existed.
+    pub fn create_group(&mut self, name: &[u8], last_id: StreamId, entries_read: Option<u64>) -> bool {
+        if self.groups.contains_key(name) {
+            return false;
+        }
+        self.groups.insert(name.to_vec(), ConsumerGroup::new(last_id, entries_read));
+        true
+    }
+
+    pub fn destroy_group(&mut self, name: &[u8]) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Runs `f` with the group along with the stream, which can't be borrowed mutably
+    /// together otherwise. `None` if there's no such group.
+    pub fn with_group<T>(&mut self, name: &[u8], f: impl FnOnce(&Self, &mut ConsumerGroup) -> T) -> Option<T> {
+        let (name, mut group) = self.groups.remove_entry(name)?;
+        let result = f(self, &mut group);
+        self.groups.insert(name, group);
+        Some(result)
+    }
+
+    /// Delivers up to `count` entries the group hasn't read yet to the consumer, adding them
+    /// to the pending entries list unless `noack`.
+    pub fn read_group(&mut self, group: &[u8], consumer: &[u8], count: usize, noack: bool, now_ms: u64) -> Option<Vec<StreamEntry>> {
+        self.with_group(group, |stream, group| {
+            group.touch_consumer(consumer, now_ms);
+            let entries: Vec<StreamEntry> = match group.last_id.next() {
+                Some(start) => stream.range(start, StreamId::MAX).take(count).collect(),
+                None => Vec::new(),
+            };
+            for entry in &entries {
+                group.last_id = entry.id;
+                // keep counting while that's exact, and work it out again otherwise
+                group.entries_read = match group.entries_read {
+                    Some(read) if !stream.has_tombstones_from(entry.id) => Some(read + 1),
+                    _ => stream.estimate_entries_read(entry.id),
+                };
+                if !noack {
+                    group.assign(entry.id, consumer, now_ms).delivery_count = 1;
+                }
+            }
+            if !entries.is_empty() {
+                group.touch_consumer(consumer, now_ms).active_at = Some(now_ms);
+            }
+            entries
+        })
+    }
+
+    /// Delivers again up to `count` of the consumer's pending entries with IDs greater than
+    /// `after`. Entries deleted from the stream since come without their fields.
+    pub fn read_pending(&mut self, group: &[u8], consumer: &[u8], after: StreamId, count: usize, now_ms: u64) -> Option<Vec<(StreamId, Option<Fields>)>> {
+        self.with_group(group, |stream, group| {
+            let ids: Vec<StreamId> = match after.next() {
+                Some(start) => group.touch_consumer(consumer, now_ms).pending.range(start..).take(count).copied().collect(),
+                None => Vec::new(),
+            };
+            ids.into_iter()
+                .map(|id| {
+                    let fields = stream.get(id).map(|e| e.fields);
+                    if fields.is_some() {
+                        if let Some(pending) = group.pending.get_mut(&id) {
+                            pending.delivered_at = now_ms;
+                            pending.delivery_count += 1;
+                        }
+                    }
+                    (id, fields)
+                })
+                .collect()
+        })
+    }
+
+    /// How many entries were added after the group's last delivered one, if that's known.
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let read = match group.entries_read {
+            Some(read) if !self.has_tombstones_from(group.last_id) => read,
+            _ => self.estimate_entries_read(group.last_id)?,
+        };
+        Some(self.entries_added.saturating_sub(read))
+    }
+
+    /// Whether entries with IDs from `start` on were deleted, in which case counting the
+    /// entries after `start` from `entries_added` would be off.
+    fn has_tombstones_from(&self, start: StreamId) -> bool {
+        self.len > 0 && self.max_deleted_id != StreamId::MIN && self.max_deleted_id >= start
+    }
+
+    /// How many entries were added up to and including `id`, if that can be told without
+    /// counting them, following redis' `streamEstimateDistanceFromFirstEverEntry`.
+    fn estimate_entries_read(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if self.len == 0 && id <= self.last_id {
+            return Some(self.entries_added);
+        }
+        match id.cmp(&self.last_id) {
+            std::cmp::Ordering::Equal => return Some(self.entries_added),
+            std::cmp::Ordering::Greater => return None,
+            std::cmp::Ordering::Less => {}
+        }
+        let first_id = self.first_entry().map_or(StreamId::MIN, |e| e.id);
+        if self.max_deleted_id == StreamId::MIN || self.max_deleted_id < first_id {
+            // nothing was deleted past the first entry, so the entries before it were trimmed
+            let before_first = self.entries_added - self.len as u64;
+            match id.cmp(&first_id) {
+                std::cmp::Ordering::Less => return Some(before_first),
+                std::cmp::Ordering::Equal => return Some(before_first + 1),
+                std::cmp::Ordering::Greater => {}
+            }
+        }
+        None
+    }
+}
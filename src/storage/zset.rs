@@ -0,0 +1,513 @@
+use std::cmp::Ordering;
+
+use rand::Rng;
+
+use super::Dict;
+
+/// Most levels a skiplist node can have, enough for 4^32 elements.
+const MAX_LEVEL: usize = 32;
+/// Each level is this likely to be followed by another one.
+const LEVEL_P: f64 = 0.25;
+/// The header node, which holds no element and starts every level.
+const HEAD: usize = 0;
+
+/// A range of scores, each end inclusive unless marked exclusive like `(1.5` in redis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreRange {
+    pub min: f64,
+    pub max: f64,
+    pub min_exclusive: bool,
+    pub max_exclusive: bool,
+}
+
+impl ScoreRange {
+    fn above_min(&self, score: f64) -> bool {
+        if self.min_exclusive { score > self.min } else { score >= self.min }
+    }
+
+    fn below_max(&self, score: f64) -> bool {
+        if self.max_exclusive { score < self.max } else { score <= self.max }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min > self.max || (self.min == self.max && (self.min_exclusive || self.max_exclusive))
+    }
+}
+
+/// An end of a lexicographical range, written `-`, `+`, `[member` or `(member` in redis.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexBound {
+    NegInf,
+    PosInf,
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+}
+
+/// A range of members, which only orders elements sensibly when they share a score.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexRange {
+    pub min: LexBound,
+    pub max: LexBound,
+}
+
+impl LexRange {
+    fn above_min(&self, member: &[u8]) -> bool {
+        match &self.min {
+            LexBound::NegInf => true,
+            LexBound::PosInf => false,
+            LexBound::Inclusive(min) => member >= min.as_slice(),
+            LexBound::Exclusive(min) => member > min.as_slice(),
+        }
+    }
+
+    fn below_max(&self, member: &[u8]) -> bool {
+        match &self.max {
+            LexBound::NegInf => false,
+            LexBound::PosInf => true,
+            LexBound::Inclusive(max) => member <= max.as_slice(),
+            LexBound::Exclusive(max) => member < max.as_slice(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Level {
+    forward: Option<usize>,
+    /// How many elements the link to `forward` skips over, counting `forward` itself,
+    /// which is what ranks are computed from.
+    span: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Node {
+    member: Vec<u8>,
+    score: f64,
+    levels: Vec<Level>,
+    backward: Option<usize>,
+}
+
+/// The elements in order of score, then member, as a skiplist whose links know how
+/// many elements they skip, like redis' `zskiplist`. Nodes live in a vector and link to
+/// each other by index; the slots of deleted nodes are reused.
+#[derive(Debug, Clone, PartialEq)]
+struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    len: usize,
+    level: usize,
+}
+
+/// Scores are never NaN, and -0 and 0 are the same score like in range checks, so they're
+/// ordered by member.
+fn cmp_element(score: f64, member: &[u8], other_score: f64, other_member: &[u8]) -> Ordering {
+    score.partial_cmp(&other_score).expect("scores are never NaN").then_with(|| member.cmp(other_member))
+}
+
+impl SkipList {
+    fn new() -> Self {
+        let head = Node {
+            member: Vec::new(),
+            score: 0.0,
+            levels: vec![Level { forward: None, span: 0 }; MAX_LEVEL],
+            backward: None,
+        };
+        Self { nodes: vec![head], free: Vec::new(), tail: None, len: 0, level: 1 }
+    }
+
+    fn forward(&self, x: usize, level: usize) -> Option<usize> {
+        self.nodes[x].levels[level].forward
+    }
+
+    fn span(&self, x: usize, level: usize) -> usize {
+        self.nodes[x].levels[level].span
+    }
+
+    /// Whether the element at `x` sorts before `(score, member)`.
+    fn is_before(&self, x: usize, score: f64, member: &[u8]) -> bool {
+        let node = &self.nodes[x];
+        cmp_element(node.score, &node.member, score, member) == Ordering::Less
+    }
+
+    fn random_level() -> usize {
+        let mut rng = rand::thread_rng();
+        let mut level = 1;
+        while level < MAX_LEVEL && rng.gen_bool(LEVEL_P) {
+            level += 1;
+        }
+        level
+    }
+
+    /// Inserts an element that isn't in the list yet.
+    fn insert(&mut self, score: f64, member: Vec<u8>) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.forward(x, i).filter(|&next| self.is_before(next, score, &member)) {
+                rank[i] += self.span(x, i);
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = Self::random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let node = Node { member, score, levels: vec![Level { forward: None, span: 0 }; level], backward: None };
+        let new = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for i in 0..level {
+            let prev = update[i];
+            self.nodes[new].levels[i].forward = self.nodes[prev].levels[i].forward;
+            self.nodes[prev].levels[i].forward = Some(new);
+            self.nodes[new].levels[i].span = self.nodes[prev].levels[i].span - (rank[0] - rank[i]);
+            self.nodes[prev].levels[i].span = rank[0] - rank[i] + 1;
+        }
+        for (i, &prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[prev].levels[i].span += 1;
+        }
+
+        self.nodes[new].backward = (update[0] != HEAD).then_some(update[0]);
+        match self.forward(new, 0) {
+            Some(next) => self.nodes[next].backward = Some(new),
+            None => self.tail = Some(new),
+        }
+        self.len += 1;
+    }
+
+    /// Removes the element, returning whether it was there.
+    fn remove(&mut self, score: f64, member: &[u8]) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i).filter(|&next| self.is_before(next, score, member)) {
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let Some(target) = self.forward(x, 0) else {
+            return false;
+        };
+        if self.nodes[target].score != score || self.nodes[target].member != member {
+            return false;
+        }
+
+        for (i, &prev) in update.iter().enumerate().take(self.level) {
+            if self.forward(prev, i) == Some(target) {
+                self.nodes[prev].levels[i].span += self.span(target, i);
+                self.nodes[prev].levels[i].span -= 1;
+                self.nodes[prev].levels[i].forward = self.forward(target, i);
+            } else {
+                self.nodes[prev].levels[i].span -= 1;
+            }
+        }
+        match self.forward(target, 0) {
+            Some(next) => self.nodes[next].backward = self.nodes[target].backward,
+            None => self.tail = self.nodes[target].backward,
+        }
+        while self.level > 1 && self.forward(HEAD, self.level - 1).is_none() {
+            self.level -= 1;
+        }
+
+        self.nodes[target].member = Vec::new();
+        self.nodes[target].levels = Vec::new();
+        self.free.push(target);
+        self.len -= 1;
+        true
+    }
+
+    /// The 0-based rank of the element, if it's in the list.
+    fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i).filter(|&next| {
+                let node = &self.nodes[next];
+                cmp_element(node.score, &node.member, score, member) != Ordering::Greater
+            }) {
+                rank += self.span(x, i);
+                x = next;
+            }
+            if x != HEAD && self.nodes[x].member == member {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// The node at the 0-based rank.
+    fn by_rank(&self, rank: usize) -> Option<usize> {
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i).filter(|_| traversed + self.span(x, i) <= target) {
+                traversed += self.span(x, i);
+                x = next;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    /// The last node for which `before` holds, which must hold for a prefix of the list,
+    /// or the header if it holds for none.
+    fn last_where(&self, before: impl Fn(&Node) -> bool) -> usize {
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i).filter(|&next| before(&self.nodes[next])) {
+                x = next;
+            }
+        }
+        x
+    }
+
+    fn first_in_score_range(&self, range: &ScoreRange) -> Option<usize> {
+        let x = self.forward(self.last_where(|node| !range.above_min(node.score)), 0)?;
+        range.below_max(self.nodes[x].score).then_some(x)
+    }
+
+    fn last_in_score_range(&self, range: &ScoreRange) -> Option<usize> {
+        let x = self.last_where(|node| range.below_max(node.score));
+        (x != HEAD && range.above_min(self.nodes[x].score)).then_some(x)
+    }
+
+    fn first_in_lex_range(&self, range: &LexRange) -> Option<usize> {
+        let x = self.forward(self.last_where(|node| !range.above_min(&node.member)), 0)?;
+        range.below_max(&self.nodes[x].member).then_some(x)
+    }
+
+    fn last_in_lex_range(&self, range: &LexRange) -> Option<usize> {
+        let x = self.last_where(|node| range.below_max(&node.member));
+        (x != HEAD && range.above_min(&self.nodes[x].member)).then_some(x)
+    }
+
+    fn first(&self) -> Option<usize> {
+        self.forward(HEAD, 0)
+    }
+
+    fn next(&self, x: usize) -> Option<usize> {
+        self.forward(x, 0)
+    }
+
+    fn prev(&self, x: usize) -> Option<usize> {
+        self.nodes[x].backward
+    }
+
+    fn element(&self, x: usize) -> (&Vec<u8>, f64) {
+        (&self.nodes[x].member, self.nodes[x].score)
+    }
+}
+
+/// Which way a range of a sorted set is walked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Ascending,
+    Descending,
+}
+
+/// A sorted set value: a dict from member to score, for lookups by member, along with a
+/// skiplist ordering the elements by score, for ranks and ranges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZSet {
+    scores: Dict<f64>,
+    list: SkipList,
+}
+
+impl Default for ZSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ZSet {
+    pub fn new() -> Self {
+        Self { scores: Dict::new(), list: SkipList::new() }
+    }
+
+    /// The encoding name redis' `OBJECT ENCODING` would report.
+    pub fn encoding(&self) -> &'static str {
+        "skiplist"
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds the member or changes its score, returning whether it's a new member.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> bool {
+        match self.scores.get(&member).copied() {
+            Some(current) if current == score => false,
+            Some(current) => {
+                self.list.remove(current, &member);
+                self.list.insert(score, member.clone());
+                self.scores.insert(member, score);
+                false
+            }
+            None => {
+                self.list.insert(score, member.clone());
+                self.scores.insert(member, score);
+                true
+            }
+        }
+    }
+
+    /// Removes the member, returning whether it was there.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        let Some(score) = self.scores.remove(member) else {
+            return false;
+        };
+        self.list.remove(score, member);
+        true
+    }
+
+    /// The 0-based rank of the member, counted from the highest score for `Descending`.
+    pub fn rank(&self, member: &[u8], direction: Direction) -> Option<usize> {
+        let score = self.score(member)?;
+        let rank = self.list.rank(score, member)?;
+        Some(match direction {
+            Direction::Ascending => rank,
+            Direction::Descending => self.len() - 1 - rank,
+        })
+    }
+
+    /// The elements from rank `start` to `stop`, both included, ranks being counted
+    /// from the highest score for `Descending`.
+    pub fn range_by_rank(&self, start: usize, stop: usize, direction: Direction) -> Vec<(Vec<u8>, f64)> {
+        if start > stop || start >= self.len() {
+            return Vec::new();
+        }
+        let stop = stop.min(self.len() - 1);
+        let first = match direction {
+            Direction::Ascending => self.list.by_rank(start),
+            Direction::Descending => self.list.by_rank(self.len() - 1 - start),
+        };
+        self.walk(first, direction).take(stop - start + 1).collect()
+    }
+
+    /// The elements within the score range, skipping `offset` of them and returning at
+    /// most `limit`.
+    pub fn range_by_score(&self, range: &ScoreRange, direction: Direction, offset: usize, limit: usize) -> Vec<(Vec<u8>, f64)> {
+        if range.is_empty() {
+            return Vec::new();
+        }
+        let first = match direction {
+            Direction::Ascending => self.list.first_in_score_range(range),
+            Direction::Descending => self.list.last_in_score_range(range),
+        };
+        self.walk(first, direction)
+            .skip(offset)
+            .take_while(|(_, score)| match direction {
+                Direction::Ascending => range.below_max(*score),
+                Direction::Descending => range.above_min(*score),
+            })
+            .take(limit)
+            .collect()
+    }
+
+    /// The elements within the lexicographical range, skipping `offset` of them and
+    /// returning at most `limit`.
+    pub fn range_by_lex(&self, range: &LexRange, direction: Direction, offset: usize, limit: usize) -> Vec<(Vec<u8>, f64)> {
+        let first = match direction {
+            Direction::Ascending => self.list.first_in_lex_range(range),
+            Direction::Descending => self.list.last_in_lex_range(range),
+        };
+        self.walk(first, direction)
+            .skip(offset)
+            .take_while(|(member, _)| match direction {
+                Direction::Ascending => range.below_max(member),
+                Direction::Descending => range.above_min(member),
+            })
+            .take(limit)
+            .collect()
+    }
+
+    /// How many elements are within the score range, found from the ranks of its ends.
+    pub fn count_in_score_range(&self, range: &ScoreRange) -> usize {
+        if range.is_empty() {
+            return 0;
+        }
+        match (self.list.first_in_score_range(range), self.list.last_in_score_range(range)) {
+            (Some(first), Some(last)) => self.node_rank(last) - self.node_rank(first) + 1,
+            _ => 0,
+        }
+    }
+
+    pub fn count_in_lex_range(&self, range: &LexRange) -> usize {
+        match (self.list.first_in_lex_range(range), self.list.last_in_lex_range(range)) {
+            (Some(first), Some(last)) if self.node_rank(first) <= self.node_rank(last) => {
+                self.node_rank(last) - self.node_rank(first) + 1
+            }
+            _ => 0,
+        }
+    }
+
+    /// Removes and returns the element with the lowest score, or the highest for `Descending`.
+    pub fn pop(&mut self, direction: Direction) -> Option<(Vec<u8>, f64)> {
+        let x = match direction {
+            Direction::Ascending => self.list.first(),
+            Direction::Descending => self.list.tail,
+        }?;
+        let (member, score) = self.list.element(x);
+        let member = member.clone();
+        self.remove(&member);
+        Some((member, score))
+    }
+
+    /// The elements in order of score.
+    pub fn iter(&self) -> impl Iterator<Item = (Vec<u8>, f64)> + '_ {
+        self.walk(self.list.first(), Direction::Ascending)
+    }
+
+    /// Calls `f` with the elements of a few buckets starting at `cursor`, until at least
+    /// `count` elements were visited, and returns the cursor to continue from (0 when done).
+    pub fn scan(&self, cursor: u64, count: usize, mut f: impl FnMut(&[u8], f64)) -> u64 {
+        self.scores.scan_bounded(cursor, count, |m, &score| f(m, score))
+    }
+
+    fn node_rank(&self, x: usize) -> usize {
+        let (member, score) = self.list.element(x);
+        self.list.rank(score, member).expect("nodes in the list have a rank")
+    }
+
+    fn walk(&self, from: Option<usize>, direction: Direction) -> impl Iterator<Item = (Vec<u8>, f64)> + '_ {
+        std::iter::successors(from, move |&x| match direction {
+            Direction::Ascending => self.list.next(x),
+            Direction::Descending => self.list.prev(x),
+        })
+        .map(|x| {
+            let (member, score) = self.list.element(x);
+            (member.clone(), score)
+        })
+    }
+}
@@ -1,12 +1,12 @@
 use std::sync::{Arc, Mutex};
 
-use rkey::{CommandHandler, RespType, Storage};
+use rkey::{CommandErr, CommandHandler, CommandRegistry, EncodingLimits, Protocol, RespType, Storage};
 
 
 #[test]
 fn test_ping_cmd() {
     let d = RespType::Array(vec![
-        RespType::BString("PING".to_string()),
+        RespType::BString("PING".into()),
     ]);
     let result = CommandHandler::new( Arc::new(Mutex::new(Storage::new()))).handle_cmd(d);
     assert_eq!(result.unwrap(), RespType::String("PONG".to_string()));
@@ -15,9 +15,9 @@ fn test_ping_cmd() {
 #[test]
 fn test_set_cmd() {
     let d = RespType::Array(vec![
-        RespType::BString("SET".to_string()),
-        RespType::BString("key1".to_string()),
-        RespType::BString("val".to_string()),
+        RespType::BString("SET".into()),
+        RespType::BString("key1".into()),
+        RespType::BString("val".into()),
     ]);
 
     let result = CommandHandler::new( Arc::new(Mutex::new(Storage::new()))).handle_cmd(d);
@@ -30,12 +30,12 @@ fn test_get_cmd() {
     insert("key1", "val", Arc::clone(&storage));
     
     let d = RespType::Array(vec![
-        RespType::BString("GET".to_string()),
-        RespType::BString("key1".to_string()),
+        RespType::BString("GET".into()),
+        RespType::BString("key1".into()),
     ]);
 
     let result = CommandHandler::new(Arc::clone(&storage)).handle_cmd(d);
-    assert_eq!(result.unwrap(), RespType::BString("val".to_string()));
+    assert_eq!(result.unwrap(), RespType::BString("val".into()));
 
 }
 
@@ -49,10 +49,10 @@ fn test_del() {
         insert(k, v, Arc::clone(&storage));
     }
 
-    let keys: [RespType; 3] = v.map(|v| RespType::BString(v.0.to_string()));
+    let keys: [RespType; 3] = v.map(|v| RespType::BString(v.0.into()));
 
     let mut resp_cmd= vec![
-        RespType::BString("DEL".to_string()),
+        RespType::BString("DEL".into()),
     ];
     resp_cmd.extend(keys);
     let resp_cmd =  RespType::Array(resp_cmd);
@@ -62,12 +62,152 @@ fn test_del() {
 
 }
 
+#[test]
+fn test_binary_values() {
+    let storage = Arc::new(Mutex::new(Storage::new()));
+    let key = vec![0xde, 0xad, 0x00, 0xbe, 0xef];
+    let value = vec![0x1f, 0x8b, 0x08, 0x00, 0xff, 0xfe];
+
+    let d = RespType::Array(vec![
+        RespType::BString("SET".into()),
+        RespType::BString(key.clone()),
+        RespType::BString(value.clone()),
+    ]);
+    let result = CommandHandler::new(Arc::clone(&storage)).handle_cmd(d);
+    assert_eq!(result.unwrap(), RespType::String("OK".to_string()));
+
+    let d = RespType::Array(vec![
+        RespType::BString("GET".into()),
+        RespType::BString(key),
+    ]);
+    let result = CommandHandler::new(Arc::clone(&storage)).handle_cmd(d);
+    assert_eq!(result.unwrap(), RespType::BString(value));
+}
+
+#[test]
+fn test_hello_switches_protocol() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    assert_eq!(handler.protocol(), Protocol::Resp2);
+
+    let d = RespType::Array(vec![
+        RespType::BString("HELLO".into()),
+        RespType::BString("3".into()),
+        RespType::BString("SETNAME".into()),
+        RespType::BString("worker-1".into()),
+    ]);
+    let RespType::Map(props) = handler.handle_cmd(d).unwrap() else {
+        panic!("HELLO should reply with a map");
+    };
+    assert!(props.contains(&(RespType::BString("proto".into()), RespType::Int(3))));
+    assert_eq!(handler.protocol(), Protocol::Resp3);
+    assert_eq!(handler.client_name(), Some(&b"worker-1"[..]));
+
+    let d = RespType::Array(vec![
+        RespType::BString("HELLO".into()),
+        RespType::BString("4".into()),
+    ]);
+    assert!(handler.handle_cmd(d).is_err());
+    assert_eq!(handler.protocol(), Protocol::Resp3);
+}
+
+fn cmd(args: &[&str]) -> RespType {
+    RespType::Array(args.iter().map(|a| RespType::BString(a.as_bytes().to_vec())).collect())
+}
+
+#[test]
+fn test_command_introspection() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+
+    let RespType::Int(count) = handler.handle_cmd(cmd(&["COMMAND", "COUNT"])).unwrap() else {
+        panic!("COMMAND COUNT should reply with an integer");
+    };
+    assert_eq!(count as usize, CommandRegistry::global().len());
+
+    let RespType::Array(all) = handler.handle_cmd(cmd(&["COMMAND"])).unwrap() else {
+        panic!("COMMAND should reply with an array");
+    };
+    assert_eq!(all.len(), count as usize);
+
+    let RespType::Array(info) = handler.handle_cmd(cmd(&["COMMAND", "INFO", "get", "nosuchcmd"])).unwrap() else {
+        panic!("COMMAND INFO should reply with an array");
+    };
+    assert_eq!(info[1], RespType::Null);
+    let RespType::Array(get) = &info[0] else {
+        panic!("command info should be an array");
+    };
+    assert_eq!(get[0], RespType::BString("get".into()));
+    assert_eq!(get[1], RespType::Int(2));
+    assert_eq!(
+        get[2],
+        RespType::Set(vec![RespType::String("readonly".to_string()), RespType::String("fast".to_string())])
+    );
+    assert_eq!(&get[3..6], &[RespType::Int(1), RespType::Int(1), RespType::Int(1)]);
+    assert_eq!(
+        get[6],
+        RespType::Set(vec![
+            RespType::String("@read".to_string()),
+            RespType::String("@fast".to_string()),
+            RespType::String("@string".to_string())
+        ])
+    );
+
+    let RespType::Map(docs) = handler.handle_cmd(cmd(&["COMMAND", "DOCS", "del"])).unwrap() else {
+        panic!("COMMAND DOCS should reply with a map");
+    };
+    assert_eq!(docs[0].0, RespType::BString("del".into()));
+    let RespType::Map(del_docs) = &docs[0].1 else {
+        panic!("command docs should be a map");
+    };
+    assert!(del_docs.contains(&(RespType::BString("group".into()), RespType::BString("generic".into()))));
+
+    assert_eq!(
+        handler.handle_cmd(cmd(&["COMMAND", "GETKEYS", "DEL", "a", "b"])).unwrap(),
+        RespType::Array(vec![RespType::BString("a".into()), RespType::BString("b".into())])
+    );
+    assert!(handler.handle_cmd(cmd(&["COMMAND", "GETKEYS", "PING"])).is_err());
+    assert!(handler.handle_cmd(cmd(&["COMMAND", "GETKEYS", "GET"])).is_err());
+}
+
+#[test]
+fn test_case_insensitive_dispatch() {
+    let storage = Arc::new(Mutex::new(Storage::new()));
+    let mut handler = CommandHandler::new(Arc::clone(&storage));
+
+    assert_eq!(handler.handle_cmd(cmd(&["set", "foo", "bar"])).unwrap(), RespType::String("OK".to_string()));
+    assert_eq!(handler.handle_cmd(cmd(&["GeT", "foo"])).unwrap(), RespType::BString("bar".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["ping"])).unwrap(), RespType::String("PONG".to_string()));
+    assert_eq!(
+        handler.handle_cmd(cmd(&["command", "getkeys", "get", "foo"])).unwrap(),
+        RespType::Array(vec![RespType::BString("foo".into())])
+    );
+}
+
+#[test]
+fn test_error_replies() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+
+    let err = handler.handle_cmd(cmd(&["FOO", "bar", "baz"])).unwrap_err();
+    assert_eq!(err, CommandErr::UnknownCommand("FOO".to_string(), vec!["bar".to_string(), "baz".to_string()]));
+    assert_eq!(err.to_string(), "ERR unknown command 'FOO', with args beginning with: 'bar' 'baz' ");
+
+    let err = handler.handle_cmd(cmd(&["get"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR wrong number of arguments for 'get' command");
+    assert_eq!(RespType::from(err).serialize(), b"-ERR wrong number of arguments for 'get' command\r\n");
+
+    assert_eq!(CommandErr::WrongType.to_string(), "WRONGTYPE Operation against a key holding the wrong kind of value");
+    assert!(CommandErr::NoAuth.to_string().starts_with("NOAUTH "));
+    assert!(CommandErr::Oom.to_string().starts_with("OOM "));
+    assert!(CommandErr::ReadOnly.to_string().starts_with("READONLY "));
+    assert!(CommandErr::Busy.to_string().starts_with("BUSY "));
+    assert_eq!(handler.handle_cmd(cmd(&["HELLO", "4"])).unwrap_err().to_string(), "NOPROTO unsupported protocol version");
+}
+
 
 fn insert(k: &str, v: &str, storage: Arc<Mutex<Storage>>) -> bool {
     let d = RespType::Array(vec![
-        RespType::BString("SET".to_string()),
-        RespType::BString(k.to_string()),
-        RespType::BString(v.to_string()),
+        RespType::BString("SET".into()),
+        RespType::BString(k.into()),
+        RespType::BString(v.into()),
     ]);
 
     let result = CommandHandler::new(storage).handle_cmd(d);
@@ -75,4 +215,1648 @@ fn insert(k: &str, v: &str, storage: Arc<Mutex<Storage>>) -> bool {
         Ok(_) => {true},
         Err(_) => {false}
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_expire_and_ttl() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    handler.handle_cmd(cmd(&["SET", "k", "v"])).unwrap();
+
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "k"])).unwrap(), RespType::Int(-1));
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "missing"])).unwrap(), RespType::Int(-2));
+    assert_eq!(handler.handle_cmd(cmd(&["EXPIRE", "missing", "10"])).unwrap(), RespType::Int(0));
+
+    // a persistent key counts as an infinite ttl
+    assert_eq!(handler.handle_cmd(cmd(&["EXPIRE", "k", "100", "GT"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["EXPIRE", "k", "100", "XX"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["EXPIRE", "k", "100", "LT"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "k"])).unwrap(), RespType::Int(100));
+    assert_eq!(handler.handle_cmd(cmd(&["EXPIRE", "k", "200", "NX"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["EXPIRE", "k", "50", "GT"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["EXPIRE", "k", "200", "GT"])).unwrap(), RespType::Int(1));
+
+    let RespType::Int(pttl) = handler.handle_cmd(cmd(&["PTTL", "k"])).unwrap() else { panic!() };
+    assert!(pttl > 199_000 && pttl <= 200_000);
+    let RespType::Int(at) = handler.handle_cmd(cmd(&["PEXPIRETIME", "k"])).unwrap() else { panic!() };
+    assert_eq!(handler.handle_cmd(cmd(&["EXPIRETIME", "k"])).unwrap(), RespType::Int(at / 1000));
+
+    assert_eq!(handler.handle_cmd(cmd(&["PERSIST", "k"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["PERSIST", "k"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "k"])).unwrap(), RespType::Int(-1));
+
+    // a deadline in the past deletes the key right away
+    assert_eq!(handler.handle_cmd(cmd(&["EXPIREAT", "k", "1"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "k"])).unwrap(), RespType::Null);
+
+    let err = handler.handle_cmd(cmd(&["EXPIRE", "k", "10", "NX", "XX"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR NX and XX, GT or LT options at the same time are not compatible");
+    let err = handler.handle_cmd(cmd(&["EXPIRE", "k", "10", "GT", "LT"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR GT and LT options at the same time are not compatible");
+    let err = handler.handle_cmd(cmd(&["EXPIRE", "k", "9223372036854775807"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR invalid expire time in 'expire' command");
+}
+
+#[test]
+fn test_lazy_and_active_expiry() {
+    let storage = Arc::new(Mutex::new(Storage::new()));
+    let mut handler = CommandHandler::new(Arc::clone(&storage));
+    handler.handle_cmd(cmd(&["SET", "k", "v"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["PEXPIRE", "k", "20"])).unwrap(), RespType::Int(1));
+    std::thread::sleep(std::time::Duration::from_millis(40));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "k"])).unwrap(), RespType::Null);
+    assert_eq!(storage.lock().unwrap().len(), 0);
+
+    for i in 0..100 {
+        let key = format!("key{i}");
+        handler.handle_cmd(cmd(&["SET", &key, "v"])).unwrap();
+        handler.handle_cmd(cmd(&["PEXPIRE", &key, "10"])).unwrap();
+    }
+    handler.handle_cmd(cmd(&["SET", "kept", "v"])).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(30));
+
+    let mut storage = storage.lock().unwrap();
+    assert_eq!(storage.active_expire_cycle(std::time::Duration::from_secs(1)), 100);
+    assert_eq!(storage.len(), 1);
+    assert_eq!(storage.volatile_len(), 0);
+}
+
+#[test]
+fn test_set_options() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    let ok = RespType::String("OK".to_string());
+
+    // the usual lock idiom only succeeds for the first taker
+    assert_eq!(handler.handle_cmd(cmd(&["SET", "lock", "a", "EX", "60", "NX"])).unwrap(), ok);
+    assert_eq!(handler.handle_cmd(cmd(&["SET", "lock", "b", "EX", "60", "NX"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "lock"])).unwrap(), RespType::Int(60));
+
+    assert_eq!(handler.handle_cmd(cmd(&["SET", "missing", "v", "XX"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["SET", "lock", "c", "XX", "KEEPTTL", "GET"])).unwrap(), RespType::BString("a".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "lock"])).unwrap(), RespType::Int(60));
+    assert_eq!(handler.handle_cmd(cmd(&["SET", "lock", "d", "GET"])).unwrap(), RespType::BString("c".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "lock"])).unwrap(), RespType::Int(-1));
+
+    assert_eq!(handler.handle_cmd(cmd(&["SET", "k", "v", "PX", "5000"])).unwrap(), ok);
+    let RespType::Int(pttl) = handler.handle_cmd(cmd(&["PTTL", "k"])).unwrap() else { panic!() };
+    assert!(pttl > 4000 && pttl <= 5000);
+    assert_eq!(handler.handle_cmd(cmd(&["SET", "k", "v", "EXAT", "1"])).unwrap(), ok);
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "k"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["SET", "k", "v", "PXAT", "99999999999999"])).unwrap(), ok);
+    assert_eq!(handler.handle_cmd(cmd(&["PEXPIRETIME", "k"])).unwrap(), RespType::Int(99999999999999));
+
+    for bad in [&["NX", "XX"][..], &["EX", "10", "PX", "10"], &["EX"], &["KEEPTTL", "EX", "1"], &["FOO"]] {
+        let mut args = vec!["SET", "k", "v"];
+        args.extend_from_slice(bad);
+        assert_eq!(handler.handle_cmd(cmd(&args)).unwrap_err(), CommandErr::SyntaxError);
+    }
+    let err = handler.handle_cmd(cmd(&["SET", "k", "v", "EX", "0"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR invalid expire time in 'set' command");
+    let err = handler.handle_cmd(cmd(&["SET", "k", "v", "EX", "ten"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR value is not an integer or out of range");
+}
+
+#[test]
+fn test_set_variants() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    let ok = RespType::String("OK".to_string());
+
+    assert_eq!(handler.handle_cmd(cmd(&["SETNX", "k", "1"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["SETNX", "k", "2"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["SETEX", "k", "100", "3"])).unwrap(), ok);
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "k"])).unwrap(), RespType::Int(100));
+    assert_eq!(handler.handle_cmd(cmd(&["PSETEX", "k", "100000", "4"])).unwrap(), ok);
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "k"])).unwrap(), RespType::Int(100));
+    let err = handler.handle_cmd(cmd(&["SETEX", "k", "-1", "5"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR invalid expire time in 'setex' command");
+
+    assert_eq!(handler.handle_cmd(cmd(&["GETSET", "k", "5"])).unwrap(), RespType::BString("4".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "k"])).unwrap(), RespType::Int(-1));
+    assert_eq!(handler.handle_cmd(cmd(&["GETSET", "new", "1"])).unwrap(), RespType::Null);
+
+    assert_eq!(handler.handle_cmd(cmd(&["GETEX", "k", "EX", "30"])).unwrap(), RespType::BString("5".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "k"])).unwrap(), RespType::Int(30));
+    assert_eq!(handler.handle_cmd(cmd(&["GETEX", "k"])).unwrap(), RespType::BString("5".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "k"])).unwrap(), RespType::Int(30));
+    assert_eq!(handler.handle_cmd(cmd(&["GETEX", "k", "PERSIST"])).unwrap(), RespType::BString("5".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "k"])).unwrap(), RespType::Int(-1));
+    assert_eq!(handler.handle_cmd(cmd(&["GETEX", "missing", "EX", "30"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["GETEX", "k", "EX"])).unwrap_err(), CommandErr::SyntaxError);
+
+    assert_eq!(handler.handle_cmd(cmd(&["GETDEL", "k"])).unwrap(), RespType::BString("5".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["GETDEL", "k"])).unwrap(), RespType::Null);
+}
+
+#[test]
+fn test_counters() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+
+    assert_eq!(handler.handle_cmd(cmd(&["INCR", "n"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["INCRBY", "n", "41"])).unwrap(), RespType::Int(42));
+    assert_eq!(handler.handle_cmd(cmd(&["DECR", "n"])).unwrap(), RespType::Int(41));
+    assert_eq!(handler.handle_cmd(cmd(&["DECRBY", "n", "-9"])).unwrap(), RespType::Int(50));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "n"])).unwrap(), RespType::BString("50".into()));
+
+    // counting keeps the key's ttl, which rate limiters rely on
+    handler.handle_cmd(cmd(&["EXPIRE", "n", "100"])).unwrap();
+    handler.handle_cmd(cmd(&["INCR", "n"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "n"])).unwrap(), RespType::Int(100));
+
+    handler.handle_cmd(cmd(&["SET", "n", "9223372036854775807"])).unwrap();
+    let err = handler.handle_cmd(cmd(&["INCR", "n"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR increment or decrement would overflow");
+    let err = handler.handle_cmd(cmd(&["DECRBY", "n", "-9223372036854775808"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR decrement would overflow");
+    for bad in ["abc", " 1", "01", "+1", "1.5", ""] {
+        handler.handle_cmd(cmd(&["SET", "s", bad])).unwrap();
+        let err = handler.handle_cmd(cmd(&["INCR", "s"])).unwrap_err();
+        assert_eq!(err.to_string(), "ERR value is not an integer or out of range");
+    }
+
+    assert_eq!(handler.handle_cmd(cmd(&["INCRBYFLOAT", "f", "10.5"])).unwrap(), RespType::BString("10.5".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["INCRBYFLOAT", "f", "0.5"])).unwrap(), RespType::BString("11".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["INCRBYFLOAT", "f", "-5e0"])).unwrap(), RespType::BString("6".into()));
+    let err = handler.handle_cmd(cmd(&["INCRBYFLOAT", "f", "inf"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR increment would produce NaN or Infinity");
+    let err = handler.handle_cmd(cmd(&["INCRBYFLOAT", "s", "1"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR value is not a valid float");
+}
+
+#[test]
+fn test_string_manipulation() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    let bulk = |s: &str| RespType::BString(s.into());
+
+    assert_eq!(handler.handle_cmd(cmd(&["APPEND", "s", "Hello"])).unwrap(), RespType::Int(5));
+    assert_eq!(handler.handle_cmd(cmd(&["APPEND", "s", " World"])).unwrap(), RespType::Int(11));
+    assert_eq!(handler.handle_cmd(cmd(&["STRLEN", "s"])).unwrap(), RespType::Int(11));
+    assert_eq!(handler.handle_cmd(cmd(&["STRLEN", "missing"])).unwrap(), RespType::Int(0));
+
+    assert_eq!(handler.handle_cmd(cmd(&["GETRANGE", "s", "0", "4"])).unwrap(), bulk("Hello"));
+    assert_eq!(handler.handle_cmd(cmd(&["GETRANGE", "s", "-5", "-1"])).unwrap(), bulk("World"));
+    assert_eq!(handler.handle_cmd(cmd(&["GETRANGE", "s", "0", "100"])).unwrap(), bulk("Hello World"));
+    assert_eq!(handler.handle_cmd(cmd(&["GETRANGE", "s", "5", "3"])).unwrap(), bulk(""));
+    assert_eq!(handler.handle_cmd(cmd(&["GETRANGE", "s", "-1", "-5"])).unwrap(), bulk(""));
+    assert_eq!(handler.handle_cmd(cmd(&["GETRANGE", "missing", "0", "-1"])).unwrap(), bulk(""));
+
+    assert_eq!(handler.handle_cmd(cmd(&["SETRANGE", "s", "6", "Redis"])).unwrap(), RespType::Int(11));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "s"])).unwrap(), bulk("Hello Redis"));
+    assert_eq!(handler.handle_cmd(cmd(&["SETRANGE", "pad", "3", "x"])).unwrap(), RespType::Int(4));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "pad"])).unwrap(), RespType::BString(vec![0, 0, 0, b'x']));
+    assert_eq!(handler.handle_cmd(cmd(&["SETRANGE", "empty", "3", ""])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "empty"])).unwrap(), RespType::Null);
+    let err = handler.handle_cmd(cmd(&["SETRANGE", "s", "-1", "x"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR offset is out of range");
+    let err = handler.handle_cmd(cmd(&["SETRANGE", "s", "536870911", "xx"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR string exceeds maximum allowed size (proto-max-bulk-len)");
+}
+
+#[test]
+fn test_multi_key_strings() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    let bulk = |s: &str| RespType::BString(s.into());
+
+    assert_eq!(handler.handle_cmd(cmd(&["MSET", "a", "1", "b", "2"])).unwrap(), RespType::String("OK".to_string()));
+    assert_eq!(
+        handler.handle_cmd(cmd(&["MGET", "a", "missing", "b"])).unwrap(),
+        RespType::Array(vec![bulk("1"), RespType::Null, bulk("2")])
+    );
+    assert_eq!(handler.handle_cmd(cmd(&["MSET", "a", "1", "b"])).unwrap_err(), CommandErr::WrongArity("mset".to_string()));
+
+    assert_eq!(handler.handle_cmd(cmd(&["MSETNX", "b", "3", "c", "3"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "c"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["MSETNX", "c", "3", "d", "4"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "d"])).unwrap(), bulk("4"));
+}
+
+#[test]
+fn test_lcs() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    handler.handle_cmd(cmd(&["MSET", "key1", "ohmytext", "key2", "mynewtext"])).unwrap();
+
+    assert_eq!(handler.handle_cmd(cmd(&["LCS", "key1", "key2"])).unwrap(), RespType::BString("mytext".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["LCS", "key1", "key2", "LEN"])).unwrap(), RespType::Int(6));
+    assert_eq!(handler.handle_cmd(cmd(&["LCS", "key1", "missing"])).unwrap(), RespType::BString("".into()));
+
+    let range = |a: isize, b: isize| RespType::Array(vec![RespType::Int(a), RespType::Int(b)]);
+    assert_eq!(
+        handler.handle_cmd(cmd(&["LCS", "key1", "key2", "IDX"])).unwrap(),
+        RespType::Map(vec![
+            (RespType::BString("matches".into()), RespType::Array(vec![
+                RespType::Array(vec![range(4, 7), range(5, 8)]),
+                RespType::Array(vec![range(2, 3), range(0, 1)]),
+            ])),
+            (RespType::BString("len".into()), RespType::Int(6)),
+        ])
+    );
+    assert_eq!(
+        handler.handle_cmd(cmd(&["LCS", "key1", "key2", "IDX", "MINMATCHLEN", "4", "WITHMATCHLEN"])).unwrap(),
+        RespType::Map(vec![
+            (RespType::BString("matches".into()), RespType::Array(vec![
+                RespType::Array(vec![range(4, 7), range(5, 8), RespType::Int(4)]),
+            ])),
+            (RespType::BString("len".into()), RespType::Int(6)),
+        ])
+    );
+    let err = handler.handle_cmd(cmd(&["LCS", "key1", "key2", "LEN", "IDX"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR If you want both the length and indexes, please just use IDX.");
+    // the table would take over 4GB
+    let long = "a".repeat(32768);
+    handler.handle_cmd(cmd(&["SET", "long1", &long])).unwrap();
+    handler.handle_cmd(cmd(&["SET", "long2", &long])).unwrap();
+    let err = handler.handle_cmd(cmd(&["LCS", "long1", "long2", "LEN"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR String too long for LCS");
+}
+
+fn bin_cmd(args: &[&[u8]]) -> RespType {
+    RespType::Array(args.iter().map(|a| RespType::BString(a.to_vec())).collect())
+}
+
+#[test]
+fn test_setbit_getbit_bitcount() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+
+    assert_eq!(handler.handle_cmd(cmd(&["SETBIT", "bits", "7", "1"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["SETBIT", "bits", "7", "0"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["SETBIT", "bits", "7", "1"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "bits"])).unwrap(), RespType::BString(vec![1]));
+    assert_eq!(handler.handle_cmd(cmd(&["GETBIT", "bits", "0"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["GETBIT", "bits", "7"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["GETBIT", "bits", "100"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["SETBIT", "bits", "20", "1"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["STRLEN", "bits"])).unwrap(), RespType::Int(3));
+
+    let err = handler.handle_cmd(cmd(&["SETBIT", "bits", "-1", "1"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR bit offset is not an integer or out of range");
+    let err = handler.handle_cmd(cmd(&["SETBIT", "bits", "4294967296", "1"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR bit offset is not an integer or out of range");
+    let err = handler.handle_cmd(cmd(&["SETBIT", "bits", "1", "2"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR bit is not an integer or out of range");
+
+    handler.handle_cmd(cmd(&["SET", "s", "foobar"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["BITCOUNT", "s"])).unwrap(), RespType::Int(26));
+    assert_eq!(handler.handle_cmd(cmd(&["BITCOUNT", "s", "0", "0"])).unwrap(), RespType::Int(4));
+    assert_eq!(handler.handle_cmd(cmd(&["BITCOUNT", "s", "1", "1", "BYTE"])).unwrap(), RespType::Int(6));
+    assert_eq!(handler.handle_cmd(cmd(&["BITCOUNT", "s", "5", "30", "BIT"])).unwrap(), RespType::Int(17));
+    assert_eq!(handler.handle_cmd(cmd(&["BITCOUNT", "s", "-2", "-1"])).unwrap(), RespType::Int(7));
+    assert_eq!(handler.handle_cmd(cmd(&["BITCOUNT", "s", "-1", "-2"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["BITCOUNT", "missing"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["BITCOUNT", "s", "0"])).unwrap_err(), CommandErr::SyntaxError);
+    assert_eq!(handler.handle_cmd(cmd(&["BITCOUNT", "s", "0", "1", "WORD"])).unwrap_err(), CommandErr::SyntaxError);
+}
+
+#[test]
+fn test_bitpos() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+
+    handler.handle_cmd(bin_cmd(&[b"SET", b"k", b"\xff\xf0\x00"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["BITPOS", "k", "0"])).unwrap(), RespType::Int(12));
+
+    handler.handle_cmd(bin_cmd(&[b"SET", b"k", b"\x00\xff\xf0"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["BITPOS", "k", "1", "0"])).unwrap(), RespType::Int(8));
+    assert_eq!(handler.handle_cmd(cmd(&["BITPOS", "k", "1", "2"])).unwrap(), RespType::Int(16));
+    assert_eq!(handler.handle_cmd(cmd(&["BITPOS", "k", "1", "2", "-1", "BYTE"])).unwrap(), RespType::Int(16));
+    assert_eq!(handler.handle_cmd(cmd(&["BITPOS", "k", "1", "7", "15", "BIT"])).unwrap(), RespType::Int(8));
+
+    handler.handle_cmd(bin_cmd(&[b"SET", b"k", b"\x00\x00\x00"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["BITPOS", "k", "1"])).unwrap(), RespType::Int(-1));
+    assert_eq!(handler.handle_cmd(cmd(&["BITPOS", "k", "1", "7", "-3", "BIT"])).unwrap(), RespType::Int(-1));
+
+    // with no end given, the clear bit right after the string is found
+    handler.handle_cmd(bin_cmd(&[b"SET", b"k", b"\xff\xff"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["BITPOS", "k", "0"])).unwrap(), RespType::Int(16));
+    assert_eq!(handler.handle_cmd(cmd(&["BITPOS", "k", "0", "0", "-1"])).unwrap(), RespType::Int(-1));
+
+    assert_eq!(handler.handle_cmd(cmd(&["BITPOS", "missing", "0"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["BITPOS", "missing", "1"])).unwrap(), RespType::Int(-1));
+    let err = handler.handle_cmd(cmd(&["BITPOS", "k", "2"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR The bit argument must be 1 or 0.");
+}
+
+#[test]
+fn test_bitop() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    handler.handle_cmd(cmd(&["MSET", "key1", "foobar", "key2", "abcdef", "short", "a"])).unwrap();
+
+    assert_eq!(handler.handle_cmd(cmd(&["BITOP", "AND", "dest", "key1", "key2"])).unwrap(), RespType::Int(6));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "dest"])).unwrap(), RespType::BString("`bc`ab".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["BITOP", "OR", "dest", "short", "key2"])).unwrap(), RespType::Int(6));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "dest"])).unwrap(), RespType::BString("abcdef".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["BITOP", "XOR", "dest", "key1"])).unwrap(), RespType::Int(6));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "dest"])).unwrap(), RespType::BString("foobar".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["BITOP", "NOT", "dest", "short"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "dest"])).unwrap(), RespType::BString(vec![!b'a']));
+
+    // an empty result deletes the destination
+    assert_eq!(handler.handle_cmd(cmd(&["BITOP", "AND", "dest", "missing"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "dest"])).unwrap(), RespType::Null);
+
+    let err = handler.handle_cmd(cmd(&["BITOP", "NOT", "dest", "key1", "key2"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR BITOP NOT must be called with a single source key.");
+    assert_eq!(handler.handle_cmd(cmd(&["BITOP", "NAND", "dest", "key1"])).unwrap_err(), CommandErr::SyntaxError);
+}
+
+#[test]
+fn test_bitfield() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    let ints = |v: &[isize]| RespType::Array(v.iter().map(|&n| RespType::Int(n)).collect());
+
+    assert_eq!(
+        handler.handle_cmd(cmd(&["BITFIELD", "k", "INCRBY", "i5", "100", "1", "GET", "u4", "0"])).unwrap(),
+        ints(&[1, 0])
+    );
+
+    for expected in [[1, 1], [2, 2], [3, 3], [0, 3]] {
+        assert_eq!(
+            handler.handle_cmd(cmd(&["BITFIELD", "c", "INCRBY", "u2", "100", "1", "OVERFLOW", "SAT", "INCRBY", "u2", "102", "1"])).unwrap(),
+            ints(&expected)
+        );
+    }
+    assert_eq!(
+        handler.handle_cmd(cmd(&["BITFIELD", "c", "OVERFLOW", "FAIL", "INCRBY", "u2", "102", "1"])).unwrap(),
+        RespType::Array(vec![RespType::Null])
+    );
+
+    assert_eq!(
+        handler.handle_cmd(cmd(&["BITFIELD", "n", "SET", "i8", "#1", "127", "INCRBY", "i8", "#1", "1", "GET", "u8", "8"])).unwrap(),
+        ints(&[0, -128, 128])
+    );
+    assert_eq!(
+        handler.handle_cmd(cmd(&["BITFIELD", "n", "OVERFLOW", "SAT", "INCRBY", "i8", "8", "-1", "SET", "u8", "0", "300", "GET", "u8", "0"])).unwrap(),
+        ints(&[-128, 0, 255])
+    );
+    assert_eq!(handler.handle_cmd(cmd(&["BITFIELD", "w", "SET", "i64", "0", "-1", "GET", "i64", "0"])).unwrap(), ints(&[0, -1]));
+
+    assert_eq!(handler.handle_cmd(cmd(&["BITFIELD_RO", "n", "GET", "i4", "0"])).unwrap(), ints(&[-1]));
+    let err = handler.handle_cmd(cmd(&["BITFIELD_RO", "n", "SET", "i4", "0", "1"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR BITFIELD_RO only supports the GET subcommand");
+    let err = handler.handle_cmd(cmd(&["BITFIELD", "n", "GET", "u64", "0"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.");
+
+    // reading a missing key doesn't create it
+    assert_eq!(handler.handle_cmd(cmd(&["BITFIELD", "missing", "GET", "u8", "0"])).unwrap(), ints(&[0]));
+    assert_eq!(handler.handle_cmd(cmd(&["GET", "missing"])).unwrap(), RespType::Null);
+}
+
+fn bulks(items: &[&str]) -> RespType {
+    RespType::Array(items.iter().map(|s| RespType::BString(s.as_bytes().to_vec())).collect())
+}
+
+#[test]
+fn test_list_push_pop() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+
+    assert_eq!(handler.handle_cmd(cmd(&["RPUSH", "q", "a", "b", "c"])).unwrap(), RespType::Int(3));
+    assert_eq!(handler.handle_cmd(cmd(&["LPUSH", "q", "z", "y"])).unwrap(), RespType::Int(5));
+    assert_eq!(handler.handle_cmd(cmd(&["LRANGE", "q", "0", "-1"])).unwrap(), bulks(&["y", "z", "a", "b", "c"]));
+    assert_eq!(handler.handle_cmd(cmd(&["LPUSHX", "missing", "a"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["RPUSHX", "q", "d"])).unwrap(), RespType::Int(6));
+    assert_eq!(handler.handle_cmd(cmd(&["LLEN", "q"])).unwrap(), RespType::Int(6));
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "q"])).unwrap(), RespType::String("list".to_string()));
+
+    assert_eq!(handler.handle_cmd(cmd(&["LPOP", "q"])).unwrap(), RespType::BString("y".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["RPOP", "q", "2"])).unwrap(), bulks(&["d", "c"]));
+    assert_eq!(handler.handle_cmd(cmd(&["LPOP", "q", "0"])).unwrap(), bulks(&[]));
+    assert_eq!(handler.handle_cmd(cmd(&["LPOP", "q", "10"])).unwrap(), bulks(&["z", "a", "b"]));
+
+    // popping the last element removes the key
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "q"])).unwrap(), RespType::String("none".to_string()));
+    assert_eq!(handler.handle_cmd(cmd(&["LPOP", "q"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["LPOP", "q", "2"])).unwrap(), RespType::NullArray);
+    let err = handler.handle_cmd(cmd(&["LPOP", "q", "-1"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR value is out of range, must be positive");
+}
+
+#[test]
+fn test_list_access() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    handler.handle_cmd(cmd(&["RPUSH", "l", "a", "b", "c", "b", "a", "b"])).unwrap();
+
+    assert_eq!(handler.handle_cmd(cmd(&["LRANGE", "l", "-2", "100"])).unwrap(), bulks(&["a", "b"]));
+    assert_eq!(handler.handle_cmd(cmd(&["LRANGE", "l", "4", "2"])).unwrap(), bulks(&[]));
+    assert_eq!(handler.handle_cmd(cmd(&["LINDEX", "l", "-1"])).unwrap(), RespType::BString("b".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["LINDEX", "l", "6"])).unwrap(), RespType::Null);
+
+    assert_eq!(handler.handle_cmd(cmd(&["LPOS", "l", "b"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["LPOS", "l", "b", "RANK", "2"])).unwrap(), RespType::Int(3));
+    assert_eq!(handler.handle_cmd(cmd(&["LPOS", "l", "b", "RANK", "-1"])).unwrap(), RespType::Int(5));
+    assert_eq!(
+        handler.handle_cmd(cmd(&["LPOS", "l", "b", "COUNT", "0"])).unwrap(),
+        RespType::Array(vec![RespType::Int(1), RespType::Int(3), RespType::Int(5)])
+    );
+    assert_eq!(
+        handler.handle_cmd(cmd(&["LPOS", "l", "b", "COUNT", "2", "RANK", "-1"])).unwrap(),
+        RespType::Array(vec![RespType::Int(5), RespType::Int(3)])
+    );
+    assert_eq!(handler.handle_cmd(cmd(&["LPOS", "l", "c", "MAXLEN", "2"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["LPOS", "l", "x"])).unwrap(), RespType::Null);
+    assert!(handler.handle_cmd(cmd(&["LPOS", "l", "b", "RANK", "0"])).unwrap_err().to_string().starts_with("ERR RANK can't be zero"));
+
+    assert_eq!(handler.handle_cmd(cmd(&["LSET", "l", "0", "A"])).unwrap(), RespType::String("OK".to_string()));
+    assert_eq!(handler.handle_cmd(cmd(&["LSET", "l", "10", "A"])).unwrap_err().to_string(), "ERR index out of range");
+    assert_eq!(handler.handle_cmd(cmd(&["LSET", "missing", "0", "A"])).unwrap_err().to_string(), "ERR no such key");
+
+    assert_eq!(handler.handle_cmd(cmd(&["LREM", "l", "-2", "b"])).unwrap(), RespType::Int(2));
+    assert_eq!(handler.handle_cmd(cmd(&["LRANGE", "l", "0", "-1"])).unwrap(), bulks(&["A", "b", "c", "a"]));
+    assert_eq!(handler.handle_cmd(cmd(&["LINSERT", "l", "BEFORE", "c", "x"])).unwrap(), RespType::Int(5));
+    assert_eq!(handler.handle_cmd(cmd(&["LINSERT", "l", "AFTER", "a", "y"])).unwrap(), RespType::Int(6));
+    assert_eq!(handler.handle_cmd(cmd(&["LINSERT", "l", "AFTER", "nope", "y"])).unwrap(), RespType::Int(-1));
+    assert_eq!(handler.handle_cmd(cmd(&["LINSERT", "missing", "AFTER", "a", "y"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["LRANGE", "l", "0", "-1"])).unwrap(), bulks(&["A", "b", "x", "c", "a", "y"]));
+
+    assert_eq!(handler.handle_cmd(cmd(&["LTRIM", "l", "1", "-2"])).unwrap(), RespType::String("OK".to_string()));
+    assert_eq!(handler.handle_cmd(cmd(&["LRANGE", "l", "0", "-1"])).unwrap(), bulks(&["b", "x", "c", "a"]));
+    handler.handle_cmd(cmd(&["LTRIM", "l", "5", "10"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "l"])).unwrap(), RespType::String("none".to_string()));
+}
+
+#[test]
+fn test_list_move_and_mpop() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    handler.handle_cmd(cmd(&["RPUSH", "src", "a", "b", "c"])).unwrap();
+
+    assert_eq!(handler.handle_cmd(cmd(&["LMOVE", "src", "dst", "LEFT", "RIGHT"])).unwrap(), RespType::BString("a".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["RPOPLPUSH", "src", "dst"])).unwrap(), RespType::BString("c".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["LRANGE", "dst", "0", "-1"])).unwrap(), bulks(&["c", "a"]));
+    // rotating a list onto itself
+    assert_eq!(handler.handle_cmd(cmd(&["LMOVE", "dst", "dst", "RIGHT", "LEFT"])).unwrap(), RespType::BString("a".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["LRANGE", "dst", "0", "-1"])).unwrap(), bulks(&["a", "c"]));
+    assert_eq!(handler.handle_cmd(cmd(&["LMOVE", "missing", "dst", "LEFT", "LEFT"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["LMOVE", "src", "dst", "UP", "LEFT"])).unwrap_err(), CommandErr::SyntaxError);
+
+    assert_eq!(
+        handler.handle_cmd(cmd(&["LMPOP", "3", "missing", "dst", "src", "LEFT", "COUNT", "5"])).unwrap(),
+        RespType::Array(vec![RespType::BString("dst".into()), bulks(&["a", "c"])])
+    );
+    assert_eq!(
+        handler.handle_cmd(cmd(&["LMPOP", "2", "dst", "src", "RIGHT"])).unwrap(),
+        RespType::Array(vec![RespType::BString("src".into()), bulks(&["b"])])
+    );
+    assert_eq!(handler.handle_cmd(cmd(&["LMPOP", "1", "src", "LEFT"])).unwrap(), RespType::NullArray);
+    assert_eq!(handler.handle_cmd(cmd(&["LMPOP", "0", "src", "LEFT"])).unwrap_err().to_string(), "ERR numkeys should be greater than 0");
+    assert_eq!(handler.handle_cmd(cmd(&["LMPOP", "1", "src", "LEFT", "COUNT", "0"])).unwrap_err().to_string(), "ERR count should be greater than 0");
+    assert_eq!(
+        handler.handle_cmd(cmd(&["COMMAND", "GETKEYS", "LMPOP", "2", "a", "b", "LEFT"])).unwrap(),
+        bulks(&["a", "b"])
+    );
+}
+
+#[test]
+fn test_wrong_type() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    handler.handle_cmd(cmd(&["RPUSH", "list", "a"])).unwrap();
+    handler.handle_cmd(cmd(&["SET", "str", "a"])).unwrap();
+
+    for args in [
+        &["GET", "list"][..],
+        &["APPEND", "list", "x"],
+        &["INCR", "list"],
+        &["STRLEN", "list"],
+        &["GETBIT", "list", "0"],
+        &["SET", "list", "x", "GET"],
+        &["LPUSH", "str", "x"],
+        &["LRANGE", "str", "0", "-1"],
+        &["LMOVE", "list", "str", "LEFT", "LEFT"],
+    ] {
+        assert_eq!(handler.handle_cmd(cmd(args)).unwrap_err(), CommandErr::WrongType, "{:?}", args);
+    }
+    // a failed move leaves the source alone
+    assert_eq!(handler.handle_cmd(cmd(&["LLEN", "list"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["MGET", "str", "list"])).unwrap(), RespType::Array(vec![RespType::BString("a".into()), RespType::Null]));
+
+    // a plain SET replaces a value of any type
+    assert_eq!(handler.handle_cmd(cmd(&["SET", "list", "x"])).unwrap(), RespType::String("OK".to_string()));
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "list"])).unwrap(), RespType::String("string".to_string()));
+}
+
+#[test]
+fn test_hash_commands() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+
+    assert_eq!(handler.handle_cmd(cmd(&["HSET", "user", "name", "ann", "age", "41"])).unwrap(), RespType::Int(2));
+    assert_eq!(handler.handle_cmd(cmd(&["HSET", "user", "name", "bob", "city", "oslo"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["HSETNX", "user", "name", "cid"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["HGET", "user", "name"])).unwrap(), RespType::BString("bob".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["HGET", "user", "nope"])).unwrap(), RespType::Null);
+    assert_eq!(
+        handler.handle_cmd(cmd(&["HMGET", "user", "age", "nope", "city"])).unwrap(),
+        RespType::Array(vec![RespType::BString("41".into()), RespType::Null, RespType::BString("oslo".into())])
+    );
+    assert_eq!(handler.handle_cmd(cmd(&["HLEN", "user"])).unwrap(), RespType::Int(3));
+    assert_eq!(handler.handle_cmd(cmd(&["HSTRLEN", "user", "city"])).unwrap(), RespType::Int(4));
+    assert_eq!(handler.handle_cmd(cmd(&["HEXISTS", "user", "age"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["HKEYS", "user"])).unwrap(), bulks(&["name", "age", "city"]));
+    assert_eq!(handler.handle_cmd(cmd(&["HVALS", "user"])).unwrap(), bulks(&["bob", "41", "oslo"]));
+    assert_eq!(
+        handler.handle_cmd(cmd(&["HGETALL", "user"])).unwrap(),
+        RespType::Map(vec![
+            (RespType::BString("name".into()), RespType::BString("bob".into())),
+            (RespType::BString("age".into()), RespType::BString("41".into())),
+            (RespType::BString("city".into()), RespType::BString("oslo".into())),
+        ])
+    );
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "user"])).unwrap(), RespType::String("hash".to_string()));
+
+    assert_eq!(handler.handle_cmd(cmd(&["HINCRBY", "user", "age", "-1"])).unwrap(), RespType::Int(40));
+    assert_eq!(handler.handle_cmd(cmd(&["HINCRBY", "user", "visits", "5"])).unwrap(), RespType::Int(5));
+    let err = handler.handle_cmd(cmd(&["HINCRBY", "user", "name", "1"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR hash value is not an integer");
+    handler.handle_cmd(cmd(&["HSET", "user", "big", &i64::MAX.to_string()])).unwrap();
+    let err = handler.handle_cmd(cmd(&["HINCRBY", "user", "big", "1"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR increment or decrement would overflow");
+    assert_eq!(handler.handle_cmd(cmd(&["HINCRBYFLOAT", "user", "age", "0.5"])).unwrap(), RespType::BString("40.5".into()));
+    let err = handler.handle_cmd(cmd(&["HINCRBYFLOAT", "user", "name", "1"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR hash value is not a float");
+
+    assert_eq!(handler.handle_cmd(cmd(&["HDEL", "user", "name", "nope", "big"])).unwrap(), RespType::Int(2));
+    assert_eq!(handler.handle_cmd(cmd(&["HDEL", "user", "age", "city", "visits"])).unwrap(), RespType::Int(3));
+    // deleting the last field removes the key
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "user"])).unwrap(), RespType::String("none".to_string()));
+
+    assert_eq!(handler.handle_cmd(cmd(&["HMSET", "h", "a", "1"])).unwrap(), RespType::String("OK".to_string()));
+    let err = handler.handle_cmd(cmd(&["HSET", "h", "a", "1", "b"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR wrong number of arguments for 'hset' command");
+    handler.handle_cmd(cmd(&["SET", "s", "v"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["HGET", "s", "a"])).unwrap_err(), CommandErr::WrongType);
+}
+
+#[test]
+fn test_hrandfield() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    handler.handle_cmd(cmd(&["HSET", "h", "a", "1", "b", "2", "c", "3"])).unwrap();
+
+    let RespType::BString(field) = handler.handle_cmd(cmd(&["HRANDFIELD", "h"])).unwrap() else {
+        panic!("HRANDFIELD should reply with a field");
+    };
+    assert!(["a", "b", "c"].contains(&std::str::from_utf8(&field).unwrap()));
+    assert_eq!(handler.handle_cmd(cmd(&["HRANDFIELD", "nope"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["HRANDFIELD", "nope", "3"])).unwrap(), bulks(&[]));
+
+    // distinct fields, at most all of them
+    let RespType::Array(fields) = handler.handle_cmd(cmd(&["HRANDFIELD", "h", "2"])).unwrap() else {
+        panic!("HRANDFIELD with a count should reply with an array");
+    };
+    assert_eq!(fields.len(), 2);
+    assert_ne!(fields[0], fields[1]);
+    let RespType::Array(fields) = handler.handle_cmd(cmd(&["HRANDFIELD", "h", "10"])).unwrap() else {
+        panic!("HRANDFIELD with a count should reply with an array");
+    };
+    assert_eq!(fields.len(), 3);
+
+    // a negative count may repeat fields
+    let RespType::Array(fields) = handler.handle_cmd(cmd(&["HRANDFIELD", "h", "-7", "WITHVALUES"])).unwrap() else {
+        panic!("HRANDFIELD with a count should reply with an array");
+    };
+    assert_eq!(fields.len(), 14);
+    for pair in fields.chunks(2) {
+        let expected = match &pair[0] {
+            RespType::BString(f) if f == b"a" => "1",
+            RespType::BString(f) if f == b"b" => "2",
+            _ => "3",
+        };
+        assert_eq!(pair[1], RespType::BString(expected.into()));
+    }
+    let err = handler.handle_cmd(cmd(&["HRANDFIELD", "h", "1", "WITHSCORES"])).unwrap_err();
+    assert_eq!(err, CommandErr::SyntaxError);
+}
+
+#[test]
+fn test_hash_encoding_and_hscan() {
+    let limits = EncodingLimits { hash_max_listpack_entries: 4, hash_max_listpack_value: 8, ..EncodingLimits::default() };
+    let storage = Arc::new(Mutex::new(Storage::with_limits(limits)));
+    let mut handler = CommandHandler::new(Arc::clone(&storage));
+    let encoding = |k: &str| storage.lock().unwrap().hash(k.as_bytes()).unwrap().unwrap().encoding();
+
+    handler.handle_cmd(cmd(&["HSET", "small", "a", "1", "b", "2", "c", "3", "d", "4"])).unwrap();
+    assert_eq!(encoding("small"), "listpack");
+    handler.handle_cmd(cmd(&["HSET", "small", "e", "5"])).unwrap();
+    assert_eq!(encoding("small"), "hashtable");
+    handler.handle_cmd(cmd(&["HSET", "long", "a", "a value too long"])).unwrap();
+    assert_eq!(encoding("long"), "hashtable");
+    handler.handle_cmd(cmd(&["HDEL", "small", "e", "d"])).unwrap();
+    assert_eq!(encoding("small"), "hashtable");
+    assert_eq!(handler.handle_cmd(cmd(&["HGET", "small", "a"])).unwrap(), RespType::BString("1".into()));
+
+    // a compact hash is scanned in one go
+    handler.handle_cmd(cmd(&["HSET", "tiny", "x", "1", "y", "2"])).unwrap();
+    assert_eq!(
+        handler.handle_cmd(cmd(&["HSCAN", "tiny", "0"])).unwrap(),
+        RespType::Array(vec![RespType::BString("0".into()), bulks(&["x", "1", "y", "2"])])
+    );
+    assert_eq!(
+        handler.handle_cmd(cmd(&["HSCAN", "tiny", "0", "MATCH", "[x]", "NOVALUES"])).unwrap(),
+        RespType::Array(vec![RespType::BString("0".into()), bulks(&["x"])])
+    );
+
+    // a large one takes a few calls, and fields added or removed meanwhile don't make
+    // the others get skipped, even as the table resizes
+    for i in 0..500 {
+        handler.handle_cmd(cmd(&["HSET", "big", &format!("field:{i}"), "v"])).unwrap();
+    }
+    let mut seen = std::collections::HashSet::new();
+    let mut cursor = "0".to_string();
+    let mut calls = 0;
+    loop {
+        let RespType::Array(reply) = handler.handle_cmd(cmd(&["HSCAN", "big", &cursor, "COUNT", "20", "NOVALUES"])).unwrap() else {
+            panic!("HSCAN should reply with an array");
+        };
+        let (RespType::BString(next), RespType::Array(fields)) = (&reply[0], &reply[1]) else {
+            panic!("HSCAN should reply with a cursor and the fields found");
+        };
+        seen.extend(fields.iter().map(|f| f.as_bytes().unwrap().to_vec()));
+        cursor = String::from_utf8(next.clone()).unwrap();
+        calls += 1;
+        if calls == 5 {
+            for i in 500..1500 {
+                handler.handle_cmd(cmd(&["HSET", "big", &format!("field:{i}"), "v"])).unwrap();
+            }
+        }
+        if cursor == "0" {
+            break;
+        }
+    }
+    assert!(calls > 5);
+    assert!((0..500).all(|i| seen.contains(format!("field:{i}").as_bytes())));
+
+    let RespType::Array(reply) = handler.handle_cmd(cmd(&["HSCAN", "big", "0", "COUNT", "10000", "MATCH", "field:1?"])).unwrap() else {
+        panic!("HSCAN should reply with an array");
+    };
+    let RespType::Array(found) = &reply[1] else {
+        panic!("HSCAN should reply with the fields found");
+    };
+    assert_eq!(found.len(), 20);
+    let err = handler.handle_cmd(cmd(&["HSCAN", "big", "abc"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR invalid cursor");
+    let err = handler.handle_cmd(cmd(&["HSCAN", "big", "0", "COUNT", "0"])).unwrap_err();
+    assert_eq!(err, CommandErr::SyntaxError);
+}
+
+fn ints(items: &[isize]) -> RespType {
+    RespType::Array(items.iter().map(|&i| RespType::Int(i)).collect())
+}
+
+#[test]
+fn test_hash_field_expiry() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    handler.handle_cmd(cmd(&["HSET", "flags", "a", "1", "b", "2", "c", "3"])).unwrap();
+
+    assert_eq!(handler.handle_cmd(cmd(&["HEXPIRE", "flags", "100", "FIELDS", "2", "a", "nope"])).unwrap(), ints(&[1, -2]));
+    assert_eq!(handler.handle_cmd(cmd(&["HEXPIRE", "flags", "50", "NX", "FIELDS", "2", "a", "b"])).unwrap(), ints(&[0, 1]));
+    assert_eq!(handler.handle_cmd(cmd(&["HEXPIRE", "flags", "200", "XX", "FIELDS", "2", "a", "c"])).unwrap(), ints(&[1, 0]));
+    // no expiry counts as an infinite one
+    assert_eq!(handler.handle_cmd(cmd(&["HEXPIRE", "flags", "300", "GT", "FIELDS", "2", "a", "c"])).unwrap(), ints(&[1, 0]));
+    assert_eq!(handler.handle_cmd(cmd(&["HEXPIRE", "flags", "400", "LT", "FIELDS", "2", "a", "c"])).unwrap(), ints(&[0, 1]));
+    assert_eq!(handler.handle_cmd(cmd(&["HTTL", "flags", "FIELDS", "4", "a", "b", "c", "nope"])).unwrap(), ints(&[300, 50, 400, -2]));
+    let RespType::Array(pttl) = handler.handle_cmd(cmd(&["HPTTL", "flags", "FIELDS", "1", "b"])).unwrap() else {
+        panic!("HPTTL should reply with an array");
+    };
+    assert!(matches!(pttl[0], RespType::Int(ms) if ms > 49_000 && ms <= 50_000));
+    assert_eq!(handler.handle_cmd(cmd(&["HTTL", "missing", "FIELDS", "1", "a"])).unwrap(), ints(&[-2]));
+
+    assert_eq!(handler.handle_cmd(cmd(&["HPEXPIREAT", "flags", "4102444800000", "FIELDS", "1", "c"])).unwrap(), ints(&[1]));
+    assert_eq!(handler.handle_cmd(cmd(&["HEXPIRETIME", "flags", "FIELDS", "1", "c"])).unwrap(), ints(&[4102444800]));
+    assert_eq!(handler.handle_cmd(cmd(&["HPERSIST", "flags", "FIELDS", "3", "a", "a", "nope"])).unwrap(), ints(&[1, -1, -2]));
+    // setting a field drops its expiry, incrementing it keeps it
+    handler.handle_cmd(cmd(&["HSET", "flags", "b", "20"])).unwrap();
+    handler.handle_cmd(cmd(&["HINCRBY", "flags", "c", "1"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["HEXPIRETIME", "flags", "FIELDS", "2", "b", "c"])).unwrap(), ints(&[-1, 4102444800]));
+
+    // a time in the past deletes the field
+    assert_eq!(handler.handle_cmd(cmd(&["HEXPIREAT", "flags", "1", "FIELDS", "1", "a"])).unwrap(), ints(&[2]));
+    assert_eq!(handler.handle_cmd(cmd(&["HLEN", "flags"])).unwrap(), RespType::Int(2));
+
+    let err = handler.handle_cmd(cmd(&["HEXPIRE", "flags", "10", "FIELDS", "2", "a"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR The `numfields` parameter must match the number of arguments");
+    let err = handler.handle_cmd(cmd(&["HEXPIRE", "flags", "10", "FIELDS", "0", "a"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR Parameter `numFields` should be greater than 0");
+    let err = handler.handle_cmd(cmd(&["HEXPIRE", "flags", "10", "NX", "1", "a"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR Mandatory argument FIELDS is missing or not at the right position");
+    let err = handler.handle_cmd(cmd(&["HEXPIRE", "flags", "-1", "FIELDS", "1", "a"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR invalid expire time in 'hexpire' command");
+}
+
+#[test]
+fn test_hash_fields_expire_lazily_and_actively() {
+    let storage = Arc::new(Mutex::new(Storage::new()));
+    let mut handler = CommandHandler::new(Arc::clone(&storage));
+
+    handler.handle_cmd(cmd(&["HSET", "h", "a", "1", "b", "2"])).unwrap();
+    handler.handle_cmd(cmd(&["HPEXPIRE", "h", "20", "FIELDS", "1", "a"])).unwrap();
+    assert_eq!(
+        handler.handle_cmd(cmd(&["HGETEX", "h", "PX", "20", "FIELDS", "2", "b", "nope"])).unwrap(),
+        RespType::Array(vec![RespType::BString("2".into()), RespType::Null])
+    );
+    std::thread::sleep(std::time::Duration::from_millis(40));
+    assert_eq!(handler.handle_cmd(cmd(&["HGET", "h", "a"])).unwrap(), RespType::Null);
+    // the last field expiring removes the key
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "h"])).unwrap(), RespType::String("none".to_string()));
+
+    handler.handle_cmd(cmd(&["HSET", "h", "a", "1", "b", "2"])).unwrap();
+    handler.handle_cmd(cmd(&["HGETEX", "h", "EX", "100", "FIELDS", "1", "a"])).unwrap();
+    assert_eq!(
+        handler.handle_cmd(cmd(&["HGETEX", "h", "PERSIST", "FIELDS", "1", "a"])).unwrap(),
+        RespType::Array(vec![RespType::BString("1".into())])
+    );
+    assert_eq!(handler.handle_cmd(cmd(&["HTTL", "h", "FIELDS", "1", "a"])).unwrap(), ints(&[-1]));
+
+    for i in 0..50 {
+        let key = format!("hash{i}");
+        handler.handle_cmd(cmd(&["HSET", &key, "a", "1", "b", "2"])).unwrap();
+        handler.handle_cmd(cmd(&["HPEXPIRE", &key, "10", "FIELDS", "1", "a"])).unwrap();
+        if i % 2 == 0 {
+            handler.handle_cmd(cmd(&["HPEXPIRE", &key, "10", "FIELDS", "1", "b"])).unwrap();
+        }
+    }
+    std::thread::sleep(std::time::Duration::from_millis(30));
+
+    // only the hashes that lost all their fields count as deleted keys
+    assert_eq!(storage.lock().unwrap().active_expire_cycle(std::time::Duration::from_secs(1)), 25);
+    assert_eq!(storage.lock().unwrap().len(), 26);
+    assert_eq!(handler.handle_cmd(cmd(&["HGETALL", "hash1"])).unwrap(), RespType::Map(vec![(RespType::BString("b".into()), RespType::BString("2".into()))]));
+}
+
+/// The members of a set reply, sorted as sets have no order.
+fn sorted_members(reply: RespType) -> Vec<String> {
+    let (RespType::Set(members) | RespType::Array(members)) = reply else {
+        panic!("expected a set of members, got {:?}", reply);
+    };
+    let mut members: Vec<String> = members.iter().map(|m| String::from_utf8(m.as_bytes().unwrap().to_vec()).unwrap()).collect();
+    members.sort();
+    members
+}
+
+#[test]
+fn test_set_commands() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+
+    assert_eq!(handler.handle_cmd(cmd(&["SADD", "tags", "a", "b", "c", "a"])).unwrap(), RespType::Int(3));
+    assert_eq!(handler.handle_cmd(cmd(&["SADD", "tags", "c", "d"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["SCARD", "tags"])).unwrap(), RespType::Int(4));
+    assert_eq!(sorted_members(handler.handle_cmd(cmd(&["SMEMBERS", "tags"])).unwrap()), ["a", "b", "c", "d"]);
+    assert_eq!(handler.handle_cmd(cmd(&["SISMEMBER", "tags", "b"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["SMISMEMBER", "tags", "b", "z"])).unwrap(), ints(&[1, 0]));
+    assert_eq!(handler.handle_cmd(cmd(&["SREM", "tags", "a", "z"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "tags"])).unwrap(), RespType::String("set".to_string()));
+
+    assert_eq!(handler.handle_cmd(cmd(&["SMOVE", "tags", "other", "b"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["SMOVE", "tags", "other", "b"])).unwrap(), RespType::Int(0));
+    assert_eq!(sorted_members(handler.handle_cmd(cmd(&["SMEMBERS", "other"])).unwrap()), ["b"]);
+    handler.handle_cmd(cmd(&["SET", "str", "v"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["SMOVE", "tags", "str", "c"])).unwrap_err(), CommandErr::WrongType);
+    assert_eq!(handler.handle_cmd(cmd(&["SISMEMBER", "tags", "c"])).unwrap(), RespType::Int(1));
+
+    let popped = sorted_members(handler.handle_cmd(cmd(&["SPOP", "tags", "5"])).unwrap());
+    assert_eq!(popped, ["c", "d"]);
+    // popping the last member removes the key
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "tags"])).unwrap(), RespType::String("none".to_string()));
+    assert_eq!(handler.handle_cmd(cmd(&["SPOP", "tags"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["SPOP", "other"])).unwrap(), RespType::BString("b".into()));
+
+    handler.handle_cmd(cmd(&["SADD", "s", "1", "2", "3"])).unwrap();
+    let RespType::Array(picked) = handler.handle_cmd(cmd(&["SRANDMEMBER", "s", "2"])).unwrap() else {
+        panic!("SRANDMEMBER with a count should reply with an array");
+    };
+    assert_eq!(picked.len(), 2);
+    assert_ne!(picked[0], picked[1]);
+    assert_eq!(sorted_members(handler.handle_cmd(cmd(&["SRANDMEMBER", "s", "5"])).unwrap()), ["1", "2", "3"]);
+    let RespType::Array(picked) = handler.handle_cmd(cmd(&["SRANDMEMBER", "s", "-6"])).unwrap() else {
+        panic!("SRANDMEMBER with a count should reply with an array");
+    };
+    assert_eq!(picked.len(), 6);
+    assert_eq!(handler.handle_cmd(cmd(&["SRANDMEMBER", "nope"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["SCARD", "s"])).unwrap(), RespType::Int(3));
+}
+
+#[test]
+fn test_set_algebra() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    handler.handle_cmd(cmd(&["SADD", "a", "1", "2", "3", "4"])).unwrap();
+    handler.handle_cmd(cmd(&["SADD", "b", "3", "4", "5"])).unwrap();
+    handler.handle_cmd(cmd(&["SADD", "c", "4", "x"])).unwrap();
+
+    assert_eq!(sorted_members(handler.handle_cmd(cmd(&["SINTER", "a", "b", "c"])).unwrap()), ["4"]);
+    assert_eq!(sorted_members(handler.handle_cmd(cmd(&["SINTER", "a", "nope"])).unwrap()), Vec::<String>::new());
+    assert_eq!(sorted_members(handler.handle_cmd(cmd(&["SUNION", "b", "c", "nope"])).unwrap()), ["3", "4", "5", "x"]);
+    assert_eq!(sorted_members(handler.handle_cmd(cmd(&["SDIFF", "a", "b", "nope"])).unwrap()), ["1", "2"]);
+    assert_eq!(sorted_members(handler.handle_cmd(cmd(&["SDIFF", "nope", "a"])).unwrap()), Vec::<String>::new());
+
+    assert_eq!(handler.handle_cmd(cmd(&["SINTERCARD", "2", "a", "b"])).unwrap(), RespType::Int(2));
+    assert_eq!(handler.handle_cmd(cmd(&["SINTERCARD", "2", "a", "b", "LIMIT", "1"])).unwrap(), RespType::Int(1));
+    let err = handler.handle_cmd(cmd(&["SINTERCARD", "2", "a", "b", "LIMIT", "-1"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR LIMIT can't be negative");
+    let err = handler.handle_cmd(cmd(&["SINTERCARD", "3", "a", "b"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR Number of keys can't be greater than number of args");
+    assert_eq!(
+        handler.handle_cmd(cmd(&["COMMAND", "GETKEYS", "SINTERCARD", "2", "a", "b", "LIMIT", "1"])).unwrap(),
+        bulks(&["a", "b"])
+    );
+
+    // the destination is overwritten whatever it held, and may be a source
+    handler.handle_cmd(cmd(&["SET", "dst", "v"])).unwrap();
+    handler.handle_cmd(cmd(&["EXPIRE", "dst", "100"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["SUNIONSTORE", "dst", "a", "b"])).unwrap(), RespType::Int(5));
+    assert_eq!(handler.handle_cmd(cmd(&["TTL", "dst"])).unwrap(), RespType::Int(-1));
+    assert_eq!(handler.handle_cmd(cmd(&["SINTERSTORE", "dst", "dst", "c"])).unwrap(), RespType::Int(1));
+    assert_eq!(sorted_members(handler.handle_cmd(cmd(&["SMEMBERS", "dst"])).unwrap()), ["4"]);
+    assert_eq!(handler.handle_cmd(cmd(&["SDIFFSTORE", "dst", "c", "c"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "dst"])).unwrap(), RespType::String("none".to_string()));
+
+    handler.handle_cmd(cmd(&["SET", "str", "v"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["SINTER", "nope", "str"])).unwrap_err(), CommandErr::WrongType);
+}
+
+#[test]
+fn test_set_encoding_and_sscan() {
+    let limits = EncodingLimits { set_max_intset_entries: 4, ..EncodingLimits::default() };
+    let storage = Arc::new(Mutex::new(Storage::with_limits(limits)));
+    let mut handler = CommandHandler::new(Arc::clone(&storage));
+    let encoding = |k: &str| storage.lock().unwrap().get_set(k.as_bytes()).unwrap().unwrap().encoding();
+
+    handler.handle_cmd(cmd(&["SADD", "ints", "3", "-1", "20", "7"])).unwrap();
+    assert_eq!(encoding("ints"), "intset");
+    assert_eq!(handler.handle_cmd(cmd(&["SMEMBERS", "ints"])).unwrap(), RespType::Set(vec![
+        RespType::BString("-1".into()),
+        RespType::BString("3".into()),
+        RespType::BString("7".into()),
+        RespType::BString("20".into()),
+    ]));
+    // only the canonical spelling of an integer is one
+    assert_eq!(handler.handle_cmd(cmd(&["SISMEMBER", "ints", "07"])).unwrap(), RespType::Int(0));
+    handler.handle_cmd(cmd(&["SADD", "ints", "5"])).unwrap();
+    assert_eq!(encoding("ints"), "hashtable");
+    handler.handle_cmd(cmd(&["SADD", "mixed", "1", "07"])).unwrap();
+    assert_eq!(encoding("mixed"), "hashtable");
+    assert_eq!(sorted_members(handler.handle_cmd(cmd(&["SMEMBERS", "mixed"])).unwrap()), ["07", "1"]);
+
+    for i in 0..300 {
+        handler.handle_cmd(cmd(&["SADD", "big", &format!("m{i}")])).unwrap();
+    }
+    let mut seen = std::collections::HashSet::new();
+    let mut cursor = "0".to_string();
+    loop {
+        let RespType::Array(reply) = handler.handle_cmd(cmd(&["SSCAN", "big", &cursor, "MATCH", "m1*"])).unwrap() else {
+            panic!("SSCAN should reply with an array");
+        };
+        let (RespType::BString(next), RespType::Array(members)) = (&reply[0], &reply[1]) else {
+            panic!("SSCAN should reply with a cursor and the members found");
+        };
+        seen.extend(members.iter().map(|m| m.as_bytes().unwrap().to_vec()));
+        cursor = String::from_utf8(next.clone()).unwrap();
+        if cursor == "0" {
+            break;
+        }
+    }
+    // m1, m10..m19 and m100..m199
+    assert_eq!(seen.len(), 111);
+    let err = handler.handle_cmd(cmd(&["SSCAN", "big", "0", "NOVALUES"])).unwrap_err();
+    assert_eq!(err, CommandErr::SyntaxError);
+}
+
+/// A flat list of members and their scores, like RESP2 replies WITHSCORES.
+fn scored(items: &[(&str, f64)]) -> RespType {
+    RespType::Array(items.iter().flat_map(|&(m, s)| [RespType::BString(m.into()), RespType::Double(s)]).collect())
+}
+
+#[test]
+fn test_zadd_and_scores() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    assert_eq!(handler.handle_cmd(cmd(&["ZADD", "z", "1", "a", "2", "b", "3", "c"])).unwrap(), RespType::Int(3));
+    assert_eq!(handler.handle_cmd(cmd(&["ZADD", "z", "1", "a", "5", "b", "4", "d"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["ZADD", "z", "CH", "1", "a", "2", "b", "4", "d"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["ZADD", "z", "NX", "9", "a", "0", "e"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["ZADD", "z", "XX", "CH", "9", "e", "0", "f"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["ZADD", "z", "GT", "CH", "1", "e", "10", "a"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["ZADD", "z", "LT", "CH", "20", "a", "3", "b"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["ZMSCORE", "z", "a", "b", "e", "f"])).unwrap(), RespType::Array(vec![
+        RespType::Double(10.0),
+        RespType::Double(2.0),
+        RespType::Double(9.0),
+        RespType::Null,
+    ]));
+
+    assert_eq!(handler.handle_cmd(cmd(&["ZADD", "z", "INCR", "2.5", "b"])).unwrap(), RespType::Double(4.5));
+    assert_eq!(handler.handle_cmd(cmd(&["ZADD", "z", "NX", "INCR", "1", "b"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["ZADD", "nope", "XX", "1", "a"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "nope"])).unwrap(), RespType::String("none".to_string()));
+    assert_eq!(handler.handle_cmd(cmd(&["ZINCRBY", "z", "-1.5", "new"])).unwrap(), RespType::Double(-1.5));
+    assert_eq!(handler.handle_cmd(cmd(&["ZINCRBY", "z", "inf", "new"])).unwrap(), RespType::Double(f64::INFINITY));
+    let err = handler.handle_cmd(cmd(&["ZINCRBY", "z", "-inf", "new"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR resulting score is not a number (NaN)");
+    assert_eq!(handler.handle_cmd(cmd(&["ZSCORE", "z", "new"])).unwrap(), RespType::Double(f64::INFINITY));
+    assert_eq!(handler.handle_cmd(cmd(&["ZSCORE", "z", "nope"])).unwrap(), RespType::Null);
+
+    // a: 10, b: 4.5, c: 3, d: 4, e: 9, new: inf
+    assert_eq!(handler.handle_cmd(cmd(&["ZCARD", "z"])).unwrap(), RespType::Int(6));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANK", "z", "c"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["ZREVRANK", "z", "c"])).unwrap(), RespType::Int(5));
+    assert_eq!(
+        handler.handle_cmd(cmd(&["ZRANK", "z", "e", "WITHSCORE"])).unwrap(),
+        RespType::Array(vec![RespType::Int(3), RespType::Double(9.0)])
+    );
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANK", "z", "nope"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["ZREM", "z", "a", "nope", "new"])).unwrap(), RespType::Int(2));
+    assert_eq!(handler.handle_cmd(cmd(&["ZREVRANK", "z", "e"])).unwrap(), RespType::Int(0));
+
+    for (args, msg) in [
+        (&["ZADD", "z", "NX", "XX", "1", "a"][..], "ERR XX and NX options at the same time are not compatible"),
+        (&["ZADD", "z", "GT", "LT", "1", "a"][..], "ERR GT, LT, and/or NX options at the same time are not compatible"),
+        (&["ZADD", "z", "INCR", "1", "a", "2", "b"][..], "ERR INCR option supports a single increment-element pair"),
+        (&["ZADD", "z", "nan", "a"][..], "ERR value is not a valid float"),
+    ] {
+        assert_eq!(handler.handle_cmd(cmd(args)).unwrap_err().to_string(), msg);
+    }
+    assert_eq!(handler.handle_cmd(cmd(&["ZADD", "z", "1", "a", "2"])).unwrap_err(), CommandErr::SyntaxError);
+    handler.handle_cmd(cmd(&["SET", "str", "v"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["ZADD", "str", "1", "a"])).unwrap_err(), CommandErr::WrongType);
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "z"])).unwrap(), RespType::String("zset".to_string()));
+}
+
+#[test]
+fn test_zrange() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    handler.handle_cmd(cmd(&["ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e"])).unwrap();
+
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "z", "1", "-2"])).unwrap(), bulks(&["b", "c", "d"]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "z", "0", "1", "REV", "WITHSCORES"])).unwrap(), scored(&[("e", 5.0), ("d", 4.0)]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "z", "(1", "3", "BYSCORE"])).unwrap(), bulks(&["b", "c"]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "z", "+inf", "(3", "BYSCORE", "REV"])).unwrap(), bulks(&["e", "d"]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "z", "-inf", "inf", "BYSCORE", "LIMIT", "1", "2"])).unwrap(), bulks(&["b", "c"]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "z", "-inf", "inf", "BYSCORE", "LIMIT", "3", "-1"])).unwrap(), bulks(&["d", "e"]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "z", "5", "1", "BYSCORE"])).unwrap(), bulks(&[]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZCOUNT", "z", "(1", "+inf"])).unwrap(), RespType::Int(4));
+    assert_eq!(handler.handle_cmd(cmd(&["ZCOUNT", "z", "3", "(3"])).unwrap(), RespType::Int(0));
+
+    handler.handle_cmd(cmd(&["ZADD", "lex", "0", "a", "0", "b", "0", "c", "0", "d"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "lex", "[b", "+", "BYLEX"])).unwrap(), bulks(&["b", "c", "d"]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "lex", "(c", "-", "BYLEX", "REV", "LIMIT", "0", "1"])).unwrap(), bulks(&["b"]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZLEXCOUNT", "lex", "(a", "[c"])).unwrap(), RespType::Int(2));
+    assert_eq!(handler.handle_cmd(cmd(&["ZLEXCOUNT", "lex", "[d", "[a"])).unwrap(), RespType::Int(0));
+    // -0 and 0 are the same score, so those elements are ordered by member
+    handler.handle_cmd(cmd(&["ZADD", "zero", "0", "a", "-0", "b", "0", "c"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "zero", "0", "-1"])).unwrap(), bulks(&["a", "b", "c"]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "zero", "[a", "[b", "BYLEX"])).unwrap(), bulks(&["a", "b"]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANK", "zero", "c"])).unwrap(), RespType::Int(2));
+
+    for (args, msg) in [
+        (&["ZRANGE", "z", "0", "1", "LIMIT", "0", "1"][..], "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"),
+        (&["ZRANGE", "lex", "-", "+", "BYLEX", "WITHSCORES"][..], "ERR syntax error, WITHSCORES not supported in combination with BYLEX"),
+        (&["ZRANGE", "z", "x", "1", "BYSCORE"][..], "ERR min or max is not a float"),
+        (&["ZLEXCOUNT", "lex", "a", "+"][..], "ERR min or max not valid string range item"),
+    ] {
+        assert_eq!(handler.handle_cmd(cmd(args)).unwrap_err().to_string(), msg);
+    }
+
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGESTORE", "dst", "z", "4", "2", "BYSCORE", "REV"])).unwrap(), RespType::Int(3));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "dst", "0", "-1"])).unwrap(), bulks(&["b", "c", "d"]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGESTORE", "dst", "nope", "0", "-1"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "dst"])).unwrap(), RespType::String("none".to_string()));
+
+    assert_eq!(handler.handle_cmd(cmd(&["ZPOPMIN", "z"])).unwrap(), scored(&[("a", 1.0)]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZPOPMAX", "z", "2"])).unwrap(), scored(&[("e", 5.0), ("d", 4.0)]));
+    let err = handler.handle_cmd(cmd(&["ZPOPMAX", "z", "-1"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR value is out of range, must be positive");
+
+    // RESP3 clients get members paired with their scores
+    handler.handle_cmd(cmd(&["HELLO", "3"])).unwrap();
+    let pair = |m: &str, s: f64| RespType::Array(vec![RespType::BString(m.into()), RespType::Double(s)]);
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "z", "0", "-1", "WITHSCORES"])).unwrap(), RespType::Array(vec![pair("b", 2.0), pair("c", 3.0)]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZPOPMIN", "z", "5"])).unwrap(), RespType::Array(vec![pair("b", 2.0), pair("c", 3.0)]));
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "z"])).unwrap(), RespType::String("none".to_string()));
+    assert_eq!(handler.handle_cmd(cmd(&["ZPOPMIN", "z"])).unwrap(), RespType::Array(Vec::new()));
+}
+
+#[test]
+fn test_zmpop() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    handler.handle_cmd(cmd(&["ZADD", "z", "1", "a", "2", "b", "3", "c"])).unwrap();
+    let pair = |m: &str, s: f64| RespType::Array(vec![RespType::BString(m.into()), RespType::Double(s)]);
+
+    assert_eq!(handler.handle_cmd(cmd(&["ZMPOP", "2", "nope", "z", "MAX", "COUNT", "2"])).unwrap(), RespType::Array(vec![
+        RespType::BString("z".into()),
+        RespType::Array(vec![pair("c", 3.0), pair("b", 2.0)]),
+    ]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZMPOP", "1", "z", "min", "COUNT", "5"])).unwrap(), RespType::Array(vec![
+        RespType::BString("z".into()),
+        RespType::Array(vec![pair("a", 1.0)]),
+    ]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZMPOP", "1", "z", "MIN"])).unwrap(), RespType::NullArray);
+    assert_eq!(handler.handle_cmd(cmd(&["ZMPOP", "1", "z", "MIDDLE"])).unwrap_err(), CommandErr::SyntaxError);
+    let err = handler.handle_cmd(cmd(&["ZMPOP", "1", "z", "MIN", "COUNT", "0"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR count should be greater than 0");
+    assert_eq!(
+        handler.handle_cmd(cmd(&["COMMAND", "GETKEYS", "BZMPOP", "0", "2", "a", "b", "MIN"])).unwrap(),
+        bulks(&["a", "b"])
+    );
+}
+
+#[test]
+fn test_zunionstore_and_zinterstore() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    handler.handle_cmd(cmd(&["ZADD", "a", "1", "x", "2", "y", "3", "z"])).unwrap();
+    handler.handle_cmd(cmd(&["ZADD", "b", "10", "y", "20", "z", "30", "w"])).unwrap();
+    handler.handle_cmd(cmd(&["SADD", "s", "z", "v"])).unwrap();
+
+    assert_eq!(handler.handle_cmd(cmd(&["ZUNIONSTORE", "dst", "2", "a", "b"])).unwrap(), RespType::Int(4));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "dst", "0", "-1", "WITHSCORES"])).unwrap(), scored(&[("x", 1.0), ("y", 12.0), ("z", 23.0), ("w", 30.0)]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZINTERSTORE", "dst", "3", "a", "b", "s", "WEIGHTS", "2", "1", "5"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["ZSCORE", "dst", "z"])).unwrap(), RespType::Double(31.0));
+    assert_eq!(handler.handle_cmd(cmd(&["ZINTERSTORE", "dst", "2", "a", "b", "AGGREGATE", "MAX"])).unwrap(), RespType::Int(2));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "dst", "0", "-1", "WITHSCORES"])).unwrap(), scored(&[("y", 10.0), ("z", 20.0)]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZUNIONSTORE", "dst", "2", "a", "s", "AGGREGATE", "min"])).unwrap(), RespType::Int(4));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "dst", "0", "-1", "WITHSCORES"])).unwrap(), scored(&[("v", 1.0), ("x", 1.0), ("z", 1.0), ("y", 2.0)]));
+    assert_eq!(handler.handle_cmd(cmd(&["ZINTERSTORE", "dst", "2", "a", "nope"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "dst"])).unwrap(), RespType::String("none".to_string()));
+
+    // inf * 0 and inf + -inf are taken as 0 instead of NaN
+    handler.handle_cmd(cmd(&["ZADD", "inf", "inf", "x"])).unwrap();
+    handler.handle_cmd(cmd(&["ZADD", "-inf", "-inf", "x"])).unwrap();
+    handler.handle_cmd(cmd(&["ZUNIONSTORE", "dst", "2", "inf", "-inf"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["ZSCORE", "dst", "x"])).unwrap(), RespType::Double(0.0));
+    handler.handle_cmd(cmd(&["ZUNIONSTORE", "dst", "1", "inf", "WEIGHTS", "0"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["ZSCORE", "dst", "x"])).unwrap(), RespType::Double(0.0));
+
+    let err = handler.handle_cmd(cmd(&["ZUNIONSTORE", "dst", "0", "a"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR at least 1 input key is needed for 'zunionstore' command");
+    let err = handler.handle_cmd(cmd(&["ZUNIONSTORE", "dst", "2", "a", "b", "WEIGHTS", "1", "x"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR weight value is not a float");
+    for args in [&["ZUNIONSTORE", "dst", "3", "a", "b"][..], &["ZUNIONSTORE", "dst", "2", "a", "b", "WEIGHTS", "1"], &["ZINTERSTORE", "dst", "1", "a", "AGGREGATE", "AVG"]] {
+        assert_eq!(handler.handle_cmd(cmd(args)).unwrap_err(), CommandErr::SyntaxError);
+    }
+    assert_eq!(
+        handler.handle_cmd(cmd(&["COMMAND", "GETKEYS", "ZUNIONSTORE", "dst", "2", "a", "b", "WEIGHTS", "1", "2"])).unwrap(),
+        bulks(&["dst", "a", "b"])
+    );
+    handler.handle_cmd(cmd(&["SET", "str", "v"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["ZUNIONSTORE", "dst", "2", "a", "str"])).unwrap_err(), CommandErr::WrongType);
+}
+
+#[test]
+fn test_zset_ranks_and_zscan() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    // scores collide a lot, so ties are ordered by member
+    let mut expected: Vec<(i64, String)> = (0..500).map(|i| ((i * 7919) % 50, format!("m{i}"))).collect();
+    for (score, member) in &expected {
+        handler.handle_cmd(cmd(&["ZADD", "big", &score.to_string(), member])).unwrap();
+    }
+    for (score, member) in expected.iter_mut().step_by(3) {
+        *score = 100 - *score;
+        handler.handle_cmd(cmd(&["ZADD", "big", &score.to_string(), member])).unwrap();
+    }
+    for (_, member) in expected.iter().skip(1).step_by(5) {
+        handler.handle_cmd(cmd(&["ZREM", "big", member])).unwrap();
+    }
+    expected = expected.into_iter().enumerate().filter(|(i, _)| i % 5 != 1).map(|(_, e)| e).collect();
+    expected.sort();
+
+    let members: Vec<&str> = expected.iter().map(|(_, m)| m.as_str()).collect();
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "big", "0", "-1"])).unwrap(), bulks(&members));
+    assert_eq!(handler.handle_cmd(cmd(&["ZRANGE", "big", "100", "109"])).unwrap(), bulks(&members[100..110]));
+    for (rank, member) in members.iter().enumerate().step_by(37) {
+        assert_eq!(handler.handle_cmd(cmd(&["ZRANK", "big", member])).unwrap(), RespType::Int(rank as isize));
+    }
+    let in_range = expected.iter().filter(|(s, _)| (10..=60).contains(s)).count();
+    assert_eq!(handler.handle_cmd(cmd(&["ZCOUNT", "big", "10", "60"])).unwrap(), RespType::Int(in_range as isize));
+
+    let mut seen = std::collections::HashMap::new();
+    let mut cursor = "0".to_string();
+    loop {
+        let RespType::Array(reply) = handler.handle_cmd(cmd(&["ZSCAN", "big", &cursor, "MATCH", "m1*"])).unwrap() else {
+            panic!("ZSCAN should reply with an array");
+        };
+        let (RespType::BString(next), RespType::Array(found)) = (&reply[0], &reply[1]) else {
+            panic!("ZSCAN should reply with a cursor and the elements found");
+        };
+        for pair in found.chunks(2) {
+            seen.insert(pair[0].as_bytes().unwrap().to_vec(), pair[1].as_bytes().unwrap().to_vec());
+        }
+        cursor = String::from_utf8(next.clone()).unwrap();
+        if cursor == "0" {
+            break;
+        }
+    }
+    let matching: Vec<_> = expected.iter().filter(|(_, m)| m.starts_with("m1")).collect();
+    assert_eq!(seen.len(), matching.len());
+    for (score, member) in matching {
+        assert_eq!(seen[member.as_bytes()], score.to_string().into_bytes());
+    }
+}
+
+/// A stream entry as `XRANGE` replies with it.
+fn entry(id: &str, fields: &[&str]) -> RespType {
+    RespType::Array(vec![RespType::BString(id.into()), bulks(fields)])
+}
+
+#[test]
+fn test_stream_commands() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    assert_eq!(handler.handle_cmd(cmd(&["XADD", "s", "1-1", "a", "1"])).unwrap(), RespType::BString("1-1".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["XADD", "s", "1-*", "b", "2"])).unwrap(), RespType::BString("1-2".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["XADD", "s", "5", "c", "3", "d", "4"])).unwrap(), RespType::BString("5-0".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["XADD", "s", "7-*", "e", "5"])).unwrap(), RespType::BString("7-0".into()));
+    let RespType::BString(auto) = handler.handle_cmd(cmd(&["XADD", "s", "*", "f", "6"])).unwrap() else {
+        panic!("XADD should reply with the ID");
+    };
+    let auto = String::from_utf8(auto).unwrap();
+    assert!(auto.ends_with("-0") && auto.len() > 10, "{auto} should be the current time");
+    assert_eq!(handler.handle_cmd(cmd(&["XLEN", "s"])).unwrap(), RespType::Int(5));
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "s"])).unwrap(), RespType::String("stream".to_string()));
+
+    for (args, msg) in [
+        (&["XADD", "s", "5-0", "x", "y"][..], "ERR The ID specified in XADD is equal or smaller than the target stream top item"),
+        (&["XADD", "s", "3-*", "x", "y"][..], "ERR The ID specified in XADD is equal or smaller than the target stream top item"),
+        (&["XADD", "new", "0-0", "x", "y"][..], "ERR The ID specified in XADD must be greater than 0-0"),
+        (&["XADD", "s", "1-x", "x", "y"][..], "ERR Invalid stream ID specified as stream command argument"),
+        (&["XADD", "s", "*", "x", "y", "z"][..], "ERR wrong number of arguments for 'xadd' command"),
+        (&["XRANGE", "s", "(-", "+"][..], "ERR Invalid stream ID specified as stream command argument"),
+        (&["XRANGE", "s", "-", "(0-0"][..], "ERR invalid end ID for the interval"),
+    ] {
+        assert_eq!(handler.handle_cmd(cmd(args)).unwrap_err().to_string(), msg);
+    }
+    assert_eq!(handler.handle_cmd(cmd(&["XADD", "new", "NOMKSTREAM", "*", "x", "y"])).unwrap(), RespType::Null);
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "new"])).unwrap(), RespType::String("none".to_string()));
+
+    assert_eq!(handler.handle_cmd(cmd(&["XRANGE", "s", "-", "5"])).unwrap(), RespType::Array(vec![
+        entry("1-1", &["a", "1"]),
+        entry("1-2", &["b", "2"]),
+        entry("5-0", &["c", "3", "d", "4"]),
+    ]));
+    assert_eq!(handler.handle_cmd(cmd(&["XRANGE", "s", "(1-1", "+", "COUNT", "2"])).unwrap(), RespType::Array(vec![
+        entry("1-2", &["b", "2"]),
+        entry("5-0", &["c", "3", "d", "4"]),
+    ]));
+    assert_eq!(handler.handle_cmd(cmd(&["XREVRANGE", "s", "(7-0", "1-2", "COUNT", "5"])).unwrap(), RespType::Array(vec![
+        entry("5-0", &["c", "3", "d", "4"]),
+        entry("1-2", &["b", "2"]),
+    ]));
+    assert_eq!(handler.handle_cmd(cmd(&["XRANGE", "s", "-", "+", "COUNT", "0"])).unwrap(), RespType::NullArray);
+    assert_eq!(handler.handle_cmd(cmd(&["XRANGE", "nope", "-", "+"])).unwrap(), RespType::Array(Vec::new()));
+
+    assert_eq!(handler.handle_cmd(cmd(&["XDEL", "s", "1-2", "5", "9-9"])).unwrap(), RespType::Int(2));
+    assert_eq!(handler.handle_cmd(cmd(&["XRANGE", "s", "-", "7"])).unwrap(), RespType::Array(vec![
+        entry("1-1", &["a", "1"]),
+        entry("7-0", &["e", "5"]),
+    ]));
+    // an emptied stream is kept, along with its last ID
+    handler.handle_cmd(cmd(&["XTRIM", "s", "MAXLEN", "0"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["XLEN", "s"])).unwrap(), RespType::Int(0));
+    let err = handler.handle_cmd(cmd(&["XADD", "s", "7-1", "x", "y"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR The ID specified in XADD is equal or smaller than the target stream top item");
+
+    handler.handle_cmd(cmd(&["SET", "str", "v"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["XADD", "str", "*", "x", "y"])).unwrap_err(), CommandErr::WrongType);
+}
+
+#[test]
+fn test_stream_trimming() {
+    let limits = EncodingLimits { stream_node_max_entries: 10, ..EncodingLimits::default() };
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::with_limits(limits))));
+    for i in 1..=100 {
+        handler.handle_cmd(cmd(&["XADD", "s", &i.to_string(), "i", &i.to_string()])).unwrap();
+    }
+
+    // approximate trims only drop whole nodes of 10 entries
+    assert_eq!(handler.handle_cmd(cmd(&["XTRIM", "s", "MAXLEN", "~", "85"])).unwrap(), RespType::Int(10));
+    assert_eq!(handler.handle_cmd(cmd(&["XTRIM", "s", "MAXLEN", "~", "10", "LIMIT", "25"])).unwrap(), RespType::Int(20));
+    assert_eq!(handler.handle_cmd(cmd(&["XTRIM", "s", "MAXLEN", "=", "65"])).unwrap(), RespType::Int(5));
+    assert_eq!(handler.handle_cmd(cmd(&["XRANGE", "s", "-", "+", "COUNT", "1"])).unwrap(), RespType::Array(vec![entry("36-0", &["i", "36"])]));
+    assert_eq!(handler.handle_cmd(cmd(&["XTRIM", "s", "MINID", "~", "45"])).unwrap(), RespType::Int(5));
+    assert_eq!(handler.handle_cmd(cmd(&["XTRIM", "s", "MINID", "45"])).unwrap(), RespType::Int(4));
+    assert_eq!(handler.handle_cmd(cmd(&["XLEN", "s"])).unwrap(), RespType::Int(56));
+
+    assert!(handler.handle_cmd(cmd(&["XADD", "s", "MAXLEN", "3", "*", "i", "last"])).unwrap().as_bytes().is_some());
+    assert_eq!(handler.handle_cmd(cmd(&["XLEN", "s"])).unwrap(), RespType::Int(3));
+    assert_eq!(handler.handle_cmd(cmd(&["XRANGE", "s", "-", "+", "COUNT", "1"])).unwrap(), RespType::Array(vec![entry("99-0", &["i", "99"])]));
+
+    for (args, msg) in [
+        (&["XTRIM", "s", "MAXLEN", "-1"][..], "ERR The MAXLEN argument must be >= 0."),
+        (&["XTRIM", "s", "MAXLEN", "1", "LIMIT", "10"][..], "ERR syntax error, LIMIT cannot be used without the special ~ option"),
+        (&["XTRIM", "s", "MAXLEN", "~", "1", "LIMIT", "-1"][..], "ERR The LIMIT argument must be >= 0."),
+    ] {
+        assert_eq!(handler.handle_cmd(cmd(args)).unwrap_err().to_string(), msg);
+    }
+    assert_eq!(handler.handle_cmd(cmd(&["XTRIM", "s", "SIZE", "1"])).unwrap_err(), CommandErr::SyntaxError);
+    assert_eq!(handler.handle_cmd(cmd(&["XTRIM", "nope", "MAXLEN", "1"])).unwrap(), RespType::Int(0));
+}
+
+#[test]
+fn test_stream_nodes_with_other_fields() {
+    let limits = EncodingLimits { stream_node_max_entries: 4, ..EncodingLimits::default() };
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::with_limits(limits))));
+    // entries with other fields than the first one of their node are packed whole
+    handler.handle_cmd(cmd(&["XADD", "s", "1-1", "a", "1", "b", "2"])).unwrap();
+    handler.handle_cmd(cmd(&["XADD", "s", "1-2", "a", "3", "b", "4"])).unwrap();
+    handler.handle_cmd(cmd(&["XADD", "s", "5-0", "b", "5", "a", "6"])).unwrap();
+    handler.handle_cmd(cmd(&["XADD", "s", "300-7", "c", ""])).unwrap();
+    handler.handle_cmd(cmd(&["XADD", "s", "301-0", "a", "7", "b", "8"])).unwrap();
+    let all = vec![
+        entry("1-1", &["a", "1", "b", "2"]),
+        entry("1-2", &["a", "3", "b", "4"]),
+        entry("5-0", &["b", "5", "a", "6"]),
+        entry("300-7", &["c", ""]),
+        entry("301-0", &["a", "7", "b", "8"]),
+    ];
+    assert_eq!(handler.handle_cmd(cmd(&["XRANGE", "s", "-", "+"])).unwrap(), RespType::Array(all.clone()));
+    let reversed = all.iter().rev().cloned().collect();
+    assert_eq!(handler.handle_cmd(cmd(&["XREVRANGE", "s", "+", "-"])).unwrap(), RespType::Array(reversed));
+
+    // removing entries packs the rest of the node again
+    assert_eq!(handler.handle_cmd(cmd(&["XDEL", "s", "1-2"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["XTRIM", "s", "MINID", "2"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["XRANGE", "s", "-", "+"])).unwrap(), RespType::Array(all[2..].to_vec()));
+    handler.handle_cmd(cmd(&["XADD", "s", "302-0", "a", "9", "b", "10"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["XRANGE", "s", "300", "300"])).unwrap(), RespType::Array(vec![all[3].clone()]));
+    assert_eq!(handler.handle_cmd(cmd(&["XRANGE", "s", "302", "+"])).unwrap(), RespType::Array(vec![entry("302-0", &["a", "9", "b", "10"])]));
+}
+
+#[test]
+fn test_xread() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    handler.handle_cmd(cmd(&["XADD", "a", "1", "x", "1"])).unwrap();
+    handler.handle_cmd(cmd(&["XADD", "a", "2", "x", "2"])).unwrap();
+    handler.handle_cmd(cmd(&["XADD", "b", "3", "y", "3"])).unwrap();
+
+    assert_eq!(handler.handle_cmd(cmd(&["XREAD", "COUNT", "1", "STREAMS", "a", "b", "nope", "0", "0", "0"])).unwrap(), RespType::Array(vec![
+        RespType::Array(vec![RespType::BString("a".into()), RespType::Array(vec![entry("1-0", &["x", "1"])])]),
+        RespType::Array(vec![RespType::BString("b".into()), RespType::Array(vec![entry("3-0", &["y", "3"])])]),
+    ]));
+    assert_eq!(handler.handle_cmd(cmd(&["XREAD", "STREAMS", "a", "b", "1", "$"])).unwrap(), RespType::Array(vec![
+        RespType::Array(vec![RespType::BString("a".into()), RespType::Array(vec![entry("2-0", &["x", "2"])])]),
+    ]));
+    assert_eq!(handler.handle_cmd(cmd(&["XREAD", "STREAMS", "a", "$"])).unwrap(), RespType::NullArray);
+
+    handler.handle_cmd(cmd(&["HELLO", "3"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["XREAD", "STREAMS", "b", "2-5"])).unwrap(), RespType::Map(vec![
+        (RespType::BString("b".into()), RespType::Array(vec![entry("3-0", &["y", "3"])])),
+    ]));
+
+    let err = handler.handle_cmd(cmd(&["XREAD", "STREAMS", "a", "b", "0"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.");
+    assert_eq!(handler.handle_cmd(cmd(&["XREAD", "COUNT", "1", "a", "0"])).unwrap_err(), CommandErr::SyntaxError);
+    assert_eq!(handler.handle_cmd(cmd(&["XREAD", "BLOCK", "-1", "STREAMS", "a", "0"])).unwrap_err().to_string(), "ERR timeout is negative");
+    assert_eq!(
+        handler.handle_cmd(cmd(&["COMMAND", "GETKEYS", "XREAD", "COUNT", "2", "STREAMS", "a", "b", "0", "0"])).unwrap(),
+        bulks(&["a", "b"])
+    );
+}
+
+#[test]
+fn test_consumer_groups() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    let err = handler.handle_cmd(cmd(&["XGROUP", "CREATE", "s", "g", "$"])).unwrap_err();
+    assert!(err.to_string().starts_with("ERR The XGROUP subcommand requires the key to exist."));
+    assert_eq!(handler.handle_cmd(cmd(&["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"])).unwrap(), RespType::String("OK".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["XGROUP", "CREATE", "s", "g", "0"])).unwrap_err().to_string(), "BUSYGROUP Consumer Group name already exists");
+    assert_eq!(handler.handle_cmd(cmd(&["XGROUP", "CREATE", "s"])).unwrap_err().to_string(), "ERR wrong number of arguments for 'xgroup|create' command");
+    assert_eq!(handler.handle_cmd(cmd(&["XGROUP", "NOPE", "s"])).unwrap_err().to_string(), "ERR unknown subcommand 'NOPE'. Try XGROUP HELP.");
+    for (id, n) in [("1", "1"), ("2", "2"), ("3", "3")] {
+        handler.handle_cmd(cmd(&["XADD", "s", id, "n", n])).unwrap();
+    }
+
+    // new entries are delivered once, to whichever consumer asks first
+    assert_eq!(handler.handle_cmd(cmd(&["XREADGROUP", "GROUP", "g", "alice", "COUNT", "2", "STREAMS", "s", ">"])).unwrap(), RespType::Array(vec![
+        RespType::Array(vec![RespType::BString("s".into()), RespType::Array(vec![entry("1-0", &["n", "1"]), entry("2-0", &["n", "2"])])]),
+    ]));
+    assert_eq!(handler.handle_cmd(cmd(&["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"])).unwrap(), RespType::Array(vec![
+        RespType::Array(vec![RespType::BString("s".into()), RespType::Array(vec![entry("3-0", &["n", "3"])])]),
+    ]));
+    assert_eq!(handler.handle_cmd(cmd(&["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"])).unwrap(), RespType::NullArray);
+
+    // any other ID reads the consumer's own pending entries, including deleted ones
+    handler.handle_cmd(cmd(&["XDEL", "s", "2"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "0"])).unwrap(), RespType::Array(vec![
+        RespType::Array(vec![
+            RespType::BString("s".into()),
+            RespType::Array(vec![entry("1-0", &["n", "1"]), RespType::Array(vec![RespType::BString("2-0".into()), RespType::NullArray])]),
+        ]),
+    ]));
+    assert_eq!(handler.handle_cmd(cmd(&["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "2"])).unwrap(), RespType::Array(vec![
+        RespType::Array(vec![RespType::BString("s".into()), RespType::Array(vec![])]),
+    ]));
+
+    let pending = handler.handle_cmd(cmd(&["XPENDING", "s", "g"])).unwrap();
+    assert_eq!(pending, RespType::Array(vec![
+        RespType::Int(3),
+        RespType::BString("1-0".into()),
+        RespType::BString("3-0".into()),
+        RespType::Array(vec![bulks(&["alice", "2"]), bulks(&["bob", "1"])]),
+    ]));
+    let RespType::Array(extended) = handler.handle_cmd(cmd(&["XPENDING", "s", "g", "-", "+", "10", "alice"])).unwrap() else {
+        panic!("expected an array");
+    };
+    assert_eq!(extended.len(), 2);
+    let RespType::Array(first) = &extended[0] else {
+        panic!("expected an array");
+    };
+    assert_eq!(first[0], RespType::BString("1-0".into()));
+    assert_eq!(first[1], RespType::BString("alice".into()));
+    // read twice, the second time from the history
+    assert_eq!(first[3], RespType::Int(2));
+    assert_eq!(handler.handle_cmd(cmd(&["XPENDING", "s", "g", "IDLE", "60000", "-", "+", "10"])).unwrap(), RespType::Array(vec![]));
+
+    assert_eq!(handler.handle_cmd(cmd(&["XACK", "s", "g", "1", "3", "9"])).unwrap(), RespType::Int(2));
+    assert_eq!(handler.handle_cmd(cmd(&["XACK", "s", "nope", "2"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["XPENDING", "s", "g"])).unwrap(), RespType::Array(vec![
+        RespType::Int(1),
+        RespType::BString("2-0".into()),
+        RespType::BString("2-0".into()),
+        RespType::Array(vec![bulks(&["alice", "1"])]),
+    ]));
+
+    handler.handle_cmd(cmd(&["HELLO", "3"])).unwrap();
+    let RespType::Array(groups) = handler.handle_cmd(cmd(&["XINFO", "GROUPS", "s"])).unwrap() else {
+        panic!("expected an array");
+    };
+    assert_eq!(groups, vec![RespType::Map(vec![
+        (RespType::BString("name".into()), RespType::BString("g".into())),
+        (RespType::BString("consumers".into()), RespType::Int(2)),
+        (RespType::BString("pending".into()), RespType::Int(1)),
+        (RespType::BString("last-delivered-id".into()), RespType::BString("3-0".into())),
+        (RespType::BString("entries-read".into()), RespType::Int(3)),
+        (RespType::BString("lag".into()), RespType::Int(0)),
+    ])]);
+    handler.handle_cmd(cmd(&["XADD", "s", "4", "n", "4"])).unwrap();
+    handler.handle_cmd(cmd(&["XGROUP", "CREATE", "s", "late", "0"])).unwrap();
+    let RespType::Array(groups) = handler.handle_cmd(cmd(&["XINFO", "GROUPS", "s"])).unwrap() else {
+        panic!("expected an array");
+    };
+    let RespType::Map(late) = &groups[1] else {
+        panic!("expected a map");
+    };
+    // an entry was deleted after where the group is at, so its lag can't be told
+    assert_eq!(late[5], (RespType::BString("lag".into()), RespType::Null));
+
+    let RespType::Map(info) = handler.handle_cmd(cmd(&["XINFO", "STREAM", "s"])).unwrap() else {
+        panic!("expected a map");
+    };
+    assert_eq!(info[0], (RespType::BString("length".into()), RespType::Int(3)));
+    assert_eq!(info[4], (RespType::BString("max-deleted-entry-id".into()), RespType::BString("2-0".into())));
+    assert_eq!(info[7], (RespType::BString("groups".into()), RespType::Int(2)));
+    assert_eq!(info[9], (RespType::BString("last-entry".into()), entry("4-0", &["n", "4"])));
+    let RespType::Array(consumers) = handler.handle_cmd(cmd(&["XINFO", "CONSUMERS", "s", "g"])).unwrap() else {
+        panic!("expected an array");
+    };
+    assert_eq!(consumers.len(), 2);
+    assert_eq!(handler.handle_cmd(cmd(&["XINFO", "STREAM", "nope"])).unwrap_err().to_string(), "ERR no such key");
+    assert_eq!(
+        handler.handle_cmd(cmd(&["XINFO", "CONSUMERS", "s", "nope"])).unwrap_err().to_string(),
+        "NOGROUP No such consumer group 'nope' for key name 's'"
+    );
+
+    assert_eq!(handler.handle_cmd(cmd(&["XGROUP", "CREATECONSUMER", "s", "g", "carol"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["XGROUP", "CREATECONSUMER", "s", "g", "carol"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["XGROUP", "DELCONSUMER", "s", "g", "alice"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["XGROUP", "SETID", "s", "g", "0"])).unwrap(), RespType::String("OK".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["XREADGROUP", "GROUP", "g", "carol", "COUNT", "1", "STREAMS", "s", ">"])).unwrap(), RespType::Map(vec![
+        (RespType::BString("s".into()), RespType::Array(vec![entry("1-0", &["n", "1"])])),
+    ]));
+    assert_eq!(handler.handle_cmd(cmd(&["XGROUP", "DESTROY", "s", "g"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["XGROUP", "DESTROY", "s", "g"])).unwrap(), RespType::Int(0));
+
+    assert_eq!(
+        handler.handle_cmd(cmd(&["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"])).unwrap_err().to_string(),
+        "NOGROUP No such key 's' or consumer group 'g' in XREADGROUP with GROUP option"
+    );
+    assert!(handler.handle_cmd(cmd(&["XREADGROUP", "GROUP", "late", "alice", "STREAMS", "s", "$"])).unwrap_err().to_string().starts_with("ERR The $ ID is meaningless"));
+    assert!(handler.handle_cmd(cmd(&["XREAD", "STREAMS", "s", ">"])).unwrap_err().to_string().starts_with("ERR The > ID can be specified only"));
+    assert_eq!(handler.handle_cmd(cmd(&["XPENDING", "s", "nope"])).unwrap_err().to_string(), "NOGROUP No such key 's' or consumer group 'nope'");
+    assert_eq!(
+        handler.handle_cmd(cmd(&["COMMAND", "GETKEYS", "XREADGROUP", "GROUP", "g", "c", "STREAMS", "a", "b", ">", ">"])).unwrap(),
+        bulks(&["a", "b"])
+    );
+    assert_eq!(handler.handle_cmd(cmd(&["COMMAND", "GETKEYS", "XGROUP", "CREATE", "s", "g", "$"])).unwrap(), bulks(&["s"]));
+}
+
+#[test]
+fn test_xclaim_and_xautoclaim() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    for id in ["1", "2", "3", "4"] {
+        handler.handle_cmd(cmd(&["XADD", "s", id, "n", id])).unwrap();
+    }
+    handler.handle_cmd(cmd(&["XGROUP", "CREATE", "s", "g", "0"])).unwrap();
+    handler.handle_cmd(cmd(&["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"])).unwrap();
+
+    // not idle long enough yet
+    assert_eq!(handler.handle_cmd(cmd(&["XCLAIM", "s", "g", "bob", "60000", "1"])).unwrap(), RespType::Array(vec![]));
+    // IDLE backdates the delivery, so the entries look idle for a minute
+    assert_eq!(
+        handler.handle_cmd(cmd(&["XCLAIM", "s", "g", "alice", "0", "1", "2", "3", "4", "IDLE", "60000", "JUSTID"])).unwrap(),
+        bulks(&["1-0", "2-0", "3-0", "4-0"])
+    );
+    assert_eq!(
+        handler.handle_cmd(cmd(&["XCLAIM", "s", "g", "bob", "30000", "1", "9", "RETRYCOUNT", "5"])).unwrap(),
+        RespType::Array(vec![entry("1-0", &["n", "1"])])
+    );
+    let RespType::Array(extended) = handler.handle_cmd(cmd(&["XPENDING", "s", "g", "-", "+", "1"])).unwrap() else {
+        panic!("expected an array");
+    };
+    let RespType::Array(first) = &extended[0] else {
+        panic!("expected an array");
+    };
+    assert_eq!(first[1], RespType::BString("bob".into()));
+    assert_eq!(first[3], RespType::Int(5));
+
+    // FORCE claims entries that weren't delivered, and LASTID moves the group on
+    handler.handle_cmd(cmd(&["XADD", "s", "5", "n", "5"])).unwrap();
+    assert_eq!(
+        handler.handle_cmd(cmd(&["XCLAIM", "s", "g", "bob", "0", "5", "FORCE", "JUSTID", "LASTID", "5"])).unwrap(),
+        bulks(&["5-0"])
+    );
+    assert_eq!(handler.handle_cmd(cmd(&["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"])).unwrap(), RespType::NullArray);
+
+    // XAUTOCLAIM scans from the cursor, dropping deleted entries from the PEL
+    handler.handle_cmd(cmd(&["XDEL", "s", "3"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["XAUTOCLAIM", "s", "g", "carol", "10000", "0", "COUNT", "1"])).unwrap(), RespType::Array(vec![
+        RespType::BString("3-0".into()),
+        RespType::Array(vec![entry("2-0", &["n", "2"])]),
+        RespType::Array(vec![]),
+    ]));
+    assert_eq!(handler.handle_cmd(cmd(&["XAUTOCLAIM", "s", "g", "carol", "10000", "3", "JUSTID"])).unwrap(), RespType::Array(vec![
+        RespType::BString("0-0".into()),
+        bulks(&["4-0"]),
+        bulks(&["3-0"]),
+    ]));
+    assert_eq!(handler.handle_cmd(cmd(&["XPENDING", "s", "g"])).unwrap(), RespType::Array(vec![
+        RespType::Int(4),
+        RespType::BString("1-0".into()),
+        RespType::BString("5-0".into()),
+        RespType::Array(vec![bulks(&["bob", "2"]), bulks(&["carol", "2"])]),
+    ]));
+
+    assert_eq!(handler.handle_cmd(cmd(&["XAUTOCLAIM", "s", "g", "c", "0", "0", "COUNT", "0"])).unwrap_err().to_string(), "ERR COUNT must be > 0");
+    assert_eq!(handler.handle_cmd(cmd(&["XCLAIM", "s", "g", "c", "x", "1"])).unwrap_err().to_string(), "ERR Invalid min-idle-time argument for XCLAIM");
+    assert_eq!(handler.handle_cmd(cmd(&["XCLAIM", "s", "g", "c", "0", "1", "BOGUS"])).unwrap_err().to_string(), "ERR Unrecognized XCLAIM option 'BOGUS'");
+    assert_eq!(handler.handle_cmd(cmd(&["XCLAIM", "s", "nope", "c", "0", "1"])).unwrap_err().to_string(), "NOGROUP No such key 's' or consumer group 'nope'");
+}
+
+#[test]
+fn test_hyperloglog() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    assert_eq!(handler.handle_cmd(cmd(&["PFADD", "hll", "foo", "bar", "zap"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["PFADD", "hll", "zap", "zap", "zap"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["PFADD", "hll", "foo", "bar"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["PFCOUNT", "hll"])).unwrap(), RespType::Int(3));
+    handler.handle_cmd(cmd(&["PFADD", "other", "1", "2", "3"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["PFCOUNT", "hll", "other", "missing"])).unwrap(), RespType::Int(6));
+    assert_eq!(handler.handle_cmd(cmd(&["PFCOUNT", "missing"])).unwrap(), RespType::Int(0));
+    // creating the key counts as a change even without elements
+    assert_eq!(handler.handle_cmd(cmd(&["PFADD", "empty"])).unwrap(), RespType::Int(1));
+    assert_eq!(handler.handle_cmd(cmd(&["PFADD", "empty"])).unwrap(), RespType::Int(0));
+
+    handler.handle_cmd(cmd(&["PFADD", "hll1", "foo", "bar", "zap", "a"])).unwrap();
+    handler.handle_cmd(cmd(&["PFADD", "hll2", "a", "b", "c", "foo"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["PFMERGE", "hll3", "hll1", "hll2"])).unwrap(), RespType::String("OK".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["PFCOUNT", "hll3"])).unwrap(), RespType::Int(6));
+    // the destination's own registers are merged in too
+    handler.handle_cmd(cmd(&["PFMERGE", "hll3", "other"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["PFCOUNT", "hll3"])).unwrap(), RespType::Int(9));
+    handler.handle_cmd(cmd(&["PFMERGE", "fresh"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["PFCOUNT", "fresh"])).unwrap(), RespType::Int(0));
+
+    // HyperLogLogs are plain strings, sparse while small, with the count cached in the header
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "hll"])).unwrap(), RespType::String("string".into()));
+    let RespType::BString(payload) = handler.handle_cmd(cmd(&["GET", "hll"])).unwrap() else {
+        panic!("expected a bulk string");
+    };
+    assert_eq!(&payload[..5], b"HYLL\x01");
+    assert_eq!(&payload[8..16], &3u64.to_le_bytes());
+    handler.handle_cmd(bin_cmd(&[b"SET", b"copy", &payload])).unwrap();
+    handler.handle_cmd(cmd(&["PFADD", "copy", "another"])).unwrap();
+    let RespType::BString(payload) = handler.handle_cmd(cmd(&["GET", "copy"])).unwrap() else {
+        panic!("expected a bulk string");
+    };
+    assert_eq!(payload[15] & 0x80, 0x80);
+    assert_eq!(handler.handle_cmd(cmd(&["PFCOUNT", "copy"])).unwrap(), RespType::Int(4));
+
+    handler.handle_cmd(cmd(&["SET", "s", "not a hll"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["PFADD", "s", "x"])).unwrap_err().to_string(), "WRONGTYPE Key is not a valid HyperLogLog string value.");
+    assert_eq!(handler.handle_cmd(cmd(&["PFCOUNT", "hll", "s"])).unwrap_err().to_string(), "WRONGTYPE Key is not a valid HyperLogLog string value.");
+    handler.handle_cmd(cmd(&["RPUSH", "l", "x"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["PFMERGE", "hll3", "l"])).unwrap_err(), CommandErr::WrongType);
+    // a sparse encoding covering too few registers
+    handler.handle_cmd(bin_cmd(&[b"SET", b"corrupt", b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80\x7f\xfe"])).unwrap();
+    assert_eq!(handler.handle_cmd(cmd(&["PFCOUNT", "corrupt"])).unwrap_err().to_string(), "INVALIDOBJ Corrupted HLL object detected");
+}
+
+#[test]
+fn test_hyperloglog_accuracy_and_dense_encoding() {
+    let limits = EncodingLimits { hll_sparse_max_bytes: 1000, ..EncodingLimits::default() };
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::with_limits(limits))));
+    let len = |handler: &mut CommandHandler, k: &str| match handler.handle_cmd(cmd(&["STRLEN", k])).unwrap() {
+        RespType::Int(len) => len,
+        other => panic!("expected an integer, got {:?}", other),
+    };
+
+    let (mut added, mut dense_at) = (0, None);
+    for batch in 0..100 {
+        let elements: Vec<String> = (0..100).map(|i| format!("element:{}", batch * 100 + i)).collect();
+        let mut args = vec!["PFADD", "a"];
+        args.extend(elements.iter().map(String::as_str));
+        handler.handle_cmd(cmd(&args)).unwrap();
+        added += 100;
+        if dense_at.is_none() && len(&mut handler, "a") == 12304 {
+            dense_at = Some(added);
+        }
+        // the sparse encoding never grows past the limit
+        assert!(len(&mut handler, "a") <= 1000 || len(&mut handler, "a") == 12304);
+    }
+    assert!(dense_at.is_some_and(|at| at < 1000));
+
+    let count = |handler: &mut CommandHandler, args: &[&str]| match handler.handle_cmd(cmd(args)).unwrap() {
+        RespType::Int(n) => n as f64,
+        other => panic!("expected an integer, got {:?}", other),
+    };
+    // redis' standard error is 0.81%, so this is well within bounds
+    let estimate = count(&mut handler, &["PFCOUNT", "a"]);
+    assert!((estimate - 10_000.0).abs() / 10_000.0 < 0.03, "estimated {}", estimate);
+
+    for batch in 0..50 {
+        let elements: Vec<String> = (0..100).map(|i| format!("element:{}", 5000 + batch * 100 + i)).collect();
+        let mut args = vec!["PFADD", "b"];
+        args.extend(elements.iter().map(String::as_str));
+        handler.handle_cmd(cmd(&args)).unwrap();
+    }
+    let union = count(&mut handler, &["PFCOUNT", "a", "b"]);
+    assert!((union - 10_000.0).abs() / 10_000.0 < 0.03, "estimated {}", union);
+    handler.handle_cmd(cmd(&["PFMERGE", "c", "b", "a"])).unwrap();
+    assert_eq!(count(&mut handler, &["PFCOUNT", "c"]), union);
+    assert_eq!(len(&mut handler, "c"), 12304);
+}
+
+#[test]
+fn test_geo() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    let added = handler.handle_cmd(cmd(&["GEOADD", "Sicily", "13.361389", "38.115556", "Palermo", "15.087269", "37.502669", "Catania"]));
+    assert_eq!(added.unwrap(), RespType::Int(2));
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "Sicily"])).unwrap(), RespType::String("zset".into()));
+    assert_eq!(handler.handle_cmd(cmd(&["ZSCORE", "Sicily", "Palermo"])).unwrap(), RespType::Double(3479099956230698.0));
+    assert_eq!(handler.handle_cmd(cmd(&["GEOADD", "Sicily", "NX", "CH", "13.361389", "38.115556", "Palermo"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["GEOADD", "missing", "XX", "13.361389", "38.115556", "Palermo"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["GEOADD", "Sicily", "13.361389", "38.115556", "Palermo", "15"])).unwrap_err(), CommandErr::SyntaxError);
+    let err = handler.handle_cmd(cmd(&["GEOADD", "Sicily", "10", "86", "North"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR invalid longitude,latitude pair 10.000000,86.000000");
+
+    assert_eq!(handler.handle_cmd(cmd(&["GEODIST", "Sicily", "Palermo", "Catania"])).unwrap(), RespType::BString(b"166274.1516".to_vec()));
+    assert_eq!(handler.handle_cmd(cmd(&["GEODIST", "Sicily", "Palermo", "Catania", "km"])).unwrap(), RespType::BString(b"166.2742".to_vec()));
+    assert_eq!(handler.handle_cmd(cmd(&["GEODIST", "Sicily", "Palermo", "Foo"])).unwrap(), RespType::Null);
+    let err = handler.handle_cmd(cmd(&["GEODIST", "Sicily", "Palermo", "Catania", "yd"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR unsupported unit provided. please use M, KM, FT, MI");
+
+    let hashes = handler.handle_cmd(cmd(&["GEOHASH", "Sicily", "Palermo", "Catania", "Foo"])).unwrap();
+    assert_eq!(hashes, RespType::Array(vec![RespType::BString(b"sqc8b49rny0".to_vec()), RespType::BString(b"sqdtr74hyu0".to_vec()), RespType::Null]));
+    let positions = handler.handle_cmd(cmd(&["GEOPOS", "Sicily", "Palermo", "Foo"])).unwrap();
+    assert_eq!(positions, RespType::Array(vec![bulks(&["13.36138933897018433", "38.11555639549629859"]), RespType::NullArray]));
+
+    let found = handler.handle_cmd(cmd(&["GEOSEARCH", "Sicily", "FROMLONLAT", "15", "37", "BYRADIUS", "200", "km", "ASC"])).unwrap();
+    assert_eq!(found, bulks(&["Catania", "Palermo"]));
+    let found = handler.handle_cmd(cmd(&["GEOSEARCH", "Sicily", "FROMLONLAT", "15", "37", "BYRADIUS", "100", "km"])).unwrap();
+    assert_eq!(found, bulks(&["Catania"]));
+    let found = handler.handle_cmd(cmd(&["GEOSEARCH", "Sicily", "FROMLONLAT", "15", "37", "BYBOX", "400", "400", "km", "DESC", "WITHDIST", "WITHHASH"])).unwrap();
+    let expected = vec![
+        RespType::Array(vec![RespType::BString(b"Palermo".to_vec()), RespType::BString(b"190.4424".to_vec()), RespType::Int(3479099956230698)]),
+        RespType::Array(vec![RespType::BString(b"Catania".to_vec()), RespType::BString(b"56.4413".to_vec()), RespType::Int(3479447370796909)]),
+    ];
+    assert_eq!(found, RespType::Array(expected));
+    // COUNT keeps the nearest points
+    let found = handler.handle_cmd(cmd(&["GEOSEARCH", "Sicily", "FROMMEMBER", "Palermo", "BYRADIUS", "500", "km", "COUNT", "1"])).unwrap();
+    assert_eq!(found, bulks(&["Palermo"]));
+    assert_eq!(handler.handle_cmd(cmd(&["GEOSEARCH", "missing", "FROMLONLAT", "15", "37", "BYRADIUS", "1", "m"])).unwrap(), RespType::Array(vec![]));
+
+    let err = handler.handle_cmd(cmd(&["GEOSEARCH", "Sicily", "BYRADIUS", "1", "m", "ASC", "WITHDIST"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for geosearch");
+    let err = handler.handle_cmd(cmd(&["GEOSEARCH", "Sicily", "FROMMEMBER", "Palermo", "BYRADIUS", "1", "m", "BYBOX", "1", "1", "m"])).unwrap_err();
+    assert_eq!(err, CommandErr::SyntaxError);
+    let err = handler.handle_cmd(cmd(&["GEOSEARCH", "Sicily", "FROMMEMBER", "Foo", "BYRADIUS", "1", "m"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR could not decode requested zset member");
+    let err = handler.handle_cmd(cmd(&["GEOSEARCH", "Sicily", "FROMMEMBER", "Palermo", "BYRADIUS", "1", "m", "ANY"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR the ANY argument requires COUNT argument");
+
+    let stored = handler.handle_cmd(cmd(&["GEOSEARCHSTORE", "dst", "Sicily", "FROMLONLAT", "15", "37", "BYRADIUS", "200", "km", "STOREDIST"])).unwrap();
+    assert_eq!(stored, RespType::Int(2));
+    let scores = handler.handle_cmd(cmd(&["ZRANGE", "dst", "0", "-1", "WITHSCORES"])).unwrap();
+    let RespType::Array(scores) = scores else { panic!("expected an array") };
+    assert_eq!(scores[0], RespType::BString(b"Catania".to_vec()));
+    assert!(matches!(scores[1], RespType::Double(d) if (d - 56.4413).abs() < 1e-4));
+    assert!(matches!(scores[3], RespType::Double(d) if (d - 190.4424).abs() < 1e-4));
+    let err = handler.handle_cmd(cmd(&["GEOSEARCHSTORE", "dst", "Sicily", "FROMLONLAT", "15", "37", "BYRADIUS", "200", "km", "WITHDIST"])).unwrap_err();
+    assert_eq!(err.to_string(), "ERR GEOSEARCHSTORE is not compatible with WITHDIST, WITHHASH and WITHCOORD options");
+    assert_eq!(handler.handle_cmd(cmd(&["GEOSEARCHSTORE", "dst", "missing", "FROMLONLAT", "15", "37", "BYRADIUS", "1", "m"])).unwrap(), RespType::Int(0));
+    assert_eq!(handler.handle_cmd(cmd(&["TYPE", "dst"])).unwrap(), RespType::String("none".into()));
+}
+
+#[test]
+fn test_geosearch_across_cell_boundaries() {
+    let mut handler = CommandHandler::new(Arc::new(Mutex::new(Storage::new())));
+    // the prime meridian splits cells at every step, so the center cell holds only one point
+    handler.handle_cmd(cmd(&["GEOADD", "g", "-0.0005", "10", "west", "0.0005", "10", "east"])).unwrap();
+    let found = handler.handle_cmd(cmd(&["GEOSEARCH", "g", "FROMLONLAT", "0.0001", "10", "BYRADIUS", "700", "m", "ASC"])).unwrap();
+    assert_eq!(found, bulks(&["east", "west"]));
+    let found = handler.handle_cmd(cmd(&["GEOSEARCH", "g", "FROMLONLAT", "0.0001", "10", "BYBOX", "1000", "1000", "m", "ASC"])).unwrap();
+    assert_eq!(found, bulks(&["east", "west"]));
+    let found = handler.handle_cmd(cmd(&["GEOSEARCH", "g", "FROMLONLAT", "-0.0001", "10", "BYRADIUS", "700", "m", "ASC"])).unwrap();
+    assert_eq!(found, bulks(&["west", "east"]));
+
+    // and the equator splits them in latitude
+    handler.handle_cmd(cmd(&["GEOADD", "h", "10", "-0.0005", "south", "10", "0.0005", "north"])).unwrap();
+    let found = handler.handle_cmd(cmd(&["GEOSEARCH", "h", "FROMLONLAT", "10", "0.0001", "BYRADIUS", "700", "m", "ASC"])).unwrap();
+    assert_eq!(found, bulks(&["north", "south"]));
+    let found = handler.handle_cmd(cmd(&["GEOSEARCH", "h", "FROMLONLAT", "10", "-0.0001", "BYBOX", "1000", "1000", "m", "ASC"])).unwrap();
+    assert_eq!(found, bulks(&["south", "north"]));
+}
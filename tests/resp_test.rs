@@ -1,4 +1,6 @@
-use rkey::{Resp, RespType};
+use std::collections::HashMap;
+
+use rkey::{FromResp, Protocol, Resp, RespDecoder, RespErr, RespLimits, RespType, ToResp};
 
 /*
     Each test should include the type serialization & deserialization
@@ -10,7 +12,7 @@ fn test_simple_string() {
     let resp_parser = Resp::new();
     let resp_str = "+OK\r\n";
     let des: RespType = RespType::String("OK".to_string());
-    assert_eq!(des.serialize(), resp_str);
+    assert_eq!(des.serialize(), resp_str.as_bytes());
 
     let parsed = resp_parser.parse_line(resp_str).unwrap();
     assert_eq!(parsed, des);
@@ -20,8 +22,8 @@ fn test_simple_string() {
 fn test_bulk_string() {
     let resp_parser = Resp::new();
     let resp_str = "$5\r\nhello\r\n";
-    let des = RespType::BString("hello".to_string());
-    assert_eq!(des.serialize(), resp_str);
+    let des = RespType::BString("hello".into());
+    assert_eq!(des.serialize(), resp_str.as_bytes());
     let parsed = resp_parser.parse_line(resp_str).unwrap();
     assert_eq!(parsed, des);
 }
@@ -33,7 +35,7 @@ fn test_integer() {
     let resp = ":5\r\n";
     let parsed = resp_parser.parse_line(resp).unwrap();
     let des = RespType::Int(5);
-    assert_eq!(des.serialize(), resp);
+    assert_eq!(des.serialize(), resp.as_bytes());
     assert_eq!(parsed, des);
 
     let resp = ":+5\r\n";
@@ -46,7 +48,7 @@ fn test_integer() {
     let parsed = resp_parser.parse_line(resp).unwrap();
     let des = RespType::Int(-5);
     assert_eq!(parsed, des);
-    assert_eq!(des.serialize(), resp);
+    assert_eq!(des.serialize(), resp.as_bytes());
     assert_eq!(parsed, des);
 }
 
@@ -55,12 +57,12 @@ fn test_array() {
     let resp_parser = Resp::new();
 
     let resp_str = "*2\r\n$5\r\nhello\r\n:223\r\n";
-    let parsed = resp_parser.parse_line(&resp_str).unwrap();
+    let parsed = resp_parser.parse_line(resp_str).unwrap();
     let des = RespType::Array(vec![
-        RespType::BString("hello".to_string()),
+        RespType::BString("hello".into()),
         RespType::Int(223)
     ]);
-    assert_eq!(des.serialize(), resp_str);
+    assert_eq!(des.serialize(), resp_str.as_bytes());
 
     assert_eq!(
         parsed,
@@ -72,7 +74,7 @@ fn test_array() {
 fn test_nested_arrays() {
     let resp_parser = Resp::new();
     let resp_str = "*3\r\n+Hello\r\n*3\r\n:1\r\n:2\r\n:3\r\n*2\r\n+Hello\r\n+World\r\n";
-    let parsed = resp_parser.parse_line(&resp_str).unwrap();
+    let parsed = resp_parser.parse_line(resp_str).unwrap();
     let des: RespType = RespType::Array(vec![
         RespType::String("Hello".to_string()),
         RespType::Array(vec![RespType::Int(1), RespType::Int(2), RespType::Int(3)]),
@@ -81,7 +83,7 @@ fn test_nested_arrays() {
             RespType::String("World".to_string())
         ])
     ]);
-    assert_eq!(des.serialize(), resp_str);
+    assert_eq!(des.serialize(), resp_str.as_bytes());
     assert_eq!(
         parsed,
         des
@@ -93,11 +95,367 @@ fn test_nested_arrays() {
 fn test_null() {
     let resp_parser = Resp::new();
     let resp_str = "$-1\r\n";
-    let parsed = resp_parser.parse_line(&resp_str).unwrap();
+    let parsed = resp_parser.parse_line(resp_str).unwrap();
     let des: RespType = RespType::Null;
-    assert_eq!(des.serialize(), resp_str);
+    assert_eq!(des.serialize(), resp_str.as_bytes());
     assert_eq!(
         parsed,
         des
     )
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_decoder_partial_reads() {
+    let mut decoder = RespDecoder::new();
+    let resp_str = b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n";
+
+    // feed the frame one byte at a time, it should only be yielded once complete
+    for (i, b) in resp_str.iter().enumerate() {
+        decoder.feed(&[*b]);
+        let frame = decoder.next_frame().unwrap();
+        if i < resp_str.len() - 1 {
+            assert_eq!(frame, None);
+        } else {
+            assert_eq!(
+                frame,
+                Some(RespType::Array(vec![
+                    RespType::BString("GET".into()),
+                    RespType::BString("hello".into())
+                ]))
+            );
+        }
+    }
+    assert_eq!(decoder.buffered(), 0);
+}
+
+#[test]
+fn test_decoder_pipelined_frames() {
+    let mut decoder = RespDecoder::new();
+    decoder.feed(b"*1\r\n$4\r\nPING\r\n:42\r\n+OK\r\n$5\r\nhel");
+
+    assert_eq!(
+        decoder.next_frame().unwrap(),
+        Some(RespType::Array(vec![RespType::BString("PING".into())]))
+    );
+    assert_eq!(decoder.next_frame().unwrap(), Some(RespType::Int(42)));
+    assert_eq!(decoder.next_frame().unwrap(), Some(RespType::String("OK".to_string())));
+    assert_eq!(decoder.next_frame().unwrap(), None);
+
+    decoder.feed(b"lo\r\n");
+    assert_eq!(decoder.next_frame().unwrap(), Some(RespType::BString("hello".into())));
+    assert_eq!(decoder.next_frame().unwrap(), None);
+}
+
+#[test]
+fn test_decoder_large_bulk_string() {
+    let mut decoder = RespDecoder::new();
+    let value = "x".repeat(100_000).into_bytes();
+    let encoded = RespType::BString(value.clone()).serialize();
+
+    for chunk in encoded.chunks(1024) {
+        decoder.feed(chunk);
+    }
+    assert_eq!(decoder.next_frame().unwrap(), Some(RespType::BString(value)));
+}
+
+#[test]
+fn test_decoder_resumes_split_frames() {
+    // every kind of element, split at every byte
+    let frame = RespType::Array(vec![
+        RespType::Null,
+        RespType::Array(vec![]),
+        RespType::Map(vec![(RespType::BString("k".into()), RespType::Array(vec![RespType::Int(1), RespType::Double(2.5)]))]),
+        RespType::Set(vec![RespType::Boolean(true)]),
+        RespType::BString("x\r\ny".into()),
+    ]);
+    let encoded = frame.serialize_with(Protocol::Resp3);
+    let mut decoder = RespDecoder::new();
+    for b in &encoded[..encoded.len() - 1] {
+        decoder.feed(&[*b]);
+        assert_eq!(decoder.next_frame().unwrap(), None);
+    }
+    decoder.feed(&encoded[encoded.len() - 1..]);
+    decoder.feed(b":7\r\n");
+    assert_eq!(decoder.next_frame().unwrap(), Some(frame));
+    assert_eq!(decoder.next_frame().unwrap(), Some(RespType::Int(7)));
+    assert_eq!(decoder.buffered(), 0);
+}
+
+#[test]
+fn test_decoder_split_multibulk_is_linear() {
+    // decoding the frame again on every read would take minutes here
+    let args: Vec<RespType> = (0..200_000).map(|i| RespType::BString(format!("key:{}", i).into_bytes())).collect();
+    let encoded = RespType::Array(args.clone()).serialize();
+    let mut decoder = RespDecoder::new();
+    let started = std::time::Instant::now();
+    let mut frames = Vec::new();
+    for chunk in encoded.chunks(1024) {
+        decoder.feed(chunk);
+        frames.extend(decoder.next_frame().unwrap());
+    }
+    assert!(started.elapsed() < std::time::Duration::from_secs(5), "took {:?}", started.elapsed());
+    assert_eq!(frames, vec![RespType::Array(args)]);
+}
+
+#[test]
+fn test_binary_bulk_string() {
+    let resp_parser = Resp::new();
+    let payload = vec![0x1f, 0x8b, 0x00, 0xff, b'\r', b'\n', 0xc3];
+    let des = RespType::BString(payload.clone());
+
+    let mut resp_str = b"$7\r\n".to_vec();
+    resp_str.extend_from_slice(&payload);
+    resp_str.extend_from_slice(b"\r\n");
+    assert_eq!(des.serialize(), resp_str);
+
+    let parsed = resp_parser.parse_line(&resp_str).unwrap();
+    assert_eq!(parsed, des);
+}
+
+#[test]
+fn test_resp3_types() {
+    let resp_parser = Resp::new();
+    let cases: Vec<(&str, RespType)> = vec![
+        ("_\r\n", RespType::Null),
+        (",1.5\r\n", RespType::Double(1.5)),
+        (",inf\r\n", RespType::Double(f64::INFINITY)),
+        ("#t\r\n", RespType::Boolean(true)),
+        ("#f\r\n", RespType::Boolean(false)),
+        ("(3492890328409238509324850943850943825024385\r\n", RespType::BigNumber("3492890328409238509324850943850943825024385".to_string())),
+        ("=15\r\ntxt:Some string\r\n", RespType::Verbatim("txt".to_string(), "Some string".into())),
+        ("~2\r\n+a\r\n:1\r\n", RespType::Set(vec![RespType::String("a".to_string()), RespType::Int(1)])),
+        (">2\r\n+message\r\n$2\r\nhi\r\n", RespType::Push(vec![RespType::String("message".to_string()), RespType::BString("hi".into())])),
+        (
+            "%2\r\n+first\r\n:1\r\n+second\r\n#t\r\n",
+            RespType::Map(vec![
+                (RespType::String("first".to_string()), RespType::Int(1)),
+                (RespType::String("second".to_string()), RespType::Boolean(true)),
+            ]),
+        ),
+        (
+            "|1\r\n+ttl\r\n:3600\r\n$3\r\nval\r\n",
+            RespType::Attribute(
+                vec![(RespType::String("ttl".to_string()), RespType::Int(3600))],
+                Box::new(RespType::BString("val".into())),
+            ),
+        ),
+    ];
+
+    for (resp_str, des) in cases {
+        assert_eq!(des.serialize_with(Protocol::Resp3), resp_str.as_bytes());
+        assert_eq!(resp_parser.parse_line(resp_str).unwrap(), des);
+    }
+}
+
+#[test]
+fn test_resp3_downgrade_to_resp2() {
+    let map = RespType::Map(vec![
+        (RespType::BString("a".into()), RespType::Double(2.5)),
+        (RespType::BString("b".into()), RespType::Boolean(true)),
+    ]);
+    assert_eq!(map.serialize(), b"*4\r\n$1\r\na\r\n$3\r\n2.5\r\n$1\r\nb\r\n:1\r\n");
+    assert_eq!(RespType::Null.serialize_with(Protocol::Resp3), b"_\r\n");
+    assert_eq!(RespType::Null.serialize(), b"$-1\r\n");
+}
+
+#[test]
+fn test_inline_commands() {
+    let mut decoder = RespDecoder::new();
+    decoder.feed(b"PING\r\n\r\nSET foo \"bar baz\"\nGET 'it\\'s'\r\nSET k \"\\x00\\n\"\r\nGET fo");
+
+    let bulk = |parts: &[&[u8]]| RespType::Array(parts.iter().map(|p| RespType::BString(p.to_vec())).collect());
+    assert_eq!(decoder.next_frame().unwrap(), Some(bulk(&[b"PING"])));
+    assert_eq!(decoder.next_frame().unwrap(), Some(bulk(&[b"SET", b"foo", b"bar baz"])));
+    assert_eq!(decoder.next_frame().unwrap(), Some(bulk(&[b"GET", b"it's"])));
+    assert_eq!(decoder.next_frame().unwrap(), Some(bulk(&[b"SET", b"k", b"\x00\n"])));
+    assert_eq!(decoder.next_frame().unwrap(), None);
+
+    decoder.feed(b"o\n");
+    assert_eq!(decoder.next_frame().unwrap(), Some(bulk(&[b"GET", b"foo"])));
+}
+
+#[test]
+fn test_inline_unbalanced_quotes() {
+    let mut decoder = RespDecoder::new();
+    decoder.feed(b"SET foo \"bar\r\n");
+    assert!(decoder.next_frame().is_err());
+}
+
+#[test]
+fn test_decoder_limits() {
+    let limits = RespLimits {
+        max_bulk_len: 16,
+        max_multibulk_len: 300,
+        max_depth: 2,
+        max_inline_len: 32,
+    };
+
+    let mut decoder = RespDecoder::with_limits(limits.clone());
+    decoder.feed(b"$17\r\n");
+    assert_eq!(decoder.next_frame(), Err(RespErr::Protocol("invalid bulk length".to_string())));
+
+    let mut decoder = RespDecoder::with_limits(limits.clone());
+    decoder.feed(b"$-5\r\n");
+    assert_eq!(decoder.next_frame(), Err(RespErr::Protocol("invalid bulk length".to_string())));
+
+    let mut decoder = RespDecoder::with_limits(limits.clone());
+    decoder.feed(b"*301\r\n");
+    assert_eq!(decoder.next_frame(), Err(RespErr::Protocol("invalid multibulk length".to_string())));
+
+    // arrays longer than 255 elements are fine within the limit
+    let mut decoder = RespDecoder::with_limits(limits.clone());
+    decoder.feed(b"*300\r\n");
+    for _ in 0..300 {
+        decoder.feed(b":1\r\n");
+    }
+    assert_eq!(decoder.next_frame().unwrap(), Some(RespType::Array((0..300).map(|_| RespType::Int(1)).collect())));
+
+    let mut decoder = RespDecoder::with_limits(limits.clone());
+    decoder.feed(b"*1\r\n*1\r\n*1\r\n:1\r\n");
+    assert_eq!(decoder.next_frame(), Err(RespErr::Protocol("too many nested aggregates".to_string())));
+
+    let mut decoder = RespDecoder::with_limits(limits);
+    decoder.feed(&[b'a'; 33]);
+    assert_eq!(decoder.next_frame(), Err(RespErr::Protocol("too big inline request".to_string())));
+}
+
+#[test]
+fn test_write_to_matches_serialize() {
+    let des = RespType::Array(vec![
+        RespType::BString("hello".into()),
+        RespType::Int(-3),
+        RespType::Double(0.25),
+        RespType::Null,
+        RespType::Map(vec![(RespType::String("k".to_string()), RespType::Boolean(false))]),
+    ]);
+
+    // the same buffer is reused across writes
+    let mut out = Vec::new();
+    for proto in [Protocol::Resp2, Protocol::Resp3] {
+        out.clear();
+        des.write_to(&mut out, proto).unwrap();
+        assert_eq!(out, des.serialize_with(proto));
+    }
+    assert_eq!(
+        des.serialize(),
+        b"*5\r\n$5\r\nhello\r\n:-3\r\n$4\r\n0.25\r\n$-1\r\n*2\r\n+k\r\n:0\r\n"
+    );
+}
+
+#[test]
+fn test_from_resp() {
+    assert_eq!(i64::from_resp(&RespType::BString("-42".into())), Ok(-42));
+    assert_eq!(u8::from_resp(&RespType::Int(7)), Ok(7));
+    assert!(u8::from_resp(&RespType::Int(300)).is_err());
+    assert!(i64::from_resp(&RespType::BString("4x".into())).is_err());
+    assert_eq!(f64::from_resp(&RespType::BString("1.5".into())), Ok(1.5));
+    assert_eq!(f64::from_resp(&RespType::BString("-inf".into())), Ok(f64::NEG_INFINITY));
+    assert_eq!(Vec::<u8>::from_resp(&RespType::BString(vec![0, 255])), Ok(vec![0, 255]));
+    assert_eq!(Option::<String>::from_resp(&RespType::Null), Ok(None));
+
+    let arr = RespType::Array(vec![RespType::BString("a".into()), RespType::Int(2)]);
+    assert_eq!(<(String, i32)>::from_resp(&arr), Ok(("a".to_string(), 2)));
+    assert_eq!(Vec::<i32>::from_resp(&RespType::Array(vec![RespType::Int(1), RespType::BString("2".into())])), Ok(vec![1, 2]));
+
+    // RESP2 replies flatten maps into arrays, RESP3 ones don't
+    let expected = HashMap::from([("f".to_string(), 1i64)]);
+    let flat = RespType::Array(vec![RespType::BString("f".into()), RespType::BString("1".into())]);
+    assert_eq!(HashMap::<String, i64>::from_resp(&flat), Ok(expected.clone()));
+    let map = RespType::Map(vec![(RespType::BString("f".into()), RespType::Int(1))]);
+    assert_eq!(HashMap::<String, i64>::from_resp(&map), Ok(expected));
+}
+
+#[test]
+fn test_to_resp() {
+    assert_eq!(5i64.to_resp(), RespType::Int(5));
+    assert_eq!(u64::MAX.to_resp(), RespType::BigNumber(u64::MAX.to_string()));
+    assert_eq!(b"abc".to_vec().to_resp(), RespType::BString("abc".into()));
+    assert_eq!("abc".to_resp(), RespType::BString("abc".into()));
+    assert_eq!(None::<i64>.to_resp(), RespType::Null);
+    assert_eq!(
+        vec![1i64, 2].to_resp(),
+        RespType::Array(vec![RespType::Int(1), RespType::Int(2)])
+    );
+    assert_eq!(
+        ("k", 2.5f64).to_resp(),
+        RespType::Array(vec![RespType::BString("k".into()), RespType::Double(2.5)])
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_bridge() {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Profile {
+        name: String,
+        age: u32,
+        tags: Vec<String>,
+        nickname: Option<String>,
+    }
+
+    let profile = Profile {
+        name: "ana".to_string(),
+        age: 31,
+        tags: vec!["admin".to_string()],
+        nickname: None,
+    };
+    let resp = rkey::to_resp(&profile).unwrap();
+    assert_eq!(
+        resp,
+        RespType::Map(vec![
+            (RespType::BString("name".into()), RespType::BString("ana".into())),
+            (RespType::BString("age".into()), RespType::Int(31)),
+            (RespType::BString("tags".into()), RespType::Array(vec![RespType::BString("admin".into())])),
+            (RespType::BString("nickname".into()), RespType::Null),
+        ])
+    );
+    assert_eq!(rkey::from_resp::<Profile>(&resp).unwrap(), profile);
+
+    // a RESP2 HGETALL reply, where every value is a bulk string
+    let hgetall = RespType::Array(vec![
+        RespType::BString("name".into()),
+        RespType::BString("ana".into()),
+        RespType::BString("age".into()),
+        RespType::BString("31".into()),
+        RespType::BString("tags".into()),
+        RespType::Array(vec![]),
+    ]);
+    let parsed: Profile = rkey::from_resp(&hgetall).unwrap();
+    assert_eq!(parsed.age, 31);
+    assert_eq!(parsed.nickname, None);
+}
+
+#[test]
+fn test_error() {
+    let resp_parser = Resp::new();
+    let resp_str = "-ERR unknown command 'foo'\r\n";
+    let des = RespType::Err("ERR unknown command 'foo'".to_string());
+    assert_eq!(des.serialize(), resp_str.as_bytes());
+    assert_eq!(resp_parser.parse_line(resp_str).unwrap(), des);
+
+    // errors nested in replies, e.g. from EXEC
+    let resp_str = "*2\r\n+OK\r\n-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+    let des = RespType::Array(vec![
+        RespType::String("OK".to_string()),
+        RespType::Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+    ]);
+    assert_eq!(resp_parser.parse_line(resp_str).unwrap(), des);
+}
+
+#[test]
+fn test_null_array() {
+    let resp_parser = Resp::new();
+    let resp_str = "*-1\r\n";
+    let des = RespType::NullArray;
+    assert_eq!(des.serialize(), resp_str.as_bytes());
+    assert_eq!(des.serialize_with(Protocol::Resp3), b"_\r\n");
+    assert_eq!(resp_parser.parse_line(resp_str).unwrap(), des);
+
+    // null bulk strings stay distinct from null arrays
+    assert_eq!(
+        resp_parser.parse_line("*2\r\n$-1\r\n*-1\r\n").unwrap(),
+        RespType::Array(vec![RespType::Null, RespType::NullArray])
+    );
+}
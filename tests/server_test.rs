@@ -0,0 +1,283 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use rkey::{RespDecoder, RespType, Server};
+
+
+fn start_server(port: u16) -> String {
+    let addr = format!("127.0.0.1:{port}");
+    let listen_addr = addr.clone();
+    std::thread::spawn(move || Server::new().listen(listen_addr).unwrap());
+
+    let started = Instant::now();
+    while TcpStream::connect(&addr).is_err() {
+        assert!(started.elapsed() < Duration::from_secs(5), "server didn't start");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    addr
+}
+
+struct Client {
+    stream: TcpStream,
+    decoder: RespDecoder,
+}
+
+impl Client {
+    fn connect(addr: &str) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        Self { stream, decoder: RespDecoder::new() }
+    }
+
+    fn send(&mut self, args: &[&str]) {
+        let frame = RespType::Array(args.iter().map(|a| RespType::BString(a.as_bytes().to_vec())).collect());
+        self.stream.write_all(&frame.serialize()).unwrap();
+    }
+
+    fn read(&mut self) -> RespType {
+        let mut buf = [0; 1024];
+        loop {
+            if let Some(frame) = self.decoder.next_frame().unwrap() {
+                return frame;
+            }
+            let n = self.stream.read(&mut buf).unwrap();
+            assert!(n > 0, "connection closed");
+            self.decoder.feed(&buf[..n]);
+        }
+    }
+
+    fn call(&mut self, args: &[&str]) -> RespType {
+        self.send(args);
+        self.read()
+    }
+}
+
+fn bulk(s: &str) -> RespType {
+    RespType::BString(s.as_bytes().to_vec())
+}
+
+
+#[test]
+fn test_blocked_clients_dont_hold_workers() {
+    let addr = start_server(16390);
+
+    // more blocked clients than the pool has workers
+    let mut waiters: Vec<Client> = (0..12).map(|_| Client::connect(&addr)).collect();
+    for waiter in &mut waiters {
+        waiter.send(&["BRPOP", "jobs", "5"]);
+    }
+    std::thread::sleep(Duration::from_millis(100));
+
+    let mut producer = Client::connect(&addr);
+    assert_eq!(producer.call(&["PING"]), RespType::String("PONG".to_string()));
+    for i in 0..12 {
+        assert_eq!(producer.call(&["LPUSH", "jobs", &i.to_string()]), RespType::Int(1));
+    }
+
+    let mut served: Vec<String> = waiters
+        .iter_mut()
+        .map(|waiter| match waiter.read() {
+            RespType::Array(reply) => {
+                assert_eq!(reply[0], bulk("jobs"));
+                String::from_utf8(reply[1].as_bytes().unwrap().to_vec()).unwrap()
+            }
+            other => panic!("unexpected reply {:?}", other),
+        })
+        .collect();
+    served.sort_by_key(|s| s.parse::<u32>().unwrap());
+    assert_eq!(served, (0..12).map(|i| i.to_string()).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_blocked_clients_are_served_in_order() {
+    let addr = start_server(16391);
+
+    let mut waiters: Vec<Client> = (0..3).map(|_| Client::connect(&addr)).collect();
+    for waiter in &mut waiters {
+        waiter.send(&["BLPOP", "other", "queue", "0"]);
+        // make sure each one blocks before the next
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    let mut producer = Client::connect(&addr);
+    producer.call(&["RPUSH", "queue", "a", "b", "c"]);
+    for (waiter, expected) in waiters.iter_mut().zip(["a", "b", "c"]) {
+        assert_eq!(waiter.read(), RespType::Array(vec![bulk("queue"), bulk(expected)]));
+    }
+
+    // a served client carries on with what it pipelined after the blocking command
+    let mut waiter = Client::connect(&addr);
+    waiter.send(&["BLMOVE", "src", "dst", "LEFT", "RIGHT", "0"]);
+    waiter.send(&["LLEN", "dst"]);
+    std::thread::sleep(Duration::from_millis(50));
+    producer.call(&["RPUSH", "src", "x"]);
+    assert_eq!(waiter.read(), bulk("x"));
+    assert_eq!(waiter.read(), RespType::Int(1));
+}
+
+#[test]
+fn test_blocking_timeouts() {
+    let addr = start_server(16392);
+    let mut client = Client::connect(&addr);
+
+    let started = Instant::now();
+    assert_eq!(client.call(&["BLPOP", "nothing", "0.1"]), RespType::NullArray);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert_eq!(client.call(&["BLMOVE", "nothing", "dst", "LEFT", "LEFT", "0.05"]), RespType::Null);
+    assert_eq!(client.call(&["BLMPOP", "0.05", "1", "nothing", "LEFT"]), RespType::NullArray);
+
+    // data already there is served without blocking
+    client.call(&["RPUSH", "list", "a", "b", "c"]);
+    assert_eq!(
+        client.call(&["BLMPOP", "0", "2", "nothing", "list", "RIGHT", "COUNT", "2"]),
+        RespType::Array(vec![bulk("list"), RespType::Array(vec![bulk("c"), bulk("b")])])
+    );
+
+    let err = client.call(&["BLPOP", "list", "-1"]);
+    assert_eq!(err, RespType::Err("ERR timeout is negative".to_string()));
+    let err = client.call(&["BLPOP", "list", "soon"]);
+    assert_eq!(err, RespType::Err("ERR timeout is not a float or out of range".to_string()));
+}
+
+#[test]
+fn test_blocking_sorted_set_pops() {
+    let addr = start_server(16393);
+
+    let mut waiters: Vec<Client> = (0..3).map(|_| Client::connect(&addr)).collect();
+    for waiter in &mut waiters {
+        waiter.send(&["BZPOPMIN", "other", "jobs", "0"]);
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    // each waiter, in the order they blocked, gets the lowest score left
+    let mut producer = Client::connect(&addr);
+    producer.call(&["ZADD", "jobs", "3", "c", "1", "a", "2", "b", "4", "d"]);
+    for (waiter, (member, score)) in waiters.iter_mut().zip([("a", "1"), ("b", "2"), ("c", "3")]) {
+        assert_eq!(waiter.read(), RespType::Array(vec![bulk("jobs"), bulk(member), bulk(score)]));
+    }
+    assert_eq!(producer.call(&["ZCARD", "jobs"]), RespType::Int(1));
+
+    let mut waiter = Client::connect(&addr);
+    waiter.send(&["BZMPOP", "0", "2", "other", "jobs2", "MAX", "COUNT", "2"]);
+    std::thread::sleep(Duration::from_millis(50));
+    producer.call(&["ZINCRBY", "jobs2", "5", "x"]);
+    assert_eq!(
+        waiter.read(),
+        RespType::Array(vec![bulk("jobs2"), RespType::Array(vec![RespType::Array(vec![bulk("x"), bulk("5")])])])
+    );
+
+    let started = Instant::now();
+    assert_eq!(waiter.call(&["BZPOPMAX", "nothing", "0.1"]), RespType::NullArray);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert_eq!(waiter.call(&["BZMPOP", "0.05", "1", "nothing", "MIN"]), RespType::NullArray);
+    assert_eq!(waiter.call(&["BZPOPMAX", "jobs", "0"]), RespType::Array(vec![bulk("jobs"), bulk("d"), bulk("4")]));
+}
+
+#[test]
+fn test_xread_block() {
+    let addr = start_server(16394);
+    let mut producer = Client::connect(&addr);
+    producer.call(&["XADD", "events", "1", "n", "1"]);
+
+    // `$` only waits for entries added after the reader blocked
+    let mut readers: Vec<Client> = (0..2).map(|_| Client::connect(&addr)).collect();
+    for reader in &mut readers {
+        reader.send(&["XREAD", "BLOCK", "0", "STREAMS", "other", "events", "$", "$"]);
+    }
+    std::thread::sleep(Duration::from_millis(50));
+    producer.call(&["XADD", "events", "2", "n", "2"]);
+    let expected = RespType::Array(vec![RespType::Array(vec![
+        bulk("events"),
+        RespType::Array(vec![RespType::Array(vec![bulk("2-0"), RespType::Array(vec![bulk("n"), bulk("2")])])]),
+    ])]);
+    for reader in &mut readers {
+        assert_eq!(reader.read(), expected);
+    }
+
+    let started = Instant::now();
+    assert_eq!(producer.call(&["XREAD", "BLOCK", "100", "STREAMS", "events", "$"]), RespType::NullArray);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    // entries already past the ID are read without blocking
+    assert_eq!(producer.call(&["XREAD", "BLOCK", "0", "COUNT", "1", "STREAMS", "events", "1"]), expected);
+}
+
+#[test]
+fn test_xreadgroup_block() {
+    let addr = start_server(16395);
+    let mut producer = Client::connect(&addr);
+    producer.call(&["XGROUP", "CREATE", "jobs", "workers", "$", "MKSTREAM"]);
+
+    // each new entry goes to one blocked consumer only
+    let mut workers: Vec<Client> = (0..2).map(|_| Client::connect(&addr)).collect();
+    for (i, worker) in workers.iter_mut().enumerate() {
+        worker.send(&["XREADGROUP", "GROUP", "workers", &format!("w{}", i), "BLOCK", "0", "STREAMS", "jobs", ">"]);
+    }
+    std::thread::sleep(Duration::from_millis(50));
+    producer.call(&["XADD", "jobs", "1", "job", "a"]);
+    producer.call(&["XADD", "jobs", "2", "job", "b"]);
+    let mut ids: Vec<RespType> = workers
+        .iter_mut()
+        .map(|worker| {
+            let RespType::Array(streams) = worker.read() else {
+                panic!("expected an array");
+            };
+            let RespType::Array(stream) = &streams[0] else {
+                panic!("expected an array");
+            };
+            let RespType::Array(entries) = &stream[1] else {
+                panic!("expected an array");
+            };
+            assert_eq!(entries.len(), 1);
+            let RespType::Array(entry) = &entries[0] else {
+                panic!("expected an array");
+            };
+            entry[0].clone()
+        })
+        .collect();
+    ids.sort_by_key(|id| format!("{:?}", id));
+    assert_eq!(ids, vec![bulk("1-0"), bulk("2-0")]);
+    let RespType::Array(summary) = producer.call(&["XPENDING", "jobs", "workers"]) else {
+        panic!("expected an array");
+    };
+    assert_eq!(summary[0], RespType::Int(2));
+
+    let started = Instant::now();
+    assert_eq!(producer.call(&["XREADGROUP", "GROUP", "workers", "w0", "BLOCK", "100", "STREAMS", "jobs", ">"]), RespType::NullArray);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+}
+
+#[test]
+fn test_errors_echoing_line_breaks_stay_one_reply() {
+    let addr = start_server(16396);
+    let mut client = Client::connect(&addr);
+    let long = "y".repeat(300);
+    client.send(&["NOSUCHCMD", "x\r\n+OK", &long]);
+    client.send(&["PING"]);
+    let RespType::Err(msg) = client.read() else {
+        panic!("expected an error");
+    };
+    assert!(msg.contains("'x  +OK'"), "{msg}");
+    assert!(msg.contains(&format!("'{}'", "y".repeat(128))) && !msg.contains(&"y".repeat(129)), "{msg}");
+    assert_eq!(client.read(), RespType::String("PONG".into()));
+}
+
+#[test]
+fn test_unread_wakeup_reply_doesnt_stall_others() {
+    let addr = start_server(16397);
+
+    // this client never reads, so its reply can't fit in the socket buffers
+    let mut sleeper = Client::connect(&addr);
+    sleeper.send(&["BLPOP", "big", "0"]);
+    std::thread::sleep(Duration::from_millis(50));
+
+    let mut producer = Client::connect(&addr);
+    let value = "v".repeat(16 * 1024 * 1024);
+    assert_eq!(producer.call(&["RPUSH", "big", &value]), RespType::Int(1));
+    assert_eq!(producer.call(&["PING"]), RespType::String("PONG".into()));
+
+    let mut other = Client::connect(&addr);
+    assert_eq!(other.call(&["BLPOP", "queue", "0.05"]), RespType::NullArray);
+    assert_eq!(other.call(&["PING"]), RespType::String("PONG".into()));
+    drop(sleeper);
+}